@@ -0,0 +1,110 @@
+use super::{blip_buffer::BlipBuffer, filter::FilterChain};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+// Turns the mixed level of the console, sampled every CPU cycle, into a stream at the host
+// sample rate: level changes go through the band-limited resampler, then the console filters.
+// The samples of the last completed frame stay available until the next one ends.
+pub struct AudioOutput {
+    clock_rate: f64,
+    sample_rate: u32,
+    blip: BlipBuffer,
+    filters: FilterChain,
+    level: f32,
+    frame_start: u64,
+    samples: Vec<f32>,
+}
+
+impl AudioOutput {
+    pub fn build_audio_output(clock_rate: f64, sample_rate: u32) -> AudioOutput {
+        return AudioOutput {
+            clock_rate,
+            sample_rate,
+            blip: BlipBuffer::build_blip_buffer(clock_rate, f64::from(sample_rate)),
+            filters: FilterChain::build_console_filters(sample_rate as f32),
+            level: 0.0,
+            frame_start: 0,
+            samples: Vec::new(),
+        };
+    }
+
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: u32) {
+        self.clock_rate = clock_rate;
+        self.sample_rate = sample_rate;
+        self.blip.set_rates(clock_rate, f64::from(sample_rate));
+        self.filters = FilterChain::build_console_filters(sample_rate as f32);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    // Slightly stretches or shrinks the output to steer the fill level of the audio device
+    // buffer. The filters keep their state so the change is inaudible.
+    pub fn set_rate_adjustment(&mut self, adjustment: f64) {
+        self.blip.set_rates(self.clock_rate, f64::from(self.sample_rate) * adjustment);
+    }
+
+    // Level of the console output at CPU cycle `cycle`, cycles count up across frames
+    pub fn update(&mut self, cycle: u64, level: f32) {
+        if level != self.level {
+            self.blip.add_delta(cycle - self.frame_start, level - self.level);
+            self.level = level;
+        }
+    }
+
+    // For an output created while the console is already running
+    pub fn start_frame(&mut self, cycle: u64) {
+        self.frame_start = cycle;
+    }
+
+    pub fn end_frame(&mut self, cycle: u64) {
+        self.samples.clear();
+        self.blip.end_frame(cycle - self.frame_start, &mut self.samples);
+        for sample in self.samples.iter_mut() {
+            *sample = self.filters.process(*sample);
+        }
+        self.frame_start = cycle;
+    }
+
+    // Samples of the last frame, centered on 0.0 and mostly within -1.0 to 1.0
+    pub fn samples(&self) -> &[f32] {
+        return &self.samples;
+    }
+
+    pub fn samples_i16(&self) -> Vec<i16> {
+        return self.samples.iter().map(|sample| to_i16(*sample)).collect();
+    }
+}
+
+pub fn to_i16(sample: f32) -> i16 {
+    return (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AudioOutput;
+
+    #[test]
+    fn test_audio_output_square_wave() {
+        let mut audio = AudioOutput::build_audio_output(1_789_773.0, 44_100);
+        // 440 Hz square wave between 0.0 and 0.25
+        let half_period = 1_789_773 / 880;
+        let mut cycle = 0;
+        let mut peak: f32 = 0.0;
+        for frame in 0..30 {
+            for _ in 0..29_780 {
+                audio.update(cycle, if (cycle / half_period) % 2 == 0 { 0.25 } else { 0.0 });
+                cycle += 1;
+            }
+            audio.end_frame(cycle);
+            assert!((audio.samples().len() as i64 - 734).abs() <= 1);
+            if frame > 10 {
+                peak = audio.samples().iter().fold(peak, |peak, sample| peak.max(sample.abs()));
+            }
+        }
+        // The filters center the wave on 0.0, the high-pass filters overshoot a little on the edges
+        assert!(peak > 0.1 && peak < 0.25);
+        assert_eq!(audio.samples_i16().len(), audio.samples().len());
+    }
+}
@@ -0,0 +1,136 @@
+use std::f64::consts::PI;
+
+// Sub-sample positions the kernel is precomputed for
+const PHASES: usize = 64;
+// Output samples touched by one level change, the output lags by half of it
+const KERNEL_WIDTH: usize = 16;
+// Fraction of the output Nyquist frequency kept by the kernel
+const CUTOFF: f64 = 0.9;
+
+// Band-limited resampler from the CPU clock to the host sample rate, after blip_buf:
+// http://www.slack.net/~ant/libs/audio.html
+// Instead of sampling the channel levels, every change of the level is recorded as a delta at its
+// exact clock time. Each delta is spread over a few output samples with a windowed sinc kernel,
+// and summing the deltas back turns the band-limited impulses into band-limited steps. This keeps
+// the square waves free of the aliasing that point sampling a 1.79 MHz signal would produce.
+pub struct BlipBuffer {
+    clock_rate: f64,
+    sample_rate: f64,
+    // Output samples per clock
+    factor: f64,
+    // Position of the current frame start in output samples, the fraction of the first sample
+    offset: f64,
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    deltas: Vec<f32>,
+    integrator: f32,
+}
+
+impl BlipBuffer {
+    pub fn build_blip_buffer(clock_rate: f64, sample_rate: f64) -> BlipBuffer {
+        return BlipBuffer {
+            clock_rate,
+            sample_rate,
+            factor: sample_rate / clock_rate,
+            offset: 0.0,
+            kernel: BlipBuffer::build_kernel(),
+            deltas: vec![0.0; KERNEL_WIDTH],
+            integrator: 0.0,
+        };
+    }
+
+    // Blackman windowed sinc, one row per phase with each row summing to 1
+    fn build_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+        let half = (KERNEL_WIDTH / 2) as f64;
+        let mut kernel = Vec::with_capacity(PHASES);
+        for phase in 0..PHASES {
+            let fraction = phase as f64 / PHASES as f64;
+            let mut taps = [0.0f64; KERNEL_WIDTH];
+            for (i, tap) in taps.iter_mut().enumerate() {
+                let x = i as f64 - half - fraction + 1.0;
+                let sinc = if x == 0.0 { CUTOFF } else { (PI * CUTOFF * x).sin() / (PI * x) };
+                let w = (x + half) / (2.0 * half);
+                let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+                *tap = sinc * window;
+            }
+            let sum: f64 = taps.iter().sum();
+            let mut row = [0.0f32; KERNEL_WIDTH];
+            for (value, tap) in row.iter_mut().zip(taps.iter()) {
+                *value = (tap / sum) as f32;
+            }
+            kernel.push(row);
+        }
+        return kernel;
+    }
+
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.clock_rate = clock_rate;
+        self.sample_rate = sample_rate;
+        self.factor = sample_rate / clock_rate;
+    }
+
+    pub fn clock_rate(&self) -> f64 {
+        return self.clock_rate;
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        return self.sample_rate;
+    }
+
+    // Records a change of the input level, `clock` counts from the start of the current frame
+    pub fn add_delta(&mut self, clock: u64, delta: f32) {
+        let position = self.offset + clock as f64 * self.factor;
+        let index = position as usize;
+        let phase = ((position - index as f64) * PHASES as f64) as usize;
+        let end = index + KERNEL_WIDTH;
+        if self.deltas.len() < end {
+            self.deltas.resize(end, 0.0);
+        }
+        for (sample, tap) in self.deltas[index..end].iter_mut().zip(self.kernel[phase].iter()) {
+            *sample += delta * tap;
+        }
+    }
+
+    // Closes the frame after `clocks` clocks and appends the samples it completed to `output`.
+    // Later deltas can only touch the samples after the frame end, the others are final.
+    pub fn end_frame(&mut self, clocks: u64, output: &mut Vec<f32>) {
+        let end = self.offset + clocks as f64 * self.factor;
+        let count = end as usize;
+        if self.deltas.len() < count + KERNEL_WIDTH {
+            self.deltas.resize(count + KERNEL_WIDTH, 0.0);
+        }
+        for delta in self.deltas.drain(..count) {
+            self.integrator += delta;
+            output.push(self.integrator);
+        }
+        self.offset = end - count as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlipBuffer;
+
+    #[test]
+    fn test_blip_buffer_sample_count() {
+        // 29780 CPU cycles make 798.67 samples at 48 kHz, the fraction carries over to the next frame
+        let mut blip = BlipBuffer::build_blip_buffer(1_789_773.0, 48_000.0);
+        let mut samples = Vec::new();
+        for _ in 0..60 {
+            blip.end_frame(29_780, &mut samples);
+        }
+        assert!((samples.len() as i64 - 47_920).abs() <= 1);
+    }
+
+    #[test]
+    fn test_blip_buffer_step() {
+        let mut blip = BlipBuffer::build_blip_buffer(1_000_000.0, 10_000.0);
+        let mut samples = Vec::new();
+        blip.add_delta(1_050, 0.5);
+        blip.end_frame(10_000, &mut samples);
+        assert_eq!(samples.len(), 100);
+        // Silent before the step, settled after it, with some ringing around the edge
+        assert!(samples[..2].iter().all(|sample| sample.abs() < 0.001));
+        assert!(samples[30..].iter().all(|sample| (sample - 0.5).abs() < 0.001));
+        assert!(samples[10..20].iter().any(|sample| *sample > 0.5));
+    }
+}
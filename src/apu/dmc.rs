@@ -0,0 +1,240 @@
+use crate::cpu::{Byte, Word};
+
+// Delta modulation channel: https://www.nesdev.org/wiki/APU_DMC
+// Plays 1 bit delta encoded samples read from CPU memory at $C000-$FFFF. Each bit moves a 7 bit
+// output level up or down by 2. The channel cannot reach the bus itself: it asks for a byte
+// through `dma_request` and the console fetches it, stalling the CPU.
+pub struct Dmc {
+    // Timer periods in CPU cycles, they differ between NTSC and PAL
+    rates: &'static [u16; 16],
+    irq_enabled: bool,
+    looping: bool,
+    rate_index: usize,
+    timer: u16,
+    output_level: Byte,
+    sample_address: Word,
+    sample_length: u16,
+
+    // Memory reader
+    current_address: Word,
+    bytes_remaining: u16,
+    sample_buffer: Option<Byte>,
+
+    // Output unit
+    shift_register: Byte,
+    bits_remaining: u8,
+    silence: bool,
+
+    interrupt: bool,
+}
+
+impl Dmc {
+    pub fn build_dmc(rates: &'static [u16; 16]) -> Dmc {
+        return Dmc {
+            rates,
+            irq_enabled: false,
+            looping: false,
+            rate_index: 0,
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            interrupt: false,
+        }
+    }
+
+    pub fn set_rates(&mut self, rates: &'static [u16; 16]) {
+        self.rates = rates;
+    }
+
+    // IL-- RRRR: IRQ enable, loop, rate index. Clearing the IRQ enable acknowledges the interrupt.
+    pub fn write_control(&mut self, data: Byte) {
+        self.irq_enabled = data & 0x80 != 0;
+        self.looping = data & 0x40 != 0;
+        self.rate_index = usize::from(data & 0x0F);
+        if !self.irq_enabled {
+            self.interrupt = false;
+        }
+    }
+
+    // -DDD DDDD: loads the output level directly
+    pub fn write_output_level(&mut self, data: Byte) {
+        self.output_level = data & 0x7F;
+    }
+
+    // Sample address %11AAAAAA.AA000000
+    pub fn write_sample_address(&mut self, data: Byte) {
+        self.sample_address = 0xC000 | (Word::from(data) << 6);
+    }
+
+    // Sample length %LLLL.LLLL0001
+    pub fn write_sample_length(&mut self, data: Byte) {
+        self.sample_length = (u16::from(data) << 4) | 0x0001;
+    }
+
+    // Through $4015: disabling stops the sample after the buffered byte, enabling restarts it
+    // if it had ended. Either way the interrupt is acknowledged.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.interrupt = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Bytes left to read, non zero while the sample plays
+    pub fn active(&self) -> bool {
+        return self.bytes_remaining > 0;
+    }
+
+    pub fn irq(&self) -> bool {
+        return self.interrupt;
+    }
+
+    // Address the memory reader needs a byte from, when the sample buffer is empty
+    pub fn dma_request(&self) -> Option<Word> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            return Some(self.current_address);
+        }
+        return None;
+    }
+
+    // Delivers the byte read at the requested address
+    pub fn fill_buffer(&mut self, data: Byte) {
+        self.sample_buffer = Some(data);
+        self.current_address = if self.current_address == 0xFFFF { 0x8000 } else { self.current_address + 1 };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.interrupt = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rates[self.rate_index] - 1;
+
+        if !self.silence {
+            if self.shift_register & 0x01 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift_register = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    // Current level, 0 to 127
+    pub fn output(&self) -> Byte {
+        return self.output_level;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nes::region::Region;
+
+    use super::Dmc;
+
+    // Runs the channel, serving its memory reads with `data`
+    fn run(dmc: &mut Dmc, cycles: usize, data: u8) -> Vec<u16> {
+        let mut reads = Vec::new();
+        for _ in 0..cycles {
+            if let Some(address) = dmc.dma_request() {
+                reads.push(address);
+                dmc.fill_buffer(data);
+            }
+            dmc.clock_timer();
+        }
+        return reads;
+    }
+
+    #[test]
+    fn test_dmc_sample_playback() {
+        let mut dmc = Dmc::build_dmc(Region::Ntsc.dmc_rates());
+        // Fastest rate, 54 cycles per bit, 17 byte sample at $C040
+        dmc.write_control(0x0F);
+        dmc.write_output_level(0x40);
+        dmc.write_sample_address(0x01);
+        dmc.write_sample_length(0x01);
+        dmc.set_enabled(true);
+        assert!(dmc.active());
+        // The first byte waits in the buffer until the current 8 bits are out
+        let reads = run(&mut dmc, 54 * 7 + 1, 0xFF);
+        assert_eq!(reads, vec![0xC040]);
+        assert_eq!(dmc.output(), 0x40);
+        run(&mut dmc, 54 * 8, 0xFF);
+        assert_eq!(dmc.output(), 0x50);
+        // Ramps up to 126 and stays there
+        run(&mut dmc, 54 * 8 * 16, 0xFF);
+        assert_eq!(dmc.output(), 126);
+        assert!(!dmc.active());
+        assert!(!dmc.irq());
+    }
+
+    #[test]
+    fn test_dmc_irq_and_loop() {
+        let mut dmc = Dmc::build_dmc(Region::Ntsc.dmc_rates());
+        dmc.write_control(0x8F);
+        dmc.write_sample_address(0xFF);
+        dmc.write_sample_length(0x00);
+        dmc.set_enabled(true);
+        // A single byte sample at $FFC0 raises the interrupt once read
+        assert_eq!(run(&mut dmc, 1, 0x00), vec![0xFFC0]);
+        assert!(dmc.irq());
+        assert!(!dmc.active());
+        dmc.set_enabled(false);
+        assert!(!dmc.irq());
+        // Looping restarts the sample without interrupt
+        dmc.write_control(0xCF);
+        dmc.set_enabled(true);
+        let reads = run(&mut dmc, 54 * 8 * 3, 0x00);
+        assert_eq!(reads, vec![0xFFC0, 0xFFC0, 0xFFC0]);
+        assert!(!dmc.irq());
+    }
+
+    #[test]
+    fn test_dmc_address_wraps() {
+        let mut dmc = Dmc::build_dmc(Region::Ntsc.dmc_rates());
+        dmc.write_control(0x0F);
+        dmc.write_sample_address(0xFF);
+        dmc.write_sample_length(0x04);
+        dmc.set_enabled(true);
+        let reads = run(&mut dmc, 54 * 8 * 66, 0x00);
+        assert_eq!(reads.len(), 65);
+        assert_eq!(reads[63], 0xFFFF);
+        assert_eq!(reads[64], 0x8000);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::cpu::Byte;
+
+// Volume envelope shared by the pulse and noise channels: https://www.nesdev.org/wiki/APU_Envelope
+// Either a constant volume or a decay from 15 to 0, one step per divider period,
+// optionally looping back to 15.
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // Constant volume or divider period
+    volume: Byte,
+    divider: Byte,
+    decay: Byte,
+}
+
+impl Envelope {
+    pub fn build_envelope() -> Envelope {
+        return Envelope {
+            start: false,
+            looping: false,
+            constant: false,
+            volume: 0,
+            divider: 0,
+            decay: 0,
+        }
+    }
+
+    // --LC VVVV, the loop flag doubles as the length counter halt flag
+    pub fn write_control(&mut self, data: Byte) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.volume = data & 0x0F;
+    }
+
+    // Restarts the decay on the next quarter frame
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+            return;
+        }
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+        self.divider = self.volume;
+        if self.decay > 0 {
+            self.decay -= 1;
+        } else if self.looping {
+            self.decay = 15;
+        }
+    }
+
+    pub fn output(&self) -> Byte {
+        if self.constant {
+            return self.volume;
+        }
+        return self.decay;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Envelope;
+
+    #[test]
+    fn test_envelope_decay() {
+        let mut envelope = Envelope::build_envelope();
+        envelope.write_control(0x01);
+        envelope.restart();
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        // Period of 2 clocks per step
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.output(), 14);
+        for _ in 0..28 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.output(), 0);
+        // Loop flag
+        envelope.write_control(0x21);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        // Constant volume
+        envelope.write_control(0x17);
+        assert_eq!(envelope.output(), 7);
+    }
+}
@@ -0,0 +1,99 @@
+use std::f32::consts::PI;
+
+// First order RC filter run at the output sample rate
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+pub struct Filter {
+    kind: FilterKind,
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl Filter {
+    pub fn build_filter(kind: FilterKind, cutoff: f32, sample_rate: f32) -> Filter {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let alpha = match kind {
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+        return Filter { kind, alpha, previous_input: 0.0, previous_output: 0.0 };
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass => self.alpha * (self.previous_output + input - self.previous_input),
+            FilterKind::LowPass => self.previous_output + self.alpha * (input - self.previous_output),
+        };
+        self.previous_input = input;
+        self.previous_output = output;
+        return output;
+    }
+}
+
+// Filters between the 2A03 and the audio output of the console:
+// https://www.nesdev.org/wiki/APU_Mixer#Emulation
+// Two high-pass filters at 90 Hz and 440 Hz remove the DC offset of the DACs, and a low-pass
+// filter at 14 kHz takes the edge off the square waves.
+pub struct FilterChain {
+    filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    pub fn build_console_filters(sample_rate: f32) -> FilterChain {
+        return FilterChain {
+            filters: vec![
+                Filter::build_filter(FilterKind::HighPass, 90.0, sample_rate),
+                Filter::build_filter(FilterKind::HighPass, 440.0, sample_rate),
+                Filter::build_filter(FilterKind::LowPass, 14_000.0, sample_rate),
+            ],
+        };
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let mut sample = input;
+        for filter in self.filters.iter_mut() {
+            sample = filter.process(sample);
+        }
+        return sample;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, FilterChain, FilterKind};
+
+    #[test]
+    fn test_filters_on_dc() {
+        let mut high_pass = Filter::build_filter(FilterKind::HighPass, 90.0, 48_000.0);
+        let mut low_pass = Filter::build_filter(FilterKind::LowPass, 14_000.0, 48_000.0);
+        let mut high = 0.0;
+        let mut low = 0.0;
+        for _ in 0..48_000 {
+            high = high_pass.process(0.5);
+            low = low_pass.process(0.5);
+        }
+        assert!(high.abs() < 0.001);
+        assert!((low - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_console_filters_center_the_signal() {
+        let mut filters = FilterChain::build_console_filters(44_100.0);
+        // A 1 kHz square wave between 0.0 and 0.4 ends up around 0.0
+        let mut sum = 0.0;
+        for n in 0..44_100 {
+            let input = if (n / 22) % 2 == 0 { 0.4 } else { 0.0 };
+            let output = filters.process(input);
+            if n >= 22_050 {
+                sum += output;
+            }
+        }
+        assert!((sum / 22_050.0f32).abs() < 0.01);
+    }
+}
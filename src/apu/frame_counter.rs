@@ -0,0 +1,179 @@
+use crate::{cpu::Byte, nes::region::Region};
+
+// What the frame counter clocks on a given CPU cycle. Half frames always come with a quarter frame.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum FrameStep {
+    None,
+    Quarter,
+    Half,
+}
+
+// Frame sequencer at $4017: https://www.nesdev.org/wiki/APU_Frame_Counter
+// Clocks the envelopes and the triangle linear counter on quarter frames, the length counters
+// and sweeps on half frames, at fixed CPU cycle positions. The 4 step mode raises an IRQ at the
+// end of each sequence unless inhibited, the 5 step mode never does.
+pub struct FrameCounter {
+    region: Region,
+    five_step: bool,
+    irq_inhibit: bool,
+    interrupt: bool,
+    // CPU cycles since the sequence started
+    cycle: u32,
+    // Mode written to $4017 and CPU cycles before it takes effect
+    pending_write: Option<(Byte, u8)>,
+}
+
+impl FrameCounter {
+    pub fn build_frame_counter(region: Region) -> FrameCounter {
+        return FrameCounter {
+            region,
+            five_step: false,
+            irq_inhibit: false,
+            interrupt: false,
+            cycle: 0,
+            pending_write: None,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    // MI-- ----: 5 step mode, IRQ inhibit. The inhibit flag acts right away, the sequencer restarts
+    // 3 CPU cycles later when written on an odd cycle (during an APU cycle), 4 when written on an even one.
+    pub fn write(&mut self, data: Byte, odd_cycle: bool) {
+        self.irq_inhibit = data & 0x40 != 0;
+        if self.irq_inhibit {
+            self.interrupt = false;
+        }
+        let delay = if odd_cycle { 3 } else { 4 };
+        self.pending_write = Some((data, delay));
+    }
+
+    pub fn irq(&self) -> bool {
+        return self.interrupt;
+    }
+
+    // Reading $4015 acknowledges the frame interrupt
+    pub fn acknowledge(&mut self) {
+        self.interrupt = false;
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock(&mut self) -> FrameStep {
+        if let Some((data, delay)) = self.pending_write {
+            if delay == 1 {
+                self.pending_write = None;
+                self.five_step = data & 0x80 != 0;
+                self.cycle = 0;
+                // Entering the 5 step mode clocks everything right away
+                if self.five_step {
+                    return FrameStep::Half;
+                }
+                return FrameStep::None;
+            }
+            self.pending_write = Some((data, delay - 1));
+        }
+
+        self.cycle += 1;
+        if self.five_step {
+            let steps = self.region.five_step_sequence();
+            if self.cycle == steps[4] + 1 {
+                self.cycle = 0;
+            }
+            return FrameCounter::step_at(self.cycle, [steps[0], steps[1], steps[2], steps[4]]);
+        }
+
+        let steps = self.region.four_step_sequence();
+        // The IRQ flag is raised on the 3 cycles around the last step
+        if self.cycle + 1 >= steps[3] && !self.irq_inhibit {
+            self.interrupt = true;
+        }
+        if self.cycle == steps[3] + 1 {
+            self.cycle = 0;
+        }
+        return FrameCounter::step_at(self.cycle, *steps);
+    }
+
+    // Steps 1 and 3 are quarter frames, 2 and 4 half frames
+    fn step_at(cycle: u32, steps: [u32; 4]) -> FrameStep {
+        if cycle == steps[1] || cycle == steps[3] {
+            return FrameStep::Half;
+        }
+        if cycle == steps[0] || cycle == steps[2] {
+            return FrameStep::Quarter;
+        }
+        return FrameStep::None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nes::region::Region;
+
+    use super::{FrameCounter, FrameStep};
+
+    // CPU cycles, counted from the first clock, on which each step happens
+    fn steps(counter: &mut FrameCounter, cycles: u32) -> Vec<(u32, FrameStep)> {
+        let mut steps = Vec::new();
+        for cycle in 1..=cycles {
+            let step = counter.clock();
+            if step != FrameStep::None {
+                steps.push((cycle, step));
+            }
+        }
+        return steps;
+    }
+
+    #[test]
+    fn test_four_step_sequence() {
+        let mut counter = FrameCounter::build_frame_counter(Region::Ntsc);
+        assert_eq!(steps(&mut counter, 29830 + 7457), vec![
+            (7457, FrameStep::Quarter),
+            (14913, FrameStep::Half),
+            (22371, FrameStep::Quarter),
+            (29829, FrameStep::Half),
+            (29830 + 7457, FrameStep::Quarter),
+        ]);
+        assert!(counter.irq());
+        counter.acknowledge();
+        assert!(!counter.irq());
+    }
+
+    #[test]
+    fn test_frame_irq_timing() {
+        let mut counter = FrameCounter::build_frame_counter(Region::Ntsc);
+        steps(&mut counter, 29827);
+        assert!(!counter.irq());
+        counter.clock();
+        assert!(counter.irq());
+        // Set again on the next two cycles even when acknowledged
+        counter.acknowledge();
+        counter.clock();
+        counter.acknowledge();
+        counter.clock();
+        assert!(counter.irq());
+        counter.acknowledge();
+        counter.clock();
+        assert!(!counter.irq());
+        // Inhibited
+        counter.write(0x40, false);
+        steps(&mut counter, 29830 * 2);
+        assert!(!counter.irq());
+    }
+
+    #[test]
+    fn test_five_step_sequence() {
+        let mut counter = FrameCounter::build_frame_counter(Region::Pal);
+        // Written on an odd cycle: takes effect 3 cycles later and clocks both units at once
+        counter.write(0x80, true);
+        assert_eq!(steps(&mut counter, 3), vec![(3, FrameStep::Half)]);
+        assert_eq!(steps(&mut counter, 41566), vec![
+            (8313, FrameStep::Quarter),
+            (16627, FrameStep::Half),
+            (24939, FrameStep::Quarter),
+            (41565, FrameStep::Half),
+        ]);
+        assert!(!counter.irq());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::cpu::Byte;
+
+// Values loaded from the top 5 bits of the length register: https://www.nesdev.org/wiki/APU_Length_Counter
+const LENGTH_TABLE: [Byte; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// Silences a channel once it counts down to 0, clocked on half frames
+pub struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    counter: Byte,
+}
+
+impl LengthCounter {
+    pub fn build_length_counter() -> LengthCounter {
+        return LengthCounter {
+            enabled: false,
+            halt: false,
+            counter: 0,
+        }
+    }
+
+    // Disabling the channel through the status register clears the counter right away
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn set_halt(&mut self, halt: bool) {
+        self.halt = halt;
+    }
+
+    pub fn load(&mut self, index: Byte) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[usize::from(index & 0x1F)];
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        return self.counter > 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LengthCounter;
+
+    #[test]
+    fn test_length_counter() {
+        let mut length = LengthCounter::build_length_counter();
+        // Loads are ignored while the channel is disabled
+        length.load(0x01);
+        assert!(!length.active());
+        length.set_enabled(true);
+        length.load(0x03);
+        length.clock();
+        assert!(length.active());
+        length.clock();
+        assert!(!length.active());
+        length.load(0x01);
+        length.set_halt(true);
+        for _ in 0..300 {
+            length.clock();
+        }
+        assert!(length.active());
+        length.set_enabled(false);
+        assert!(!length.active());
+    }
+}
@@ -0,0 +1,51 @@
+use crate::cpu::Byte;
+
+const PULSE_TABLE_SIZE: usize = 31;
+const TND_TABLE_SIZE: usize = 203;
+
+// Nonlinear DACs of the 2A03: https://www.nesdev.org/wiki/APU_Mixer
+// The two pulse channels share one resistor network, triangle, noise and DMC another, so a loud
+// channel compresses the others on the same output. Both curves are precomputed as lookup tables:
+// pulse_table[n] = 95.52 / (8128 / n + 100), tnd_table[n] = 163.67 / (24329 / n + 100)
+// The sum stays within 0.0 to 1.0.
+pub struct Mixer {
+    pulse_table: [f32; PULSE_TABLE_SIZE],
+    tnd_table: [f32; TND_TABLE_SIZE],
+}
+
+impl Mixer {
+    pub fn build_mixer() -> Mixer {
+        let mut pulse_table = [0.0; PULSE_TABLE_SIZE];
+        for (n, level) in pulse_table.iter_mut().enumerate().skip(1) {
+            *level = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd_table = [0.0; TND_TABLE_SIZE];
+        for (n, level) in tnd_table.iter_mut().enumerate().skip(1) {
+            *level = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        return Mixer { pulse_table, tnd_table };
+    }
+
+    // Pulse, triangle and noise levels are 0 to 15, the DMC level 0 to 127
+    pub fn mix(&self, pulse1: Byte, pulse2: Byte, triangle: Byte, noise: Byte, dmc: Byte) -> f32 {
+        let pulse = self.pulse_table[usize::from(pulse1) + usize::from(pulse2)];
+        let tnd = self.tnd_table[3 * usize::from(triangle) + 2 * usize::from(noise) + usize::from(dmc)];
+        return pulse + tnd;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mixer;
+
+    #[test]
+    fn test_mixer_levels() {
+        let mixer = Mixer::build_mixer();
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0), 0.0);
+        assert!((mixer.mix(15, 15, 0, 0, 0) - 0.2575).abs() < 0.001);
+        assert!((mixer.mix(0, 0, 15, 15, 127) - 0.7424).abs() < 0.001);
+        assert!(mixer.mix(15, 15, 15, 15, 127) <= 1.0);
+        // The DAC is not linear, two channels at the same level are less than twice as loud as one
+        assert!(mixer.mix(15, 15, 0, 0, 0) < 2.0 * mixer.mix(15, 0, 0, 0, 0));
+    }
+}
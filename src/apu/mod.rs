@@ -0,0 +1,337 @@
+use crate::{cpu::{Byte, Word}, memory::MemoryMapped, nes::region::Region};
+
+use self::{dmc::Dmc, frame_counter::{FrameCounter, FrameStep}, mixer::Mixer, noise::Noise, pulse::Pulse, triangle::Triangle};
+
+pub mod audio_output;
+pub mod blip_buffer;
+pub mod dmc;
+pub mod envelope;
+pub mod filter;
+pub mod frame_counter;
+pub mod length_counter;
+pub mod mixer;
+pub mod noise;
+pub mod opll;
+pub mod pulse;
+pub mod sweep;
+pub mod triangle;
+
+// Sources of the console audio, for exporting them separately
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum AudioChannel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+    // Sound chip on the cartridge
+    Expansion,
+}
+
+impl AudioChannel {
+    pub const ALL: [AudioChannel; 6] = [
+        AudioChannel::Pulse1,
+        AudioChannel::Pulse2,
+        AudioChannel::Triangle,
+        AudioChannel::Noise,
+        AudioChannel::Dmc,
+        AudioChannel::Expansion,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioChannel::Pulse1 => "pulse1",
+            AudioChannel::Pulse2 => "pulse2",
+            AudioChannel::Triangle => "triangle",
+            AudioChannel::Noise => "noise",
+            AudioChannel::Dmc => "dmc",
+            AudioChannel::Expansion => "expansion",
+        }
+    }
+}
+
+// 2A03 audio unit at $4000-$4017: two pulse channels, a triangle, a noise channel and the DMC,
+// with the status register at $4015 and the frame counter at $4017.
+pub struct Apu {
+    region: Region,
+    cycle: u64,
+    pulses: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    mixer: Mixer,
+}
+
+impl Apu {
+    pub fn build_apu() -> Apu {
+        let region = Region::Ntsc;
+        return Apu {
+            region,
+            cycle: 0,
+            pulses: [Pulse::build_apu_pulse(true), Pulse::build_apu_pulse(false)],
+            triangle: Triangle::build_triangle(),
+            noise: Noise::build_noise(region.noise_periods()),
+            dmc: Dmc::build_dmc(region.dmc_rates()),
+            frame_counter: FrameCounter::build_frame_counter(region),
+            mixer: Mixer::build_mixer(),
+        }
+    }
+
+    // Frame counter steps, noise periods and DMC rates depend on the console variant
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.set_periods(region.noise_periods());
+        self.dmc.set_rates(region.dmc_rates());
+        self.frame_counter.set_region(region);
+    }
+
+    pub fn region(&self) -> Region {
+        return self.region;
+    }
+
+    // Reset silences the channels like a write of 0 to $4015
+    pub fn reset(&mut self) {
+        self.write_status(0x00);
+    }
+
+    // Clocked once per CPU cycle
+    pub fn clock(&mut self) {
+        self.cycle += 1;
+        // The pulse timers run at half the CPU clock
+        if self.cycle & 0x01 == 0 {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_timer();
+            }
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        let step = self.frame_counter.clock();
+        if step != FrameStep::None {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_quarter_frame();
+            }
+            self.triangle.clock_quarter_frame();
+            self.noise.clock_quarter_frame();
+        }
+        if step == FrameStep::Half {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_half_frame();
+            }
+            self.triangle.clock_half_frame();
+            self.noise.clock_half_frame();
+        }
+    }
+
+    // $4015 read: IF-D NT21, DMC and frame interrupts, DMC active and channels with a running
+    // length counter. Reading acknowledges the frame interrupt, bit 5 is open bus.
+    fn read_status(&mut self, open_bus: Byte) -> Byte {
+        let mut status = open_bus & 0x20;
+        status |= Byte::from(self.pulses[0].length_active());
+        status |= Byte::from(self.pulses[1].length_active()) << 1;
+        status |= Byte::from(self.triangle.length_active()) << 2;
+        status |= Byte::from(self.noise.length_active()) << 3;
+        status |= Byte::from(self.dmc.active()) << 4;
+        status |= Byte::from(self.frame_counter.irq()) << 6;
+        status |= Byte::from(self.dmc.irq()) << 7;
+        self.frame_counter.acknowledge();
+        return status;
+    }
+
+    // $4015 write: ---D NT21, enables the channels
+    fn write_status(&mut self, data: Byte) {
+        self.pulses[0].set_enabled(data & 0x01 != 0);
+        self.pulses[1].set_enabled(data & 0x02 != 0);
+        self.triangle.set_enabled(data & 0x04 != 0);
+        self.noise.set_enabled(data & 0x08 != 0);
+        self.dmc.set_enabled(data & 0x10 != 0);
+    }
+
+    // Current level of a pulse channel, 0 to 15
+    pub fn pulse_output(&self, index: usize) -> Byte {
+        return self.pulses[index].output();
+    }
+
+    // 0 to 15
+    pub fn triangle_output(&self) -> Byte {
+        return self.triangle.output();
+    }
+
+    // 0 to 15
+    pub fn noise_output(&self) -> Byte {
+        return self.noise.output();
+    }
+
+    // 0 to 127
+    pub fn dmc_output(&self) -> Byte {
+        return self.dmc.output();
+    }
+
+    // Level of the 2A03 audio output, 0.0 to 1.0
+    pub fn output(&self) -> f32 {
+        return self.mixer.mix(
+            self.pulses[0].output(),
+            self.pulses[1].output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        );
+    }
+
+    // Level of one channel going alone through the mixer. The DACs are not linear, so the
+    // channels add up to a little more than the mix. The expansion audio is not the APU's.
+    pub fn channel_output(&self, channel: AudioChannel) -> f32 {
+        match channel {
+            AudioChannel::Pulse1 => self.mixer.mix(self.pulses[0].output(), 0, 0, 0, 0),
+            AudioChannel::Pulse2 => self.mixer.mix(0, self.pulses[1].output(), 0, 0, 0),
+            AudioChannel::Triangle => self.mixer.mix(0, 0, self.triangle.output(), 0, 0),
+            AudioChannel::Noise => self.mixer.mix(0, 0, 0, self.noise.output(), 0),
+            AudioChannel::Dmc => self.mixer.mix(0, 0, 0, 0, self.dmc.output()),
+            AudioChannel::Expansion => 0.0,
+        }
+    }
+
+    // Address the DMC needs a sample byte from, the console reads it and calls `dmc_fill_buffer`
+    pub fn dmc_dma_request(&self) -> Option<Word> {
+        return self.dmc.dma_request();
+    }
+
+    pub fn dmc_fill_buffer(&mut self, data: Byte) {
+        self.dmc.fill_buffer(data);
+    }
+
+    pub fn irq(&self) -> bool {
+        return self.frame_counter.irq() || self.dmc.irq();
+    }
+}
+
+impl MemoryMapped for Apu {
+    // Only $4015 is readable
+    fn read_byte(&mut self, address: Word, open_bus: Byte) -> Byte {
+        if address == 0x4015 {
+            return self.read_status(open_bus);
+        }
+        return open_bus;
+    }
+
+    fn write_byte(&mut self, address: Word, data: Byte) {
+        match address {
+            0x4000..=0x4007 => {
+                let pulse = &mut self.pulses[usize::from(address - 0x4000) / 4];
+                match address & 0x03 {
+                    0 => pulse.write_control(data),
+                    1 => pulse.write_sweep(data),
+                    2 => pulse.write_timer_low(data),
+                    _ => pulse.write_timer_high(data),
+                }
+            }
+            0x4008 => self.triangle.write_linear_counter(data),
+            0x400A => self.triangle.write_timer_low(data),
+            0x400B => self.triangle.write_timer_high(data),
+            0x400C => self.noise.write_control(data),
+            0x400E => self.noise.write_period(data),
+            0x400F => self.noise.write_length(data),
+            0x4010 => self.dmc.write_control(data),
+            0x4011 => self.dmc.write_output_level(data),
+            0x4012 => self.dmc.write_sample_address(data),
+            0x4013 => self.dmc.write_sample_length(data),
+            0x4015 => self.write_status(data),
+            0x4017 => self.frame_counter.write(data, self.cycle & 0x01 != 0),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::MemoryMapped;
+
+    use super::Apu;
+
+    #[test]
+    fn test_apu_pulse_registers() {
+        let mut apu = Apu::build_apu();
+        apu.write_byte(0x4015, 0x02);
+        // Pulse 2: 25% duty, constant volume 12, period 8 (18 CPU cycles per step)
+        apu.write_byte(0x4004, 0x7C);
+        apu.write_byte(0x4006, 0x08);
+        apu.write_byte(0x4007, 0x08);
+        let mut levels = Vec::new();
+        for _ in 0..8 {
+            for _ in 0..18 {
+                apu.clock();
+            }
+            levels.push(apu.pulse_output(1));
+        }
+        assert_eq!(levels, vec![12, 12, 0, 0, 0, 0, 0, 0]);
+        // Pulse 1 was not enabled, its length counter ignores the load
+        apu.write_byte(0x4000, 0x7C);
+        apu.write_byte(0x4002, 0x08);
+        apu.write_byte(0x4003, 0x08);
+        assert_eq!(apu.pulse_output(0), 0);
+        apu.write_byte(0x4015, 0x00);
+        assert_eq!(apu.pulse_output(1), 0);
+    }
+
+    #[test]
+    fn test_apu_dmc_registers() {
+        let mut apu = Apu::build_apu();
+        apu.write_byte(0x4011, 0x7F);
+        assert_eq!(apu.dmc_output(), 0x7F);
+        apu.write_byte(0x4010, 0x8F);
+        apu.write_byte(0x4012, 0x10);
+        apu.write_byte(0x4013, 0x00);
+        assert_eq!(apu.dmc_dma_request(), None);
+        apu.write_byte(0x4015, 0x10);
+        assert_eq!(apu.dmc_dma_request(), Some(0xC400));
+        apu.dmc_fill_buffer(0x00);
+        assert!(apu.irq());
+        // Writing $4015 acknowledges the DMC interrupt
+        apu.write_byte(0x4015, 0x00);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_apu_status() {
+        let mut apu = Apu::build_apu();
+        apu.write_byte(0x4015, 0x0F);
+        // Length index 1 loads 254, index 3 loads 2
+        apu.write_byte(0x4003, 0x08);
+        apu.write_byte(0x4007, 0x18);
+        apu.write_byte(0x400B, 0x08);
+        apu.write_byte(0x400F, 0x08);
+        assert_eq!(apu.read_byte(0x4015, 0xFF), 0x2F);
+        // Two half frames in 4 step mode count pulse 2 down, then the frame IRQ is raised
+        for _ in 0..29830 {
+            apu.clock();
+        }
+        assert!(apu.irq());
+        assert_eq!(apu.read_byte(0x4015, 0x00), 0x4D);
+        assert!(!apu.irq());
+        assert_eq!(apu.read_byte(0x4015, 0x00), 0x0D);
+    }
+
+    #[test]
+    fn test_apu_frame_counter_write() {
+        let mut apu = Apu::build_apu();
+        apu.write_byte(0x4015, 0x01);
+        apu.write_byte(0x4003, 0x18);
+        // Entering the 5 step mode clocks a half frame a few cycles after the write
+        apu.write_byte(0x4017, 0x80);
+        for _ in 0..4 {
+            apu.clock();
+        }
+        assert_eq!(apu.read_byte(0x4015, 0x00), 0x01);
+        for _ in 0..14913 {
+            apu.clock();
+        }
+        assert_eq!(apu.read_byte(0x4015, 0x00), 0x00);
+        // No frame IRQ in 5 step mode
+        for _ in 0..40000 {
+            apu.clock();
+        }
+        assert!(!apu.irq());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::cpu::{Byte, Word};
+
+use super::{envelope::Envelope, length_counter::LengthCounter};
+
+// Noise channel: https://www.nesdev.org/wiki/APU_Noise
+// A 15 bit linear feedback shift register clocked by a timer with 16 preset periods. The short
+// mode takes the feedback from bit 6 instead of bit 1, giving a 93 step metallic loop.
+pub struct Noise {
+    // Timer periods in CPU cycles, they differ between NTSC and PAL
+    periods: &'static [u16; 16],
+    period_index: usize,
+    short_mode: bool,
+    timer: Word,
+    shift_register: Word,
+    envelope: Envelope,
+    length_counter: LengthCounter,
+}
+
+impl Noise {
+    pub fn build_noise(periods: &'static [u16; 16]) -> Noise {
+        return Noise {
+            periods,
+            period_index: 0,
+            short_mode: false,
+            timer: 0,
+            shift_register: 1,
+            envelope: Envelope::build_envelope(),
+            length_counter: LengthCounter::build_length_counter(),
+        }
+    }
+
+    pub fn set_periods(&mut self, periods: &'static [u16; 16]) {
+        self.periods = periods;
+    }
+
+    // --LC VVVV: length counter halt / envelope loop, constant volume, volume / envelope period
+    pub fn write_control(&mut self, data: Byte) {
+        self.envelope.write_control(data);
+        self.length_counter.set_halt(data & 0x20 != 0);
+    }
+
+    // M--- PPPP: short mode, period index
+    pub fn write_period(&mut self, data: Byte) {
+        self.short_mode = data & 0x80 != 0;
+        self.period_index = usize::from(data & 0x0F);
+    }
+
+    // LLLL L---: length counter load, restarts the envelope
+    pub fn write_length(&mut self, data: Byte) {
+        self.length_counter.load(data >> 3);
+        self.envelope.restart();
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn length_active(&self) -> bool {
+        return self.length_counter.active();
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.periods[self.period_index] - 1;
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 0x01;
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    // Current volume level, 0 to 15
+    pub fn output(&self) -> Byte {
+        if !self.length_counter.active() || self.shift_register & 0x01 != 0 {
+            return 0;
+        }
+        return self.envelope.output();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nes::region::Region;
+
+    use super::Noise;
+
+    // Number of timer periods before the shift register comes back to its initial value
+    fn sequence_length(noise: &mut Noise) -> usize {
+        let start = noise.shift_register;
+        let mut length = 0;
+        loop {
+            for _ in 0..4 {
+                noise.clock_timer();
+            }
+            length += 1;
+            if noise.shift_register == start {
+                return length;
+            }
+        }
+    }
+
+    #[test]
+    fn test_noise_sequences() {
+        let mut noise = Noise::build_noise(Region::Ntsc.noise_periods());
+        assert_eq!(sequence_length(&mut noise), 32767);
+        noise.write_period(0x80);
+        assert_eq!(sequence_length(&mut noise), 93);
+    }
+
+    #[test]
+    fn test_noise_output() {
+        let mut noise = Noise::build_noise(Region::Ntsc.noise_periods());
+        noise.set_enabled(true);
+        noise.write_control(0x3A);
+        noise.write_length(0x08);
+        // The output follows bit 0 of the shift register, which starts set
+        assert_eq!(noise.output(), 0);
+        let mut levels = Vec::new();
+        for _ in 0..64 {
+            noise.clock_timer();
+            levels.push(noise.output());
+        }
+        assert!(levels.contains(&10));
+        assert!(levels.contains(&0));
+        noise.set_enabled(false);
+        assert_eq!(noise.output(), 0);
+    }
+}
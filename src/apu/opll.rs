@@ -0,0 +1,428 @@
+use std::f32::consts::PI;
+
+use crate::cpu::{Byte, Word};
+
+// The VRC7 sound core runs from a 3.58 MHz clock and completes a sample every 72 clocks,
+// which is every 36 CPU cycles on NTSC
+const CPU_CYCLES_PER_SAMPLE: u8 = 36;
+const SAMPLE_RATE: f32 = 3_579_545.0 / 72.0;
+const CHANNELS: usize = 6;
+// Attenuation below which an operator is inaudible
+const MAX_ATTENUATION: f32 = 48.0;
+// Level of one channel at full volume, on the scale of the 2A03 mixer output
+const CHANNEL_SCALE: f32 = 0.1;
+
+// Built-in instruments of the VRC7, dumped from the chip: https://www.nesdev.org/wiki/VRC7_audio
+const PATCHES: [[Byte; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+const MULTIPLIERS: [f32; 16] = [0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0];
+
+// Key scale attenuation in dB for the top 4 bits of the F-number, at 6 dB per octave and block 7
+const KSL_TABLE: [f32; 16] = [0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0];
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+// Settings of one operator, decoded from the 8 bytes of an instrument
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: f32,
+    key_scale_level: Byte,
+    rectified: bool,
+    attack: Byte,
+    decay: Byte,
+    sustain_level: Byte,
+    release: Byte,
+}
+
+impl OperatorPatch {
+    // Operator 0 is the modulator, 1 the carrier
+    fn decode(patch: &[Byte; 8], operator: usize) -> OperatorPatch {
+        return OperatorPatch {
+            tremolo: patch[operator] & 0x80 != 0,
+            vibrato: patch[operator] & 0x40 != 0,
+            sustained: patch[operator] & 0x20 != 0,
+            key_scale_rate: patch[operator] & 0x10 != 0,
+            multiplier: MULTIPLIERS[usize::from(patch[operator] & 0x0F)],
+            key_scale_level: patch[2 + operator] >> 6,
+            rectified: patch[3] & (0x08 << operator) != 0,
+            attack: patch[4 + operator] >> 4,
+            decay: patch[4 + operator] & 0x0F,
+            sustain_level: patch[6 + operator] >> 4,
+            release: patch[6 + operator] & 0x0F,
+        }
+    }
+}
+
+struct Operator {
+    // Position in the waveform, in cycles
+    phase: f32,
+    // Envelope attenuation in dB
+    envelope: f32,
+    state: EnvelopeState,
+    // Last two outputs, for the modulator feedback
+    outputs: [f32; 2],
+}
+
+impl Operator {
+    fn build_operator() -> Operator {
+        return Operator {
+            phase: 0.0,
+            envelope: MAX_ATTENUATION,
+            state: EnvelopeState::Off,
+            outputs: [0.0, 0.0],
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.state != EnvelopeState::Off {
+            self.state = EnvelopeState::Release;
+        }
+    }
+
+    fn clock_envelope(&mut self, patch: &OperatorPatch, key_scale: Byte, release_rate: Byte) {
+        match self.state {
+            EnvelopeState::Attack => {
+                let rate = Operator::effective_rate(patch.attack, patch, key_scale);
+                if rate >= 60 {
+                    self.envelope = 0.0;
+                } else if rate > 0 {
+                    // The attack curve is steep at first and slows down near full volume
+                    let step = MAX_ATTENUATION / (Operator::attack_time(rate) * SAMPLE_RATE);
+                    self.envelope -= step * (1.0 + self.envelope / 8.0);
+                }
+                if self.envelope <= 0.0 {
+                    self.envelope = 0.0;
+                    self.state = EnvelopeState::Decay;
+                }
+            }
+            EnvelopeState::Decay => {
+                let sustain_level = f32::from(patch.sustain_level) * 3.0;
+                self.envelope += Operator::decay_step(Operator::effective_rate(patch.decay, patch, key_scale));
+                if self.envelope >= sustain_level {
+                    self.envelope = sustain_level;
+                    self.state = EnvelopeState::Sustain;
+                }
+            }
+            EnvelopeState::Sustain => {
+                // Percussive tones keep decaying with the release rate
+                if !patch.sustained {
+                    self.envelope += Operator::decay_step(Operator::effective_rate(patch.release, patch, key_scale));
+                }
+            }
+            EnvelopeState::Release => {
+                self.envelope += Operator::decay_step(Operator::effective_rate(release_rate, patch, key_scale));
+            }
+            EnvelopeState::Off => {}
+        }
+        if self.envelope >= MAX_ATTENUATION {
+            self.envelope = MAX_ATTENUATION;
+            if self.state != EnvelopeState::Attack {
+                self.state = EnvelopeState::Off;
+            }
+        }
+    }
+
+    fn effective_rate(rate: Byte, patch: &OperatorPatch, key_scale: Byte) -> u8 {
+        if rate == 0 {
+            return 0;
+        }
+        let offset = if patch.key_scale_rate { key_scale } else { key_scale >> 2 };
+        return (rate * 4 + offset).min(63);
+    }
+
+    // Time to go from silence to full volume, halved every 4 rate steps
+    fn attack_time(rate: u8) -> f32 {
+        return 1.73 * f32::powf(2.0, -(f32::from(rate) - 4.0) / 4.0);
+    }
+
+    // Attenuation added per sample, the full 48 dB range takes 20.8 s at rate 4
+    fn decay_step(rate: u8) -> f32 {
+        if rate == 0 {
+            return 0.0;
+        }
+        let time = 20.8 * f32::powf(2.0, -(f32::from(rate) - 4.0) / 4.0);
+        return MAX_ATTENUATION / (time * SAMPLE_RATE);
+    }
+
+    fn output(&mut self, modulation: f32, attenuation: f32, rectified: bool) -> f32 {
+        let mut wave = f32::sin(2.0 * PI * (self.phase + modulation));
+        if rectified && wave < 0.0 {
+            wave = 0.0;
+        }
+        let total = self.envelope + attenuation;
+        let output = if total >= MAX_ATTENUATION { 0.0 } else { wave * f32::powf(10.0, -total / 20.0) };
+        self.outputs = [self.outputs[1], output];
+        return output;
+    }
+}
+
+struct Channel {
+    fnum: Word,
+    block: Byte,
+    key_on: bool,
+    sustain: bool,
+    instrument: Byte,
+    volume: Byte,
+    modulator: Operator,
+    carrier: Operator,
+}
+
+impl Channel {
+    fn build_channel() -> Channel {
+        return Channel {
+            fnum: 0,
+            block: 0,
+            key_on: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Operator::build_operator(),
+            carrier: Operator::build_operator(),
+        }
+    }
+
+    // Rate offset for the key scale rate: block and top F-number bit
+    fn key_scale(&self) -> Byte {
+        return (self.block << 1) | (self.fnum >> 8) as Byte;
+    }
+
+    fn key_scale_attenuation(&self, level: Byte) -> f32 {
+        if level == 0 {
+            return 0.0;
+        }
+        let attenuation = (KSL_TABLE[usize::from(self.fnum >> 5)] - 6.0 * f32::from(7 - self.block)).max(0.0);
+        return attenuation / f32::from(1u8 << (3 - level));
+    }
+}
+
+// Yamaha OPLL derived FM synthesizer of the VRC7: six 2-operator channels, 15 fixed instruments
+// and one user defined. The model works in floating point at the chip sample rate, it follows the
+// envelope, key scaling and LFO behaviour of the YM2413 without being bit exact.
+pub struct Opll {
+    custom: [Byte; 8],
+    address: Byte,
+    channels: [Channel; CHANNELS],
+    cycle: u8,
+    time: f32,
+    output: f32,
+}
+
+impl Opll {
+    pub fn build_opll() -> Opll {
+        return Opll {
+            custom: [0x00; 8],
+            address: 0,
+            channels: [
+                Channel::build_channel(), Channel::build_channel(), Channel::build_channel(),
+                Channel::build_channel(), Channel::build_channel(), Channel::build_channel(),
+            ],
+            cycle: 0,
+            time: 0.0,
+            output: 0.0,
+        }
+    }
+
+    // Silences every channel and clears the registers
+    pub fn reset(&mut self) {
+        *self = Opll::build_opll();
+    }
+
+    pub fn write_address(&mut self, data: Byte) {
+        self.address = data;
+    }
+
+    pub fn write_data(&mut self, data: Byte) {
+        let index = usize::from(self.address & 0x0F);
+        match self.address {
+            0x00..=0x07 => self.custom[index] = data,
+            0x10..=0x15 => self.channels[index].fnum = (self.channels[index].fnum & 0x100) | Word::from(data),
+            0x20..=0x25 => {
+                let channel = &mut self.channels[index];
+                channel.fnum = (channel.fnum & 0xFF) | (Word::from(data & 0x01) << 8);
+                channel.block = (data >> 1) & 0x07;
+                channel.sustain = data & 0x20 != 0;
+                let key_on = data & 0x10 != 0;
+                if key_on && !channel.key_on {
+                    channel.modulator.key_on();
+                    channel.carrier.key_on();
+                } else if !key_on && channel.key_on {
+                    channel.modulator.key_off();
+                    channel.carrier.key_off();
+                }
+                channel.key_on = key_on;
+            }
+            0x30..=0x35 => {
+                self.channels[index].instrument = data >> 4;
+                self.channels[index].volume = data & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        self.cycle += 1;
+        if self.cycle == CPU_CYCLES_PER_SAMPLE {
+            self.cycle = 0;
+            self.output = self.sample();
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        return self.output;
+    }
+
+    fn sample(&mut self) -> f32 {
+        self.time += 1.0 / SAMPLE_RATE;
+        // Tremolo: 3.7 Hz, 4.8 dB deep. Vibrato: 6.4 Hz, 14 cents deep.
+        let tremolo = (1.0 + f32::sin(2.0 * PI * 3.7 * self.time)) / 2.0 * 4.8;
+        let vibrato = f32::powf(2.0, f32::sin(2.0 * PI * 6.4 * self.time) * 14.0 / 1200.0);
+        let mut output = 0.0;
+        for index in 0..CHANNELS {
+            let patch = if self.channels[index].instrument == 0 {
+                self.custom
+            } else {
+                PATCHES[usize::from(self.channels[index].instrument - 1)]
+            };
+            output += Opll::channel_sample(&mut self.channels[index], &patch, tremolo, vibrato);
+        }
+        return output * CHANNEL_SCALE;
+    }
+
+    fn channel_sample(channel: &mut Channel, patch: &[Byte; 8], tremolo: f32, vibrato: f32) -> f32 {
+        let operators = [OperatorPatch::decode(patch, 0), OperatorPatch::decode(patch, 1)];
+        let key_scale = channel.key_scale();
+        // Released notes fade out at rate 5 with the sustain bit, 7 for percussive tones
+        let release = |operator: &OperatorPatch| {
+            if channel.sustain {
+                5
+            } else if operator.sustained {
+                operator.release
+            } else {
+                7
+            }
+        };
+        let releases = [release(&operators[0]), release(&operators[1])];
+        channel.modulator.clock_envelope(&operators[0], key_scale, releases[0]);
+        channel.carrier.clock_envelope(&operators[1], key_scale, releases[1]);
+
+        let base = f32::from(channel.fnum) * f32::from(1u8 << channel.block) / 524_288.0;
+        for (operator, settings) in [&mut channel.modulator, &mut channel.carrier].into_iter().zip(operators.iter()) {
+            let step = base * settings.multiplier * if settings.vibrato { vibrato } else { 1.0 };
+            operator.phase = (operator.phase + step).fract();
+        }
+
+        let mut modulator_attenuation = f32::from(patch[2] & 0x3F) * 0.75 + channel.key_scale_attenuation(operators[0].key_scale_level);
+        if operators[0].tremolo {
+            modulator_attenuation += tremolo;
+        }
+        let feedback_level = patch[3] & 0x07;
+        let feedback = if feedback_level == 0 {
+            0.0
+        } else {
+            (channel.modulator.outputs[0] + channel.modulator.outputs[1]) / 2.0 * f32::from(1u8 << feedback_level) / 64.0
+        };
+        let modulation = channel.modulator.output(feedback, modulator_attenuation, operators[0].rectified) * 2.0;
+
+        let mut carrier_attenuation = f32::from(channel.volume) * 3.0 + channel.key_scale_attenuation(operators[1].key_scale_level);
+        if operators[1].tremolo {
+            carrier_attenuation += tremolo;
+        }
+        return channel.carrier.output(modulation, carrier_attenuation, operators[1].rectified);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EnvelopeState, Opll};
+
+    fn write(opll: &mut Opll, register: u8, data: u8) {
+        opll.write_address(register);
+        opll.write_data(data);
+    }
+
+    fn run_samples(opll: &mut Opll, samples: usize) -> f32 {
+        let mut peak: f32 = 0.0;
+        for _ in 0..samples * 36 {
+            opll.clock();
+            peak = peak.max(opll.output().abs());
+        }
+        return peak;
+    }
+
+    #[test]
+    fn test_opll_key_on_and_off() {
+        let mut opll = Opll::build_opll();
+        assert_eq!(run_samples(&mut opll, 100), 0.0);
+        // Instrument 3 (piano) at full volume, A4 in block 4
+        write(&mut opll, 0x30, 0x30);
+        write(&mut opll, 0x10, 0x20);
+        write(&mut opll, 0x20, 0x19);
+        assert!(run_samples(&mut opll, 2000) > 0.01);
+        write(&mut opll, 0x20, 0x09);
+        run_samples(&mut opll, 200_000);
+        assert_eq!(opll.channels[0].carrier.state, EnvelopeState::Off);
+        assert_eq!(run_samples(&mut opll, 100), 0.0);
+    }
+
+    #[test]
+    fn test_opll_volume() {
+        let mut loud = Opll::build_opll();
+        let mut quiet = Opll::build_opll();
+        for (opll, volume) in [(&mut loud, 0x10), (&mut quiet, 0x1A)] {
+            write(opll, 0x30, volume);
+            write(opll, 0x10, 0x40);
+            write(opll, 0x20, 0x17);
+        }
+        // 10 steps of 3 dB
+        let ratio = run_samples(&mut quiet, 4000) / run_samples(&mut loud, 4000);
+        assert!((ratio - 0.0316).abs() < 0.01, "ratio {}", ratio);
+    }
+
+    #[test]
+    fn test_opll_custom_instrument() {
+        let mut opll = Opll::build_opll();
+        // Instrument 0 uses the custom patch, all zero it never leaves the attack phase
+        write(&mut opll, 0x30, 0x00);
+        write(&mut opll, 0x10, 0x40);
+        write(&mut opll, 0x20, 0x17);
+        assert_eq!(run_samples(&mut opll, 1000), 0.0);
+        // Instant attack, sustained carrier
+        write(&mut opll, 0x01, 0x21);
+        write(&mut opll, 0x05, 0xF0);
+        assert!(run_samples(&mut opll, 1000) > 0.05);
+        opll.reset();
+        assert_eq!(run_samples(&mut opll, 100), 0.0);
+    }
+}
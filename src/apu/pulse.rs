@@ -0,0 +1,167 @@
+use crate::cpu::{Byte, Word};
+
+use super::{envelope::Envelope, length_counter::LengthCounter, sweep::Sweep};
+
+// Waveforms of the 4 duty settings: 12.5%, 25%, 50% and 25% negated
+const DUTY_SEQUENCES: [[Byte; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// Square wave channel: https://www.nesdev.org/wiki/APU_Pulse
+// The same design is found in the MMC5 expansion audio, without the sweep unit and its muting.
+pub struct Pulse {
+    duty: Byte,
+    sequence_step: usize,
+    timer_period: Word,
+    timer: Word,
+    envelope: Envelope,
+    length_counter: LengthCounter,
+    sweep: Option<Sweep>,
+}
+
+impl Pulse {
+    pub fn build_pulse() -> Pulse {
+        return Pulse {
+            duty: 0,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::build_envelope(),
+            length_counter: LengthCounter::build_length_counter(),
+            sweep: None,
+        }
+    }
+
+    // Pulse channel of the 2A03, the first one negates its sweep with the ones' complement
+    pub fn build_apu_pulse(ones_complement: bool) -> Pulse {
+        let mut pulse = Pulse::build_pulse();
+        pulse.sweep = Some(Sweep::build_sweep(ones_complement));
+        return pulse;
+    }
+
+    // DDLC VVVV: duty, length counter halt / envelope loop, constant volume, volume / envelope period
+    pub fn write_control(&mut self, data: Byte) {
+        self.duty = data >> 6;
+        self.envelope.write_control(data);
+        self.length_counter.set_halt(data & 0x20 != 0);
+    }
+
+    pub fn write_sweep(&mut self, data: Byte) {
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.write(data);
+        }
+    }
+
+    pub fn write_timer_low(&mut self, data: Byte) {
+        self.timer_period = (self.timer_period & 0x0700) | Word::from(data);
+    }
+
+    // LLLL LTTT: length counter load and timer high bits, restarts the waveform and the envelope
+    pub fn write_timer_high(&mut self, data: Byte) {
+        self.timer_period = (self.timer_period & 0x00FF) | (Word::from(data & 0x07) << 8);
+        self.length_counter.load(data >> 3);
+        self.sequence_step = 0;
+        self.envelope.restart();
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn length_active(&self) -> bool {
+        return self.length_counter.active();
+    }
+
+    // Clocked every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.clock(&mut self.timer_period);
+        }
+    }
+
+    fn muted(&self) -> bool {
+        return self.sweep.as_ref().is_some_and(|sweep| sweep.mutes(self.timer_period));
+    }
+
+    // Current volume level, 0 to 15
+    pub fn output(&self) -> Byte {
+        if !self.length_counter.active() || self.muted() || DUTY_SEQUENCES[usize::from(self.duty)][self.sequence_step] == 0 {
+            return 0;
+        }
+        return self.envelope.output();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pulse;
+
+    #[test]
+    fn test_pulse_waveform() {
+        let mut pulse = Pulse::build_pulse();
+        pulse.set_enabled(true);
+        // 50% duty, constant volume 9, period 1
+        pulse.write_control(0xB9);
+        pulse.write_timer_low(0x01);
+        pulse.write_timer_high(0x08);
+        let mut levels = Vec::new();
+        for _ in 0..8 {
+            pulse.clock_timer();
+            levels.push(pulse.output());
+            pulse.clock_timer();
+        }
+        assert_eq!(levels, vec![9, 9, 9, 9, 0, 0, 0, 0]);
+        pulse.set_enabled(false);
+        assert!(!pulse.length_active());
+        assert_eq!(pulse.output(), 0);
+    }
+
+    #[test]
+    fn test_pulse_sweep() {
+        let mut pulse = Pulse::build_apu_pulse(true);
+        pulse.set_enabled(true);
+        // 75% duty so the first step is high, constant volume 5
+        pulse.write_control(0xF5);
+        pulse.write_timer_low(0x07);
+        pulse.write_timer_high(0x08);
+        // Periods below 8 are muted
+        assert_eq!(pulse.output(), 0);
+        pulse.write_timer_low(0x08);
+        assert_eq!(pulse.output(), 5);
+        // Sweep up by half the period each half frame until the target leaves the 11 bit range
+        pulse.write_sweep(0x81);
+        pulse.write_timer_low(0xFF);
+        pulse.write_timer_high(0x0B);
+        assert_eq!(pulse.output(), 5);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.timer_period, 0x5FE);
+        assert_eq!(pulse.output(), 0);
+        // The MMC5 variant has no sweep and never mutes
+        let mut pulse = Pulse::build_pulse();
+        pulse.set_enabled(true);
+        pulse.write_control(0xF5);
+        pulse.write_timer_low(0x02);
+        pulse.write_timer_high(0x0F);
+        pulse.write_sweep(0x81);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.timer_period, 0x702);
+        assert_eq!(pulse.output(), 5);
+    }
+}
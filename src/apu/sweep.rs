@@ -0,0 +1,113 @@
+use crate::cpu::{Byte, Word};
+
+// Highest period the 11 bit timer can take, a larger sweep target mutes the channel
+const MAX_PERIOD: Word = 0x07FF;
+
+// Pitch bend unit of the 2A03 pulse channels: https://www.nesdev.org/wiki/APU_Sweep
+// Periodically adds or subtracts a shifted copy of the timer period to itself. The target is
+// computed continuously and mutes the channel when out of range, even when the sweep is off.
+// Pulse 1 negates with the ones' complement, subtracting one more than pulse 2.
+pub struct Sweep {
+    ones_complement: bool,
+    enabled: bool,
+    period: Byte,
+    negate: bool,
+    shift: Byte,
+    divider: Byte,
+    reload: bool,
+}
+
+impl Sweep {
+    pub fn build_sweep(ones_complement: bool) -> Sweep {
+        return Sweep {
+            ones_complement,
+            enabled: false,
+            period: 0,
+            negate: false,
+            shift: 0,
+            divider: 0,
+            reload: false,
+        }
+    }
+
+    // EPPP NSSS: enable, divider period, negate, shift count
+    pub fn write(&mut self, data: Byte) {
+        self.enabled = data & 0x80 != 0;
+        self.period = (data >> 4) & 0x07;
+        self.negate = data & 0x08 != 0;
+        self.shift = data & 0x07;
+        self.reload = true;
+    }
+
+    pub fn target_period(&self, timer_period: Word) -> Word {
+        let change = timer_period >> self.shift;
+        if !self.negate {
+            return timer_period + change;
+        }
+        let change = if self.ones_complement { change + 1 } else { change };
+        return timer_period.saturating_sub(change);
+    }
+
+    pub fn mutes(&self, timer_period: Word) -> bool {
+        return timer_period < 8 || self.target_period(timer_period) > MAX_PERIOD;
+    }
+
+    // Clocked on half frames, may update the timer period of the channel
+    pub fn clock(&mut self, timer_period: &mut Word) {
+        if self.divider == 0 && self.enabled && self.shift != 0 && !self.mutes(*timer_period) {
+            *timer_period = self.target_period(*timer_period);
+        }
+        if self.divider == 0 || self.reload {
+            self.divider = self.period;
+            self.reload = false;
+        } else {
+            self.divider -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sweep;
+
+    #[test]
+    fn test_sweep_negate() {
+        let mut pulse1 = Sweep::build_sweep(true);
+        let mut pulse2 = Sweep::build_sweep(false);
+        pulse1.write(0x89);
+        pulse2.write(0x89);
+        // Shift 1: 0x100 - 0x80 (- 1 on pulse 1)
+        assert_eq!(pulse1.target_period(0x100), 0x07F);
+        assert_eq!(pulse2.target_period(0x100), 0x080);
+    }
+
+    #[test]
+    fn test_sweep_updates_period() {
+        let mut sweep = Sweep::build_sweep(false);
+        // Enabled, divider period 1 (2 half frames), shift 2
+        sweep.write(0x92);
+        let mut period = 0x100;
+        // The divider starts at 0 so the first clock already updates the period
+        sweep.clock(&mut period);
+        assert_eq!(period, 0x140);
+        sweep.clock(&mut period);
+        assert_eq!(period, 0x140);
+        sweep.clock(&mut period);
+        assert_eq!(period, 0x190);
+    }
+
+    #[test]
+    fn test_sweep_muting() {
+        let mut sweep = Sweep::build_sweep(false);
+        // Disabled with shift 0 still mutes when doubling the period overflows
+        assert!(!sweep.mutes(0x3FF));
+        assert!(sweep.mutes(0x400));
+        assert!(sweep.mutes(0x007));
+        // A muted channel does not sweep
+        sweep.write(0x81);
+        let mut period = 0x600;
+        sweep.clock(&mut period);
+        sweep.clock(&mut period);
+        assert_eq!(period, 0x600);
+    }
+}
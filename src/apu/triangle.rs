@@ -0,0 +1,137 @@
+use crate::cpu::{Byte, Word};
+
+use super::length_counter::LengthCounter;
+
+// Triangle channel: https://www.nesdev.org/wiki/APU_Triangle
+// A 32 step sequence, 15 down to 0 then back up to 15, advanced by a timer clocked every CPU
+// cycle. Besides the length counter a linear counter with a finer resolution gates the sequencer,
+// a silenced triangle holds its last level instead of dropping to 0.
+pub struct Triangle {
+    timer_period: Word,
+    timer: Word,
+    sequence_step: Byte,
+    // Control flag, doubles as the length counter halt flag
+    control: bool,
+    linear_reload_value: Byte,
+    linear_counter: Byte,
+    linear_reload: bool,
+    length_counter: LengthCounter,
+}
+
+impl Triangle {
+    pub fn build_triangle() -> Triangle {
+        return Triangle {
+            timer_period: 0,
+            timer: 0,
+            sequence_step: 0,
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            length_counter: LengthCounter::build_length_counter(),
+        }
+    }
+
+    // CRRR RRRR: control / length counter halt, linear counter reload value
+    pub fn write_linear_counter(&mut self, data: Byte) {
+        self.control = data & 0x80 != 0;
+        self.linear_reload_value = data & 0x7F;
+        self.length_counter.set_halt(self.control);
+    }
+
+    pub fn write_timer_low(&mut self, data: Byte) {
+        self.timer_period = (self.timer_period & 0x0700) | Word::from(data);
+    }
+
+    // LLLL LTTT: length counter load and timer high bits, sets the linear counter reload flag
+    pub fn write_timer_high(&mut self, data: Byte) {
+        self.timer_period = (self.timer_period & 0x00FF) | (Word::from(data & 0x07) << 8);
+        self.length_counter.load(data >> 3);
+        self.linear_reload = true;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn length_active(&self) -> bool {
+        return self.length_counter.active();
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period;
+        if self.linear_counter > 0 && self.length_counter.active() {
+            self.sequence_step = (self.sequence_step + 1) % 32;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    // Current level, 0 to 15
+    pub fn output(&self) -> Byte {
+        if self.sequence_step < 16 {
+            return 15 - self.sequence_step;
+        }
+        return self.sequence_step - 16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Triangle;
+
+    #[test]
+    fn test_triangle_sequence() {
+        let mut triangle = Triangle::build_triangle();
+        triangle.set_enabled(true);
+        triangle.write_linear_counter(0x10);
+        triangle.write_timer_low(0x00);
+        triangle.write_timer_high(0x08);
+        // Gated until the linear counter is loaded on a quarter frame
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 15);
+        triangle.clock_quarter_frame();
+        let mut levels = Vec::new();
+        for _ in 0..32 {
+            triangle.clock_timer();
+            levels.push(triangle.output());
+        }
+        let expected: Vec<u8> = (0..15).rev().chain(0..16).chain([15]).collect();
+        assert_eq!(levels, expected);
+    }
+
+    #[test]
+    fn test_triangle_linear_counter() {
+        let mut triangle = Triangle::build_triangle();
+        triangle.set_enabled(true);
+        triangle.write_linear_counter(0x02);
+        triangle.write_timer_high(0x08);
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 14);
+        // Counted down to 0: the level is held
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 14);
+    }
+}
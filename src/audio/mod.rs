@@ -0,0 +1,67 @@
+use std::fmt::{self, Display};
+
+pub mod null_sink;
+pub mod ring_buffer;
+pub mod rodio_sink;
+pub mod wav;
+pub mod wav_export;
+
+// Largest change of the resampling ratio used to steer the buffer fill, 0.5%.
+// Small enough to go unnoticed in pitch: https://docs.libretro.com/guides/dynamic-rate-control/
+const MAX_RATE_DELTA: f64 = 0.005;
+
+// Destination of the samples the emulator produces every frame
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+    fn push_samples(&mut self, samples: &[f32]);
+    // Samples queued and not played yet
+    fn buffered(&self) -> usize;
+    // Fill level the sink tries to hold, the latency it adds
+    fn target_buffered(&self) -> usize;
+    // Factor to apply to the sample rate of the emulator for the next frame. The console and the
+    // sound card clocks never match exactly, so the output rate is nudged up when the buffer runs
+    // low and down when it fills up, keeping the audio in step with the video.
+    fn rate_adjustment(&self) -> f64 {
+        return rate_adjustment(self.buffered(), self.target_buffered());
+    }
+}
+
+pub fn rate_adjustment(buffered: usize, target: usize) -> f64 {
+    if target == 0 {
+        return 1.0;
+    }
+    let error = (target as f64 - buffered as f64) / target as f64;
+    return 1.0 + MAX_RATE_DELTA * error.clamp(-1.0, 1.0);
+}
+
+#[derive(Debug)]
+pub enum AudioError {
+    NoDevice,
+    Stream(String),
+}
+
+impl Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioError::NoDevice => write!(f, "no audio output device"),
+            AudioError::Stream(message) => write!(f, "could not open the audio stream: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+#[cfg(test)]
+mod tests {
+    use super::rate_adjustment;
+
+    #[test]
+    fn test_rate_adjustment() {
+        assert_eq!(rate_adjustment(1000, 1000), 1.0);
+        // An empty buffer asks for more samples, a full one for less
+        assert!((rate_adjustment(0, 1000) - 1.005).abs() < 1e-9);
+        assert!((rate_adjustment(2000, 1000) - 0.995).abs() < 1e-9);
+        assert!((rate_adjustment(5000, 1000) - 0.995).abs() < 1e-9);
+        assert!(rate_adjustment(750, 1000) > 1.0);
+    }
+}
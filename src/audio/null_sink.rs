@@ -0,0 +1,58 @@
+use super::AudioSink;
+
+// Sink discarding the samples, for headless runs and tests. It behaves like a device that plays
+// everything right away at the exact rate, so it never asks for a rate adjustment.
+pub struct NullSink {
+    sample_rate: u32,
+    samples_written: u64,
+}
+
+impl NullSink {
+    pub fn build_null_sink(sample_rate: u32) -> NullSink {
+        return NullSink { sample_rate, samples_written: 0 };
+    }
+
+    pub fn samples_written(&self) -> u64 {
+        return self.samples_written;
+    }
+}
+
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    fn push_samples(&mut self, samples: &[f32]) {
+        self.samples_written += samples.len() as u64;
+    }
+
+    fn buffered(&self) -> usize {
+        return 0;
+    }
+
+    fn target_buffered(&self) -> usize {
+        return 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{audio::AudioSink, cartridge::Cartridge, nes::{Nes, tests::build_test_rom}};
+
+    use super::NullSink;
+
+    #[test]
+    fn test_null_sink_with_nes() {
+        let cartridge = Cartridge::from_ines(&build_test_rom(&[0x4C, 0x00, 0x80], &[0x40])).unwrap();
+        let mut nes = Nes::build_nes(cartridge);
+        let mut sink = NullSink::build_null_sink(44_100);
+        nes.set_sample_rate(sink.sample_rate());
+        for _ in 0..61 {
+            nes.run_frame();
+            sink.push_samples(nes.audio_samples());
+            nes.set_audio_rate_adjustment(sink.rate_adjustment());
+        }
+        // About a second of audio, the first frame is shorter
+        assert!((sink.samples_written() as i64 - 44_100).abs() < 800);
+    }
+}
@@ -0,0 +1,149 @@
+use std::sync::{Arc, atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering}};
+
+// Single producer, single consumer queue of samples shared between the emulation thread and the
+// audio callback. Neither side ever blocks: the producer drops what does not fit (overrun) and
+// the consumer gets nothing when it runs dry (underrun), both are counted for diagnostics.
+// Samples are stored as their f32 bit patterns so the slots can be atomics.
+struct Shared {
+    slots: Box<[AtomicU32]>,
+    // Total samples ever read and written, the slot index is the count modulo the capacity
+    read: AtomicUsize,
+    written: AtomicUsize,
+    underruns: AtomicU64,
+    overruns: AtomicU64,
+}
+
+impl Shared {
+    fn len(&self) -> usize {
+        return self.written.load(Ordering::Acquire).wrapping_sub(self.read.load(Ordering::Acquire));
+    }
+}
+
+pub struct Producer {
+    shared: Arc<Shared>,
+}
+
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+
+pub fn build_ring_buffer(capacity: usize) -> (Producer, Consumer) {
+    let slots: Vec<AtomicU32> = (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect();
+    let shared = Arc::new(Shared {
+        slots: slots.into_boxed_slice(),
+        read: AtomicUsize::new(0),
+        written: AtomicUsize::new(0),
+        underruns: AtomicU64::new(0),
+        overruns: AtomicU64::new(0),
+    });
+    return (Producer { shared: shared.clone() }, Consumer { shared });
+}
+
+impl Producer {
+    // Queues as many samples as fit and returns how many were taken
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        let capacity = self.shared.slots.len();
+        let written = self.shared.written.load(Ordering::Relaxed);
+        let free = capacity - self.shared.len();
+        let count = samples.len().min(free);
+        for (i, sample) in samples[..count].iter().enumerate() {
+            self.shared.slots[written.wrapping_add(i) % capacity].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.shared.written.store(written.wrapping_add(count), Ordering::Release);
+        if count < samples.len() {
+            self.shared.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        return count;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.shared.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    pub fn capacity(&self) -> usize {
+        return self.shared.slots.len();
+    }
+
+    pub fn underruns(&self) -> u64 {
+        return self.shared.underruns.load(Ordering::Relaxed);
+    }
+
+    pub fn overruns(&self) -> u64 {
+        return self.shared.overruns.load(Ordering::Relaxed);
+    }
+}
+
+impl Consumer {
+    pub fn pop(&mut self) -> Option<f32> {
+        let read = self.shared.read.load(Ordering::Relaxed);
+        if self.shared.written.load(Ordering::Acquire) == read {
+            self.shared.underruns.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let bits = self.shared.slots[read % self.shared.slots.len()].load(Ordering::Relaxed);
+        self.shared.read.store(read.wrapping_add(1), Ordering::Release);
+        return Some(f32::from_bits(bits));
+    }
+
+    pub fn len(&self) -> usize {
+        return self.shared.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_ring_buffer;
+
+    #[test]
+    fn test_ring_buffer_wraps() {
+        let (mut producer, mut consumer) = build_ring_buffer(4);
+        assert_eq!(producer.push(&[1.0, 2.0, 3.0]), 3);
+        assert_eq!(consumer.pop(), Some(1.0));
+        assert_eq!(consumer.pop(), Some(2.0));
+        assert_eq!(producer.push(&[4.0, 5.0, 6.0]), 3);
+        assert_eq!(consumer.len(), 4);
+        let samples: Vec<Option<f32>> = (0..4).map(|_| consumer.pop()).collect();
+        assert_eq!(samples, vec![Some(3.0), Some(4.0), Some(5.0), Some(6.0)]);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn test_ring_buffer_underrun_and_overrun() {
+        let (mut producer, mut consumer) = build_ring_buffer(2);
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(producer.underruns(), 1);
+        // The samples that do not fit are dropped
+        assert_eq!(producer.push(&[1.0, 2.0, 3.0]), 2);
+        assert_eq!(producer.overruns(), 1);
+        assert_eq!(consumer.pop(), Some(1.0));
+        assert_eq!(consumer.pop(), Some(2.0));
+    }
+
+    #[test]
+    fn test_ring_buffer_across_threads() {
+        let (mut producer, mut consumer) = build_ring_buffer(64);
+        let reader = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            while received.len() < 1000 {
+                if let Some(sample) = consumer.pop() {
+                    received.push(sample);
+                }
+            }
+            return received;
+        });
+        let samples: Vec<f32> = (0..1000).map(|n| n as f32).collect();
+        let mut sent = 0;
+        while sent < samples.len() {
+            sent += producer.push(&samples[sent..]);
+        }
+        assert_eq!(reader.join().unwrap(), samples);
+    }
+}
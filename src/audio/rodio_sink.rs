@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use rodio::{OutputStream, Source, StreamError};
+
+use super::{AudioError, AudioSink, ring_buffer::{Consumer, Producer, build_ring_buffer}};
+
+// rodio pulls from this source on its own thread. It never ends: after an underrun it holds the
+// last sample, avoiding a click, and waits for the buffer to refill to the target level before
+// playing again so a late frame does not turn into a string of tiny gaps.
+pub struct RingSource {
+    consumer: Consumer,
+    sample_rate: u32,
+    prefill: usize,
+    waiting: bool,
+    last_sample: f32,
+}
+
+impl RingSource {
+    pub fn build_ring_source(consumer: Consumer, sample_rate: u32, prefill: usize) -> RingSource {
+        return RingSource { consumer, sample_rate, prefill, waiting: true, last_sample: 0.0 };
+    }
+}
+
+impl Iterator for RingSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.waiting && self.consumer.len() < self.prefill {
+            return Some(self.last_sample);
+        }
+        self.waiting = false;
+        match self.consumer.pop() {
+            Some(sample) => self.last_sample = sample,
+            None => self.waiting = true,
+        }
+        return Some(self.last_sample);
+    }
+}
+
+impl Source for RingSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        return None;
+    }
+
+    fn channels(&self) -> u16 {
+        return 1;
+    }
+
+    fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        return None;
+    }
+}
+
+// Streams to the default output device through rodio. `latency` is the amount of audio kept
+// queued, the ring buffer holds twice as much to absorb uneven frame times.
+pub struct RodioSink {
+    // Dropping the stream stops the playback
+    _stream: OutputStream,
+    producer: Producer,
+    sample_rate: u32,
+    target: usize,
+}
+
+impl RodioSink {
+    pub fn build_rodio_sink(sample_rate: u32, latency: Duration) -> Result<RodioSink, AudioError> {
+        let (stream, handle) = OutputStream::try_default().map_err(|error| match error {
+            StreamError::NoDevice => AudioError::NoDevice,
+            error => AudioError::Stream(error.to_string()),
+        })?;
+        let target = ((f64::from(sample_rate) * latency.as_secs_f64()) as usize).max(1);
+        let (producer, consumer) = build_ring_buffer(target * 2);
+        handle
+            .play_raw(RingSource::build_ring_source(consumer, sample_rate, target))
+            .map_err(|error| AudioError::Stream(error.to_string()))?;
+        return Ok(RodioSink { _stream: stream, producer, sample_rate, target });
+    }
+
+    pub fn underruns(&self) -> u64 {
+        return self.producer.underruns();
+    }
+
+    pub fn overruns(&self) -> u64 {
+        return self.producer.overruns();
+    }
+}
+
+impl AudioSink for RodioSink {
+    fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    fn push_samples(&mut self, samples: &[f32]) {
+        self.producer.push(samples);
+    }
+
+    fn buffered(&self) -> usize {
+        return self.producer.len();
+    }
+
+    fn target_buffered(&self) -> usize {
+        return self.target;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::Source;
+
+    use crate::audio::ring_buffer::build_ring_buffer;
+
+    use super::RingSource;
+
+    #[test]
+    fn test_ring_source_prefill_and_underrun() {
+        let (mut producer, consumer) = build_ring_buffer(16);
+        let mut source = RingSource::build_ring_source(consumer, 48_000, 3);
+        assert_eq!(source.channels(), 1);
+        assert_eq!(source.sample_rate(), 48_000);
+        // Silence until the buffer reaches the prefill level
+        producer.push(&[0.1, 0.2]);
+        assert_eq!(source.next(), Some(0.0));
+        producer.push(&[0.3]);
+        let played: Vec<Option<f32>> = (0..3).map(|_| source.next()).collect();
+        assert_eq!(played, vec![Some(0.1), Some(0.2), Some(0.3)]);
+        // Running dry holds the last sample and waits for a full prefill again
+        assert_eq!(source.next(), Some(0.3));
+        producer.push(&[0.4]);
+        assert_eq!(source.next(), Some(0.3));
+        producer.push(&[0.5, 0.6]);
+        assert_eq!(source.next(), Some(0.4));
+        assert_eq!(producer.underruns(), 1);
+    }
+}
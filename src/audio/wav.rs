@@ -0,0 +1,125 @@
+use std::{fs::File, io::{self, BufWriter, Seek, SeekFrom, Write}, path::Path};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::apu::audio_output::to_i16;
+
+const HEADER_SIZE: u32 = 44;
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IEEE_FLOAT: u16 = 3;
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum SampleFormat {
+    Pcm16,
+    Float32,
+}
+
+impl SampleFormat {
+    fn bytes_per_sample(&self) -> u16 {
+        match self {
+            SampleFormat::Pcm16 => 2,
+            SampleFormat::Float32 => 4,
+        }
+    }
+}
+
+// RIFF WAVE writer: http://soundfile.sapp.org/doc/WaveFormat/
+// The chunk sizes are unknown until the end, `finish` seeks back to fill them in.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    format: SampleFormat,
+    data_size: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16, format: SampleFormat) -> io::Result<WavWriter<BufWriter<File>>> {
+        return WavWriter::build_wav_writer(BufWriter::new(File::create(path)?), sample_rate, channels, format);
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn build_wav_writer(writer: W, sample_rate: u32, channels: u16, format: SampleFormat) -> io::Result<WavWriter<W>> {
+        let mut wav = WavWriter { writer, format, data_size: 0 };
+        wav.write_header(sample_rate, channels)?;
+        return Ok(wav);
+    }
+
+    fn write_header(&mut self, sample_rate: u32, channels: u16) -> io::Result<()> {
+        let block_align = channels * self.format.bytes_per_sample();
+        self.writer.write_all(b"RIFF")?;
+        self.writer.write_u32::<LittleEndian>(HEADER_SIZE - 8)?;
+        self.writer.write_all(b"WAVE")?;
+        self.writer.write_all(b"fmt ")?;
+        self.writer.write_u32::<LittleEndian>(16)?;
+        self.writer.write_u16::<LittleEndian>(match self.format {
+            SampleFormat::Pcm16 => FORMAT_PCM,
+            SampleFormat::Float32 => FORMAT_IEEE_FLOAT,
+        })?;
+        self.writer.write_u16::<LittleEndian>(channels)?;
+        self.writer.write_u32::<LittleEndian>(sample_rate)?;
+        self.writer.write_u32::<LittleEndian>(sample_rate * u32::from(block_align))?;
+        self.writer.write_u16::<LittleEndian>(block_align)?;
+        self.writer.write_u16::<LittleEndian>(self.format.bytes_per_sample() * 8)?;
+        self.writer.write_all(b"data")?;
+        self.writer.write_u32::<LittleEndian>(0)?;
+        return Ok(());
+    }
+
+    // Interleaved when there is more than one channel, 16 bit samples are clamped to -1.0 to 1.0
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            match self.format {
+                SampleFormat::Pcm16 => self.writer.write_i16::<LittleEndian>(to_i16(*sample))?,
+                SampleFormat::Float32 => self.writer.write_f32::<LittleEndian>(*sample)?,
+            }
+        }
+        self.data_size += samples.len() as u32 * u32::from(self.format.bytes_per_sample());
+        return Ok(());
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_u32::<LittleEndian>(HEADER_SIZE - 8 + self.data_size)?;
+        self.writer.seek(SeekFrom::Start(u64::from(HEADER_SIZE) - 4))?;
+        self.writer.write_u32::<LittleEndian>(self.data_size)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        return Ok(self.writer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{SampleFormat, WavWriter};
+
+    #[test]
+    fn test_wav_pcm16() {
+        let mut wav = WavWriter::build_wav_writer(Cursor::new(Vec::new()), 44_100, 1, SampleFormat::Pcm16).unwrap();
+        wav.write_samples(&[0.0, 1.0, -2.0]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+        assert_eq!(data.len(), 50);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(&data[4..8], &42u32.to_le_bytes());
+        assert_eq!(&data[20..22], &1u16.to_le_bytes());
+        assert_eq!(&data[24..28], &44_100u32.to_le_bytes());
+        assert_eq!(&data[28..32], &88_200u32.to_le_bytes());
+        assert_eq!(&data[34..36], &16u16.to_le_bytes());
+        assert_eq!(&data[40..44], &6u32.to_le_bytes());
+        assert_eq!(&data[44..], &[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn test_wav_float32() {
+        let mut wav = WavWriter::build_wav_writer(Cursor::new(Vec::new()), 48_000, 2, SampleFormat::Float32).unwrap();
+        wav.write_samples(&[0.5, -0.25]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+        assert_eq!(&data[20..22], &3u16.to_le_bytes());
+        assert_eq!(&data[22..24], &2u16.to_le_bytes());
+        assert_eq!(&data[32..34], &8u16.to_le_bytes());
+        assert_eq!(&data[40..44], &8u32.to_le_bytes());
+        assert_eq!(&data[44..48], &0.5f32.to_le_bytes());
+        assert_eq!(&data[48..52], &(-0.25f32).to_le_bytes());
+    }
+}
@@ -0,0 +1,142 @@
+use std::{fs::File, io::{self, BufWriter}, path::{Path, PathBuf}};
+
+use rodio::{Sample, Source};
+
+use crate::{apu::AudioChannel, nes::Nes};
+
+use super::wav::{SampleFormat, WavWriter};
+
+// Captures the audio of a running console to WAV files, frame by frame. With stems every channel
+// also goes to its own file next to the mix, "<name>-pulse1.wav" and so on, so the channels can
+// be compared separately. Stems need `Nes::set_stems_enabled`, `build_wav_export` turns it on.
+pub struct WavExport {
+    mix: WavWriter<BufWriter<File>>,
+    stems: Vec<(AudioChannel, WavWriter<BufWriter<File>>)>,
+}
+
+impl WavExport {
+    pub fn build_wav_export(nes: &mut Nes, path: &Path, format: SampleFormat, stems: bool) -> io::Result<WavExport> {
+        let sample_rate = nes.sample_rate();
+        let mix = WavWriter::create(path, sample_rate, 1, format)?;
+        let mut stem_writers = Vec::new();
+        if stems {
+            nes.set_stems_enabled(true);
+            for channel in AudioChannel::ALL {
+                stem_writers.push((channel, WavWriter::create(&stem_path(path, channel), sample_rate, 1, format)?));
+            }
+        }
+        return Ok(WavExport { mix, stems: stem_writers });
+    }
+
+    // Call after every `run_frame`
+    pub fn write_frame(&mut self, nes: &Nes) -> io::Result<()> {
+        self.mix.write_samples(nes.audio_samples())?;
+        for (channel, writer) in self.stems.iter_mut() {
+            writer.write_samples(nes.stem_samples(*channel))?;
+        }
+        return Ok(());
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.mix.finish()?;
+        for (_, writer) in self.stems {
+            writer.finish()?;
+        }
+        return Ok(());
+    }
+}
+
+// Renders a finite rodio source, such as the buffers of the `sound_waves` generators, to a WAV
+// file with the rate and channel count of the source
+pub fn export_source<S>(source: S, path: &Path, format: SampleFormat) -> io::Result<()>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    let mut writer = WavWriter::create(path, source.sample_rate(), source.channels(), format)?;
+    let samples: Vec<f32> = source.convert_samples().collect();
+    writer.write_samples(&samples)?;
+    writer.finish()?;
+    return Ok(());
+}
+
+// "out/song.wav" gives "out/song-triangle.wav"
+pub fn stem_path(path: &Path, channel: AudioChannel) -> PathBuf {
+    let stem = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    return path.with_file_name(format!("{}-{}.wav", stem, channel.name()));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{apu::AudioChannel, audio::wav::SampleFormat, cartridge::Cartridge, cpu::sound_waves, nes::{Nes, tests::build_test_rom}};
+
+    use super::{WavExport, export_source, stem_path};
+
+    #[test]
+    fn test_stem_path() {
+        assert_eq!(stem_path(Path::new("out/song.wav"), AudioChannel::Triangle), Path::new("out/song-triangle.wav"));
+    }
+
+    #[test]
+    fn test_wav_export_stems() {
+        let program = [
+            0xA9, 0x40,       // LDA #$40
+            0x8D, 0x17, 0x40, // STA $4017
+            0xA9, 0x04,       // LDA #$04
+            0x8D, 0x15, 0x40, // STA $4015
+            0xA9, 0xFF,       // LDA #$FF
+            0x8D, 0x08, 0x40, // STA $4008
+            0xA9, 0x7E,       // LDA #$7E
+            0x8D, 0x0A, 0x40, // STA $400A
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x0B, 0x40, // STA $400B
+            0x4C, 0x19, 0x80, // JMP $8019
+        ];
+        let cartridge = Cartridge::from_ines(&build_test_rom(&program, &[0x40])).unwrap();
+        let mut nes = Nes::build_nes(cartridge);
+        let directory = std::env::temp_dir().join(format!("wav_export_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("triangle_test.wav");
+        let mut export = WavExport::build_wav_export(&mut nes, &path, SampleFormat::Float32, true).unwrap();
+        for _ in 0..10 {
+            nes.run_frame();
+            export.write_frame(&nes).unwrap();
+        }
+        export.finish().unwrap();
+
+        let read_samples = |path: &Path| -> Vec<f32> {
+            let data = std::fs::read(path).unwrap();
+            return data[44..].chunks(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect();
+        };
+        let mix = read_samples(&path);
+        let triangle = read_samples(&stem_path(&path, AudioChannel::Triangle));
+        let pulse = read_samples(&stem_path(&path, AudioChannel::Pulse1));
+        assert_eq!(mix.len(), triangle.len());
+        assert!(mix.len() > 7000);
+        // Only the triangle plays, the other stems are silent
+        assert!(triangle.iter().any(|sample| sample.abs() > 0.01));
+        assert!(pulse.iter().all(|sample| *sample == 0.0));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_export_sound_waves() {
+        let directory = std::env::temp_dir().join(format!("wav_export_source_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("square.wav");
+        export_source(sound_waves::square_wave(1, 480), &path, SampleFormat::Pcm16).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        // Mono at 48 kHz
+        assert_eq!(u16::from_le_bytes([data[22], data[23]]), 1);
+        assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 48_000);
+        let samples: Vec<i16> = data[44..].chunks(2).map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])).collect();
+        assert_eq!(samples.len(), 48_000);
+        // A 480 Hz square wave flips every 50 samples between silence and full scale
+        assert!(samples[..45].iter().all(|sample| *sample > 32_000));
+        assert!(samples[55..95].iter().all(|sample| *sample == 0));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,499 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process,
+    thread,
+    time::{Duration, Instant},
+};
+
+use gilrs::{Axis, Button, Gilrs};
+use minifb::{Key, KeyRepeat, ScaleMode, Window, WindowOptions};
+
+use cpu6502emu::{
+    audio::{AudioSink, null_sink::NullSink, rodio_sink::RodioSink},
+    cartridge::Cartridge,
+    config::{Config, parse_region},
+    controller::Buttons,
+    cpu::Byte,
+    nes::{Nes, region::Region},
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH, palette::Palette},
+    video::{ntsc::{NtscFilter, NtscSettings}, png::encode_png},
+};
+
+// Plays a ROM in a window, scaled by whole multiples of the 256x240 picture so every pixel keeps
+// the same size. Players 1 and 2 use the keyboard as set in the configuration, and the first two
+// gamepads: d-pad or left stick, East for A, South for B. Options given on the command line
+// replace the values of the configuration file, for every ROM.
+const USAGE: &str = "usage: nes-frontend [rom.nes] [options]
+  --config FILE            settings, TOML (default ~/.config/nes-frontend/config.toml if present)
+  --scale N                initial window size in multiples of 256x240
+  --region auto|ntsc|pal|dendy
+  --palette FILE           a 64 or 512 entry .pal file
+  --ntsc                   NTSC composite video filter
+  --rate HZ                audio sample rate
+  --latency MS             audio latency
+  --mute                   no sound
+  --saves DIR              battery saves directory
+  --screenshots DIR        screenshots directory
+Default hotkeys: P pause, F5 reset, O load a ROM, F12 screenshot, Escape quit.";
+
+// Beyond this delay the frame clock restarts instead of running frames back to back to catch up
+const MAX_LATE: Duration = Duration::from_millis(100);
+const STICK_THRESHOLD: f32 = 0.5;
+
+// Names of the keys in the configuration, digits are also accepted as "0" to "9"
+const KEY_NAMES: [(&str, Key); 106] = [
+    ("Key0", Key::Key0), ("Key1", Key::Key1), ("Key2", Key::Key2), ("Key3", Key::Key3), ("Key4", Key::Key4),
+    ("Key5", Key::Key5), ("Key6", Key::Key6), ("Key7", Key::Key7), ("Key8", Key::Key8), ("Key9", Key::Key9),
+    ("A", Key::A), ("B", Key::B), ("C", Key::C), ("D", Key::D), ("E", Key::E), ("F", Key::F), ("G", Key::G),
+    ("H", Key::H), ("I", Key::I), ("J", Key::J), ("K", Key::K), ("L", Key::L), ("M", Key::M), ("N", Key::N),
+    ("O", Key::O), ("P", Key::P), ("Q", Key::Q), ("R", Key::R), ("S", Key::S), ("T", Key::T), ("U", Key::U),
+    ("V", Key::V), ("W", Key::W), ("X", Key::X), ("Y", Key::Y), ("Z", Key::Z),
+    ("F1", Key::F1), ("F2", Key::F2), ("F3", Key::F3), ("F4", Key::F4), ("F5", Key::F5), ("F6", Key::F6),
+    ("F7", Key::F7), ("F8", Key::F8), ("F9", Key::F9), ("F10", Key::F10), ("F11", Key::F11), ("F12", Key::F12),
+    ("F13", Key::F13), ("F14", Key::F14), ("F15", Key::F15),
+    ("Down", Key::Down), ("Left", Key::Left), ("Right", Key::Right), ("Up", Key::Up),
+    ("Apostrophe", Key::Apostrophe), ("Backquote", Key::Backquote), ("Backslash", Key::Backslash),
+    ("Comma", Key::Comma), ("Equal", Key::Equal), ("LeftBracket", Key::LeftBracket), ("Minus", Key::Minus),
+    ("Period", Key::Period), ("RightBracket", Key::RightBracket), ("Semicolon", Key::Semicolon),
+    ("Slash", Key::Slash), ("Backspace", Key::Backspace), ("Delete", Key::Delete), ("End", Key::End),
+    ("Enter", Key::Enter), ("Escape", Key::Escape), ("Home", Key::Home), ("Insert", Key::Insert),
+    ("Menu", Key::Menu), ("PageDown", Key::PageDown), ("PageUp", Key::PageUp), ("Pause", Key::Pause),
+    ("Space", Key::Space), ("Tab", Key::Tab), ("NumLock", Key::NumLock), ("CapsLock", Key::CapsLock),
+    ("ScrollLock", Key::ScrollLock), ("LeftShift", Key::LeftShift), ("RightShift", Key::RightShift),
+    ("LeftCtrl", Key::LeftCtrl), ("RightCtrl", Key::RightCtrl),
+    ("NumPad0", Key::NumPad0), ("NumPad1", Key::NumPad1), ("NumPad2", Key::NumPad2), ("NumPad3", Key::NumPad3),
+    ("NumPad4", Key::NumPad4), ("NumPad5", Key::NumPad5), ("NumPad6", Key::NumPad6), ("NumPad7", Key::NumPad7),
+    ("NumPad8", Key::NumPad8), ("NumPad9", Key::NumPad9), ("NumPadDot", Key::NumPadDot),
+    ("NumPadSlash", Key::NumPadSlash), ("NumPadAsterisk", Key::NumPadAsterisk), ("NumPadMinus", Key::NumPadMinus),
+    ("NumPadPlus", Key::NumPadPlus), ("NumPadEnter", Key::NumPadEnter),
+    ("LeftAlt", Key::LeftAlt), ("RightAlt", Key::RightAlt), ("LeftSuper", Key::LeftSuper), ("RightSuper", Key::RightSuper),
+];
+
+fn key_from_name(name: &str) -> Option<Key> {
+    if name.len() == 1 && name.as_bytes()[0].is_ascii_digit() {
+        return key_from_name(&format!("Key{}", name));
+    }
+    return KEY_NAMES.iter().find(|(key_name, _)| key_name.eq_ignore_ascii_case(name)).map(|(_, key)| *key);
+}
+
+fn key(setting: &str, name: &str) -> Result<Key, String> {
+    return key_from_name(name).ok_or(format!("{}: unknown key {}", setting, name));
+}
+
+struct Hotkeys {
+    pause: Key,
+    reset: Key,
+    load_rom: Key,
+    screenshot: Key,
+    quit: Key,
+}
+
+struct Bindings {
+    // Keys held for each button of players 1 and 2
+    players: [Vec<(Key, Buttons)>; 2],
+    hotkeys: Hotkeys,
+}
+
+impl Bindings {
+    // The configuration keeps the key names, only the frontend knows which ones exist
+    fn from_config(config: &Config) -> Result<Bindings, String> {
+        let mut players: [Vec<(Key, Buttons)>; 2] = [Vec::new(), Vec::new()];
+        for (player, (keys, names)) in players.iter_mut().zip(config.players.iter()).enumerate() {
+            for (name, button) in names.iter() {
+                keys.push((key(&format!("player{}", player + 1), name)?, *button));
+            }
+        }
+        let hotkeys = &config.hotkeys;
+        return Ok(Bindings {
+            players,
+            hotkeys: Hotkeys {
+                pause: key("hotkeys.pause", &hotkeys.pause)?,
+                reset: key("hotkeys.reset", &hotkeys.reset)?,
+                load_rom: key("hotkeys.load_rom", &hotkeys.load_rom)?,
+                screenshot: key("hotkeys.screenshot", &hotkeys.screenshot)?,
+                quit: key("hotkeys.quit", &hotkeys.quit)?,
+            },
+        });
+    }
+}
+
+// Command line values, applied over the configuration file
+#[derive(Default)]
+struct Options {
+    rom: Option<PathBuf>,
+    config: Option<PathBuf>,
+    scale: Option<usize>,
+    region: Option<Option<Region>>,
+    palette: Option<PathBuf>,
+    ntsc: bool,
+    sample_rate: Option<u32>,
+    latency: Option<u32>,
+    mute: bool,
+    saves: Option<PathBuf>,
+    screenshots: Option<PathBuf>,
+}
+
+impl Options {
+    fn apply(&self, config: &mut Config) {
+        if let Some(scale) = self.scale {
+            config.video.scale = scale;
+        }
+        if let Some(region) = self.region {
+            config.region = region;
+        }
+        if let Some(palette) = &self.palette {
+            config.video.palette = Some(palette.clone());
+        }
+        if self.ntsc {
+            config.video.ntsc_filter = true;
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            config.audio.sample_rate = sample_rate;
+        }
+        if let Some(latency) = self.latency {
+            config.audio.latency = latency;
+        }
+        if self.mute {
+            config.audio.mute = true;
+        }
+        if let Some(saves) = &self.saves {
+            config.paths.saves = saves.clone();
+        }
+        if let Some(screenshots) = &self.screenshots {
+            config.paths.screenshots = screenshots.clone();
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--config" => options.config = Some(PathBuf::from(value(arg)?)),
+            "--scale" => options.scale = Some(value(arg)?.parse::<usize>().ok().filter(|scale| (1..=16).contains(scale)).ok_or("--scale: must be between 1 and 16")?),
+            "--region" => options.region = Some(parse_region(arg, &value(arg)?).map_err(|error| error.to_string())?),
+            "--palette" => options.palette = Some(PathBuf::from(value(arg)?)),
+            "--ntsc" => options.ntsc = true,
+            "--rate" => options.sample_rate = Some(value(arg)?.parse::<u32>().ok().filter(|rate| (8_000..=192_000).contains(rate)).ok_or("--rate: must be between 8000 and 192000")?),
+            "--latency" => options.latency = Some(value(arg)?.parse::<u32>().ok().filter(|latency| (1..=1_000).contains(latency)).ok_or("--latency: must be between 1 and 1000")?),
+            "--mute" => options.mute = true,
+            "--saves" => options.saves = Some(PathBuf::from(value(arg)?)),
+            "--screenshots" => options.screenshots = Some(PathBuf::from(value(arg)?)),
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => options.rom = Some(PathBuf::from(arg)),
+        }
+    }
+    return Ok(options);
+}
+
+// The file given on the command line, or the one of the user when there is one
+fn load_config(options: &Options) -> Result<Config, Box<dyn Error>> {
+    if let Some(path) = &options.config {
+        return Ok(Config::load(path)?);
+    }
+    let directory = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    if let Some(path) = directory.map(|directory| directory.join("nes-frontend").join("config.toml")).filter(|path| path.exists()) {
+        return Ok(Config::load(&path)?);
+    }
+    return Ok(Config::default());
+}
+
+// "saves/Game.sav" for "roms/Game.nes"
+fn save_path(config: &Config, rom: &Path) -> PathBuf {
+    return config.paths.saves.join(rom.with_extension("sav").file_name().unwrap_or_default());
+}
+
+// Buttons of the first two gamepads
+fn gamepad_buttons(gilrs: &mut Gilrs) -> [Buttons; 2] {
+    // Events update the state the gamepads report
+    while gilrs.next_event().is_some() {}
+    let mut players = [Buttons::empty(); 2];
+    for (buttons, (_, gamepad)) in players.iter_mut().zip(gilrs.gamepads()) {
+        for (button, pressed) in [
+            (Buttons::A, Button::East),
+            (Buttons::B, Button::South),
+            (Buttons::SELECT, Button::Select),
+            (Buttons::START, Button::Start),
+            (Buttons::UP, Button::DPadUp),
+            (Buttons::DOWN, Button::DPadDown),
+            (Buttons::LEFT, Button::DPadLeft),
+            (Buttons::RIGHT, Button::DPadRight),
+        ] {
+            buttons.set(button, gamepad.is_pressed(pressed));
+        }
+        let (x, y) = (gamepad.value(Axis::LeftStickX), gamepad.value(Axis::LeftStickY));
+        buttons.set(Buttons::LEFT, buttons.contains(Buttons::LEFT) || x < -STICK_THRESHOLD);
+        buttons.set(Buttons::RIGHT, buttons.contains(Buttons::RIGHT) || x > STICK_THRESHOLD);
+        buttons.set(Buttons::UP, buttons.contains(Buttons::UP) || y > STICK_THRESHOLD);
+        buttons.set(Buttons::DOWN, buttons.contains(Buttons::DOWN) || y < -STICK_THRESHOLD);
+    }
+    return players;
+}
+
+// Some games misbehave when opposite directions are held together, which a d-pad cannot do
+fn without_opposite_directions(mut buttons: Buttons) -> Buttons {
+    if buttons.contains(Buttons::UP | Buttons::DOWN) {
+        buttons.remove(Buttons::UP | Buttons::DOWN);
+    }
+    if buttons.contains(Buttons::LEFT | Buttons::RIGHT) {
+        buttons.remove(Buttons::LEFT | Buttons::RIGHT);
+    }
+    return buttons;
+}
+
+// Copies the RGBA picture into the 0RGB window buffer, `scale` window pixels per picture pixel
+fn blit(rgba: &[Byte], scale: usize, pixels: &mut Vec<u32>) {
+    let width = SCREEN_WIDTH * scale;
+    pixels.resize(width * SCREEN_HEIGHT * scale, 0);
+    for (y, row) in rgba.chunks(SCREEN_WIDTH * 4).enumerate() {
+        let line = &mut pixels[y * scale * width..(y * scale + 1) * width];
+        for (x, pixel) in row.chunks_exact(4).enumerate() {
+            let color = u32::from(pixel[0]) << 16 | u32::from(pixel[1]) << 8 | u32::from(pixel[2]);
+            line[x * scale..(x + 1) * scale].fill(color);
+        }
+        for copy in 1..scale {
+            pixels.copy_within(y * scale * width..(y * scale + 1) * width, (y * scale + copy) * width);
+        }
+    }
+}
+
+struct Frontend {
+    options: Options,
+    // Settings of the file with the command line applied, before the overrides of a ROM
+    config: Config,
+    // Settings of the running ROM
+    rom_config: Config,
+    bindings: Bindings,
+    window: Window,
+    audio: Box<dyn AudioSink>,
+    // Missing when the gamepad backend could not start
+    gamepads: Option<Gilrs>,
+    nes: Option<Nes>,
+    rom: Option<PathBuf>,
+    paused: bool,
+    palette: Palette,
+    ntsc: Option<NtscFilter>,
+    rgba: Vec<Byte>,
+    pixels: Vec<u32>,
+}
+
+impl Frontend {
+    fn build_frontend(options: Options) -> Result<Frontend, Box<dyn Error>> {
+        let mut config = load_config(&options)?;
+        options.apply(&mut config);
+        let bindings = Bindings::from_config(&config)?;
+        let window_options = WindowOptions { resize: true, scale_mode: ScaleMode::Center, ..WindowOptions::default() };
+        let scale = config.video.scale;
+        let mut window = Window::new("nes-frontend", SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale, window_options)?;
+        // Frames are paced by the emulated frame rate, not by minifb
+        window.set_target_fps(0);
+        let sample_rate = config.audio.sample_rate;
+        let audio: Box<dyn AudioSink> = if config.audio.mute {
+            Box::new(NullSink::build_null_sink(sample_rate))
+        } else {
+            match RodioSink::build_rodio_sink(sample_rate, Duration::from_millis(u64::from(config.audio.latency))) {
+                Ok(sink) => Box::new(sink),
+                Err(error) => {
+                    eprintln!("nes-frontend: {}, playing without sound", error);
+                    Box::new(NullSink::build_null_sink(sample_rate))
+                }
+            }
+        };
+        let gamepads = Gilrs::new().map_err(|error| eprintln!("nes-frontend: no gamepad support: {}", error)).ok();
+        let mut frontend = Frontend {
+            options,
+            rom_config: config.clone(),
+            config,
+            bindings,
+            window,
+            audio,
+            gamepads,
+            nes: None,
+            rom: None,
+            paused: false,
+            palette: Palette::build_default_palette(),
+            ntsc: None,
+            rgba: vec![0x00; SCREEN_WIDTH * SCREEN_HEIGHT * 4],
+            pixels: Vec::new(),
+        };
+        frontend.apply_video_config()?;
+        return Ok(frontend);
+    }
+
+    fn apply_video_config(&mut self) -> Result<(), String> {
+        let video = &self.rom_config.video;
+        self.palette = match &video.palette {
+            Some(path) => Palette::load(path).map_err(|error| format!("{}: {}", path.display(), error))?,
+            None => Palette::build_default_palette(),
+        };
+        self.ntsc = video.ntsc_filter.then(|| NtscFilter::build_ntsc_filter(NtscSettings::default(), SCREEN_WIDTH));
+        return Ok(());
+    }
+
+    fn load_rom(&mut self, path: &Path) -> Result<(), String> {
+        let data = fs::read(path).map_err(|error| format!("could not read {}: {}", path.display(), error))?;
+        let mut cartridge = Cartridge::from_ines(&data).map_err(|error| format!("could not load {}: {}", path.display(), error))?;
+        let mut rom_config = self.config.for_rom(&data);
+        self.options.apply(&mut rom_config);
+        let bindings = Bindings::from_config(&rom_config)?;
+
+        self.write_battery_save();
+        if cartridge.battery_ram().is_some() {
+            if let Ok(save) = fs::read(save_path(&rom_config, path)) {
+                cartridge.load_battery_ram(&save);
+            }
+        }
+        let mut nes = match rom_config.region {
+            Some(region) => Nes::build_nes_with_region(cartridge, region),
+            None => Nes::build_nes(cartridge),
+        };
+        nes.set_sample_rate(self.audio.sample_rate());
+        self.nes = Some(nes);
+        self.rom = Some(path.to_path_buf());
+        self.rom_config = rom_config;
+        self.bindings = bindings;
+        self.paused = false;
+        self.apply_video_config()?;
+        self.update_title();
+        return Ok(());
+    }
+
+    // Keeps the battery backed RAM of the running game, if it has some
+    fn write_battery_save(&self) {
+        let (Some(nes), Some(rom)) = (&self.nes, &self.rom) else {
+            return;
+        };
+        if let Some(ram) = nes.cartridge().battery_ram() {
+            let path = save_path(&self.rom_config, rom);
+            let result = fs::create_dir_all(&self.rom_config.paths.saves).and_then(|_| fs::write(&path, ram));
+            if let Err(error) = result {
+                eprintln!("nes-frontend: could not write {}: {}", path.display(), error);
+            }
+        }
+    }
+
+    fn write_screenshot(&self) -> Result<PathBuf, String> {
+        let name = self.rom.as_ref().and_then(|rom| rom.file_stem()).map_or(String::from("screenshot"), |stem| stem.to_string_lossy().into_owned());
+        let directory = &self.rom_config.paths.screenshots;
+        let path = directory.join(format!("{}-{}.png", name, chrono::Local::now().format("%Y%m%d-%H%M%S")));
+        fs::create_dir_all(directory)
+            .and_then(|_| fs::write(&path, encode_png(SCREEN_WIDTH, SCREEN_HEIGHT, &self.rgba)))
+            .map_err(|error| format!("could not write {}: {}", path.display(), error))?;
+        return Ok(path);
+    }
+
+    fn update_title(&mut self) {
+        let name = self.rom.as_ref().and_then(|rom| rom.file_name()).map_or(String::from("no ROM, press the load hotkey"), |name| name.to_string_lossy().into_owned());
+        let paused = if self.paused { " (paused)" } else { "" };
+        self.window.set_title(&format!("nes-frontend - {}{}", name, paused));
+    }
+
+    fn handle_hotkeys(&mut self) {
+        let hotkeys = &self.bindings.hotkeys;
+        let (pause, reset, load_rom, screenshot) = (hotkeys.pause, hotkeys.reset, hotkeys.load_rom, hotkeys.screenshot);
+        for key in self.window.get_keys_pressed(KeyRepeat::No) {
+            if key == pause && self.nes.is_some() {
+                self.paused = !self.paused;
+                self.update_title();
+            } else if key == reset {
+                if let Some(nes) = self.nes.as_mut() {
+                    nes.reset();
+                }
+            } else if key == screenshot {
+                match self.write_screenshot() {
+                    Ok(path) => println!("saved {}", path.display()),
+                    Err(message) => eprintln!("nes-frontend: {}", message),
+                }
+            } else if key == load_rom {
+                let directory = self.rom.as_ref().and_then(|rom| rom.parent()).map_or(String::new(), |directory| format!("{}/", directory.display()));
+                if let Some(path) = tinyfiledialogs::open_file_dialog("Load a ROM", &directory, Some((&["*.nes"], "NES ROMs"))) {
+                    if let Err(message) = self.load_rom(Path::new(&path)) {
+                        eprintln!("nes-frontend: {}", message);
+                    }
+                }
+            }
+        }
+    }
+
+    fn run_frame(&mut self) {
+        let Some(nes) = self.nes.as_mut() else {
+            self.rgba.fill(0x00);
+            return;
+        };
+        if self.paused {
+            return;
+        }
+        let pads = self.gamepads.as_mut().map_or([Buttons::empty(); 2], gamepad_buttons);
+        for (player, (keys, pad)) in self.bindings.players.iter().zip(pads).enumerate() {
+            let mut buttons = pad;
+            for (key, button) in keys.iter() {
+                if self.window.is_key_down(*key) {
+                    buttons |= *button;
+                }
+            }
+            nes.set_buttons(player, without_opposite_directions(buttons));
+        }
+        nes.set_audio_rate_adjustment(self.audio.rate_adjustment());
+        nes.run_frame();
+        self.audio.push_samples(nes.audio_samples());
+        let ppu = nes.ppu();
+        match self.ntsc.as_mut() {
+            Some(filter) => filter.fill_rgba(ppu.frame_buffer(), &mut self.rgba),
+            None => self.palette.fill_rgba(ppu.frame_buffer(), &mut self.rgba),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(rom) = self.options.rom.clone() {
+            self.load_rom(&rom)?;
+        } else {
+            self.update_title();
+        }
+        let mut next_frame = Instant::now();
+        while self.window.is_open() && !self.window.is_key_down(self.bindings.hotkeys.quit) {
+            self.handle_hotkeys();
+            self.run_frame();
+
+            // The largest whole multiple of the picture that fits, centered by minifb
+            let (width, height) = self.window.get_size();
+            let scale = (width / SCREEN_WIDTH).min(height / SCREEN_HEIGHT).max(1);
+            blit(&self.rgba, scale, &mut self.pixels);
+            self.window.update_with_buffer(&self.pixels, SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale)?;
+
+            let region = self.nes.as_ref().map_or(Region::Ntsc, |nes| nes.region());
+            next_frame += Duration::from_secs_f64(1.0 / region.frame_rate());
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else if now - next_frame > MAX_LATE {
+                next_frame = now;
+            }
+        }
+        self.write_battery_save();
+        return Ok(());
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{}", message);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(error) = Frontend::build_frontend(options).and_then(|mut frontend| frontend.run()) {
+        eprintln!("nes-frontend: {}", error);
+        process::exit(1);
+    }
+}
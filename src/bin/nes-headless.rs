@@ -0,0 +1,492 @@
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+};
+
+use cpu6502emu::{
+    audio::{wav::SampleFormat, wav_export::WavExport},
+    cartridge::Cartridge,
+    controller::Buttons,
+    cpu::{Byte, Cpu, Word},
+    memory::Memory,
+    nes::{Nes, region::Region},
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH, palette::Palette},
+    video::{ntsc::{NtscFilter, NtscSettings}, png::encode_png, recorder::Recorder},
+};
+
+// Runs a ROM without display or sound device and reports how it ended, for CI.
+// Exit status: 0 when a stop condition was met (or the limit was reached and none was given)
+// and after --help, 1 when the limit was reached first, 2 on bad arguments or an invalid input
+// script, 3 when a file could not be read or written, 4 when the emulator panicked (for example
+// on an invalid opcode) and 5 when the iNES image could not be loaded.
+// `--exit-code-from` replaces 0 and 1 by the value of a memory byte, for test ROMs reporting
+// their own result.
+const USAGE: &str = "usage: nes-headless <rom.nes | program.bin> [options]
+  --frames N               stop after N frames (default 600)
+  --cycles N               stop after N CPU cycles
+  --region ntsc|pal|dendy  override the region of the ROM header
+  --until-pc ADDR          stop when the instruction at ADDR is reached (repeatable)
+  --until-mem ADDR=VAL     stop when the byte at ADDR is VAL, ADDR!=VAL for the opposite;
+                           all of them must hold (repeatable)
+  --until-trap             stop when an instruction jumps to itself
+  --input FILE             scripted input, lines of \"FRAME [p1-p4] BUTTONS...\"
+  --four-score             plug a Four Score adapter, needed by p3 and p4 in the input script
+  --cpu-state FILE         write the final CPU registers
+  --dump START-END:FILE    write a memory range (repeatable)
+  --screenshot FILE        write the last frame as a PNG image, PPM when FILE ends in .ppm
+  --palette FILE           colors of the screenshot and video, a 64 or 512 entry .pal file
+  --ntsc                   screenshot through the NTSC composite video filter
+  --audio FILE             write the audio as a WAV file
+  --video FILE             record a Y4M video, with its audio in the same name ending in .wav
+  --exit-code-from ADDR    exit with the value of the byte at ADDR
+  --load ADDR              raw binaries: load address (default 0000)
+  --start ADDR             raw binaries: entry point, written to the reset vector
+Addresses and values are hexadecimal, counts are decimal.
+Exit status: 0 done or --help, 1 limit reached before the stop conditions, 2 bad arguments or
+input script, 3 file not readable or writable, 4 the emulator panicked (for example an invalid
+opcode), 5 invalid iNES image.";
+
+const DEFAULT_FRAMES: u64 = 600;
+// Raw binaries count frames in NTSC frame lengths
+const RAW_FRAME_CYCLES: u64 = 29_781;
+const INES_MAGIC: &[Byte] = b"NES\x1A";
+
+const EXIT_LIMIT: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_CRASH: i32 = 4;
+const EXIT_ROM: i32 = 5;
+
+struct MemoryCondition {
+    address: Word,
+    value: Byte,
+    equal: bool,
+}
+
+struct Dump {
+    start: Word,
+    end: Word,
+    path: PathBuf,
+}
+
+struct Options {
+    input: PathBuf,
+    frames: Option<u64>,
+    cycles: Option<u64>,
+    region: Option<Region>,
+    until_pc: Vec<Word>,
+    until_mem: Vec<MemoryCondition>,
+    until_trap: bool,
+    script: Option<PathBuf>,
+    four_score: bool,
+    cpu_state: Option<PathBuf>,
+    dumps: Vec<Dump>,
+    screenshot: Option<PathBuf>,
+    palette: Option<PathBuf>,
+    ntsc: bool,
+    audio: Option<PathBuf>,
+    video: Option<PathBuf>,
+    exit_code_from: Option<Word>,
+    load_address: Word,
+    start_address: Option<Word>,
+}
+
+impl Options {
+    fn has_conditions(&self) -> bool {
+        return !self.until_pc.is_empty() || !self.until_mem.is_empty() || self.until_trap;
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u32, String> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    return u32::from_str_radix(digits, 16).map_err(|_| format!("invalid hexadecimal number {}", text));
+}
+
+fn parse_address(text: &str) -> Result<Word, String> {
+    return Word::try_from(parse_hex(text)?).map_err(|_| format!("address {} out of range", text));
+}
+
+fn parse_byte(text: &str) -> Result<Byte, String> {
+    return Byte::try_from(parse_hex(text)?).map_err(|_| format!("value {} out of range", text));
+}
+
+fn parse_count(text: &str) -> Result<u64, String> {
+    return text.parse::<u64>().map_err(|_| format!("invalid count {}", text));
+}
+
+fn parse_memory_condition(text: &str) -> Result<MemoryCondition, String> {
+    if let Some((address, value)) = text.split_once("!=") {
+        return Ok(MemoryCondition { address: parse_address(address)?, value: parse_byte(value)?, equal: false });
+    }
+    if let Some((address, value)) = text.split_once('=') {
+        return Ok(MemoryCondition { address: parse_address(address)?, value: parse_byte(value)?, equal: true });
+    }
+    return Err(format!("invalid memory condition {}", text));
+}
+
+fn parse_dump(text: &str) -> Result<Dump, String> {
+    let (range, path) = text.split_once(':').ok_or(format!("invalid dump {}", text))?;
+    let (start, end) = range.split_once('-').ok_or(format!("invalid range {}", range))?;
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+    if end < start {
+        return Err(format!("invalid range {}", range));
+    }
+    return Ok(Dump { start, end, path: PathBuf::from(path) });
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        input: PathBuf::new(),
+        frames: None,
+        cycles: None,
+        region: None,
+        until_pc: Vec::new(),
+        until_mem: Vec::new(),
+        until_trap: false,
+        script: None,
+        four_score: false,
+        cpu_state: None,
+        dumps: Vec::new(),
+        screenshot: None,
+        palette: None,
+        ntsc: false,
+        audio: None,
+        video: None,
+        exit_code_from: None,
+        load_address: 0x0000,
+        start_address: None,
+    };
+    let mut input = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--frames" => options.frames = Some(parse_count(&value(arg)?)?),
+            "--cycles" => options.cycles = Some(parse_count(&value(arg)?)?),
+            "--region" => {
+                options.region = Some(match value(arg)?.as_str() {
+                    "ntsc" => Region::Ntsc,
+                    "pal" => Region::Pal,
+                    "dendy" => Region::Dendy,
+                    region => return Err(format!("unknown region {}", region)),
+                });
+            }
+            "--until-pc" => options.until_pc.push(parse_address(&value(arg)?)?),
+            "--until-mem" => options.until_mem.push(parse_memory_condition(&value(arg)?)?),
+            "--until-trap" => options.until_trap = true,
+            "--input" => options.script = Some(PathBuf::from(value(arg)?)),
+            "--four-score" => options.four_score = true,
+            "--cpu-state" => options.cpu_state = Some(PathBuf::from(value(arg)?)),
+            "--dump" => options.dumps.push(parse_dump(&value(arg)?)?),
+            "--screenshot" => options.screenshot = Some(PathBuf::from(value(arg)?)),
+            "--ntsc" => options.ntsc = true,
+            "--palette" => options.palette = Some(PathBuf::from(value(arg)?)),
+            "--audio" => options.audio = Some(PathBuf::from(value(arg)?)),
+            "--video" => options.video = Some(PathBuf::from(value(arg)?)),
+            "--exit-code-from" => options.exit_code_from = Some(parse_address(&value(arg)?)?),
+            "--load" => options.load_address = parse_address(&value(arg)?)?,
+            "--start" => options.start_address = Some(parse_address(&value(arg)?)?),
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => input = Some(PathBuf::from(arg)),
+        }
+    }
+    options.input = input.ok_or("missing input file")?;
+    return Ok(options);
+}
+
+// One line of the input script: from `frame` on, `player` holds `buttons`
+struct InputEvent {
+    frame: u64,
+    player: usize,
+    buttons: Buttons,
+}
+
+// Players 3 and 4 only exist with a Four Score plugged in
+fn parse_script(text: &str, four_score: bool) -> Result<Vec<InputEvent>, String> {
+    let mut events = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("input script line {}: {}", number + 1, message);
+        let mut words = line.split_whitespace();
+        let frame = words.next().unwrap().parse::<u64>().map_err(|_| error("invalid frame number"))?;
+        let mut event = InputEvent { frame, player: 0, buttons: Buttons::empty() };
+        for word in words {
+            event.buttons |= match word.to_ascii_uppercase().as_str() {
+                "P3" | "P4" if !four_score => {
+                    return Err(error(&format!("{} needs --four-score", word)));
+                }
+                "P1" | "P2" | "P3" | "P4" => {
+                    event.player = usize::from(word.as_bytes()[1] - b'1');
+                    Buttons::empty()
+                }
+                "A" => Buttons::A,
+                "B" => Buttons::B,
+                "SELECT" => Buttons::SELECT,
+                "START" => Buttons::START,
+                "UP" => Buttons::UP,
+                "DOWN" => Buttons::DOWN,
+                "LEFT" => Buttons::LEFT,
+                "RIGHT" => Buttons::RIGHT,
+                _ => return Err(error(&format!("unknown button {}", word))),
+            };
+        }
+        events.push(event);
+    }
+    events.sort_by_key(|event| event.frame);
+    return Ok(events);
+}
+
+enum StopReason {
+    Pc(Word),
+    Memory,
+    Trap(Word),
+    Limit,
+}
+
+// Checked on every opcode fetch
+struct StopConditions<'a> {
+    options: &'a Options,
+    last_opcode: Option<Word>,
+}
+
+impl StopConditions<'_> {
+    fn check(&mut self, cpu: &Cpu, read: impl Fn(Word) -> Byte) -> Option<StopReason> {
+        let address = cpu.fetched_opcode_address()?;
+        if self.options.until_pc.contains(&address) {
+            return Some(StopReason::Pc(address));
+        }
+        if self.options.until_trap && self.last_opcode == Some(address) {
+            return Some(StopReason::Trap(address));
+        }
+        self.last_opcode = Some(address);
+        let conditions = &self.options.until_mem;
+        if !conditions.is_empty() && conditions.iter().all(|condition| (read(condition.address) == condition.value) == condition.equal) {
+            return Some(StopReason::Memory);
+        }
+        return None;
+    }
+}
+
+// What the run leaves behind for the outputs
+struct Outcome {
+    reason: StopReason,
+    frames: u64,
+    cycles: u64,
+}
+
+fn cycle_limit(options: &Options) -> u64 {
+    return options.cycles.unwrap_or(u64::MAX);
+}
+
+fn frame_limit(options: &Options) -> u64 {
+    if options.frames.is_none() && options.cycles.is_some() {
+        return u64::MAX;
+    }
+    return options.frames.unwrap_or(DEFAULT_FRAMES);
+}
+
+fn run_nes(nes: &mut Nes, options: &Options, script: &[InputEvent], audio: &mut Option<WavExport>, video: &mut Option<Recorder>) -> Result<Outcome, String> {
+    let mut conditions = StopConditions { options, last_opcode: None };
+    let mut events = script.iter().peekable();
+    let mut frames = 0;
+    let mut reason = StopReason::Limit;
+    while frames < frame_limit(options) {
+        while let Some(event) = events.next_if(|event| event.frame <= frames) {
+            nes.set_buttons(event.player, event.buttons);
+        }
+        let mut stop = None;
+        let stopped = nes.run_frame_until(|nes| {
+            if nes.cpu_cycles() >= cycle_limit(options) {
+                stop = Some(StopReason::Limit);
+            } else {
+                stop = conditions.check(nes.cpu(), |address| nes.peek_byte(address));
+            }
+            return stop.is_some();
+        });
+        if let Some(export) = audio.as_mut() {
+            export.write_frame(nes).map_err(|error| format!("could not write the audio: {}", error))?;
+        }
+        if let Some(recorder) = video.as_mut() {
+            recorder.write_frame(nes).map_err(|error| format!("could not write the video: {}", error))?;
+        }
+        if stopped {
+            reason = stop.unwrap();
+            break;
+        }
+        frames += 1;
+    }
+    return Ok(Outcome { reason, frames, cycles: nes.cpu_cycles() });
+}
+
+fn run_raw(cpu: &mut Cpu, memory: &mut Memory, options: &Options) -> Outcome {
+    let mut conditions = StopConditions { options, last_opcode: None };
+    let limit = cycle_limit(options).min(frame_limit(options).saturating_mul(RAW_FRAME_CYCLES));
+    let mut cycles = 0;
+    while cycles < limit {
+        cpu.exec_cycle(memory);
+        cycles += 1;
+        if let Some(reason) = conditions.check(cpu, |address| memory.peek_byte(address)) {
+            return Outcome { reason, frames: cycles / RAW_FRAME_CYCLES, cycles };
+        }
+    }
+    return Outcome { reason: StopReason::Limit, frames: cycles / RAW_FRAME_CYCLES, cycles };
+}
+
+fn write_file(path: &Path, data: &[Byte]) -> Result<(), String> {
+    return fs::write(path, data).map_err(|error| format!("could not write {}: {}", path.display(), error));
+}
+
+fn load_palette(options: &Options) -> Result<Palette, String> {
+    return match &options.palette {
+        Some(path) => Palette::load(path).map_err(|error| format!("{}: {}", path.display(), error)),
+        None => Ok(Palette::build_default_palette()),
+    };
+}
+
+fn write_outputs(options: &Options, outcome: &Outcome, cpu: &Cpu, read: impl Fn(Word) -> Byte, frame: Option<&[Word]>) -> Result<(), String> {
+    let reason = match outcome.reason {
+        StopReason::Pc(address) => format!("reached PC ${:04X}", address),
+        StopReason::Memory => String::from("memory condition met"),
+        StopReason::Trap(address) => format!("trapped at ${:04X}", address),
+        StopReason::Limit => String::from("limit reached"),
+    };
+    let report = format!("{} after {} frames, {} cycles\n{}\n", reason, outcome.frames, outcome.cycles, cpu.registers());
+    print!("{}", report);
+    if let Some(path) = &options.cpu_state {
+        write_file(path, report.as_bytes())?;
+    }
+    for dump in options.dumps.iter() {
+        let data: Vec<Byte> = (dump.start..=dump.end).map(&read).collect();
+        write_file(&dump.path, &data)?;
+    }
+    if let (Some(path), Some(frame)) = (&options.screenshot, frame) {
+        let rgba = if options.ntsc {
+            NtscFilter::build_ntsc_filter(NtscSettings::default(), SCREEN_WIDTH).frame_to_rgba(frame)
+        } else {
+            load_palette(options)?.frame_to_rgba(frame)
+        };
+        if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("ppm")) {
+            // Binary PPM: a text header then the RGB pixels
+            let mut image = format!("P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+            image.extend(rgba.chunks(4).flat_map(|pixel| &pixel[..3]));
+            write_file(path, &image)?;
+        } else {
+            write_file(path, &encode_png(SCREEN_WIDTH, SCREEN_HEIGHT, &rgba))?;
+        }
+    }
+    return Ok(());
+}
+
+fn exit_code(options: &Options, outcome: &Outcome, read: impl Fn(Word) -> Byte) -> i32 {
+    if let Some(address) = options.exit_code_from {
+        return i32::from(read(address));
+    }
+    if matches!(outcome.reason, StopReason::Limit) && options.has_conditions() {
+        return EXIT_LIMIT;
+    }
+    return 0;
+}
+
+// Runs an iNES cartridge when there is one, the data as a raw binary otherwise
+fn run(options: &Options, data: &[Byte], cartridge: Option<Cartridge>, script: &[InputEvent]) -> Result<i32, String> {
+    let Some(cartridge) = cartridge else {
+        // A bare 6502 with 64 KiB of RAM
+        let mut memory = Memory::build_memory();
+        for (offset, byte) in data.iter().enumerate().take(0x10000 - usize::from(options.load_address)) {
+            memory.write_byte(options.load_address + offset as Word, *byte);
+        }
+        if let Some(start) = options.start_address {
+            memory.write_word(0xFFFC, start);
+        }
+        let mut cpu = Cpu::build_cpu();
+        cpu.reset(&memory);
+        let outcome = run_raw(&mut cpu, &mut memory, options);
+        write_outputs(options, &outcome, &cpu, |address| memory.peek_byte(address), None)?;
+        return Ok(exit_code(options, &outcome, |address| memory.peek_byte(address)));
+    };
+
+    let mut nes = match options.region {
+        Some(region) => Nes::build_nes_with_region(cartridge, region),
+        None => Nes::build_nes(cartridge),
+    };
+    if options.four_score {
+        nes.plug_four_score();
+    }
+    let mut audio = match &options.audio {
+        Some(path) => Some(WavExport::build_wav_export(&mut nes, path, SampleFormat::Pcm16, false)
+            .map_err(|error| format!("could not write {}: {}", path.display(), error))?),
+        None => None,
+    };
+    let mut video = match &options.video {
+        Some(path) => Some(Recorder::build_recorder(&nes, path, &path.with_extension("wav"), load_palette(options)?)
+            .map_err(|error| format!("could not write {}: {}", path.display(), error))?),
+        None => None,
+    };
+    let outcome = run_nes(&mut nes, options, script, &mut audio, &mut video)?;
+    if let Some(export) = audio {
+        export.finish().map_err(|error| format!("could not write the audio: {}", error))?;
+    }
+    if let Some(recorder) = video {
+        recorder.finish().map_err(|error| format!("could not write the video: {}", error))?;
+    }
+    write_outputs(options, &outcome, nes.cpu(), |address| nes.peek_byte(address), Some(nes.ppu().frame_buffer()))?;
+    return Ok(exit_code(options, &outcome, |address| nes.peek_byte(address)));
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        // Help was asked for
+        Err(message) if message.is_empty() => {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    // A script that can not be read is a file error, one that does not parse a usage error
+    let script = match &options.script {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(text) => parse_script(&text, options.four_score).unwrap_or_else(|message| {
+                eprintln!("nes-headless: {}", message);
+                process::exit(EXIT_USAGE);
+            }),
+            Err(error) => {
+                eprintln!("nes-headless: could not read {}: {}", path.display(), error);
+                process::exit(EXIT_IO);
+            }
+        },
+        None => Vec::new(),
+    };
+    let data = fs::read(&options.input).unwrap_or_else(|error| {
+        eprintln!("nes-headless: could not read {}: {}", options.input.display(), error);
+        process::exit(EXIT_IO);
+    });
+    let cartridge = if data.starts_with(INES_MAGIC) {
+        Some(Cartridge::from_ines(&data).unwrap_or_else(|error| {
+            eprintln!("nes-headless: could not load {}: {}", options.input.display(), error);
+            process::exit(EXIT_ROM);
+        }))
+    } else {
+        None
+    };
+    // The CPU panics on opcodes it does not know, the panic message is printed by the default hook
+    match panic::catch_unwind(AssertUnwindSafe(|| run(&options, &data, cartridge, &script))) {
+        Ok(Ok(code)) => process::exit(code),
+        Ok(Err(message)) => {
+            eprintln!("nes-headless: {}", message);
+            process::exit(EXIT_IO);
+        }
+        Err(_) => process::exit(EXIT_CRASH),
+    }
+}
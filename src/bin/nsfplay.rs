@@ -0,0 +1,129 @@
+use std::{path::PathBuf, process};
+
+use cpu6502emu::{
+    apu::audio_output::DEFAULT_SAMPLE_RATE,
+    audio::wav::{SampleFormat, WavWriter},
+    nes::region::Region,
+    nsf::{Nsf, player::NsfPlayer},
+};
+
+// Length of a track when the file does not give one, in milliseconds
+const DEFAULT_DURATION: u32 = 150_000;
+// Audio is rendered in chunks of this many milliseconds
+const CHUNK: u32 = 100;
+
+const USAGE: &str = "usage: nsfplay <file.nsf> -o <out.wav> [--track N] [--seconds S] [--rate HZ] [--float] [--pal] [--vgm <log.vgm>]";
+
+struct Options {
+    input: PathBuf,
+    output: PathBuf,
+    // Log of the sound register writes
+    vgm: Option<PathBuf>,
+    // 1 based, the starting song of the file when missing
+    track: Option<u8>,
+    seconds: Option<u32>,
+    sample_rate: u32,
+    format: SampleFormat,
+    pal: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut vgm = None;
+    let mut track = None;
+    let mut seconds = None;
+    let mut sample_rate = DEFAULT_SAMPLE_RATE;
+    let mut format = SampleFormat::Pcm16;
+    let mut pal = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(PathBuf::from(value(arg)?)),
+            "--vgm" => vgm = Some(PathBuf::from(value(arg)?)),
+            "--track" => track = Some(value(arg)?.parse::<u8>().map_err(|_| "invalid track number")?),
+            "--seconds" => seconds = Some(value(arg)?.parse::<u32>().map_err(|_| "invalid duration")?),
+            "--rate" => sample_rate = value(arg)?.parse::<u32>().map_err(|_| "invalid sample rate")?,
+            "--float" => format = SampleFormat::Float32,
+            "--pal" => pal = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => input = Some(PathBuf::from(arg)),
+        }
+    }
+    if track == Some(0) {
+        return Err(String::from("tracks are numbered from 1"));
+    }
+    return Ok(Options {
+        input: input.ok_or("missing input file")?,
+        output: output.ok_or("missing output file")?,
+        vgm,
+        track,
+        seconds,
+        sample_rate,
+        format,
+        pal,
+    });
+}
+
+fn render(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let nsf = Nsf::parse(&std::fs::read(&options.input)?)?;
+    let region = if options.pal || (nsf.pal && !nsf.dual_region) { Region::Pal } else { Region::Ntsc };
+    let track = options.track.map_or(nsf.starting_song, |track| track - 1);
+    if track >= nsf.total_songs {
+        return Err(format!("the file has {} tracks", nsf.total_songs).into());
+    }
+    let info = nsf.tracks.get(usize::from(track)).cloned().unwrap_or_default();
+    println!("{}", [&nsf.title, &nsf.artist, &nsf.copyright].iter().filter(|text| !text.is_empty()).map(|text| text.as_str()).collect::<Vec<_>>().join(" - "));
+    println!("track {}/{}{}", track + 1, nsf.total_songs, info.name.map_or(String::new(), |name| format!(": {}", name)));
+
+    // The NSFe time does not include the fade out that follows it
+    let (duration, fade) = match options.seconds {
+        Some(seconds) => (seconds * 1000, 0),
+        None => (info.duration.unwrap_or(DEFAULT_DURATION), info.fade.unwrap_or(0)),
+    };
+    let mut player = NsfPlayer::build_nsf_player(nsf, region, options.sample_rate)?;
+    if options.vgm.is_some() {
+        player.start_vgm_log();
+    }
+    player.start_track(track)?;
+    let mut wav = WavWriter::create(&options.output, options.sample_rate, 1, options.format)?;
+
+    let total = duration + fade;
+    let mut elapsed = 0;
+    let mut samples = Vec::new();
+    while elapsed < total {
+        let chunk = CHUNK.min(total - elapsed);
+        let sample_duration = 1000.0 / f64::from(options.sample_rate);
+        samples.clear();
+        for (index, sample) in player.run_for(chunk).iter().enumerate() {
+            let time = f64::from(elapsed) + index as f64 * sample_duration;
+            let gain = if time < f64::from(duration) { 1.0 } else { 1.0 - (time - f64::from(duration)) / f64::from(fade) };
+            samples.push(sample * gain.max(0.0) as f32);
+        }
+        wav.write_samples(&samples)?;
+        elapsed += chunk;
+    }
+    wav.finish()?;
+    if let (Some(path), Some(log)) = (&options.vgm, player.stop_vgm_log()) {
+        std::fs::write(path, log)?;
+    }
+    println!("wrote {:.1} s to {}", f64::from(total) / 1000.0, options.output.display());
+    return Ok(());
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(error) = render(&options) {
+        eprintln!("nsfplay: {}", error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,71 @@
+use std::{path::PathBuf, process};
+
+use cpu6502emu::{
+    apu::audio_output::DEFAULT_SAMPLE_RATE,
+    audio::wav::{SampleFormat, WavWriter},
+    vgm::player::VgmPlayer,
+};
+
+// Audio is rendered in chunks of this many milliseconds
+const CHUNK: u32 = 100;
+
+const USAGE: &str = "usage: vgmrender <file.vgm> -o <out.wav> [--rate HZ] [--float]";
+
+struct Options {
+    input: PathBuf,
+    output: PathBuf,
+    sample_rate: u32,
+    format: SampleFormat,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut sample_rate = DEFAULT_SAMPLE_RATE;
+    let mut format = SampleFormat::Pcm16;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(PathBuf::from(value(arg)?)),
+            "--rate" => sample_rate = value(arg)?.parse::<u32>().map_err(|_| "invalid sample rate")?,
+            "--float" => format = SampleFormat::Float32,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => input = Some(PathBuf::from(arg)),
+        }
+    }
+    return Ok(Options {
+        input: input.ok_or("missing input file")?,
+        output: output.ok_or("missing output file")?,
+        sample_rate,
+        format,
+    });
+}
+
+// Renders a VGM log of the NES APU with our own chips and mixer, to compare with a recording
+fn render(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let mut player = VgmPlayer::build_vgm_player(&std::fs::read(&options.input)?, options.sample_rate)?;
+    let mut wav = WavWriter::create(&options.output, options.sample_rate, 1, options.format)?;
+    while !player.is_finished() {
+        wav.write_samples(player.run_for(CHUNK))?;
+    }
+    wav.finish()?;
+    println!("wrote {:.1} s to {}", f64::from(player.duration()) / 1000.0, options.output.display());
+    return Ok(());
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(error) = render(&options) {
+        eprintln!("vgmrender: {}", error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::cpu::{Byte, Word};
+
+use super::{CartridgeError, Header, Mirroring, mappers};
+use super::mappers::mmc3::IrqRevision;
+
+// Board logic sitting between the console and the cartridge memories. The CPU sees $4020-$FFFF
+// through it and the PPU sees the pattern tables at $0000-$1FFF.
+pub trait Mapper {
+    // What a CPU read would return, without its side effects, for debuggers and memory dumps
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte;
+    // Boards with registers that change when read override this, the others only peek
+    fn cpu_read(&mut self, address: Word, open_bus: Byte) -> Byte {
+        return self.cpu_peek(address, open_bus);
+    }
+    fn cpu_write(&mut self, address: Word, data: Byte);
+    fn ppu_read(&mut self, address: Word) -> Byte;
+    fn ppu_write(&mut self, address: Word, data: Byte);
+    fn mirroring(&self) -> Mirroring;
+    fn memory(&self) -> &CartridgeMemory;
+    fn memory_mut(&mut self) -> &mut CartridgeMemory;
+    // Nametable accesses at $2000-$3EFF. The console only has 2 KiB of nametable RAM (CIRAM),
+    // boards bring their own memory or rearrange it by overriding these.
+    fn read_nametable(&mut self, address: Word, ciram: &[Byte]) -> Byte {
+        return ciram[self.mirroring().nametable_offset(address)];
+    }
+    fn write_nametable(&mut self, address: Word, data: Byte, ciram: &mut [Byte]) {
+        ciram[self.mirroring().nametable_offset(address)] = data;
+    }
+    // CPU writes to the PPU registers, for boards that snoop them on the data bus
+    fn ppu_register_write(&mut self, _address: Word, _data: Byte) {}
+    // Called once per CPU cycle, for boards that track time or bus activity
+    fn cpu_clock(&mut self) {}
+    // Expansion audio level, on the scale of the 2A03 mixer output (0.0 to 1.0)
+    fn audio_output(&self) -> f32 {
+        return 0.0;
+    }
+    // Level of the cartridge /IRQ output, true when an interrupt is being requested
+    fn irq(&self) -> bool {
+        return false;
+    }
+}
+
+// ROM and RAM chips of the board, with helpers to address them in banks of any size.
+// Bank numbers wrap around the chip size like the missing high address lines would.
+pub struct CartridgeMemory {
+    pub prg_rom: Vec<Byte>,
+    pub chr: Vec<Byte>,
+    pub chr_is_ram: bool,
+    pub prg_ram: Vec<Byte>,
+    pub mirroring: Mirroring,
+}
+
+impl CartridgeMemory {
+    pub fn prg_bank_count(&self, bank_size: usize) -> usize {
+        return (self.prg_rom.len() / bank_size).max(1);
+    }
+
+    pub fn chr_bank_count(&self, bank_size: usize) -> usize {
+        return (self.chr.len() / bank_size).max(1);
+    }
+
+    pub fn read_prg(&self, bank: usize, bank_size: usize, address: Word) -> Byte {
+        let offset = (bank % self.prg_bank_count(bank_size)) * bank_size + usize::from(address) % bank_size;
+        return self.prg_rom[offset % self.prg_rom.len()];
+    }
+
+    pub fn read_chr(&self, bank: usize, bank_size: usize, address: Word) -> Byte {
+        return self.chr[self.chr_offset(bank, bank_size, address)];
+    }
+
+    // CHR ROM ignores writes
+    pub fn write_chr(&mut self, bank: usize, bank_size: usize, address: Word, data: Byte) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(bank, bank_size, address);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn chr_offset(&self, bank: usize, bank_size: usize, address: Word) -> usize {
+        let offset = (bank % self.chr_bank_count(bank_size)) * bank_size + usize::from(address) % bank_size;
+        return offset % self.chr.len();
+    }
+
+    // PRG RAM at $6000-$7FFF
+    pub fn read_prg_ram(&self, address: Word) -> Byte {
+        return self.prg_ram[usize::from(address - 0x6000) % self.prg_ram.len()];
+    }
+
+    pub fn write_prg_ram(&mut self, address: Word, data: Byte) {
+        let index = usize::from(address - 0x6000) % self.prg_ram.len();
+        self.prg_ram[index] = data;
+    }
+}
+
+// Boards without logic to prevent it let the ROM drive the data bus during writes,
+// so the latched value is the AND of the CPU data and the ROM byte at that address.
+pub fn bus_conflict(data: Byte, rom_data: Byte) -> Byte {
+    return data & rom_data;
+}
+
+// NES 2.0 submappers 1 and 2 of the discrete boards tell whether the board has bus conflicts,
+// otherwise the behaviour of the most common board is used.
+pub fn has_bus_conflicts(header: &Header, board_default: bool) -> bool {
+    match header.submapper {
+        1 => false,
+        2 => true,
+        _ => board_default,
+    }
+}
+
+pub fn build_mapper(header: &Header, memory: CartridgeMemory) -> Result<Box<dyn Mapper>, CartridgeError> {
+    match header.mapper {
+        0 => Ok(Box::new(mappers::nrom::Nrom::build_nrom(memory))),
+        1 => Ok(Box::new(mappers::mmc1::Mmc1::build_mmc1(memory))),
+        2 => Ok(Box::new(mappers::uxrom::Uxrom::build_uxrom(memory, has_bus_conflicts(header, true)))),
+        3 => Ok(Box::new(mappers::cnrom::Cnrom::build_cnrom(memory, has_bus_conflicts(header, true)))),
+        4 => {
+            // Submapper 4 marks the boards with the older MMC3A from NEC
+            let revision = if header.submapper == 4 { IrqRevision::Nec } else { IrqRevision::Sharp };
+            return Ok(Box::new(mappers::mmc3::Mmc3::build_mmc3(memory, revision)));
+        }
+        5 => {
+            // iNES images do not tell the RAM size, give them the largest a board can have
+            let mut memory = memory;
+            if !header.nes2 {
+                memory.prg_ram.resize(mappers::mmc5::MAX_PRG_RAM_SIZE, 0x00);
+            }
+            return Ok(Box::new(mappers::mmc5::Mmc5::build_mmc5(memory)));
+        }
+        7 => Ok(Box::new(mappers::axrom::Axrom::build_axrom(memory, has_bus_conflicts(header, false)))),
+        9 => Ok(Box::new(mappers::mmc2::Mmc2::build_mmc2(memory))),
+        10 => Ok(Box::new(mappers::mmc2::Mmc2::build_mmc4(memory))),
+        11 => Ok(Box::new(mappers::color_dreams::ColorDreams::build_color_dreams(memory, has_bus_conflicts(header, true)))),
+        19 => Ok(Box::new(mappers::namco163::Namco163::build_namco163(memory))),
+        21 | 22 | 23 | 25 => Ok(Box::new(mappers::vrc2_4::Vrc24::build_vrc2_4(memory, header.mapper, header.submapper))),
+        24 | 26 => Ok(Box::new(mappers::vrc6::Vrc6::build_vrc6(memory, header.mapper))),
+        34 => {
+            // NES 2.0 tells the boards apart, for iNES images only NINA-001 has CHR ROM banks
+            let nina = match header.submapper {
+                1 => true,
+                2 => false,
+                _ => header.chr_rom_size > 0x2000,
+            };
+            if nina {
+                return Ok(Box::new(mappers::nina001::Nina001::build_nina001(memory)));
+            }
+            return Ok(Box::new(mappers::bnrom::Bnrom::build_bnrom(memory, true)));
+        }
+        66 => Ok(Box::new(mappers::gxrom::Gxrom::build_gxrom(memory, has_bus_conflicts(header, true)))),
+        69 => Ok(Box::new(mappers::fme7::Fme7::build_fme7(memory))),
+        71 => Ok(Box::new(mappers::camerica::Camerica::build_camerica(memory))),
+        85 => Ok(Box::new(mappers::vrc7::Vrc7::build_vrc7(memory, header.submapper))),
+        mapper => Err(CartridgeError::UnsupportedMapper(mapper)),
+    }
+}
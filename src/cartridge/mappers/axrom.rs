@@ -0,0 +1,79 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper, bus_conflict};
+use crate::cpu::{Byte, Word};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+// Mapper 7: a switchable 32 KiB PRG bank at $8000 and single screen mirroring.
+// Writes to $8000-$FFFF: bits 0-2 select the PRG bank, bit 4 the nametable.
+// ANROM and AOROM boards avoid bus conflicts, AMROM does not (submapper 2).
+pub struct Axrom {
+    memory: CartridgeMemory,
+    bus_conflicts: bool,
+    prg_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl Axrom {
+    pub fn build_axrom(memory: CartridgeMemory, bus_conflicts: bool) -> Axrom {
+        return Axrom { memory, bus_conflicts, prg_bank: 0, mirroring: Mirroring::SingleScreenLower };
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x8000..=0xFFFF => self.memory.read_prg(self.prg_bank, PRG_BANK_SIZE, address - 0x8000),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        if let 0x8000..=0xFFFF = address {
+            let mut data = data;
+            if self.bus_conflicts {
+                data = bus_conflict(data, self.cpu_read(address, data));
+            }
+            self.prg_bank = usize::from(data & 0x07);
+            self.mirroring = if data & 0x10 != 0 { Mirroring::SingleScreenUpper } else { Mirroring::SingleScreenLower };
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        return self.memory.read_chr(0, 0x2000, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.memory.write_chr(0, 0x2000, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, Mirroring, tests::build_ines};
+
+    #[test]
+    fn test_axrom_banks_and_mirroring() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(8, 0, 0x70, 0x00)).unwrap();
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenLower);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x00);
+        cartridge.cpu_write(0x8000, 0x12);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x04);
+        assert_eq!(cartridge.cpu_read(0xFFFF, 0x00), 0x05);
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenUpper);
+        // No PRG RAM on AxROM boards
+        assert_eq!(cartridge.cpu_read(0x6000, 0x66), 0x66);
+    }
+}
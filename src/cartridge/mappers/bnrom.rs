@@ -0,0 +1,83 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper, bus_conflict};
+use crate::cpu::{Byte, Word};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+// Mapper 34 on BNROM boards: writes to $8000-$FFFF select a 32 KiB PRG bank, conflicting with
+// the PRG ROM. CHR is 8 KiB of unbanked RAM.
+pub struct Bnrom {
+    memory: CartridgeMemory,
+    bus_conflicts: bool,
+    prg_bank: usize,
+}
+
+impl Bnrom {
+    pub fn build_bnrom(memory: CartridgeMemory, bus_conflicts: bool) -> Bnrom {
+        return Bnrom { memory, bus_conflicts, prg_bank: 0 };
+    }
+}
+
+impl Mapper for Bnrom {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF => self.memory.read_prg_ram(address),
+            0x8000..=0xFFFF => self.memory.read_prg(self.prg_bank, PRG_BANK_SIZE, address),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        match address {
+            0x6000..=0x7FFF => self.memory.write_prg_ram(address, data),
+            0x8000..=0xFFFF => {
+                let mut data = data;
+                if self.bus_conflicts {
+                    data = bus_conflict(data, self.cpu_read(address, data));
+                }
+                self.prg_bank = usize::from(data);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        return self.memory.read_chr(0, 0x2000, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.memory.write_chr(0, 0x2000, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.memory.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, tests::build_ines};
+
+    #[test]
+    fn test_bnrom_banking() {
+        // 8 x 32 KiB PRG, CHR RAM
+        let mut cartridge = Cartridge::from_ines(&build_ines(16, 0, 0x20, 0x20)).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x00);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x01);
+        // Bank 0 reads $01 at $C000: the write conflicts
+        cartridge.cpu_write(0xC000, 0x06);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x00);
+        cartridge.cpu_write(0xC000, 0x07);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x02);
+        cartridge.ppu_write(0x1234, 0x42);
+        assert_eq!(cartridge.ppu_read(0x1234), 0x42);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+// Mapper 71: Camerica and Codemasters BF909x boards. Writes to $C000-$FFFF select the 16 KiB
+// PRG bank at $8000, the last bank is fixed at $C000. The Fire Hawk board (BF9097, submapper 1)
+// also selects a one screen mirroring with bit 4 of writes to $8000-$9FFF. No other game writes
+// to that range, so it is decoded for every board and images without a submapper work too.
+pub struct Camerica {
+    memory: CartridgeMemory,
+    prg_bank: usize,
+    // One screen mirroring once selected by the Fire Hawk register
+    mirroring: Option<Mirroring>,
+}
+
+impl Camerica {
+    pub fn build_camerica(memory: CartridgeMemory) -> Camerica {
+        return Camerica { memory, prg_bank: 0, mirroring: None };
+    }
+}
+
+impl Mapper for Camerica {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF => self.memory.read_prg_ram(address),
+            0x8000..=0xBFFF => self.memory.read_prg(self.prg_bank, PRG_BANK_SIZE, address),
+            0xC000..=0xFFFF => self.memory.read_prg(self.memory.prg_bank_count(PRG_BANK_SIZE) - 1, PRG_BANK_SIZE, address),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        match address {
+            0x6000..=0x7FFF => self.memory.write_prg_ram(address, data),
+            0x8000..=0x9FFF => {
+                let screen = if data & 0x10 != 0 { Mirroring::SingleScreenUpper } else { Mirroring::SingleScreenLower };
+                self.mirroring = Some(screen);
+            }
+            0xC000..=0xFFFF => self.prg_bank = usize::from(data & 0x0F),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        return self.memory.read_chr(0, 0x2000, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.memory.write_chr(0, 0x2000, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring.unwrap_or(self.memory.mirroring);
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, Mirroring, tests::build_ines};
+
+    fn build_camerica_cartridge(submapper: u8) -> Cartridge {
+        // Mapper 71, 8 x 16 KiB PRG, CHR RAM, vertical mirroring
+        let mut image = build_ines(8, 0, 0x71, 0x48);
+        image[8] = submapper << 4;
+        return Cartridge::from_ines(&image).unwrap();
+    }
+
+    #[test]
+    fn test_camerica_banking() {
+        let mut cartridge = build_camerica_cartridge(0);
+        cartridge.cpu_write(0xC000, 0x13);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x03);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x07);
+        // $A000-$BFFF is not decoded
+        cartridge.cpu_write(0xA000, 0x05);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x03);
+    }
+
+    #[test]
+    fn test_camerica_fire_hawk_mirroring() {
+        let mut cartridge = build_camerica_cartridge(1);
+        assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+        cartridge.cpu_write(0x9000, 0x10);
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenUpper);
+        cartridge.cpu_write(0x8000, 0x00);
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenLower);
+    }
+}
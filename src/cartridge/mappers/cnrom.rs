@@ -0,0 +1,89 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper, bus_conflict};
+use crate::cpu::{Byte, Word};
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+// Mapper 3: fixed PRG ROM like NROM and a switchable 8 KiB CHR bank.
+// Writes to $8000-$FFFF select the bank, conflicting with the PRG ROM on most boards.
+pub struct Cnrom {
+    memory: CartridgeMemory,
+    bus_conflicts: bool,
+    chr_bank: usize,
+}
+
+impl Cnrom {
+    pub fn build_cnrom(memory: CartridgeMemory, bus_conflicts: bool) -> Cnrom {
+        return Cnrom { memory, bus_conflicts, chr_bank: 0 };
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF => self.memory.read_prg_ram(address),
+            0x8000..=0xFFFF => self.memory.read_prg(0, 0x8000, address - 0x8000),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        match address {
+            0x6000..=0x7FFF => self.memory.write_prg_ram(address, data),
+            0x8000..=0xFFFF => {
+                let mut data = data;
+                if self.bus_conflicts {
+                    data = bus_conflict(data, self.cpu_read(address, data));
+                }
+                self.chr_bank = usize::from(data);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        return self.memory.read_chr(self.chr_bank, CHR_BANK_SIZE, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.memory.write_chr(self.chr_bank, CHR_BANK_SIZE, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.memory.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, tests::build_ines};
+
+    #[test]
+    fn test_cnrom_switches_chr_bank() {
+        let mut image = build_ines(2, 4, 0x30, 0x08);
+        image[8] = 0x10;
+        let mut cartridge = Cartridge::from_ines(&image).unwrap();
+        assert_eq!(cartridge.ppu_read(0x0000), 0x80);
+        cartridge.cpu_write(0x8000, 0x02);
+        assert_eq!(cartridge.ppu_read(0x1FFF), 0x82);
+        // Bank numbers wrap around the CHR size
+        cartridge.cpu_write(0x8000, 0x07);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x83);
+    }
+
+    #[test]
+    fn test_cnrom_bus_conflict() {
+        // The second PRG bank is filled with $01 so only bit 0 survives a write there
+        let mut cartridge = Cartridge::from_ines(&build_ines(2, 4, 0x30, 0x00)).unwrap();
+        cartridge.cpu_write(0xC000, 0x03);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x81);
+    }
+}
@@ -0,0 +1,454 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+// The sound generators are clocked every 16 CPU cycles
+const AUDIO_DIVIDER: u8 = 16;
+// A 5B channel at full volume is about as loud as a 2A03 pulse at full volume
+const CHANNEL_SCALE: f32 = 0.149;
+
+// Square channel of the 5B, toggling every `period` ticks of the divider
+struct ToneChannel {
+    period: Word,
+    counter: Word,
+    high: bool,
+}
+
+impl ToneChannel {
+    fn build_tone_channel() -> ToneChannel {
+        return ToneChannel {
+            period: 0,
+            counter: 0,
+            high: false,
+        }
+    }
+
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter >= self.period {
+            self.counter = 0;
+            self.high = !self.high;
+        }
+    }
+}
+
+// Sunsoft 5B: the FME-7 with a YM2149F (AY-3-8910 compatible) PSG. Three square channels that
+// can be mixed with a noise generator, with a 4 bit logarithmic volume or a shared envelope.
+// https://www.nesdev.org/wiki/Sunsoft_5B_audio
+pub struct Sunsoft5b {
+    address: Byte,
+    tones: [ToneChannel; 3],
+    noise_period: Byte,
+    noise_counter: Byte,
+    // 17 bit LFSR
+    noise_shift: u32,
+    // Register 7: --NNNTTT, a set bit disables the noise or tone of a channel
+    mixer: Byte,
+    // Registers 8-A: ---EVVVV, E selects the envelope instead of the fixed volume
+    volumes: [Byte; 3],
+    envelope_period: Word,
+    envelope_counter: Word,
+    // Register D: CAtH continue, attack, alternate, hold
+    envelope_shape: Byte,
+    envelope_step: Byte,
+    envelope_attack: bool,
+    envelope_holding: bool,
+    divider: u8,
+}
+
+impl Sunsoft5b {
+    pub fn build_sunsoft_5b() -> Sunsoft5b {
+        return Sunsoft5b {
+            address: 0,
+            tones: [ToneChannel::build_tone_channel(), ToneChannel::build_tone_channel(), ToneChannel::build_tone_channel()],
+            noise_period: 0,
+            noise_counter: 0,
+            noise_shift: 1,
+            mixer: 0,
+            volumes: [0; 3],
+            envelope_period: 0,
+            envelope_counter: 0,
+            envelope_shape: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: true,
+            divider: 0,
+        }
+    }
+
+    pub fn write_address(&mut self, data: Byte) {
+        self.address = data;
+    }
+
+    pub fn write_data(&mut self, data: Byte) {
+        match self.address {
+            0x00 | 0x02 | 0x04 => {
+                let tone = &mut self.tones[usize::from(self.address / 2)];
+                tone.period = (tone.period & 0x0F00) | Word::from(data);
+            }
+            0x01 | 0x03 | 0x05 => {
+                let tone = &mut self.tones[usize::from(self.address / 2)];
+                tone.period = (tone.period & 0x00FF) | (Word::from(data & 0x0F) << 8);
+            }
+            0x06 => self.noise_period = data & 0x1F,
+            0x07 => self.mixer = data,
+            0x08..=0x0A => self.volumes[usize::from(self.address - 0x08)] = data & 0x1F,
+            0x0B => self.envelope_period = (self.envelope_period & 0xFF00) | Word::from(data),
+            0x0C => self.envelope_period = (self.envelope_period & 0x00FF) | (Word::from(data) << 8),
+            0x0D => {
+                // Writing the shape restarts the envelope
+                self.envelope_shape = data & 0x0F;
+                self.envelope_step = 0;
+                self.envelope_counter = 0;
+                self.envelope_attack = data & 0x04 != 0;
+                self.envelope_holding = false;
+            }
+            _ => {}
+        }
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < AUDIO_DIVIDER {
+            return;
+        }
+        self.divider = 0;
+        for tone in self.tones.iter_mut() {
+            tone.clock();
+        }
+        self.clock_noise();
+        self.clock_envelope();
+    }
+
+    fn clock_noise(&mut self) {
+        self.noise_counter += 1;
+        if self.noise_counter >= self.noise_period {
+            self.noise_counter = 0;
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 0x01;
+            self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        self.envelope_counter += 1;
+        if self.envelope_counter < self.envelope_period {
+            return;
+        }
+        self.envelope_counter = 0;
+        self.envelope_step += 1;
+        if self.envelope_step < 32 {
+            return;
+        }
+        let continues = self.envelope_shape & 0x08 != 0;
+        let alternate = self.envelope_shape & 0x02 != 0;
+        let hold = self.envelope_shape & 0x01 != 0;
+        if !continues {
+            // Shapes 0-7 end silent
+            self.envelope_step = 31;
+            self.envelope_attack = false;
+            self.envelope_holding = true;
+        } else if hold {
+            self.envelope_step = 31;
+            self.envelope_attack ^= alternate;
+            self.envelope_holding = true;
+        } else {
+            self.envelope_step = 0;
+            self.envelope_attack ^= alternate;
+        }
+    }
+
+    fn envelope_level(&self) -> Byte {
+        if self.envelope_attack {
+            return self.envelope_step;
+        }
+        return 31 - self.envelope_step;
+    }
+
+    // Level of a 5 bit volume index, 1.5 dB per step
+    fn level(index: Byte) -> f32 {
+        if index == 0 {
+            return 0.0;
+        }
+        return f32::powf(10.0, -f32::from(31 - index) * 1.5 / 20.0);
+    }
+
+    pub fn output(&self) -> f32 {
+        let noise = self.noise_shift & 0x01 != 0;
+        let mut output = 0.0;
+        for (index, tone) in self.tones.iter().enumerate() {
+            let tone_enabled = self.mixer & (0x01 << index) == 0;
+            let noise_enabled = self.mixer & (0x08 << index) == 0;
+            if (tone_enabled && !tone.high) || (noise_enabled && !noise) {
+                continue;
+            }
+            let volume = self.volumes[index];
+            let level = if volume & 0x10 != 0 {
+                self.envelope_level()
+            } else if volume == 0 {
+                0
+            } else {
+                volume * 2 + 1
+            };
+            output += Sunsoft5b::level(level);
+        }
+        return output * CHANNEL_SCALE;
+    }
+}
+
+// Sunsoft FME-7 and 5B: https://www.nesdev.org/wiki/Sunsoft_FME-7
+// A command register at $8000 selects which bank, mirroring or IRQ setting the parameter written
+// at $A000 goes to. Four 8 KiB PRG windows including $6000, eight 1 KiB CHR banks and a 16 bit
+// IRQ counter decremented every CPU cycle.
+pub struct Fme7 {
+    memory: CartridgeMemory,
+    command: Byte,
+    chr_banks: [Byte; 8],
+    // Command 8: ERBB BBBB, E RAM enable, R RAM instead of ROM at $6000
+    prg_bank_6000: Byte,
+    prg_banks: [Byte; 3],
+    mirroring: Mirroring,
+    irq_enabled: bool,
+    counter_enabled: bool,
+    counter: Word,
+    irq_pending: bool,
+    audio: Sunsoft5b,
+}
+
+impl Fme7 {
+    pub fn build_fme7(memory: CartridgeMemory) -> Fme7 {
+        return Fme7 {
+            memory,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_bank_6000: 0,
+            prg_banks: [0; 3],
+            mirroring: Mirroring::Vertical,
+            irq_enabled: false,
+            counter_enabled: false,
+            counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5b::build_sunsoft_5b(),
+        };
+    }
+
+    fn write_parameter(&mut self, data: Byte) {
+        match self.command {
+            0x00..=0x07 => self.chr_banks[usize::from(self.command)] = data,
+            0x08 => self.prg_bank_6000 = data,
+            0x09..=0x0B => self.prg_banks[usize::from(self.command - 0x09)] = data,
+            0x0C => {
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            // Any write to the control acknowledges the IRQ
+            0x0D => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.counter_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x0E => self.counter = (self.counter & 0xFF00) | Word::from(data),
+            _ => self.counter = (self.counter & 0x00FF) | (Word::from(data) << 8),
+        }
+    }
+
+    fn prg_ram_selected(&self) -> bool {
+        return self.prg_bank_6000 & 0x40 != 0;
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        return self.prg_bank_6000 & 0x80 != 0;
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_selected() && self.prg_ram_enabled() => self.memory.read_prg_ram(address),
+            0x6000..=0x7FFF if self.prg_ram_selected() => open_bus,
+            0x6000..=0x7FFF => self.memory.read_prg(usize::from(self.prg_bank_6000 & 0x3F), PRG_BANK_SIZE, address),
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[usize::from((address - 0x8000) / 0x2000)];
+                self.memory.read_prg(usize::from(bank & 0x3F), PRG_BANK_SIZE, address)
+            }
+            0xE000..=0xFFFF => self.memory.read_prg(self.memory.prg_bank_count(PRG_BANK_SIZE) - 1, PRG_BANK_SIZE, address),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_selected() && self.prg_ram_enabled() => self.memory.write_prg_ram(address, data),
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            0xC000..=0xDFFF => self.audio.write_address(data),
+            0xE000..=0xFFFF => self.audio.write_data(data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        let bank = self.chr_banks[usize::from(address / 0x400)];
+        return self.memory.read_chr(usize::from(bank), CHR_BANK_SIZE, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        let bank = self.chr_banks[usize::from(address / 0x400)];
+        self.memory.write_chr(usize::from(bank), CHR_BANK_SIZE, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.counter_enabled {
+            self.counter = self.counter.wrapping_sub(1);
+            if self.counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        return self.audio.output();
+    }
+
+    fn irq(&self) -> bool {
+        return self.irq_pending;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, Mirroring, tests::build_ines};
+
+    // 8 x 16 KiB PRG, 4 x 8 KiB CHR
+    fn build_fme7_cartridge() -> Cartridge {
+        return Cartridge::from_ines(&build_ines(8, 4, 0x50, 0x40)).unwrap();
+    }
+
+    fn command(cartridge: &mut Cartridge, command: u8, parameter: u8) {
+        cartridge.cpu_write(0x8000, command);
+        cartridge.cpu_write(0xA000, parameter);
+    }
+
+    #[test]
+    fn test_fme7_banking() {
+        let mut cartridge = build_fme7_cartridge();
+        command(&mut cartridge, 0x09, 0x02);
+        command(&mut cartridge, 0x0A, 0x05);
+        command(&mut cartridge, 0x0B, 0x09);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x01);
+        assert_eq!(cartridge.cpu_read(0xA000, 0x00), 0x02);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x04);
+        assert_eq!(cartridge.cpu_read(0xE000, 0x00), 0x07);
+        command(&mut cartridge, 0x05, 0x1B);
+        assert_eq!(cartridge.ppu_read(0x1400), 0x83);
+        command(&mut cartridge, 0x0C, 0x01);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_fme7_prg_ram() {
+        let mut cartridge = build_fme7_cartridge();
+        // ROM bank 6 at $6000
+        command(&mut cartridge, 0x08, 0x06);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x00), 0x03);
+        // RAM selected but disabled
+        command(&mut cartridge, 0x08, 0x40);
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000, 0xFF), 0xFF);
+        command(&mut cartridge, 0x08, 0xC0);
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000, 0xFF), 0x42);
+    }
+
+    #[test]
+    fn test_fme7_irq() {
+        let mut cartridge = build_fme7_cartridge();
+        command(&mut cartridge, 0x0E, 0x02);
+        command(&mut cartridge, 0x0F, 0x00);
+        command(&mut cartridge, 0x0D, 0x81);
+        for _ in 0..2 {
+            cartridge.cpu_clock();
+            assert!(!cartridge.irq());
+        }
+        // Raised when the counter wraps from 0 to $FFFF
+        cartridge.cpu_clock();
+        assert!(cartridge.irq());
+        command(&mut cartridge, 0x0D, 0x81);
+        assert!(!cartridge.irq());
+    }
+
+    #[test]
+    fn test_sunsoft_5b_audio() {
+        let mut cartridge = build_fme7_cartridge();
+        let mut audio = |register: u8, data: u8| {
+            cartridge.cpu_write(0xC000, register);
+            cartridge.cpu_write(0xE000, data);
+        };
+        // Channel A tone only, period 2, full volume
+        audio(0x00, 0x02);
+        audio(0x07, 0x3E);
+        audio(0x08, 0x0F);
+        let mut levels = vec![];
+        for _ in 0..16 * 8 {
+            cartridge.cpu_clock();
+            levels.push(cartridge.audio_output());
+        }
+        // Toggles every 32 CPU cycles between silence and the full level
+        assert_eq!(levels[16 * 2 - 2], levels[0]);
+        assert_ne!(levels[16 * 2 - 1], levels[0]);
+        let peak = levels.iter().cloned().fold(0.0, f32::max);
+        assert!((peak - 0.149).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_sunsoft_5b_envelope() {
+        let mut audio = super::Sunsoft5b::build_sunsoft_5b();
+        // Tone and noise off: the channel outputs its level
+        audio.write_address(0x07);
+        audio.write_data(0x3F);
+        audio.write_address(0x08);
+        audio.write_data(0x10);
+        audio.write_address(0x0B);
+        audio.write_data(0x01);
+        // Shape 0: decay then silence
+        audio.write_address(0x0D);
+        audio.write_data(0x00);
+        let start = audio.output();
+        for _ in 0..16 * 16 {
+            audio.clock();
+        }
+        assert!(audio.output() < start);
+        for _ in 0..16 * 32 {
+            audio.clock();
+        }
+        assert_eq!(audio.output(), 0.0);
+        // Shape 14: triangle, rising again after reaching the bottom
+        audio.write_data(0x0E);
+        for _ in 0..16 * 40 {
+            audio.clock();
+        }
+        assert!(audio.output() > 0.0);
+    }
+}
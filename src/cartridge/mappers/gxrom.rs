@@ -0,0 +1,92 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper, bus_conflict};
+use crate::cpu::{Byte, Word};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+// Mapper 66: GxROM and MxROM. Writes to $8000-$FFFF select a 32 KiB PRG bank in bits 4-5 and an
+// 8 KiB CHR bank in bits 0-1, conflicting with the PRG ROM.
+pub struct Gxrom {
+    memory: CartridgeMemory,
+    bus_conflicts: bool,
+    prg_bank: usize,
+    chr_bank: usize,
+}
+
+impl Gxrom {
+    pub fn build_gxrom(memory: CartridgeMemory, bus_conflicts: bool) -> Gxrom {
+        return Gxrom { memory, bus_conflicts, prg_bank: 0, chr_bank: 0 };
+    }
+}
+
+impl Mapper for Gxrom {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF => self.memory.read_prg_ram(address),
+            0x8000..=0xFFFF => self.memory.read_prg(self.prg_bank, PRG_BANK_SIZE, address),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        match address {
+            0x6000..=0x7FFF => self.memory.write_prg_ram(address, data),
+            0x8000..=0xFFFF => {
+                let mut data = data;
+                if self.bus_conflicts {
+                    data = bus_conflict(data, self.cpu_read(address, data));
+                }
+                self.prg_bank = usize::from((data >> 4) & 0x03);
+                self.chr_bank = usize::from(data & 0x03);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        return self.memory.read_chr(self.chr_bank, CHR_BANK_SIZE, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.memory.write_chr(self.chr_bank, CHR_BANK_SIZE, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.memory.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, tests::build_ines};
+
+    #[test]
+    fn test_gxrom_banking() {
+        // 4 x 32 KiB PRG, 4 x 8 KiB CHR, no bus conflicts
+        let mut image = build_ines(8, 4, 0x20, 0x48);
+        image[8] = 0x10;
+        let mut cartridge = Cartridge::from_ines(&image).unwrap();
+        cartridge.cpu_write(0x8000, 0x13);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x02);
+        assert_eq!(cartridge.cpu_read(0xFFFF, 0x00), 0x03);
+        assert_eq!(cartridge.ppu_read(0x1FFF), 0x83);
+    }
+
+    #[test]
+    fn test_gxrom_bus_conflict() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(8, 4, 0x20, 0x40)).unwrap();
+        // Bank 0 reads $01 at $C000
+        cartridge.cpu_write(0xC000, 0x33);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x00);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x81);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+// Boards with 512 KiB of PRG ROM (SUROM, SXROM) use CHR register bit 4 to pick the 256 KiB half
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
+
+// Mapper 1: registers are loaded one bit at a time through a 5 bit shift register.
+// Each write to $8000-$FFFF shifts in bit 0, the fifth write copies the value to the register
+// selected by address bits 13-14. Writing a value with bit 7 set resets the shift register.
+// Details on https://www.nesdev.org/wiki/MMC1
+pub struct Mmc1 {
+    memory: CartridgeMemory,
+    shift_register: Byte,
+    shift_count: u8,
+    // 43210
+    // |||++- Mirroring: 0 one screen lower, 1 one screen upper, 2 vertical, 3 horizontal
+    // |++--- PRG mode: 0/1 32 KiB, 2 fix first bank at $8000, 3 fix last bank at $C000
+    // +----- CHR mode: 0 one 8 KiB bank, 1 two 4 KiB banks
+    control: Byte,
+    chr_bank_0: Byte,
+    chr_bank_1: Byte,
+    prg_bank: Byte,
+    cycle: u64,
+    last_write_cycle: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn build_mmc1(memory: CartridgeMemory) -> Mmc1 {
+        return Mmc1 {
+            memory,
+            shift_register: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write_cycle: None,
+        };
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        return self.prg_bank & 0x10 == 0;
+    }
+
+    fn prg_bank_for(&self, address: Word) -> usize {
+        let bank = usize::from(self.prg_bank & 0x0F);
+        let outer = if self.memory.prg_rom.len() > PRG_OUTER_BANK_SIZE {
+            usize::from(self.chr_bank_0 & 0x10)
+        } else {
+            0
+        };
+        let last_bank = (self.memory.prg_bank_count(PRG_BANK_SIZE) - 1) & 0x0F;
+        let upper = address >= 0xC000;
+        let bank = match (self.control >> 2) & 0x03 {
+            0 | 1 => (bank & 0x0E) | usize::from(upper),
+            2 => if upper { bank } else { 0 },
+            _ => if upper { last_bank } else { bank },
+        };
+        return outer | bank;
+    }
+
+    fn chr_bank_for(&self, address: Word) -> usize {
+        if self.control & 0x10 == 0 {
+            return usize::from(self.chr_bank_0 & 0x1E) | usize::from(address >= 0x1000);
+        }
+        if address < 0x1000 {
+            return usize::from(self.chr_bank_0);
+        }
+        return usize::from(self.chr_bank_1);
+    }
+
+    fn write_register(&mut self, address: Word, data: Byte) {
+        match address {
+            0x8000..=0x9FFF => self.control = data,
+            0xA000..=0xBFFF => self.chr_bank_0 = data,
+            0xC000..=0xDFFF => self.chr_bank_1 = data,
+            _ => self.prg_bank = data,
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.memory.read_prg_ram(address),
+            0x8000..=0xFFFF => self.memory.read_prg(self.prg_bank_for(address), PRG_BANK_SIZE, address),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.memory.write_prg_ram(address, data),
+            0x8000..=0xFFFF => {
+                // The serial port ignores a write on the cycle right after another one, so the
+                // dummy write of read-modify-write instructions is the only one that counts
+                let consecutive = self.last_write_cycle == Some(self.cycle.wrapping_sub(1));
+                self.last_write_cycle = Some(self.cycle);
+                if consecutive {
+                    return;
+                }
+                if data & 0x80 != 0 {
+                    self.shift_register = 0;
+                    self.shift_count = 0;
+                    self.control |= 0x0C;
+                    return;
+                }
+                self.shift_register |= (data & 0x01) << self.shift_count;
+                self.shift_count += 1;
+                if self.shift_count == 5 {
+                    let value = self.shift_register;
+                    self.write_register(address, value);
+                    self.shift_register = 0;
+                    self.shift_count = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        return self.memory.read_chr(self.chr_bank_for(address), CHR_BANK_SIZE, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.memory.write_chr(self.chr_bank_for(address), CHR_BANK_SIZE, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+
+    fn cpu_clock(&mut self) {
+        self.cycle += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, Mirroring, tests::build_ines};
+    use crate::cpu::{Byte, Word};
+
+    fn serial_write(cartridge: &mut Cartridge, address: Word, value: Byte) {
+        for bit in 0..5 {
+            cartridge.cpu_write(address, (value >> bit) & 0x01);
+            cartridge.cpu_clock();
+            cartridge.cpu_clock();
+        }
+    }
+
+    #[test]
+    fn test_mmc1_power_on_fixes_last_bank() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(8, 2, 0x10, 0x00)).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x00);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x07);
+        serial_write(&mut cartridge, 0xE000, 0x03);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x03);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x07);
+    }
+
+    #[test]
+    fn test_mmc1_prg_modes() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(8, 2, 0x10, 0x00)).unwrap();
+        serial_write(&mut cartridge, 0xE000, 0x05);
+        // 32 KiB mode ignores bit 0 of the bank number
+        serial_write(&mut cartridge, 0x8000, 0x00);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x04);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x05);
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenLower);
+        // First bank fixed at $8000
+        serial_write(&mut cartridge, 0x8000, 0x0A);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x00);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x05);
+        assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_mmc1_chr_modes() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(2, 4, 0x10, 0x00)).unwrap();
+        // 4 KiB banks: the 8 KiB CHR banks are filled with $80 | bank
+        serial_write(&mut cartridge, 0x8000, 0x1F);
+        serial_write(&mut cartridge, 0xA000, 0x05);
+        serial_write(&mut cartridge, 0xC000, 0x02);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x82);
+        assert_eq!(cartridge.ppu_read(0x1000), 0x81);
+        // 8 KiB mode ignores bit 0 and the second register
+        serial_write(&mut cartridge, 0x8000, 0x0F);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x82);
+        assert_eq!(cartridge.ppu_read(0x1000), 0x82);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_mmc1_reset_and_consecutive_writes() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(8, 2, 0x10, 0x00)).unwrap();
+        cartridge.cpu_write(0xE000, 0x01);
+        cartridge.cpu_clock();
+        // Ignored, it comes right after the previous write
+        cartridge.cpu_write(0xE000, 0x01);
+        cartridge.cpu_clock();
+        cartridge.cpu_clock();
+        for bit in [0x00, 0x00, 0x00, 0x00] {
+            cartridge.cpu_write(0xE000, bit);
+            cartridge.cpu_clock();
+            cartridge.cpu_clock();
+        }
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x01);
+        // Bit 7 clears the shift register
+        cartridge.cpu_write(0xE000, 0x01);
+        cartridge.cpu_clock();
+        cartridge.cpu_clock();
+        cartridge.cpu_write(0xE000, 0x80);
+        cartridge.cpu_clock();
+        cartridge.cpu_clock();
+        serial_write(&mut cartridge, 0xE000, 0x02);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x02);
+    }
+
+    #[test]
+    fn test_mmc1_battery_prg_ram() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(2, 1, 0x12, 0x00)).unwrap();
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x00), 0x42);
+        assert_eq!(cartridge.battery_ram().unwrap()[0], 0x42);
+        // Disabled through bit 4 of the PRG bank register
+        serial_write(&mut cartridge, 0xE000, 0x10);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x55), 0x55);
+        serial_write(&mut cartridge, 0xE000, 0x00);
+        cartridge.load_battery_ram(&[0x24]);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x00), 0x24);
+    }
+}
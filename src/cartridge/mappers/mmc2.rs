@@ -0,0 +1,173 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+const CHR_BANK_SIZE: usize = 0x1000;
+const LATCH_FD: Byte = 0xFD;
+const LATCH_FE: Byte = 0xFE;
+
+// Nintendo MMC2 (mapper 9) and MMC4 (mapper 10): https://www.nesdev.org/wiki/MMC2
+// Each 4 KiB pattern table has two banks and a latch choosing between them. The latch flips when
+// the PPU fetches tile $FD or $FE, after the fetch, so a game can switch banks mid-screen by
+// placing those tiles. MMC2 switches 8 KiB of PRG ROM at $8000, MMC4 16 KiB.
+pub struct Mmc2 {
+    memory: CartridgeMemory,
+    mmc4: bool,
+    prg_bank: Byte,
+    // Banks selected by the $FD and $FE latch values, for $0000 and $1000
+    chr_banks: [[Byte; 2]; 2],
+    latches: [Byte; 2],
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    pub fn build_mmc2(memory: CartridgeMemory) -> Mmc2 {
+        return Mmc2::build(memory, false);
+    }
+
+    pub fn build_mmc4(memory: CartridgeMemory) -> Mmc2 {
+        return Mmc2::build(memory, true);
+    }
+
+    fn build(memory: CartridgeMemory, mmc4: bool) -> Mmc2 {
+        return Mmc2 {
+            memory,
+            mmc4,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [LATCH_FE, LATCH_FE],
+            mirroring: Mirroring::Vertical,
+        };
+    }
+
+    fn chr_bank_for(&self, address: Word) -> usize {
+        let table = usize::from(address >> 12) & 0x01;
+        let latch = if self.latches[table] == LATCH_FD { 0 } else { 1 };
+        return usize::from(self.chr_banks[table][latch]);
+    }
+
+    // MMC2 only watches the first byte of the tile for the lower pattern table
+    fn update_latch(&mut self, address: Word) {
+        let lower_wide = self.mmc4;
+        match address {
+            0x0FD8 => self.latches[0] = LATCH_FD,
+            0x0FE8 => self.latches[0] = LATCH_FE,
+            0x0FD9..=0x0FDF if lower_wide => self.latches[0] = LATCH_FD,
+            0x0FE9..=0x0FEF if lower_wide => self.latches[0] = LATCH_FE,
+            0x1FD8..=0x1FDF => self.latches[1] = LATCH_FD,
+            0x1FE8..=0x1FEF => self.latches[1] = LATCH_FE,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        let last = self.memory.prg_bank_count(0x2000);
+        match address {
+            0x6000..=0x7FFF => self.memory.read_prg_ram(address),
+            0x8000..=0xBFFF if self.mmc4 => self.memory.read_prg(usize::from(self.prg_bank & 0x0F), 0x4000, address),
+            0x8000..=0x9FFF => self.memory.read_prg(usize::from(self.prg_bank & 0x0F), 0x2000, address),
+            0xA000..=0xFFFF if self.mmc4 => self.memory.read_prg(last / 2 - 1, 0x4000, address),
+            // The last three 8 KiB banks are fixed at $A000-$FFFF
+            0xA000..=0xFFFF => self.memory.read_prg(last - 3 + usize::from((address - 0xA000) / 0x2000), 0x2000, address),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        match address {
+            0x6000..=0x7FFF => self.memory.write_prg_ram(address, data),
+            0xA000..=0xAFFF => self.prg_bank = data,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = data & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = data & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = data & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = data & 0x1F,
+            0xF000..=0xFFFF => {
+                self.mirroring = if data & 0x01 != 0 { Mirroring::Horizontal } else { Mirroring::Vertical };
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        let data = self.memory.read_chr(self.chr_bank_for(address), CHR_BANK_SIZE, address);
+        self.update_latch(address);
+        return data;
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.memory.write_chr(self.chr_bank_for(address), CHR_BANK_SIZE, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, Mirroring, tests::build_ines};
+
+    #[test]
+    fn test_mmc2_prg_banking() {
+        // 8 x 16 KiB PRG, 16 x 8 KiB
+        let mut cartridge = Cartridge::from_ines(&build_ines(8, 4, 0x90, 0x00)).unwrap();
+        cartridge.cpu_write(0xA000, 0x05);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x02);
+        assert_eq!(cartridge.cpu_read(0xA000, 0x00), 0x06);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x07);
+        assert_eq!(cartridge.cpu_read(0xE000, 0x00), 0x07);
+        cartridge.cpu_write(0xF000, 0x01);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_mmc2_latches() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(8, 4, 0x90, 0x00)).unwrap();
+        // 4 KiB banks: FD/0000 = 2, FE/0000 = 4, FD/1000 = 6, FE/1000 = 7
+        cartridge.cpu_write(0xB000, 0x02);
+        cartridge.cpu_write(0xC000, 0x04);
+        cartridge.cpu_write(0xD000, 0x06);
+        cartridge.cpu_write(0xE000, 0x07);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x82);
+        assert_eq!(cartridge.ppu_read(0x1000), 0x83);
+        // The fetch of tile $FD still uses the old bank, the next ones the new one
+        assert_eq!(cartridge.ppu_read(0x0FD8), 0x82);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x81);
+        // Only $0FD8 and $0FE8 trigger the lower latch on MMC2
+        cartridge.ppu_read(0x0FEA);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x81);
+        cartridge.ppu_read(0x0FE8);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x82);
+        // The upper latch accepts the whole tile
+        cartridge.ppu_read(0x1FDB);
+        assert_eq!(cartridge.ppu_read(0x1000), 0x83);
+        cartridge.ppu_read(0x1FDF);
+        cartridge.cpu_write(0xD000, 0x00);
+        assert_eq!(cartridge.ppu_read(0x1000), 0x80);
+    }
+
+    #[test]
+    fn test_mmc4() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(8, 4, 0xA0, 0x00)).unwrap();
+        cartridge.cpu_write(0xA000, 0x05);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x05);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x07);
+        cartridge.cpu_write(0xB000, 0x02);
+        cartridge.cpu_write(0xC000, 0x04);
+        // MMC4 accepts the whole tile for both latches
+        cartridge.ppu_read(0x0FDC);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x81);
+        cartridge.ppu_read(0x0FEF);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x82);
+    }
+}
@@ -0,0 +1,337 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+// A12 has to stay low for this many CPU cycles before a rising edge clocks the counter,
+// which filters out the short low periods between the sprite pattern fetches
+const A12_LOW_CYCLES: u64 = 3;
+
+// Behaviour of the IRQ counter when it gets reloaded with 0.
+// Sharp (MMC3B/C, the "new" behaviour) raises an IRQ on every clock while the counter is 0.
+// NEC (MMC3A, the "old" behaviour) only raises it when the counter decrements to 0 or after a $C001 reload.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum IrqRevision {
+    Sharp,
+    Nec,
+}
+
+// Mapper 4: eight bank registers selected through $8000 and written through $8001.
+// R0-R1 select 2 KiB CHR banks, R2-R5 1 KiB CHR banks, R6-R7 8 KiB PRG banks.
+// The scanline counter is clocked by rising edges of PPU A12, once per line when the
+// background uses $0000 and the sprites $1000.
+// Details on https://www.nesdev.org/wiki/MMC3
+pub struct Mmc3 {
+    memory: CartridgeMemory,
+    // 76543210
+    // ||   +++- Register to update on the next $8001 write
+    // |+------- PRG mode: 0 R6 at $8000, 1 R6 at $C000 (second to last bank at the other place)
+    // +-------- CHR mode: 0 2 KiB banks at $0000, 1 2 KiB banks at $1000
+    bank_select: Byte,
+    registers: [Byte; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+    irq_revision: IrqRevision,
+    irq_latch: Byte,
+    irq_counter: Byte,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12: bool,
+    a12_low_since: u64,
+    cycle: u64,
+}
+
+impl Mmc3 {
+    pub fn build_mmc3(memory: CartridgeMemory, irq_revision: IrqRevision) -> Mmc3 {
+        return Mmc3 {
+            mirroring: memory.mirroring,
+            memory,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            irq_revision,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+            a12_low_since: 0,
+            cycle: 0,
+        };
+    }
+
+    fn prg_bank_for(&self, address: Word) -> usize {
+        let second_last = self.memory.prg_bank_count(PRG_BANK_SIZE) - 2;
+        let swapped = self.bank_select & 0x40 != 0;
+        match address {
+            0x8000..=0x9FFF if swapped => second_last,
+            0x8000..=0x9FFF => usize::from(self.registers[6] & 0x3F),
+            0xA000..=0xBFFF => usize::from(self.registers[7] & 0x3F),
+            0xC000..=0xDFFF if swapped => usize::from(self.registers[6] & 0x3F),
+            0xC000..=0xDFFF => second_last,
+            _ => second_last + 1,
+        }
+    }
+
+    fn chr_bank_for(&self, address: Word) -> usize {
+        let mut address = usize::from(address & 0x1FFF);
+        if self.bank_select & 0x80 != 0 {
+            address ^= 0x1000;
+        }
+        let slot = address / CHR_BANK_SIZE;
+        match slot {
+            0..=3 => usize::from(self.registers[slot / 2] & 0xFE) | (slot % 2),
+            _ => usize::from(self.registers[slot - 2]),
+        }
+    }
+
+    // Watches the PPU address bus for the rising edges of A12
+    fn watch_a12(&mut self, address: Word) {
+        let a12 = address & 0x1000 != 0;
+        if a12 && !self.a12 && self.cycle - self.a12_low_since >= A12_LOW_CYCLES {
+            self.clock_irq_counter();
+        } else if !a12 && self.a12 {
+            self.a12_low_since = self.cycle;
+        }
+        self.a12 = a12;
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let reload = self.irq_reload;
+        let previous = self.irq_counter;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        let fires = match self.irq_revision {
+            IrqRevision::Sharp => self.irq_counter == 0,
+            IrqRevision::Nec => self.irq_counter == 0 && (previous != 0 || reload),
+        };
+        if fires && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled => self.memory.read_prg_ram(address),
+            0x8000..=0xFFFF => self.memory.read_prg(self.prg_bank_for(address), PRG_BANK_SIZE, address),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        let even = address & 0x01 == 0;
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram_write_protect => {
+                self.memory.write_prg_ram(address, data);
+            }
+            0x8000..=0x9FFF if even => self.bank_select = data,
+            0x8000..=0x9FFF => self.registers[usize::from(self.bank_select & 0x07)] = data,
+            // Four screen boards wire the nametables themselves
+            0xA000..=0xBFFF if even && self.memory.mirroring != Mirroring::FourScreen => {
+                self.mirroring = if data & 0x01 != 0 { Mirroring::Horizontal } else { Mirroring::Vertical };
+            }
+            0xA000..=0xBFFF if even => {}
+            0xA000..=0xBFFF => {
+                self.prg_ram_enabled = data & 0x80 != 0;
+                self.prg_ram_write_protect = data & 0x40 != 0;
+            }
+            0xC000..=0xDFFF if even => self.irq_latch = data,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        self.watch_a12(address);
+        return self.memory.read_chr(self.chr_bank_for(address), CHR_BANK_SIZE, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.watch_a12(address);
+        self.memory.write_chr(self.chr_bank_for(address), CHR_BANK_SIZE, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+
+    fn cpu_clock(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn irq(&self) -> bool {
+        return self.irq_pending;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, Mirroring, tests::build_ines};
+
+    use super::A12_LOW_CYCLES;
+
+    fn build_mmc3_cartridge(submapper: u8) -> Cartridge {
+        // 8 x 16 KiB PRG, 4 x 8 KiB CHR, NES 2.0 header for the submapper
+        let mut image = build_ines(8, 4, 0x40, 0x08);
+        image[8] = submapper << 4;
+        image[10] = 0x07;
+        return Cartridge::from_ines(&image).unwrap();
+    }
+
+    // One scanline of the usual setup: background at $0000, sprites at $1000
+    fn scanline(cartridge: &mut Cartridge) {
+        cartridge.ppu_read(0x0000);
+        for _ in 0..A12_LOW_CYCLES * 30 {
+            cartridge.cpu_clock();
+        }
+        for _ in 0..8 {
+            cartridge.ppu_read(0x1FF0);
+            cartridge.cpu_clock();
+        }
+    }
+
+    #[test]
+    fn test_mmc3_prg_banking() {
+        let mut cartridge = build_mmc3_cartridge(0);
+        // 8 KiB banks of a PRG filled per 16 KiB bank: 8 KiB bank n reads as n / 2
+        cartridge.cpu_write(0x8000, 0x06);
+        cartridge.cpu_write(0x8001, 0x04);
+        cartridge.cpu_write(0x8000, 0x07);
+        cartridge.cpu_write(0x8001, 0x07);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x02);
+        assert_eq!(cartridge.cpu_read(0xA000, 0x00), 0x03);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x07);
+        assert_eq!(cartridge.cpu_read(0xE000, 0x00), 0x07);
+        // Swap $8000 and $C000, the second to last bank goes to $8000
+        cartridge.cpu_write(0x8000, 0x40);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x07);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x02);
+    }
+
+    #[test]
+    fn test_mmc3_chr_banking() {
+        let mut cartridge = build_mmc3_cartridge(0);
+        // 2 KiB bank 4 is the first half of 8 KiB bank 1, 1 KiB bank 25 belongs to 8 KiB bank 3
+        cartridge.cpu_write(0x8000, 0x00);
+        cartridge.cpu_write(0x8001, 0x09);
+        cartridge.cpu_write(0x8000, 0x05);
+        cartridge.cpu_write(0x8001, 0x19);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x81);
+        assert_eq!(cartridge.ppu_read(0x07FF), 0x81);
+        assert_eq!(cartridge.ppu_read(0x1C00), 0x83);
+        // CHR mode 1 swaps the halves of the pattern tables
+        cartridge.cpu_write(0x8000, 0x80);
+        assert_eq!(cartridge.ppu_read(0x1000), 0x81);
+        assert_eq!(cartridge.ppu_read(0x0C00), 0x83);
+    }
+
+    #[test]
+    fn test_mmc3_mirroring_and_prg_ram_protect() {
+        let mut cartridge = build_mmc3_cartridge(0);
+        cartridge.cpu_write(0xA000, 0x01);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+        cartridge.cpu_write(0xA000, 0x00);
+        assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+
+        cartridge.cpu_write(0xA001, 0x80);
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x00), 0x42);
+        cartridge.cpu_write(0xA001, 0xC0);
+        cartridge.cpu_write(0x6000, 0x24);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x00), 0x42);
+        cartridge.cpu_write(0xA001, 0x00);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x55), 0x55);
+    }
+
+    #[test]
+    fn test_mmc3_scanline_irq() {
+        let mut cartridge = build_mmc3_cartridge(0);
+        cartridge.cpu_write(0xC000, 0x02);
+        cartridge.cpu_write(0xC001, 0x00);
+        cartridge.cpu_write(0xE001, 0x00);
+        // Reload to 2, then 1, then 0
+        scanline(&mut cartridge);
+        scanline(&mut cartridge);
+        assert!(!cartridge.irq());
+        scanline(&mut cartridge);
+        assert!(cartridge.irq());
+        // Acknowledged and disabled by $E000
+        cartridge.cpu_write(0xE000, 0x00);
+        assert!(!cartridge.irq());
+        scanline(&mut cartridge);
+        scanline(&mut cartridge);
+        scanline(&mut cartridge);
+        assert!(!cartridge.irq());
+    }
+
+    #[test]
+    fn test_mmc3_a12_filter() {
+        let mut cartridge = build_mmc3_cartridge(0);
+        cartridge.cpu_write(0xC000, 0x00);
+        cartridge.cpu_write(0xC001, 0x00);
+        cartridge.cpu_write(0xE001, 0x00);
+        scanline(&mut cartridge);
+        assert!(cartridge.irq());
+        cartridge.cpu_write(0xE000, 0x00);
+        cartridge.cpu_write(0xE001, 0x00);
+        // A12 only went low for a cycle: the rise is filtered out
+        cartridge.ppu_read(0x0000);
+        cartridge.cpu_clock();
+        cartridge.ppu_read(0x1000);
+        assert!(!cartridge.irq());
+    }
+
+    #[test]
+    fn test_mmc3_irq_revisions() {
+        // A latch of 0 raises an IRQ on every scanline on Sharp chips
+        let mut cartridge = build_mmc3_cartridge(0);
+        cartridge.cpu_write(0xC000, 0x00);
+        cartridge.cpu_write(0xC001, 0x00);
+        cartridge.cpu_write(0xE001, 0x00);
+        scanline(&mut cartridge);
+        assert!(cartridge.irq());
+        cartridge.cpu_write(0xE000, 0x00);
+        cartridge.cpu_write(0xE001, 0x00);
+        scanline(&mut cartridge);
+        assert!(cartridge.irq());
+
+        // Only once after the $C001 write on NEC chips (MMC3A, submapper 4)
+        let mut cartridge = build_mmc3_cartridge(4);
+        cartridge.cpu_write(0xC000, 0x00);
+        cartridge.cpu_write(0xC001, 0x00);
+        cartridge.cpu_write(0xE001, 0x00);
+        scanline(&mut cartridge);
+        assert!(cartridge.irq());
+        cartridge.cpu_write(0xE000, 0x00);
+        cartridge.cpu_write(0xE001, 0x00);
+        scanline(&mut cartridge);
+        assert!(!cartridge.irq());
+    }
+}
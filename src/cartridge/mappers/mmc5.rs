@@ -0,0 +1,830 @@
+use crate::apu::pulse::Pulse;
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+pub const MAX_PRG_RAM_SIZE: usize = 0x10000;
+const PRG_BANK_SIZE: usize = 0x2000;
+const EXRAM_SIZE: usize = 0x400;
+// The PPU reads the bus at least every other dot while rendering, a longer silence means vblank
+// or rendering turned off
+const IDLE_CYCLES: u8 = 3;
+// The audio frame sequencer runs at a fixed 240 Hz
+const AUDIO_FRAME_CYCLES: u16 = 7457;
+// Nametable reads counted from the start of a scanline: 32 background tiles (on screen columns
+// 2 to 33), 16 garbage reads during the sprite fetches, then the first 2 tiles of the next line
+const SPRITE_FETCHES_START: u8 = 32;
+const SPRITE_FETCHES_END: u8 = 47;
+const PREFETCH_START: u8 = 48;
+
+// Sound of the MMC5: two pulse channels like the 2A03 ones without sweep, clocked by a fixed
+// 240 Hz sequencer, and a raw 8 bit PCM channel. https://www.nesdev.org/wiki/MMC5_audio
+pub struct Mmc5Audio {
+    pulses: [Pulse; 2],
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq_pending: bool,
+    pcm_level: Byte,
+    audio_cycle: u16,
+    odd_cycle: bool,
+}
+
+impl Mmc5Audio {
+    pub fn build_mmc5_audio() -> Mmc5Audio {
+        return Mmc5Audio {
+            pulses: [Pulse::build_pulse(), Pulse::build_pulse()],
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq_pending: false,
+            pcm_level: 0,
+            audio_cycle: 0,
+            odd_cycle: false,
+        };
+    }
+
+    // $5010 and $5015, None for the other addresses
+    pub fn peek_register(&self, address: Word) -> Option<Byte> {
+        match address {
+            0x5010 => {
+                return Some((if self.pcm_irq_pending { 0x80 } else { 0x00 }) | (if self.pcm_read_mode { 0x01 } else { 0x00 }));
+            }
+            0x5015 => {
+                return Some(Byte::from(self.pulses[0].length_active()) | (Byte::from(self.pulses[1].length_active()) << 1));
+            }
+            _ => {
+                return None;
+            }
+        }
+    }
+
+    // Reading $5010 acknowledges the PCM IRQ
+    pub fn read_register(&mut self, address: Word) -> Option<Byte> {
+        let data = self.peek_register(address);
+        if address == 0x5010 {
+            self.pcm_irq_pending = false;
+        }
+        return data;
+    }
+
+    // $5000-$5015
+    pub fn write_register(&mut self, address: Word, data: Byte) {
+        match address {
+            0x5000..=0x5007 => {
+                let pulse = &mut self.pulses[usize::from(address - 0x5000) / 4];
+                match address & 0x03 {
+                    0 => pulse.write_control(data),
+                    2 => pulse.write_timer_low(data),
+                    3 => pulse.write_timer_high(data),
+                    // No sweep unit
+                    _ => {}
+                }
+            }
+            0x5010 => {
+                self.pcm_read_mode = data & 0x01 != 0;
+                self.pcm_irq_enabled = data & 0x80 != 0;
+            }
+            // A zero is not a valid sample, the output keeps its level
+            0x5011 if !self.pcm_read_mode && data != 0 => self.pcm_level = data,
+            0x5015 => {
+                self.pulses[0].set_enabled(data & 0x01 != 0);
+                self.pulses[1].set_enabled(data & 0x02 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    // PCM read mode samples whatever the CPU reads at $8000-$BFFF
+    pub fn snoop_read(&mut self, address: Word, data: Byte) {
+        if self.pcm_read_mode && (0x8000..0xC000).contains(&address) {
+            if data == 0 {
+                self.pcm_irq_pending = self.pcm_irq_enabled;
+            } else {
+                self.pcm_level = data;
+            }
+        }
+    }
+
+    pub fn clock(&mut self) {
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_timer();
+            }
+        }
+        self.audio_cycle += 1;
+        if self.audio_cycle == AUDIO_FRAME_CYCLES {
+            self.audio_cycle = 0;
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_quarter_frame();
+                pulse.clock_half_frame();
+            }
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        return self.pcm_irq_pending;
+    }
+
+    // The pulses go through the same kind of DAC as the 2A03 ones, the PCM channel reaches
+    // about the level of a full scale DMC
+    pub fn output(&self) -> f32 {
+        let pulses = f32::from(self.pulses[0].output()) + f32::from(self.pulses[1].output());
+        let pulse_output = if pulses > 0.0 { 95.88 / (8128.0 / pulses + 100.0) } else { 0.0 };
+        return pulse_output + f32::from(self.pcm_level) / 255.0 * 0.42;
+    }
+}
+
+// Mapper 5: https://www.nesdev.org/wiki/MMC5
+// The chip watches the PPU bus to find out what is being fetched: three reads in a row of the
+// same nametable address mark the start of a scanline, and counting the nametable reads from
+// there tells background tiles from sprites. This drives the scanline IRQ, the extended
+// attributes, the split screen and the separate CHR banks of 8x16 sprites.
+pub struct Mmc5 {
+    memory: CartridgeMemory,
+    exram: [Byte; EXRAM_SIZE],
+    prg_mode: Byte,
+    chr_mode: Byte,
+    prg_ram_protect: [Byte; 2],
+    // 0 nametable, 1 extended attributes, 2 CPU RAM, 3 CPU ROM
+    exram_mode: Byte,
+    // 2 bits per nametable: 0 CIRAM A, 1 CIRAM B, 2 ExRAM, 3 fill mode
+    nametable_mapping: Byte,
+    fill_tile: Byte,
+    fill_attribute: Byte,
+    prg_ram_bank: Byte,
+    // $5114-$5117, bit 7 selects ROM
+    prg_banks: [Byte; 4],
+    // $5120-$512B with the upper bits of $5130 applied when written
+    chr_banks: [Word; 12],
+    chr_upper: Byte,
+    last_chr_set_b: bool,
+    split_control: Byte,
+    split_scroll: Byte,
+    split_bank: Byte,
+    irq_compare: Byte,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplicand: Byte,
+    multiplier: Byte,
+
+    // PPU bus snooping
+    sprites_16: bool,
+    rendering: bool,
+    in_frame: bool,
+    scanline: Byte,
+    last_address: Word,
+    repeat_count: u8,
+    idle_cycles: u8,
+    nametable_fetch: u8,
+    ext_attribute: Byte,
+
+    audio: Mmc5Audio,
+}
+
+impl Mmc5 {
+    pub fn build_mmc5(memory: CartridgeMemory) -> Mmc5 {
+        return Mmc5 {
+            memory,
+            exram: [0x00; EXRAM_SIZE],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0, 0],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_ram_bank: 0,
+            prg_banks: [0x00, 0x00, 0x00, 0xFF],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            last_chr_set_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            sprites_16: false,
+            rendering: false,
+            in_frame: false,
+            scanline: 0,
+            last_address: 0,
+            repeat_count: 0,
+            idle_cycles: 0,
+            nametable_fetch: 0,
+            ext_attribute: 0,
+            audio: Mmc5Audio::build_mmc5_audio(),
+        };
+    }
+
+    // Bank and ROM/RAM flag of the 8 KiB slot at $8000-$FFFF
+    fn prg_slot(&self, address: Word) -> (usize, bool) {
+        let slot = usize::from((address - 0x8000) / 0x2000);
+        let (index, bank) = match (self.prg_mode, slot) {
+            (0, _) => (3, usize::from(self.prg_banks[3] & 0x7C) | slot),
+            (1, _) | (2, 0..=1) => {
+                let index = slot / 2 * 2 + 1;
+                (index, usize::from(self.prg_banks[index] & 0x7E) | (slot & 0x01))
+            }
+            _ => (slot, usize::from(self.prg_banks[slot] & 0x7F)),
+        };
+        // Banks selected by $5117 are always ROM
+        return (bank, self.prg_banks[index] & 0x80 != 0 || index == 3);
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        return self.prg_ram_protect == [0x02, 0x01];
+    }
+
+    fn prg_ram_index(&self, bank: usize, address: Word) -> usize {
+        let banks = (self.memory.prg_ram.len() / PRG_BANK_SIZE).max(1);
+        return ((bank % banks) * PRG_BANK_SIZE + usize::from(address & 0x1FFF)) % self.memory.prg_ram.len();
+    }
+
+    fn peek_register(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x5010 | 0x5015 => {
+                return self.audio.peek_register(address).unwrap_or(open_bus);
+            }
+            0x5204 => {
+                return (if self.irq_pending { 0x80 } else { 0x00 }) | (if self.in_frame { 0x40 } else { 0x00 });
+            }
+            0x5205 => {
+                return (u16::from(self.multiplicand) * u16::from(self.multiplier)) as Byte;
+            }
+            0x5206 => {
+                return ((u16::from(self.multiplicand) * u16::from(self.multiplier)) >> 8) as Byte;
+            }
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => {
+                return self.exram[usize::from(address - 0x5C00)];
+            }
+            _ => {
+                return open_bus;
+            }
+        }
+    }
+
+    fn write_register(&mut self, address: Word, data: Byte) {
+        match address {
+            0x5000..=0x5015 => self.audio.write_register(address, data),
+            0x5100 => self.prg_mode = data & 0x03,
+            0x5101 => self.chr_mode = data & 0x03,
+            0x5102 => self.prg_ram_protect[0] = data & 0x03,
+            0x5103 => self.prg_ram_protect[1] = data & 0x03,
+            0x5104 => self.exram_mode = data & 0x03,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0x03,
+            0x5113 => self.prg_ram_bank = data & 0x07,
+            0x5114..=0x5117 => self.prg_banks[usize::from(address - 0x5114)] = data,
+            0x5120..=0x512B => {
+                let index = usize::from(address - 0x5120);
+                self.chr_banks[index] = Word::from(data) | (Word::from(self.chr_upper) << 8);
+                self.last_chr_set_b = index >= 8;
+            }
+            0x5130 => self.chr_upper = data & 0x03,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_bank = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5C00..=0x5FFF => {
+                // Nametable modes only accept writes during rendering, other writes store 0
+                let index = usize::from(address - 0x5C00);
+                match self.exram_mode {
+                    0 | 1 => self.exram[index] = if self.in_frame { data } else { 0x00 },
+                    2 => self.exram[index] = data,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Follows the PPU address bus, every nametable and pattern read goes through here
+    fn watch_ppu_bus(&mut self, address: Word) {
+        self.idle_cycles = 0;
+        if address == self.last_address {
+            self.repeat_count += 1;
+        } else {
+            self.repeat_count = 0;
+        }
+        self.last_address = address;
+        let nametable = (0x2000..=0x3EFF).contains(&address) && address & 0x03FF < 0x03C0;
+        if nametable && self.repeat_count == 2 {
+            self.detect_scanline();
+        } else if nametable {
+            self.nametable_fetch = self.nametable_fetch.saturating_add(1);
+        }
+    }
+
+    fn detect_scanline(&mut self) {
+        self.nametable_fetch = 0;
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline = 0;
+            return;
+        }
+        self.scanline = self.scanline.wrapping_add(1);
+        if self.scanline == self.irq_compare && self.irq_compare != 0 {
+            self.irq_pending = true;
+        }
+    }
+
+    fn leave_frame(&mut self) {
+        self.in_frame = false;
+        self.repeat_count = 0;
+        self.last_address = 0;
+    }
+
+    fn sprite_fetch(&self) -> bool {
+        return self.in_frame && (SPRITE_FETCHES_START..=SPRITE_FETCHES_END).contains(&self.nametable_fetch);
+    }
+
+    // On screen column and scanline of the background tile being fetched
+    fn background_tile(&self) -> Option<(u8, Byte)> {
+        if !self.in_frame {
+            return None;
+        }
+        match self.nametable_fetch {
+            0..=31 => Some((self.nametable_fetch + 2, self.scanline)),
+            48 | 49 => Some((self.nametable_fetch - PREFETCH_START, self.scanline.wrapping_add(1))),
+            _ => None,
+        }
+    }
+
+    // Scanline inside the split region when the tile being fetched belongs to it
+    fn split_line(&self) -> Option<Byte> {
+        if self.split_control & 0x80 == 0 || self.exram_mode >= 2 {
+            return None;
+        }
+        let (column, scanline) = self.background_tile()?;
+        let threshold = self.split_control & 0x1F;
+        let inside = if self.split_control & 0x40 != 0 { column >= threshold } else { column < threshold };
+        if !inside || column >= 32 {
+            return None;
+        }
+        return Some(((u16::from(self.split_scroll) + u16::from(scanline)) % 240) as Byte);
+    }
+
+    fn chr_bank(&self, address: Word, set_b: bool) -> (usize, usize) {
+        let slot = usize::from(address / 0x400);
+        let (register, size) = match (self.chr_mode, set_b) {
+            (0, false) => (7, 0x2000),
+            (1, false) => (slot / 4 * 4 + 3, 0x1000),
+            (2, false) => (slot / 2 * 2 + 1, 0x0800),
+            (_, false) => (slot, 0x0400),
+            (0, true) | (1, true) => (11, 0x2000 >> self.chr_mode),
+            (2, true) => (8 + (slot % 4) / 2 * 2 + 1, 0x0800),
+            (_, true) => (8 + slot % 4, 0x0400),
+        };
+        return (usize::from(self.chr_banks[register]), size);
+    }
+
+    fn use_chr_set_b(&self) -> bool {
+        if self.sprites_16 && self.in_frame {
+            return !self.sprite_fetch();
+        }
+        return self.last_chr_set_b;
+    }
+
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x5000..=0x5FFF => {
+                return self.peek_register(address, open_bus);
+            }
+            0x6000..=0x7FFF => {
+                let index = self.prg_ram_index(usize::from(self.prg_ram_bank), address);
+                return self.memory.prg_ram[index];
+            }
+            0x8000..=0xFFFF => {
+                let (bank, rom) = self.prg_slot(address);
+                if !rom {
+                    return self.memory.prg_ram[self.prg_ram_index(bank, address)];
+                }
+                return self.memory.read_prg(bank, PRG_BANK_SIZE, address);
+            }
+            _ => {
+                return open_bus;
+            }
+        }
+    }
+
+    // Reading $5204 acknowledges the scanline IRQ, $5010 the PCM IRQ, and PCM read mode samples
+    // whatever the CPU reads from ROM
+    fn cpu_read(&mut self, address: Word, open_bus: Byte) -> Byte {
+        let data = self.cpu_peek(address, open_bus);
+        match address {
+            0x5010 => {
+                self.audio.read_register(address);
+            }
+            0x5204 => self.irq_pending = false,
+            0x8000..=0xFFFF if self.prg_slot(address).1 => self.audio.snoop_read(address, data),
+            _ => {}
+        }
+        return data;
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        match address {
+            0x5000..=0x5FFF => self.write_register(address, data),
+            0x6000..=0x7FFF if self.prg_ram_writable() => {
+                let index = self.prg_ram_index(usize::from(self.prg_ram_bank), address);
+                self.memory.prg_ram[index] = data;
+            }
+            0x8000..=0xDFFF if self.prg_ram_writable() => {
+                let (bank, rom) = self.prg_slot(address);
+                if !rom {
+                    let index = self.prg_ram_index(bank, address);
+                    self.memory.prg_ram[index] = data;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        self.watch_ppu_bus(address);
+        if let Some(line) = self.split_line() {
+            let bank = usize::from(self.split_bank);
+            let address = (address & 0x0FF8) | Word::from(line & 0x07);
+            return self.memory.read_chr(bank, 0x1000, address);
+        }
+        if self.exram_mode == 1 && self.background_tile().is_some() {
+            let bank = usize::from(self.ext_attribute & 0x3F) | (usize::from(self.chr_upper) << 6);
+            return self.memory.read_chr(bank, 0x1000, address);
+        }
+        let (bank, size) = self.chr_bank(address, self.use_chr_set_b());
+        return self.memory.read_chr(bank, size, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        let (bank, size) = self.chr_bank(address, self.last_chr_set_b);
+        self.memory.write_chr(bank, size, address, data);
+    }
+
+    fn read_nametable(&mut self, address: Word, ciram: &[Byte]) -> Byte {
+        self.watch_ppu_bus(address);
+        let offset = usize::from(address & 0x03FF);
+        let attribute = offset >= 0x3C0;
+        if let Some(line) = self.split_line() {
+            let (column, _) = self.background_tile().unwrap();
+            let row = usize::from(line / 8);
+            if !attribute {
+                return self.exram[row * 32 + usize::from(column)];
+            }
+            let data = self.exram[0x3C0 + row / 4 * 8 + usize::from(column) / 4];
+            let shift = (row & 0x02) * 2 + (usize::from(column) & 0x02);
+            return ((data >> shift) & 0x03) * 0x55;
+        }
+        if self.exram_mode == 1 && self.background_tile().is_some() {
+            if attribute {
+                return (self.ext_attribute >> 6) * 0x55;
+            }
+            self.ext_attribute = self.exram[offset];
+        }
+        let table = usize::from(address >> 10) & 0x03;
+        match (self.nametable_mapping >> (table * 2)) & 0x03 {
+            0 => ciram[offset],
+            1 => ciram[0x400 + offset],
+            2 if self.exram_mode < 2 => self.exram[offset],
+            2 => 0x00,
+            _ if attribute => self.fill_attribute * 0x55,
+            _ => self.fill_tile,
+        }
+    }
+
+    fn write_nametable(&mut self, address: Word, data: Byte, ciram: &mut [Byte]) {
+        let offset = usize::from(address & 0x03FF);
+        let table = usize::from(address >> 10) & 0x03;
+        match (self.nametable_mapping >> (table * 2)) & 0x03 {
+            0 => ciram[offset] = data,
+            1 => ciram[0x400 + offset] = data,
+            2 if self.exram_mode < 2 => self.exram[offset] = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_register_write(&mut self, address: Word, data: Byte) {
+        match address {
+            0x2000 => self.sprites_16 = data & 0x20 != 0,
+            0x2001 => {
+                self.rendering = data & 0x18 != 0;
+                if !self.rendering {
+                    self.leave_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Only used for CPU side $2007 accesses, the nametables are wired through $5105
+    fn mirroring(&self) -> Mirroring {
+        return self.memory.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.idle_cycles < IDLE_CYCLES {
+            self.idle_cycles += 1;
+            if self.idle_cycles == IDLE_CYCLES {
+                self.leave_frame();
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        return (self.irq_pending && self.irq_enabled) || self.audio.irq();
+    }
+
+    fn audio_output(&self) -> f32 {
+        return self.audio.output();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, tests::build_ines};
+    use crate::cpu::Word;
+
+    // 8 x 16 KiB PRG, 4 x 8 KiB CHR
+    fn build_mmc5_cartridge() -> Cartridge {
+        return Cartridge::from_ines(&build_ines(8, 4, 0x50, 0x00)).unwrap();
+    }
+
+    // Fetches of one rendered scanline as the PPU does them, background at $0000 and sprites at $1000.
+    // The line starts with the third read of the address of the two dummy fetches ending the previous one.
+    fn render_scanline(cartridge: &mut Cartridge, ciram: &[u8], nametable: Word) {
+        for tile in 0..32 {
+            cartridge.read_nametable(nametable + 2 + tile, ciram);
+            cartridge.read_nametable(0x23C0, ciram);
+            cartridge.ppu_read(0x0000);
+            cartridge.ppu_read(0x0008);
+            cartridge.cpu_clock();
+            cartridge.cpu_clock();
+        }
+        for _ in 0..8 {
+            cartridge.read_nametable(nametable, ciram);
+            cartridge.read_nametable(nametable, ciram);
+            cartridge.ppu_read(0x1FF0);
+            cartridge.ppu_read(0x1FF8);
+            cartridge.cpu_clock();
+            cartridge.cpu_clock();
+        }
+        for tile in 0..2 {
+            cartridge.read_nametable(nametable + tile, ciram);
+            cartridge.read_nametable(0x23C0, ciram);
+            cartridge.ppu_read(0x0000);
+            cartridge.ppu_read(0x0008);
+        }
+        cartridge.read_nametable(nametable + 2, ciram);
+        cartridge.read_nametable(nametable + 2, ciram);
+        cartridge.cpu_clock();
+    }
+
+    // Turns rendering on and ends the pre-render line, the next read of $2002 starts scanline 0
+    fn start_frame(cartridge: &mut Cartridge, ciram: &[u8]) {
+        cartridge.ppu_register_write(0x2001, 0x18);
+        cartridge.read_nametable(0x2002, ciram);
+        cartridge.read_nametable(0x2002, ciram);
+    }
+
+    #[test]
+    fn test_mmc5_prg_modes() {
+        let mut cartridge = build_mmc5_cartridge();
+        // Power on: mode 3 with $5117 = $FF
+        assert_eq!(cartridge.cpu_read(0xE000, 0x00), 0x07);
+        cartridge.cpu_write(0x5114, 0x82);
+        cartridge.cpu_write(0x5115, 0x85);
+        cartridge.cpu_write(0x5116, 0x8C);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x01);
+        assert_eq!(cartridge.cpu_read(0xA000, 0x00), 0x02);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x06);
+        // Mode 1: two 16 KiB banks
+        cartridge.cpu_write(0x5100, 0x01);
+        cartridge.cpu_write(0x5117, 0x8B);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x02);
+        assert_eq!(cartridge.cpu_read(0xA000, 0x00), 0x02);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x05);
+        // Mode 0: one 32 KiB bank
+        cartridge.cpu_write(0x5100, 0x00);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x04);
+        assert_eq!(cartridge.cpu_read(0xE000, 0x00), 0x05);
+    }
+
+    #[test]
+    fn test_mmc5_prg_modes_5117_rom() {
+        let mut cartridge = build_mmc5_cartridge();
+        cartridge.cpu_write(0x5102, 0x02);
+        cartridge.cpu_write(0x5103, 0x01);
+        // Bit 7 of $5117 is ignored
+        cartridge.cpu_write(0x5117, 0x0F);
+        cartridge.cpu_write(0x5100, 0x01);
+        cartridge.cpu_write(0xC000, 0x42);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x07);
+        assert_eq!(cartridge.cpu_read(0xE000, 0x00), 0x07);
+        cartridge.cpu_write(0x5100, 0x00);
+        cartridge.cpu_write(0x8000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x06);
+        assert_eq!(cartridge.cpu_read(0xA000, 0x00), 0x06);
+        assert_eq!(cartridge.cpu_read(0xE000, 0x00), 0x07);
+    }
+
+    #[test]
+    fn test_mmc5_prg_ram() {
+        let mut cartridge = build_mmc5_cartridge();
+        // Write protected until $5102 = 2 and $5103 = 1
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x00), 0x00);
+        cartridge.cpu_write(0x5102, 0x02);
+        cartridge.cpu_write(0x5103, 0x01);
+        cartridge.cpu_write(0x5113, 0x03);
+        cartridge.cpu_write(0x6000, 0x42);
+        // The same RAM bank mapped at $8000
+        cartridge.cpu_write(0x5114, 0x03);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x42);
+        cartridge.cpu_write(0x8001, 0x24);
+        assert_eq!(cartridge.cpu_read(0x6001, 0x00), 0x24);
+    }
+
+    #[test]
+    fn test_mmc5_chr_modes() {
+        let mut cartridge = build_mmc5_cartridge();
+        cartridge.cpu_write(0x5101, 0x03);
+        cartridge.cpu_write(0x5120, 0x08);
+        cartridge.cpu_write(0x5127, 0x1F);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x81);
+        assert_eq!(cartridge.ppu_read(0x1C00), 0x83);
+        // Set B was written last and is used for everything with 8x8 sprites
+        cartridge.cpu_write(0x512B, 0x10);
+        assert_eq!(cartridge.ppu_read(0x1C00), 0x82);
+        assert_eq!(cartridge.ppu_read(0x0C00), 0x82);
+        // 8 KiB mode
+        cartridge.cpu_write(0x5101, 0x00);
+        cartridge.cpu_write(0x5127, 0x01);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x81);
+    }
+
+    #[test]
+    fn test_mmc5_sprite_chr_set() {
+        let mut cartridge = build_mmc5_cartridge();
+        let ciram = [0x00; 0x800];
+        cartridge.cpu_write(0x5101, 0x00);
+        cartridge.cpu_write(0x5127, 0x01);
+        cartridge.cpu_write(0x512B, 0x02);
+        cartridge.ppu_register_write(0x2000, 0x20);
+        start_frame(&mut cartridge, &ciram);
+        cartridge.read_nametable(0x2002, &ciram);
+        // 8x16 sprites: background from set B, sprites from set A
+        assert_eq!(cartridge.ppu_read(0x0000), 0x82);
+        for tile in 1..34 {
+            cartridge.read_nametable(0x2000 + tile, &ciram);
+        }
+        assert_eq!(cartridge.ppu_read(0x1000), 0x81);
+    }
+
+    #[test]
+    fn test_mmc5_nametable_mapping() {
+        let mut cartridge = build_mmc5_cartridge();
+        let mut ciram = [0x00; 0x800];
+        // A, B, ExRAM, fill
+        cartridge.cpu_write(0x5105, 0xE4);
+        cartridge.cpu_write(0x5106, 0x33);
+        cartridge.cpu_write(0x5107, 0x02);
+        cartridge.write_nametable(0x2005, 0x11, &mut ciram);
+        cartridge.write_nametable(0x2405, 0x22, &mut ciram);
+        cartridge.write_nametable(0x2805, 0x44, &mut ciram);
+        assert_eq!(ciram[0x005], 0x11);
+        assert_eq!(ciram[0x405], 0x22);
+        assert_eq!(cartridge.read_nametable(0x2805, &ciram), 0x44);
+        assert_eq!(cartridge.read_nametable(0x2C05, &ciram), 0x33);
+        assert_eq!(cartridge.read_nametable(0x2FC5, &ciram), 0xAA);
+        // ExRAM as CPU RAM
+        cartridge.cpu_write(0x5104, 0x02);
+        assert_eq!(cartridge.cpu_read(0x5C05, 0x00), 0x44);
+        cartridge.cpu_write(0x5C06, 0x55);
+        assert_eq!(cartridge.cpu_read(0x5C06, 0x00), 0x55);
+        assert_eq!(cartridge.read_nametable(0x2805, &ciram), 0x00);
+        // Read only
+        cartridge.cpu_write(0x5104, 0x03);
+        cartridge.cpu_write(0x5C06, 0x66);
+        assert_eq!(cartridge.cpu_read(0x5C06, 0x00), 0x55);
+    }
+
+    #[test]
+    fn test_mmc5_extended_attributes() {
+        let mut cartridge = build_mmc5_cartridge();
+        let ciram = [0x00; 0x800];
+        cartridge.cpu_write(0x5104, 0x02);
+        // Tile 2 of the first row: palette 3, 4 KiB CHR bank 5
+        cartridge.cpu_write(0x5C02, 0xC5);
+        cartridge.cpu_write(0x5104, 0x01);
+        start_frame(&mut cartridge, &ciram);
+        cartridge.read_nametable(0x2002, &ciram);
+        assert_eq!(cartridge.read_nametable(0x23C0, &ciram), 0xFF);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x82);
+    }
+
+    #[test]
+    fn test_mmc5_split_screen() {
+        let mut cartridge = build_mmc5_cartridge();
+        let ciram = [0x00; 0x800];
+        cartridge.cpu_write(0x5104, 0x02);
+        // Row 1 of the split nametable, column 2
+        cartridge.cpu_write(0x5C22, 0x77);
+        cartridge.cpu_write(0x5104, 0x00);
+        // Left split up to column 4, scrolled down by 8 lines, CHR bank 3
+        cartridge.cpu_write(0x5200, 0x84);
+        cartridge.cpu_write(0x5201, 0x08);
+        cartridge.cpu_write(0x5202, 0x03);
+        start_frame(&mut cartridge, &ciram);
+        assert_eq!(cartridge.read_nametable(0x2002, &ciram), 0x77);
+        assert_eq!(cartridge.ppu_read(0x0770), 0x81);
+        // Column 6 is outside the split
+        for tile in 1..4 {
+            cartridge.read_nametable(0x2002 + tile, &ciram);
+        }
+        assert_eq!(cartridge.read_nametable(0x2006, &ciram), 0x00);
+    }
+
+    #[test]
+    fn test_mmc5_scanline_irq() {
+        let mut cartridge = build_mmc5_cartridge();
+        let ciram = [0x00; 0x800];
+        cartridge.cpu_write(0x5203, 0x03);
+        cartridge.cpu_write(0x5204, 0x80);
+        start_frame(&mut cartridge, &ciram);
+        render_scanline(&mut cartridge, &ciram, 0x2000);
+        assert_eq!(cartridge.cpu_read(0x5204, 0x00), 0x40);
+        for _ in 0..3 {
+            assert!(!cartridge.irq());
+            render_scanline(&mut cartridge, &ciram, 0x2000);
+        }
+        // Raised at the start of scanline 3
+        assert!(cartridge.irq());
+        // Peeking at the status leaves it pending, reading it acknowledges it
+        assert_eq!(cartridge.cpu_peek(0x5204, 0x00), 0xC0);
+        assert!(cartridge.irq());
+        assert_eq!(cartridge.cpu_read(0x5204, 0x00), 0xC0);
+        assert!(!cartridge.irq());
+        // The frame ends when the PPU stops reading
+        for _ in 0..3 {
+            cartridge.cpu_clock();
+        }
+        assert_eq!(cartridge.cpu_read(0x5204, 0x00), 0x00);
+    }
+
+    #[test]
+    fn test_mmc5_multiplier() {
+        let mut cartridge = build_mmc5_cartridge();
+        assert_eq!(cartridge.cpu_read(0x5205, 0x00), 0x01);
+        assert_eq!(cartridge.cpu_read(0x5206, 0x00), 0xFE);
+        cartridge.cpu_write(0x5205, 0x12);
+        cartridge.cpu_write(0x5206, 0x34);
+        assert_eq!(cartridge.cpu_read(0x5205, 0x00), 0xA8);
+        assert_eq!(cartridge.cpu_read(0x5206, 0x00), 0x03);
+    }
+
+    #[test]
+    fn test_mmc5_audio() {
+        let mut cartridge = build_mmc5_cartridge();
+        assert_eq!(cartridge.audio_output(), 0.0);
+        cartridge.cpu_write(0x5015, 0x01);
+        cartridge.cpu_write(0x5000, 0xBF);
+        cartridge.cpu_write(0x5002, 0x10);
+        cartridge.cpu_write(0x5003, 0x08);
+        assert_eq!(cartridge.cpu_read(0x5015, 0x00), 0x01);
+        let mut high = false;
+        for _ in 0..200 {
+            cartridge.cpu_clock();
+            high |= cartridge.audio_output() > 0.0;
+        }
+        assert!(high);
+        // PCM write mode
+        cartridge.cpu_write(0x5015, 0x00);
+        cartridge.cpu_write(0x5011, 0xFF);
+        assert!((cartridge.audio_output() - 0.42).abs() < 0.001);
+        // PCM read mode raises an IRQ on a zero sample
+        cartridge.cpu_write(0x5010, 0x81);
+        cartridge.cpu_write(0x5114, 0x80);
+        cartridge.cpu_read(0x8000, 0x00);
+        assert!(cartridge.irq());
+        assert_eq!(cartridge.cpu_read(0x5010, 0x00), 0x81);
+        assert!(!cartridge.irq());
+    }
+}
@@ -0,0 +1,19 @@
+pub mod axrom;
+pub mod bnrom;
+pub mod camerica;
+pub mod cnrom;
+pub mod color_dreams;
+pub mod fme7;
+pub mod gxrom;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod mmc5;
+pub mod namco163;
+pub mod nina001;
+pub mod nrom;
+pub mod uxrom;
+pub mod vrc2_4;
+pub mod vrc6;
+pub mod vrc7;
+pub mod vrc_irq;
@@ -0,0 +1,390 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const SOUND_RAM_SIZE: usize = 0x80;
+// One channel is updated every 15 CPU cycles
+const CYCLES_PER_CHANNEL: u8 = 15;
+const IRQ_COUNTER_MAX: Word = 0x7FFF;
+// Bank numbers from $E0 select a page of the console nametable RAM instead of CHR ROM
+const CIRAM_BANK: Byte = 0xE0;
+// A channel at full volume is a little louder than a 2A03 pulse at full volume
+const OUTPUT_SCALE: f32 = 0.18 / 120.0;
+
+// Wavetable synthesizer of the Namco 163: https://www.nesdev.org/wiki/Namco_163_audio
+// Up to 8 channels read 4 bit samples from the 128 bytes of internal RAM, their registers live
+// at the top of that same RAM. The chip has a single DAC and updates one channel at a time,
+// so the output switches between the enabled channels every 15 CPU cycles.
+pub struct Namco163Audio {
+    ram: [Byte; SOUND_RAM_SIZE],
+    // $F800: IAAA AAAA, I auto increment, A RAM address
+    address: Byte,
+    // Channel being updated, counting down from 7
+    channel: usize,
+    cycle: u8,
+    output: f32,
+}
+
+impl Namco163Audio {
+    pub fn build_namco163_audio() -> Namco163Audio {
+        return Namco163Audio {
+            ram: [0x00; SOUND_RAM_SIZE],
+            address: 0,
+            channel: 7,
+            cycle: 0,
+            output: 0.0,
+        }
+    }
+
+    pub fn write_address(&mut self, data: Byte) {
+        self.address = data;
+    }
+
+    fn ram_index(&mut self) -> usize {
+        let index = usize::from(self.address & 0x7F);
+        if self.address & 0x80 != 0 {
+            self.address = 0x80 | (self.address.wrapping_add(1) & 0x7F);
+        }
+        return index;
+    }
+
+    // The byte the data port would return, without the auto-increment
+    pub fn peek_data(&self) -> Byte {
+        return self.ram[usize::from(self.address & 0x7F)];
+    }
+
+    pub fn read_data(&mut self) -> Byte {
+        let index = self.ram_index();
+        return self.ram[index];
+    }
+
+    pub fn write_data(&mut self, data: Byte) {
+        let index = self.ram_index();
+        self.ram[index] = data;
+    }
+
+    // Channels 8 - n to 7 are enabled, with n from bits 4-6 of $7F plus one
+    fn enabled_channels(&self) -> usize {
+        return usize::from((self.ram[0x7F] >> 4) & 0x07) + 1;
+    }
+
+    fn sample(&self, index: usize) -> Byte {
+        let data = self.ram[(index / 2) % SOUND_RAM_SIZE];
+        if index & 0x01 == 0 {
+            return data & 0x0F;
+        }
+        return data >> 4;
+    }
+
+    fn update_channel(&mut self) {
+        // Registers of channel c at $40 + 8c: frequency in bytes 0, 2 and 4 (2 bits), phase in
+        // bytes 1, 3 and 5, length in the top 6 bits of byte 4, wave address and volume
+        let base = 0x40 + self.channel * 8;
+        let registers = &self.ram[base..base + 8];
+        let frequency = u32::from(registers[0]) | (u32::from(registers[2]) << 8) | (u32::from(registers[4] & 0x03) << 16);
+        let mut phase = u32::from(registers[1]) | (u32::from(registers[3]) << 8) | (u32::from(registers[5]) << 16);
+        let length = (256 - u32::from(registers[4] & 0xFC)) << 16;
+        let wave_address = usize::from(registers[6]);
+        let volume = f32::from(registers[7] & 0x0F);
+
+        phase = (phase + frequency) % length;
+        self.ram[base + 1] = phase as Byte;
+        self.ram[base + 3] = (phase >> 8) as Byte;
+        self.ram[base + 5] = (phase >> 16) as Byte;
+
+        let sample = self.sample((usize::from((phase >> 16) as Byte) + wave_address) & 0xFF);
+        self.output = (f32::from(sample) - 8.0) * volume * OUTPUT_SCALE;
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        self.cycle += 1;
+        if self.cycle < CYCLES_PER_CHANNEL {
+            return;
+        }
+        self.cycle = 0;
+        self.update_channel();
+        self.channel = if self.channel <= 8 - self.enabled_channels() { 7 } else { self.channel - 1 };
+    }
+
+    pub fn output(&self) -> f32 {
+        return self.output;
+    }
+}
+
+// Namco 129 and 163: https://www.nesdev.org/wiki/Namco_163
+// Three switchable 8 KiB PRG banks, eight 1 KiB CHR banks and four nametable banks which can each
+// select CHR ROM or the console nametable RAM, a 15 bit IRQ counter and expansion audio.
+pub struct Namco163 {
+    memory: CartridgeMemory,
+    // CHR banks for $0000-$1FFF, then the four nametables
+    chr_banks: [Byte; 12],
+    prg_banks: [Byte; 3],
+    // $E000 bit 6
+    sound_disabled: bool,
+    // $E800 bits 6 and 7: nametable RAM is not selectable in the lower and upper pattern table
+    chr_ram_disabled: [bool; 2],
+    // $F800: write protection of PRG RAM, writes need $4x with a clear bit for the 2 KiB page
+    write_protect: Byte,
+    irq_counter: Word,
+    irq_enabled: bool,
+    irq_pending: bool,
+    audio: Namco163Audio,
+}
+
+impl Namco163 {
+    pub fn build_namco163(memory: CartridgeMemory) -> Namco163 {
+        return Namco163 {
+            memory,
+            chr_banks: [0; 12],
+            prg_banks: [0; 3],
+            sound_disabled: false,
+            chr_ram_disabled: [false, false],
+            write_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: Namco163Audio::build_namco163_audio(),
+        };
+    }
+
+    fn prg_ram_writable(&self, address: Word) -> bool {
+        let page = (address - 0x6000) / 0x800;
+        return self.write_protect & 0xF0 == 0x40 && self.write_protect & (0x01 << page) == 0;
+    }
+
+    // CIRAM page selected by a bank register, if any
+    fn ciram_page(&self, slot: usize) -> Option<usize> {
+        let bank = self.chr_banks[slot];
+        let allowed = slot >= 8 || !self.chr_ram_disabled[slot / 4];
+        if bank >= CIRAM_BANK && allowed {
+            return Some(usize::from(bank & 0x01));
+        }
+        return None;
+    }
+}
+
+impl Mapper for Namco163 {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x4800..=0x4FFF => self.audio.peek_data(),
+            0x5000..=0x57FF => self.irq_counter as Byte,
+            0x5800..=0x5FFF => ((self.irq_counter >> 8) as Byte) | if self.irq_enabled { 0x80 } else { 0x00 },
+            0x6000..=0x7FFF => self.memory.read_prg_ram(address),
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[usize::from((address - 0x8000) / 0x2000)];
+                self.memory.read_prg(usize::from(bank & 0x3F), PRG_BANK_SIZE, address)
+            }
+            0xE000..=0xFFFF => self.memory.read_prg(self.memory.prg_bank_count(PRG_BANK_SIZE) - 1, PRG_BANK_SIZE, address),
+            _ => open_bus,
+        }
+    }
+
+    // Reads of the data port advance its address when auto-increment is on
+    fn cpu_read(&mut self, address: Word, open_bus: Byte) -> Byte {
+        if let 0x4800..=0x4FFF = address {
+            return self.audio.read_data();
+        }
+        return self.cpu_peek(address, open_bus);
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        match address {
+            0x4800..=0x4FFF => self.audio.write_data(data),
+            // Writing either half of the counter acknowledges the IRQ
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | Word::from(data);
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | (Word::from(data & 0x7F) << 8);
+                self.irq_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF if self.prg_ram_writable(address) => self.memory.write_prg_ram(address, data),
+            0x8000..=0xDFFF => self.chr_banks[usize::from((address - 0x8000) / 0x800)] = data,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = data & 0x3F;
+                self.sound_disabled = data & 0x40 != 0;
+            }
+            0xE800..=0xEFFF => {
+                self.prg_banks[1] = data & 0x3F;
+                self.chr_ram_disabled = [data & 0x40 != 0, data & 0x80 != 0];
+            }
+            0xF000..=0xF7FF => self.prg_banks[2] = data & 0x3F,
+            0xF800..=0xFFFF => {
+                self.write_protect = data;
+                self.audio.write_address(data);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        let slot = usize::from(address / 0x400);
+        // Pattern tables mapped to nametable RAM are not emulated, the PPU only hands the
+        // CIRAM to nametable accesses
+        if self.ciram_page(slot).is_some() {
+            return 0x00;
+        }
+        return self.memory.read_chr(usize::from(self.chr_banks[slot]), CHR_BANK_SIZE, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        let slot = usize::from(address / 0x400);
+        if self.ciram_page(slot).is_none() {
+            self.memory.write_chr(usize::from(self.chr_banks[slot]), CHR_BANK_SIZE, address, data);
+        }
+    }
+
+    fn read_nametable(&mut self, address: Word, ciram: &[Byte]) -> Byte {
+        let slot = 8 + usize::from(address >> 10) % 4;
+        match self.ciram_page(slot) {
+            Some(page) => ciram[page * 0x400 + usize::from(address & 0x03FF)],
+            None => self.memory.read_chr(usize::from(self.chr_banks[slot]), CHR_BANK_SIZE, address),
+        }
+    }
+
+    fn write_nametable(&mut self, address: Word, data: Byte, ciram: &mut [Byte]) {
+        let slot = 8 + usize::from(address >> 10) % 4;
+        // Nametables mapped to CHR ROM are read only
+        if let Some(page) = self.ciram_page(slot) {
+            ciram[page * 0x400 + usize::from(address & 0x03FF)] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.memory.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.irq_enabled && self.irq_counter < IRQ_COUNTER_MAX {
+            self.irq_counter += 1;
+            if self.irq_counter == IRQ_COUNTER_MAX {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.sound_disabled {
+            return 0.0;
+        }
+        return self.audio.output();
+    }
+
+    fn irq(&self) -> bool {
+        return self.irq_pending;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, tests::build_ines};
+
+    // 8 x 16 KiB PRG, 4 x 8 KiB CHR
+    fn build_namco163_cartridge() -> Cartridge {
+        return Cartridge::from_ines(&build_ines(8, 4, 0x30, 0x10)).unwrap();
+    }
+
+    #[test]
+    fn test_namco163_banking() {
+        let mut cartridge = build_namco163_cartridge();
+        cartridge.cpu_write(0xE000, 0x02);
+        cartridge.cpu_write(0xE800, 0x05);
+        cartridge.cpu_write(0xF000, 0x09);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x01);
+        assert_eq!(cartridge.cpu_read(0xA000, 0x00), 0x02);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x04);
+        assert_eq!(cartridge.cpu_read(0xE000, 0x00), 0x07);
+        cartridge.cpu_write(0xB800, 0x1B);
+        assert_eq!(cartridge.ppu_read(0x1C00), 0x83);
+    }
+
+    #[test]
+    fn test_namco163_nametables() {
+        let mut cartridge = build_namco163_cartridge();
+        let mut ciram = vec![0x00; 0x800];
+        // Nametables 0 and 1 in CIRAM pages 1 and 0, nametable 2 from CHR ROM bank 8
+        cartridge.cpu_write(0xC000, 0xE1);
+        cartridge.cpu_write(0xC800, 0xE0);
+        cartridge.cpu_write(0xD000, 0x08);
+        cartridge.write_nametable(0x2005, 0x42, &mut ciram);
+        assert_eq!(ciram[0x405], 0x42);
+        cartridge.write_nametable(0x2405, 0x24, &mut ciram);
+        assert_eq!(ciram[0x005], 0x24);
+        assert_eq!(cartridge.read_nametable(0x2005, &ciram), 0x42);
+        assert_eq!(cartridge.read_nametable(0x2805, &ciram), 0x81);
+        cartridge.write_nametable(0x2805, 0x11, &mut ciram);
+        assert_eq!(cartridge.read_nametable(0x2805, &ciram), 0x81);
+    }
+
+    #[test]
+    fn test_namco163_prg_ram_protection() {
+        let mut cartridge = build_namco163_cartridge();
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x00), 0x00);
+        // Enabled, with the second 2 KiB page protected
+        cartridge.cpu_write(0xF800, 0x42);
+        cartridge.cpu_write(0x6000, 0x42);
+        cartridge.cpu_write(0x6800, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x00), 0x42);
+        assert_eq!(cartridge.cpu_read(0x6800, 0x00), 0x00);
+    }
+
+    #[test]
+    fn test_namco163_irq() {
+        let mut cartridge = build_namco163_cartridge();
+        cartridge.cpu_write(0x5000, 0xFD);
+        cartridge.cpu_write(0x5800, 0xFF);
+        assert_eq!(cartridge.cpu_read(0x5800, 0x00), 0xFF);
+        cartridge.cpu_clock();
+        assert!(!cartridge.irq());
+        cartridge.cpu_clock();
+        assert!(cartridge.irq());
+        // The counter stops at $7FFF
+        cartridge.cpu_clock();
+        assert_eq!(cartridge.cpu_read(0x5000, 0x00), 0xFF);
+        cartridge.cpu_write(0x5800, 0x00);
+        assert!(!cartridge.irq());
+    }
+
+    #[test]
+    fn test_namco163_audio() {
+        let mut cartridge = build_namco163_cartridge();
+        // Auto increment from address 0: a 4 sample wave alternating 0 and 15
+        cartridge.cpu_write(0xF800, 0x80);
+        cartridge.cpu_write(0x4800, 0xF0);
+        cartridge.cpu_write(0x4800, 0xF0);
+        // Channel 7: frequency $10000 advances one sample per update, length 4, volume 15,
+        // one channel enabled
+        cartridge.cpu_write(0xF800, 0xF8);
+        for data in [0x00, 0x00, 0x00, 0x00, 0xFD, 0x00, 0x00, 0x0F] {
+            cartridge.cpu_write(0x4800, data);
+        }
+        // The address wraps around to the wave data
+        assert_eq!(cartridge.cpu_read(0x4800, 0x00), 0xF0);
+        let mut levels = vec![];
+        for _ in 0..15 * 4 {
+            cartridge.cpu_clock();
+            levels.push(cartridge.audio_output());
+        }
+        assert!(levels[14] > 0.0);
+        assert!(levels[29] < 0.0);
+        assert!(levels[44] > 0.0);
+        // Sound disable
+        cartridge.cpu_write(0xE000, 0x40);
+        assert_eq!(cartridge.audio_output(), 0.0);
+    }
+}
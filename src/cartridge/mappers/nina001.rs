@@ -0,0 +1,86 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+// Mapper 34 on the AVE NINA-001 board: the registers sit on top of the PRG RAM, $7FFD selects
+// a 32 KiB PRG bank, $7FFE and $7FFF select the 4 KiB CHR banks at $0000 and $1000.
+pub struct Nina001 {
+    memory: CartridgeMemory,
+    prg_bank: usize,
+    chr_banks: [usize; 2],
+}
+
+impl Nina001 {
+    pub fn build_nina001(memory: CartridgeMemory) -> Nina001 {
+        return Nina001 { memory, prg_bank: 0, chr_banks: [0, 1] };
+    }
+}
+
+impl Mapper for Nina001 {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF => self.memory.read_prg_ram(address),
+            0x8000..=0xFFFF => self.memory.read_prg(self.prg_bank, PRG_BANK_SIZE, address),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        // The RAM keeps a copy of the register writes
+        if let 0x6000..=0x7FFF = address {
+            self.memory.write_prg_ram(address, data);
+        }
+        match address {
+            0x7FFD => self.prg_bank = usize::from(data & 0x01),
+            0x7FFE => self.chr_banks[0] = usize::from(data & 0x0F),
+            0x7FFF => self.chr_banks[1] = usize::from(data & 0x0F),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        let bank = self.chr_banks[usize::from(address >> 12) & 0x01];
+        return self.memory.read_chr(bank, CHR_BANK_SIZE, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        let bank = self.chr_banks[usize::from(address >> 12) & 0x01];
+        self.memory.write_chr(bank, CHR_BANK_SIZE, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.memory.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, tests::build_ines};
+
+    #[test]
+    fn test_nina001_banking() {
+        // 2 x 32 KiB PRG, 4 x 8 KiB CHR: detected as NINA-001 without submapper
+        let mut cartridge = Cartridge::from_ines(&build_ines(4, 4, 0x20, 0x20)).unwrap();
+        cartridge.cpu_write(0x7FFD, 0x01);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x02);
+        assert_eq!(cartridge.cpu_read(0x7FFD, 0x00), 0x01);
+        cartridge.cpu_write(0x7FFE, 0x05);
+        cartridge.cpu_write(0x7FFF, 0x06);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x82);
+        assert_eq!(cartridge.ppu_read(0x1000), 0x83);
+        // Writes to $8000 do nothing
+        cartridge.cpu_write(0x8000, 0x00);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x02);
+    }
+}
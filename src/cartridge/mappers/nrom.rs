@@ -0,0 +1,66 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+// Mapper 0: 16 or 32 KiB of PRG ROM and 8 KiB of CHR without any banking.
+// NROM-128 images see their only bank at both $8000 and $C000.
+pub struct Nrom {
+    memory: CartridgeMemory,
+}
+
+impl Nrom {
+    pub fn build_nrom(memory: CartridgeMemory) -> Nrom {
+        return Nrom { memory };
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF => self.memory.read_prg_ram(address),
+            0x8000..=0xFFFF => self.memory.read_prg(0, 0x8000, address - 0x8000),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        if let 0x6000..=0x7FFF = address {
+            self.memory.write_prg_ram(address, data);
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        return self.memory.read_chr(0, 0x2000, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.memory.write_chr(0, 0x2000, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.memory.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, tests::build_ines};
+
+    #[test]
+    fn test_nrom_256_maps_both_banks() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(2, 0, 0x00, 0x00)).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x00);
+        assert_eq!(cartridge.cpu_read(0xFFFF, 0x00), 0x01);
+        assert_eq!(cartridge.cpu_read(0x5000, 0x55), 0x55);
+        cartridge.ppu_write(0x1234, 0x42);
+        assert_eq!(cartridge.ppu_read(0x1234), 0x42);
+    }
+}
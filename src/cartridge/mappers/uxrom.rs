@@ -0,0 +1,100 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper, bus_conflict};
+use crate::cpu::{Byte, Word};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+// Mapper 2: a switchable 16 KiB bank at $8000 and the last bank fixed at $C000.
+// Any write to $8000-$FFFF selects the bank, through a bus conflict on UNROM/UOROM.
+pub struct Uxrom {
+    memory: CartridgeMemory,
+    bus_conflicts: bool,
+    prg_bank: usize,
+}
+
+impl Uxrom {
+    pub fn build_uxrom(memory: CartridgeMemory, bus_conflicts: bool) -> Uxrom {
+        return Uxrom { memory, bus_conflicts, prg_bank: 0 };
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF => self.memory.read_prg_ram(address),
+            0x8000..=0xBFFF => self.memory.read_prg(self.prg_bank, PRG_BANK_SIZE, address),
+            0xC000..=0xFFFF => {
+                let last_bank = self.memory.prg_bank_count(PRG_BANK_SIZE) - 1;
+                return self.memory.read_prg(last_bank, PRG_BANK_SIZE, address);
+            }
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        match address {
+            0x6000..=0x7FFF => self.memory.write_prg_ram(address, data),
+            0x8000..=0xFFFF => {
+                let mut data = data;
+                if self.bus_conflicts {
+                    data = bus_conflict(data, self.cpu_read(address, data));
+                }
+                self.prg_bank = usize::from(data);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        return self.memory.read_chr(0, 0x2000, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.memory.write_chr(0, 0x2000, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.memory.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, tests::build_ines};
+
+    #[test]
+    fn test_uxrom_switches_lower_bank() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(8, 0, 0x20, 0x08)).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x00);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x07);
+        // Bus conflicts by default: the ROM at $C000 holds $07 and keeps all bits of the bank number
+        cartridge.cpu_write(0xC000, 0x05);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x05);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x07);
+    }
+
+    #[test]
+    fn test_uxrom_bus_conflict() {
+        let mut image = build_ines(8, 0, 0x20, 0x08);
+        let mut cartridge = Cartridge::from_ines(&image).unwrap();
+        cartridge.cpu_write(0xC000, 0x03);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x03);
+        // Bank 3 is filled with $03 so only the low two bits of the write survive
+        cartridge.cpu_write(0x8000, 0x05);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x01);
+
+        // Submapper 1 boards do not have conflicts
+        image[8] = 0x10;
+        let mut cartridge = Cartridge::from_ines(&image).unwrap();
+        cartridge.cpu_write(0x8000, 0x03);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x03);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+use super::vrc_irq::VrcIrq;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// Konami VRC2 and VRC4: https://www.nesdev.org/wiki/VRC2_and_VRC4
+// Each board wires two CPU address lines to the register select inputs of the chip,
+// in a different order depending on the board. Images without a submapper are decoded
+// with the lines of all the candidate boards ORed together.
+// VRC2 is a subset of VRC4: no IRQ, no PRG swap mode, and mirroring on a single bit.
+pub struct Vrc24 {
+    memory: CartridgeMemory,
+    // CPU address bits wired to the chip A0 and A1 inputs
+    lines: Vec<(u8, u8)>,
+    vrc2: bool,
+    // VRC2a drops the lowest bit of the CHR bank numbers
+    chr_shift: u8,
+    prg_banks: [Byte; 2],
+    prg_swap: bool,
+    chr_banks: [Word; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+}
+
+impl Vrc24 {
+    pub fn build_vrc2_4(memory: CartridgeMemory, mapper: u16, submapper: Byte) -> Vrc24 {
+        let (lines, vrc2) = match (mapper, submapper) {
+            (21, 1) => (vec![(1, 2)], false),
+            (21, 2) => (vec![(6, 7)], false),
+            (21, _) => (vec![(1, 2), (6, 7)], false),
+            (22, _) => (vec![(1, 0)], true),
+            (23, 1) => (vec![(0, 1)], false),
+            (23, 2) => (vec![(2, 3)], false),
+            (23, 3) => (vec![(0, 1)], true),
+            (23, _) => (vec![(0, 1), (2, 3)], false),
+            (25, 1) => (vec![(1, 0)], false),
+            (25, 2) => (vec![(3, 2)], false),
+            (25, 3) => (vec![(1, 0)], true),
+            _ => (vec![(1, 0), (3, 2)], false),
+        };
+        return Vrc24 {
+            memory,
+            lines,
+            vrc2,
+            chr_shift: if mapper == 22 { 1 } else { 0 },
+            prg_banks: [0, 0],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            irq: VrcIrq::build_vrc_irq(),
+        };
+    }
+
+    // Maps the address to the $x000-$x003 register layout of the chip
+    fn register(&self, address: Word) -> Word {
+        let mut select = 0;
+        for (a0, a1) in self.lines.iter() {
+            select |= (address >> a0) & 0x01;
+            select |= ((address >> a1) & 0x01) << 1;
+        }
+        return (address & 0xF000) | select;
+    }
+
+    fn prg_bank_for(&self, address: Word) -> usize {
+        let second_last = self.memory.prg_bank_count(PRG_BANK_SIZE) - 2;
+        match address {
+            0x8000..=0x9FFF if self.prg_swap => second_last,
+            0x8000..=0x9FFF => usize::from(self.prg_banks[0] & 0x1F),
+            0xA000..=0xBFFF => usize::from(self.prg_banks[1] & 0x1F),
+            0xC000..=0xDFFF if self.prg_swap => usize::from(self.prg_banks[0] & 0x1F),
+            0xC000..=0xDFFF => second_last,
+            _ => second_last + 1,
+        }
+    }
+
+    fn chr_bank_for(&self, address: Word) -> usize {
+        return usize::from(self.chr_banks[usize::from(address / 0x400)] >> self.chr_shift);
+    }
+
+    // $B000-$E003: each 1 KiB CHR bank number is written as two nibbles
+    fn write_chr_bank(&mut self, register: Word, data: Byte) {
+        let index = usize::from((register >> 12) - 0x0B) * 2 + usize::from(register & 0x02) / 2;
+        let bank = self.chr_banks[index];
+        if register & 0x01 == 0 {
+            self.chr_banks[index] = (bank & 0x1F0) | Word::from(data & 0x0F);
+        } else {
+            self.chr_banks[index] = (bank & 0x00F) | (Word::from(data & 0x1F) << 4);
+        }
+    }
+}
+
+impl Mapper for Vrc24 {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF => self.memory.read_prg_ram(address),
+            0x8000..=0xFFFF => self.memory.read_prg(self.prg_bank_for(address), PRG_BANK_SIZE, address),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        if let 0x6000..=0x7FFF = address {
+            self.memory.write_prg_ram(address, data);
+            return;
+        }
+        if address < 0x8000 {
+            return;
+        }
+        let register = self.register(address);
+        match register {
+            0x8000..=0x8003 => self.prg_banks[0] = data,
+            0x9000..=0x9003 if self.vrc2 => {
+                self.mirroring = if data & 0x01 != 0 { Mirroring::Horizontal } else { Mirroring::Vertical };
+            }
+            0x9000 => {
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0x9002 => self.prg_swap = data & 0x02 != 0,
+            0xA000..=0xA003 => self.prg_banks[1] = data,
+            0xB000..=0xEFFF => self.write_chr_bank(register, data),
+            0xF000 if !self.vrc2 => self.irq.write_latch_low(data),
+            0xF001 if !self.vrc2 => self.irq.write_latch_high(data),
+            0xF002 if !self.vrc2 => self.irq.write_control(data),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        return self.memory.read_chr(self.chr_bank_for(address), CHR_BANK_SIZE, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.memory.write_chr(self.chr_bank_for(address), CHR_BANK_SIZE, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq(&self) -> bool {
+        return self.irq.pending();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, Mirroring, tests::build_ines};
+
+    // 8 x 16 KiB PRG, 4 x 8 KiB CHR
+    fn build_vrc_cartridge(mapper: u8, submapper: u8) -> Cartridge {
+        let mut image = build_ines(8, 4, (mapper & 0x0F) << 4, (mapper & 0xF0) | 0x08);
+        image[8] = submapper << 4;
+        return Cartridge::from_ines(&image).unwrap();
+    }
+
+    #[test]
+    fn test_vrc4_prg_banking() {
+        let mut cartridge = build_vrc_cartridge(21, 1);
+        // VRC4a: A1 and A2 select the register
+        cartridge.cpu_write(0x8000, 0x05);
+        cartridge.cpu_write(0xA000, 0x02);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x02);
+        assert_eq!(cartridge.cpu_read(0xA000, 0x00), 0x01);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x07);
+        assert_eq!(cartridge.cpu_read(0xE000, 0x00), 0x07);
+        // PRG swap mode through $9004 (register 2 at A2)
+        cartridge.cpu_write(0x9004, 0x02);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x07);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x02);
+    }
+
+    #[test]
+    fn test_vrc4_address_lines() {
+        // The same register, CHR bank 0 high nibble, on each board
+        for (mapper, submapper, address) in [(21, 1, 0xB002), (21, 2, 0xB040), (23, 1, 0xB001), (23, 2, 0xB004), (25, 1, 0xB002), (25, 2, 0xB008)] {
+            let mut cartridge = build_vrc_cartridge(mapper, submapper);
+            cartridge.cpu_write(0xB000, 0x08);
+            cartridge.cpu_write(address, 0x01);
+            assert_eq!(cartridge.ppu_read(0x0000), 0x83, "mapper {} submapper {}", mapper, submapper);
+        }
+        // Without submapper both wirings are decoded
+        let mut cartridge = build_vrc_cartridge(25, 0);
+        cartridge.cpu_write(0xB008, 0x01);
+        cartridge.cpu_write(0xB000, 0x08);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x83);
+    }
+
+    #[test]
+    fn test_vrc4_mirroring() {
+        let mut cartridge = build_vrc_cartridge(23, 1);
+        cartridge.cpu_write(0x9000, 0x01);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+        cartridge.cpu_write(0x9000, 0x03);
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_vrc2a_chr_banks() {
+        let mut cartridge = build_vrc_cartridge(22, 0);
+        // VRC2a ignores the lowest bank bit: 1 KiB bank 16 selects bank 8
+        cartridge.cpu_write(0xB000, 0x00);
+        cartridge.cpu_write(0xB002, 0x01);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x81);
+        // No one screen mirroring nor IRQ
+        cartridge.cpu_write(0x9000, 0x03);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+        cartridge.cpu_write(0xF004, 0x07);
+        for _ in 0..300 {
+            cartridge.cpu_clock();
+        }
+        assert!(!cartridge.irq());
+    }
+
+    #[test]
+    fn test_vrc4_irq() {
+        let mut cartridge = build_vrc_cartridge(25, 1);
+        // VRC4b: A1 is register bit 0, A0 bit 1
+        cartridge.cpu_write(0xF000, 0x0E);
+        cartridge.cpu_write(0xF002, 0x0F);
+        cartridge.cpu_write(0xF001, 0x06);
+        cartridge.cpu_clock();
+        assert!(!cartridge.irq());
+        cartridge.cpu_clock();
+        assert!(cartridge.irq());
+        cartridge.cpu_write(0xF003, 0x00);
+        assert!(!cartridge.irq());
+    }
+}
@@ -0,0 +1,414 @@
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+use super::vrc_irq::VrcIrq;
+
+// A VRC6 pulse at full volume is about as loud as a 2A03 pulse at full volume
+const OUTPUT_SCALE: f32 = 0.149 / 15.0;
+
+// Square channel of the VRC6 with 8 duty settings and a digital mode
+pub struct Vrc6Pulse {
+    volume: Byte,
+    duty: Byte,
+    digital: bool,
+    enabled: bool,
+    period: Word,
+    timer: Word,
+    step: Byte,
+}
+
+impl Vrc6Pulse {
+    pub fn build_vrc6_pulse() -> Vrc6Pulse {
+        return Vrc6Pulse {
+            volume: 0,
+            duty: 0,
+            digital: false,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            step: 0,
+        }
+    }
+
+    pub fn write(&mut self, register: Word, data: Byte) {
+        match register {
+            // MDDD VVVV
+            0 => {
+                self.digital = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0x07;
+                self.volume = data & 0x0F;
+            }
+            1 => self.period = (self.period & 0x0F00) | Word::from(data),
+            _ => {
+                self.period = (self.period & 0x00FF) | (Word::from(data & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    pub fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) % 16;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> Byte {
+        if self.enabled && (self.digital || self.step <= self.duty) {
+            return self.volume;
+        }
+        return 0;
+    }
+}
+
+// Sawtooth channel: an accumulator incremented by the rate every other clock, reset after 7 steps
+pub struct Vrc6Sawtooth {
+    rate: Byte,
+    enabled: bool,
+    period: Word,
+    timer: Word,
+    step: Byte,
+    accumulator: Byte,
+}
+
+impl Vrc6Sawtooth {
+    pub fn build_vrc6_sawtooth() -> Vrc6Sawtooth {
+        return Vrc6Sawtooth {
+            rate: 0,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    pub fn write(&mut self, register: Word, data: Byte) {
+        match register {
+            0 => self.rate = data & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | Word::from(data),
+            _ => {
+                self.period = (self.period & 0x00FF) | (Word::from(data & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    pub fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 0x01 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    // The top 5 bits of the accumulator
+    pub fn output(&self) -> Byte {
+        return self.accumulator >> 3;
+    }
+}
+
+// The three sound channels of the VRC6, also found on their own in NSF files
+pub struct Vrc6Audio {
+    pulses: [Vrc6Pulse; 2],
+    sawtooth: Vrc6Sawtooth,
+    // $9003: frequency control, bit 0 halts all channels, bits 1 and 2 speed them up 16 or 256 times
+    frequency_control: Byte,
+}
+
+impl Vrc6Audio {
+    pub fn build_vrc6_audio() -> Vrc6Audio {
+        return Vrc6Audio {
+            pulses: [Vrc6Pulse::build_vrc6_pulse(), Vrc6Pulse::build_vrc6_pulse()],
+            sawtooth: Vrc6Sawtooth::build_vrc6_sawtooth(),
+            frequency_control: 0,
+        };
+    }
+
+    // Registers as wired on VRC6a: $9000-$9003, $A000-$A002 and $B000-$B002
+    pub fn write(&mut self, register: Word, data: Byte) {
+        match register {
+            0x9000..=0x9002 => self.pulses[0].write(register & 0x03, data),
+            0x9003 => self.frequency_control = data,
+            0xA000..=0xA002 => self.pulses[1].write(register & 0x03, data),
+            0xB000..=0xB002 => self.sawtooth.write(register & 0x03, data),
+            _ => {}
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if self.frequency_control & 0x01 != 0 {
+            return;
+        }
+        let shift = if self.frequency_control & 0x04 != 0 {
+            8
+        } else if self.frequency_control & 0x02 != 0 {
+            4
+        } else {
+            0
+        };
+        for pulse in self.pulses.iter_mut() {
+            pulse.clock(shift);
+        }
+        self.sawtooth.clock(shift);
+    }
+
+    // The three channels are summed linearly by the chip
+    pub fn output(&self) -> f32 {
+        let level = self.pulses[0].output() + self.pulses[1].output() + self.sawtooth.output();
+        return f32::from(level) * OUTPUT_SCALE;
+    }
+}
+
+// Konami VRC6, mappers 24 (VRC6a) and 26 (VRC6b, A0 and A1 swapped): https://www.nesdev.org/wiki/VRC6
+// 16 KiB + 8 KiB switchable PRG banks, eight 1 KiB CHR banks, the VRC IRQ and 3 sound channels.
+// Only the pattern table side of the PPU banking modes is emulated, CHR ROM nametables are not.
+pub struct Vrc6 {
+    memory: CartridgeMemory,
+    swap_lines: bool,
+    prg_bank_16: Byte,
+    prg_bank_8: Byte,
+    chr_banks: [Byte; 8],
+    // $B003: --ENMMPP, E PRG RAM enable, N nametable source, MM mirroring, PP CHR banking mode
+    banking_control: Byte,
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn build_vrc6(memory: CartridgeMemory, mapper: u16) -> Vrc6 {
+        return Vrc6 {
+            memory,
+            swap_lines: mapper == 26,
+            prg_bank_16: 0,
+            prg_bank_8: 0,
+            chr_banks: [0; 8],
+            banking_control: 0,
+            irq: VrcIrq::build_vrc_irq(),
+            audio: Vrc6Audio::build_vrc6_audio(),
+        };
+    }
+
+    fn register(&self, address: Word) -> Word {
+        if self.swap_lines {
+            return (address & 0xF000) | ((address & 0x01) << 1) | ((address & 0x02) >> 1);
+        }
+        return address & 0xF003;
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        return self.banking_control & 0x80 != 0;
+    }
+
+    fn chr_bank_for(&self, address: Word) -> (usize, usize) {
+        let slot = usize::from(address / 0x400);
+        match self.banking_control & 0x03 {
+            0 => (usize::from(self.chr_banks[slot]), 0x400),
+            1 => (usize::from(self.chr_banks[slot / 2]), 0x800),
+            // 1 KiB banks from R0-R3 at $0000, 2 KiB banks from R4-R5 at $1000
+            _ if slot < 4 => (usize::from(self.chr_banks[slot]), 0x400),
+            _ => (usize::from(self.chr_banks[4 + (slot - 4) / 2]), 0x800),
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.memory.read_prg_ram(address),
+            0x8000..=0xBFFF => self.memory.read_prg(usize::from(self.prg_bank_16 & 0x0F), 0x4000, address),
+            0xC000..=0xDFFF => self.memory.read_prg(usize::from(self.prg_bank_8 & 0x1F), 0x2000, address),
+            0xE000..=0xFFFF => self.memory.read_prg(self.memory.prg_bank_count(0x2000) - 1, 0x2000, address),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        if let 0x6000..=0x7FFF = address {
+            if self.prg_ram_enabled() {
+                self.memory.write_prg_ram(address, data);
+            }
+            return;
+        }
+        if address < 0x8000 {
+            return;
+        }
+        let register = self.register(address);
+        match register {
+            0x8000..=0x8003 => self.prg_bank_16 = data,
+            0xB003 => self.banking_control = data,
+            0x9000..=0xB002 => self.audio.write(register, data),
+            0xC000..=0xC003 => self.prg_bank_8 = data,
+            0xD000..=0xD003 => self.chr_banks[usize::from(register & 0x03)] = data,
+            0xE000..=0xE003 => self.chr_banks[4 + usize::from(register & 0x03)] = data,
+            0xF000 => self.irq.write_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        let (bank, size) = self.chr_bank_for(address);
+        return self.memory.read_chr(bank, size, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        let (bank, size) = self.chr_bank_for(address);
+        self.memory.write_chr(bank, size, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.banking_control >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        return self.irq.pending();
+    }
+
+    fn audio_output(&self) -> f32 {
+        return self.audio.output();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, Mirroring, tests::build_ines};
+
+    use super::{Vrc6Pulse, Vrc6Sawtooth};
+
+    fn build_vrc6_cartridge(mapper: u8) -> Cartridge {
+        return Cartridge::from_ines(&build_ines(8, 4, (mapper & 0x0F) << 4, mapper & 0xF0)).unwrap();
+    }
+
+    #[test]
+    fn test_vrc6_banking() {
+        let mut cartridge = build_vrc6_cartridge(24);
+        cartridge.cpu_write(0x8000, 0x03);
+        cartridge.cpu_write(0xC000, 0x09);
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x03);
+        assert_eq!(cartridge.cpu_read(0xBFFF, 0x00), 0x03);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x04);
+        assert_eq!(cartridge.cpu_read(0xE000, 0x00), 0x07);
+        cartridge.cpu_write(0xE003, 0x10);
+        assert_eq!(cartridge.ppu_read(0x1C00), 0x82);
+        // 2 KiB banks in mode 1
+        cartridge.cpu_write(0xB003, 0x25);
+        cartridge.cpu_write(0xD001, 0x04);
+        assert_eq!(cartridge.ppu_read(0x0800), 0x81);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_vrc6b_swapped_lines() {
+        let mut cartridge = build_vrc6_cartridge(26);
+        // $B003 on the chip is $B003 on both boards, $D001 becomes $D002
+        cartridge.cpu_write(0xD001, 0x08);
+        assert_eq!(cartridge.ppu_read(0x0800), 0x81);
+        // PRG RAM is disabled until $B003 bit 7 is set
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x55), 0x55);
+        cartridge.cpu_write(0xB003, 0x80);
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000, 0x00), 0x42);
+    }
+
+    #[test]
+    fn test_vrc6_irq() {
+        let mut cartridge = build_vrc6_cartridge(24);
+        cartridge.cpu_write(0xF000, 0xFE);
+        cartridge.cpu_write(0xF001, 0x06);
+        cartridge.cpu_clock();
+        cartridge.cpu_clock();
+        assert!(cartridge.irq());
+        cartridge.cpu_write(0xF002, 0x00);
+        assert!(!cartridge.irq());
+    }
+
+    #[test]
+    fn test_vrc6_pulse_duty() {
+        let mut pulse = Vrc6Pulse::build_vrc6_pulse();
+        // Duty 3: 4 steps high out of 16, volume 10
+        pulse.write(0, 0x3A);
+        pulse.write(1, 0x00);
+        pulse.write(2, 0x80);
+        let mut high = 0;
+        for _ in 0..16 {
+            pulse.clock(0);
+            if pulse.output() == 10 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, 4);
+        // Digital mode outputs the volume all the time
+        pulse.write(0, 0x8A);
+        assert_eq!(pulse.output(), 10);
+    }
+
+    #[test]
+    fn test_vrc6_sawtooth() {
+        let mut sawtooth = Vrc6Sawtooth::build_vrc6_sawtooth();
+        sawtooth.write(0, 0x08);
+        sawtooth.write(2, 0x80);
+        let mut levels = Vec::new();
+        for _ in 0..14 {
+            sawtooth.clock(0);
+            levels.push(sawtooth.output());
+        }
+        assert_eq!(levels, vec![0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0]);
+    }
+
+    #[test]
+    fn test_vrc6_audio_output() {
+        let mut cartridge = build_vrc6_cartridge(24);
+        assert_eq!(cartridge.audio_output(), 0.0);
+        cartridge.cpu_write(0x9000, 0x8F);
+        cartridge.cpu_write(0x9002, 0x80);
+        assert!((cartridge.audio_output() - 0.149).abs() < 0.001);
+        // Halted channels keep their level
+        cartridge.cpu_write(0x9003, 0x01);
+        cartridge.cpu_clock();
+        assert!(cartridge.audio_output() > 0.0);
+    }
+}
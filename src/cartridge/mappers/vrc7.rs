@@ -0,0 +1,236 @@
+use crate::apu::opll::Opll;
+use crate::cartridge::Mirroring;
+use crate::cartridge::mapper::{CartridgeMemory, Mapper};
+use crate::cpu::{Byte, Word};
+
+use super::vrc_irq::VrcIrq;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// Konami VRC7: https://www.nesdev.org/wiki/VRC7
+// Three switchable 8 KiB PRG banks, eight 1 KiB CHR banks, the VRC IRQ and an OPLL FM
+// synthesizer. VRC7b selects the second register of each pair with A3, VRC7a with A4.
+// Images without a submapper decode both lines.
+pub struct Vrc7 {
+    memory: CartridgeMemory,
+    // CPU address bits selecting the second register
+    select_mask: Word,
+    prg_banks: [Byte; 3],
+    chr_banks: [Byte; 8],
+    // $E000: RS-- --MM, R WRAM enable, S sound reset, MM mirroring
+    control: Byte,
+    irq: VrcIrq,
+    opll: Opll,
+}
+
+// CPU address bits selecting the second register of each pair for a NES 2.0 submapper
+pub fn select_mask(submapper: Byte) -> Word {
+    match submapper {
+        1 => 0x0008,
+        2 => 0x0010,
+        _ => 0x0018,
+    }
+}
+
+// Maps a CPU address to the $x000/$x010 register layout of VRC7a, and $9030 for the sound data
+// port. Also used by the VGM logger to find the writes to the sound chip.
+pub fn register(select_mask: Word, address: Word) -> Word {
+    let second = if address & select_mask != 0 { 0x0010 } else { 0x0000 };
+    if address & 0xF000 == 0x9000 && address & 0x0020 != 0 {
+        return 0x9020 | second;
+    }
+    return (address & 0xF000) | second;
+}
+
+impl Vrc7 {
+    pub fn build_vrc7(memory: CartridgeMemory, submapper: Byte) -> Vrc7 {
+        return Vrc7 {
+            memory,
+            select_mask: select_mask(submapper),
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::build_vrc_irq(),
+            opll: Opll::build_opll(),
+        };
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        return self.control & 0x80 != 0;
+    }
+
+    fn sound_reset(&self) -> bool {
+        return self.control & 0x40 != 0;
+    }
+
+    fn chr_bank_for(&self, address: Word) -> usize {
+        return usize::from(self.chr_banks[usize::from(address / 0x400)]);
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.memory.read_prg_ram(address),
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[usize::from((address - 0x8000) / 0x2000)];
+                self.memory.read_prg(usize::from(bank & 0x3F), PRG_BANK_SIZE, address)
+            }
+            0xE000..=0xFFFF => self.memory.read_prg(self.memory.prg_bank_count(PRG_BANK_SIZE) - 1, PRG_BANK_SIZE, address),
+            _ => open_bus,
+        }
+    }
+
+    fn cpu_write(&mut self, address: Word, data: Byte) {
+        if let 0x6000..=0x7FFF = address {
+            if self.prg_ram_enabled() {
+                self.memory.write_prg_ram(address, data);
+            }
+            return;
+        }
+        if address < 0x8000 {
+            return;
+        }
+        let register = register(self.select_mask, address);
+        match register {
+            0x8000 => self.prg_banks[0] = data,
+            0x8010 => self.prg_banks[1] = data,
+            0x9000 => self.prg_banks[2] = data,
+            // The sound chip ignores writes while held in reset
+            0x9010 if !self.sound_reset() => self.opll.write_address(data),
+            0x9030 if !self.sound_reset() => self.opll.write_data(data),
+            0xA000..=0xD010 => {
+                let index = usize::from((register >> 12) - 0x0A) * 2 + usize::from(register & 0x0010) / 0x10;
+                self.chr_banks[index] = data;
+            }
+            0xE000 => {
+                self.control = data;
+                if self.sound_reset() {
+                    self.opll.reset();
+                }
+            }
+            0xE010 => self.irq.write_latch(data),
+            0xF000 => self.irq.write_control(data),
+            0xF010 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: Word) -> Byte {
+        return self.memory.read_chr(self.chr_bank_for(address), CHR_BANK_SIZE, address);
+    }
+
+    fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.memory.write_chr(self.chr_bank_for(address), CHR_BANK_SIZE, address, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        return &self.memory;
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        return &mut self.memory;
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+        if !self.sound_reset() {
+            self.opll.clock();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        return self.opll.output();
+    }
+
+    fn irq(&self) -> bool {
+        return self.irq.pending();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, Mirroring, tests::build_ines};
+
+    // 8 x 16 KiB PRG, 4 x 8 KiB CHR
+    fn build_vrc7_cartridge(submapper: u8) -> Cartridge {
+        let mut image = build_ines(8, 4, 0x50, 0x58);
+        image[8] = submapper << 4;
+        return Cartridge::from_ines(&image).unwrap();
+    }
+
+    #[test]
+    fn test_vrc7_banking() {
+        for (submapper, second) in [(1, 0x0008), (2, 0x0010), (0, 0x0008), (0, 0x0010)] {
+            let mut cartridge = build_vrc7_cartridge(submapper);
+            cartridge.cpu_write(0x8000, 0x02);
+            cartridge.cpu_write(0x8000 | second, 0x05);
+            cartridge.cpu_write(0x9000, 0x09);
+            assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x01);
+            assert_eq!(cartridge.cpu_read(0xA000, 0x00), 0x02);
+            assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x04);
+            assert_eq!(cartridge.cpu_read(0xE000, 0x00), 0x07);
+            // 1 KiB CHR bank 9 in the second slot of $0000
+            cartridge.cpu_write(0xA000 | second, 0x09);
+            assert_eq!(cartridge.ppu_read(0x0400), 0x81);
+            cartridge.cpu_write(0xD000 | second, 0x1F);
+            assert_eq!(cartridge.ppu_read(0x1C00), 0x83);
+        }
+    }
+
+    #[test]
+    fn test_vrc7_control() {
+        let mut cartridge = build_vrc7_cartridge(2);
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000, 0xFF), 0xFF);
+        cartridge.cpu_write(0xE000, 0x81);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000, 0xFF), 0x42);
+        cartridge.cpu_write(0xE000, 0x82);
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenLower);
+    }
+
+    #[test]
+    fn test_vrc7_irq() {
+        let mut cartridge = build_vrc7_cartridge(2);
+        cartridge.cpu_write(0xE010, 0xFE);
+        cartridge.cpu_write(0xF000, 0x06);
+        cartridge.cpu_clock();
+        assert!(!cartridge.irq());
+        cartridge.cpu_clock();
+        assert!(cartridge.irq());
+        cartridge.cpu_write(0xF010, 0x00);
+        assert!(!cartridge.irq());
+    }
+
+    #[test]
+    fn test_vrc7_audio() {
+        let mut cartridge = build_vrc7_cartridge(2);
+        // Channel 0: instrument 1 at full volume, key on
+        for (register, data) in [(0x30, 0x10), (0x10, 0x80), (0x20, 0x19)] {
+            cartridge.cpu_write(0x9010, register);
+            cartridge.cpu_write(0x9030, data);
+        }
+        let mut peak: f32 = 0.0;
+        for _ in 0..36 * 2000 {
+            cartridge.cpu_clock();
+            peak = peak.max(cartridge.audio_output().abs());
+        }
+        assert!(peak > 0.01);
+        // Sound reset silences the chip
+        cartridge.cpu_write(0xE000, 0x40);
+        cartridge.cpu_clock();
+        assert_eq!(cartridge.audio_output(), 0.0);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::cpu::Byte;
+
+// The prescaler divides the CPU clock by 113.667 to approximate one scanline
+const PRESCALER_PERIOD: i16 = 341;
+const PRESCALER_STEP: i16 = 3;
+
+// IRQ counter shared by VRC4, VRC6 and VRC7: https://www.nesdev.org/wiki/VRC_IRQ
+// An 8 bit counter counting up from the latch, clocked either by every CPU cycle or by a
+// prescaler approximating scanlines. An IRQ is raised when it overflows.
+pub struct VrcIrq {
+    latch: Byte,
+    counter: Byte,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub fn build_vrc_irq() -> VrcIrq {
+        return VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_PERIOD,
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    pub fn write_latch(&mut self, data: Byte) {
+        self.latch = data;
+    }
+
+    // VRC4 loads the latch one nibble at a time
+    pub fn write_latch_low(&mut self, data: Byte) {
+        self.latch = (self.latch & 0xF0) | (data & 0x0F);
+    }
+
+    pub fn write_latch_high(&mut self, data: Byte) {
+        self.latch = (self.latch & 0x0F) | (data << 4);
+    }
+
+    // ---- -MEA: M cycle mode, E enable, A enable again after acknowledge
+    pub fn write_control(&mut self, data: Byte) {
+        self.enable_after_ack = data & 0x01 != 0;
+        self.enabled = data & 0x02 != 0;
+        self.cycle_mode = data & 0x04 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock_counter();
+            return;
+        }
+        self.prescaler -= PRESCALER_STEP;
+        if self.prescaler <= 0 {
+            self.prescaler += PRESCALER_PERIOD;
+            self.clock_counter();
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    pub fn pending(&self) -> bool {
+        return self.pending;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VrcIrq;
+
+    #[test]
+    fn test_cycle_mode() {
+        let mut irq = VrcIrq::build_vrc_irq();
+        irq.write_latch(0xFD);
+        irq.write_control(0x06);
+        irq.clock();
+        irq.clock();
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(irq.pending());
+        // Reloaded from the latch
+        irq.acknowledge();
+        irq.clock();
+        irq.clock();
+        irq.clock();
+        assert!(!irq.pending());
+    }
+
+    #[test]
+    fn test_scanline_mode() {
+        let mut irq = VrcIrq::build_vrc_irq();
+        irq.write_latch_low(0x0F);
+        irq.write_latch_high(0x0F);
+        irq.write_control(0x03);
+        // 341 / 3 rounded up: one scanline worth of CPU cycles
+        for _ in 0..113 {
+            irq.clock();
+        }
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(irq.pending());
+        // The A flag keeps it enabled after the acknowledge
+        irq.acknowledge();
+        for _ in 0..114 {
+            irq.clock();
+        }
+        assert!(irq.pending());
+    }
+}
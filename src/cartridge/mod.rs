@@ -0,0 +1,365 @@
+use std::fmt::{self, Display};
+
+use crate::{cpu::{Byte, Word}, memory::MemoryMapped};
+
+use self::mapper::{CartridgeMemory, Mapper};
+
+pub mod mapper;
+pub mod mappers;
+
+// iNES and NES 2.0 file layout: https://www.nesdev.org/wiki/NES_2.0
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_ROM_BANK_SIZE: usize = 0x4000;
+const CHR_ROM_BANK_SIZE: usize = 0x2000;
+const DEFAULT_PRG_RAM_SIZE: usize = 0x2000;
+const DEFAULT_CHR_RAM_SIZE: usize = 0x2000;
+// Above the largest size the regular NES 2.0 notation can express, no real board comes close
+const MAX_ROM_SIZE: usize = 0x400_0000;
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
+}
+
+impl Mirroring {
+    // Translates a PPU address in $2000-$2FFF into an offset of the nametable RAM.
+    // Four screen boards carry 2 extra KiB on the cartridge which are addressed right after CIRAM.
+    pub fn nametable_offset(&self, address: Word) -> usize {
+        let address = usize::from(address & 0x0FFF);
+        let table = address / 0x400;
+        let offset = address % 0x400;
+        let physical_table = match self {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        return physical_table * 0x400 + offset;
+    }
+}
+
+// CPU/PPU timing the image was made for (NES 2.0 byte 12, iNES byte 9)
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum TvSystem {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum CartridgeError {
+    InvalidHeader,
+    Truncated { expected: usize, found: usize },
+    MissingPrgRom,
+    RomTooLarge,
+    UnsupportedMapper(u16),
+}
+
+impl Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::InvalidHeader => write!(f, "missing iNES header"),
+            CartridgeError::Truncated { expected, found } => write!(f, "ROM image is truncated: expected {} bytes, found {}", expected, found),
+            CartridgeError::MissingPrgRom => write!(f, "the header declares no PRG ROM"),
+            CartridgeError::RomTooLarge => write!(f, "ROM size in the header is larger than {} bytes", MAX_ROM_SIZE),
+            CartridgeError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub mapper: u16,
+    pub submapper: Byte,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub nes2: bool,
+    pub tv_system: TvSystem,
+}
+
+impl Header {
+    pub fn parse(data: &[Byte]) -> Result<Header, CartridgeError> {
+        if data.len() < HEADER_SIZE || &data[0..4] != b"NES\x1A" {
+            return Err(CartridgeError::InvalidHeader);
+        }
+        let flags6 = data[6];
+        let flags7 = data[7];
+        let nes2 = flags7 & 0x0C == 0x08;
+        let mirroring = if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let mut header = Header {
+            prg_rom_size: usize::from(data[4]) * PRG_ROM_BANK_SIZE,
+            chr_rom_size: usize::from(data[5]) * CHR_ROM_BANK_SIZE,
+            prg_ram_size: DEFAULT_PRG_RAM_SIZE,
+            prg_nvram_size: 0,
+            chr_ram_size: if data[5] == 0 { DEFAULT_CHR_RAM_SIZE } else { 0 },
+            mapper: Word::from(flags6 >> 4) | Word::from(flags7 & 0xF0),
+            submapper: 0,
+            mirroring,
+            battery: flags6 & 0x02 != 0,
+            trainer: flags6 & 0x04 != 0,
+            nes2,
+            tv_system: if data[9] & 0x01 != 0 { TvSystem::Pal } else { TvSystem::Ntsc },
+        };
+        if nes2 {
+            header.mapper |= Word::from(data[8] & 0x0F) << 8;
+            header.submapper = data[8] >> 4;
+            header.prg_rom_size = Header::nes2_rom_size(data[4], data[9] & 0x0F, PRG_ROM_BANK_SIZE)?;
+            header.chr_rom_size = Header::nes2_rom_size(data[5], data[9] >> 4, CHR_ROM_BANK_SIZE)?;
+            header.prg_ram_size = Header::nes2_ram_size(data[10] & 0x0F);
+            header.prg_nvram_size = Header::nes2_ram_size(data[10] >> 4);
+            header.chr_ram_size = Header::nes2_ram_size(data[11] & 0x0F);
+            header.tv_system = match data[12] & 0x03 {
+                0 => TvSystem::Ntsc,
+                1 => TvSystem::Pal,
+                2 => TvSystem::MultiRegion,
+                _ => TvSystem::Dendy,
+            };
+        }
+        return Ok(header);
+    }
+
+    // Sizes whose MSB nibble is $F use the exponent-multiplier notation, which can describe sizes
+    // up to 7 * 2^63 bytes: those beyond MAX_ROM_SIZE come from a corrupt or crafted header
+    fn nes2_rom_size(lsb: Byte, msb: Byte, unit: usize) -> Result<usize, CartridgeError> {
+        if msb == 0x0F {
+            let exponent = u32::from(lsb >> 2);
+            let multiplier = usize::from(lsb & 0x03) * 2 + 1;
+            return usize::checked_pow(2, exponent)
+                .and_then(|size| size.checked_mul(multiplier))
+                .filter(|size| *size <= MAX_ROM_SIZE)
+                .ok_or(CartridgeError::RomTooLarge);
+        }
+        return Ok((usize::from(msb) << 8 | usize::from(lsb)) * unit);
+    }
+
+    fn nes2_ram_size(shift: Byte) -> usize {
+        if shift == 0 {
+            return 0;
+        }
+        return 64 << shift;
+    }
+}
+
+pub struct Cartridge {
+    header: Header,
+    mapper: Box<dyn Mapper>,
+}
+
+impl Cartridge {
+    pub fn from_ines(data: &[Byte]) -> Result<Cartridge, CartridgeError> {
+        let header = Header::parse(data)?;
+        // There would be nothing for the CPU to run
+        if header.prg_rom_size == 0 {
+            return Err(CartridgeError::MissingPrgRom);
+        }
+        let prg_start = HEADER_SIZE + if header.trainer { TRAINER_SIZE } else { 0 };
+        let chr_start = prg_start + header.prg_rom_size;
+        let expected = chr_start + header.chr_rom_size;
+        if data.len() < expected {
+            return Err(CartridgeError::Truncated { expected, found: data.len() });
+        }
+        let prg_rom = data[prg_start..chr_start].to_vec();
+        let chr_is_ram = header.chr_rom_size == 0;
+        let chr = if chr_is_ram {
+            vec![0x00; header.chr_ram_size.max(DEFAULT_CHR_RAM_SIZE)]
+        } else {
+            data[chr_start..expected].to_vec()
+        };
+        let prg_ram = vec![0x00; (header.prg_ram_size + header.prg_nvram_size).max(DEFAULT_PRG_RAM_SIZE)];
+        let memory = CartridgeMemory { prg_rom, chr, chr_is_ram, prg_ram, mirroring: header.mirroring };
+        let mapper = mapper::build_mapper(&header, memory)?;
+        return Ok(Cartridge { header, mapper });
+    }
+
+    pub fn header(&self) -> &Header {
+        return &self.header;
+    }
+
+    // Boards with mapper controlled mirroring override the solder pad setting of the header
+    pub fn mirroring(&self) -> Mirroring {
+        return self.mapper.mirroring();
+    }
+
+    pub fn irq(&self) -> bool {
+        return self.mapper.irq();
+    }
+
+    pub fn cpu_clock(&mut self) {
+        self.mapper.cpu_clock();
+    }
+
+    // Contents of the battery backed PRG RAM, to be saved when the game is closed
+    pub fn battery_ram(&self) -> Option<&[Byte]> {
+        if !self.header.battery {
+            return None;
+        }
+        return Some(&self.mapper.memory().prg_ram);
+    }
+
+    pub fn load_battery_ram(&mut self, data: &[Byte]) {
+        let prg_ram = &mut self.mapper.memory_mut().prg_ram;
+        let length = data.len().min(prg_ram.len());
+        prg_ram[..length].copy_from_slice(&data[..length]);
+    }
+
+    pub fn cpu_read(&mut self, address: Word, open_bus: Byte) -> Byte {
+        return self.mapper.cpu_read(address, open_bus);
+    }
+
+    pub fn cpu_peek(&self, address: Word, open_bus: Byte) -> Byte {
+        return self.mapper.cpu_peek(address, open_bus);
+    }
+
+    pub fn cpu_write(&mut self, address: Word, data: Byte) {
+        self.mapper.cpu_write(address, data);
+    }
+
+    pub fn ppu_read(&mut self, address: Word) -> Byte {
+        return self.mapper.ppu_read(address);
+    }
+
+    pub fn ppu_write(&mut self, address: Word, data: Byte) {
+        self.mapper.ppu_write(address, data);
+    }
+
+    pub fn read_nametable(&mut self, address: Word, ciram: &[Byte]) -> Byte {
+        return self.mapper.read_nametable(address, ciram);
+    }
+
+    pub fn write_nametable(&mut self, address: Word, data: Byte, ciram: &mut [Byte]) {
+        self.mapper.write_nametable(address, data, ciram);
+    }
+
+    pub fn ppu_register_write(&mut self, address: Word, data: Byte) {
+        self.mapper.ppu_register_write(address, data);
+    }
+
+    pub fn audio_output(&self) -> f32 {
+        return self.mapper.audio_output();
+    }
+}
+
+impl MemoryMapped for Cartridge {
+    fn read_byte(&mut self, address: Word, open_bus: Byte) -> Byte {
+        return self.cpu_read(address, open_bus);
+    }
+
+    fn write_byte(&mut self, address: Word, data: Byte) {
+        self.cpu_write(address, data);
+    }
+
+    fn peek_byte(&self, address: Word, open_bus: Byte) -> Byte {
+        return self.cpu_peek(address, open_bus);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // Builds an iNES image with the given flags. PRG banks are filled with their bank number.
+    pub fn build_ines(prg_banks: Byte, chr_banks: Byte, flags6: Byte, flags7: Byte) -> Vec<Byte> {
+        let mut image = vec![b'N', b'E', b'S', 0x1A, prg_banks, chr_banks, flags6, flags7, 0, 0, 0, 0, 0, 0, 0, 0];
+        for bank in 0..prg_banks {
+            image.extend(vec![bank; PRG_ROM_BANK_SIZE]);
+        }
+        for bank in 0..chr_banks {
+            image.extend(vec![0x80 | bank; CHR_ROM_BANK_SIZE]);
+        }
+        return image;
+    }
+
+    #[test]
+    fn test_parse_ines_header() {
+        let image = build_ines(2, 1, 0x03, 0x00);
+        let header = Header::parse(&image).unwrap();
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x2000);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert!(header.battery);
+        assert!(!header.nes2);
+        assert_eq!(header.mapper, 0);
+        assert_eq!(header.tv_system, TvSystem::Ntsc);
+    }
+
+    #[test]
+    fn test_parse_nes2_header() {
+        let mut image = build_ines(1, 0, 0x10, 0x48);
+        image[8] = 0x31;
+        image[10] = 0x70;
+        image[11] = 0x07;
+        image[12] = 0x03;
+        let header = Header::parse(&image).unwrap();
+        assert!(header.nes2);
+        assert_eq!(header.mapper, 0x141);
+        assert_eq!(header.submapper, 3);
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+        assert_eq!(header.chr_ram_size, 0x2000);
+        assert_eq!(header.tv_system, TvSystem::Dendy);
+
+        // Exponent-multiplier notation: 2^14 * 3
+        image[4] = 14 << 2 | 0x01;
+        image[9] = 0x0F;
+        assert_eq!(Header::parse(&image).unwrap().prg_rom_size, 0xC000);
+    }
+
+    #[test]
+    fn test_invalid_images() {
+        assert_eq!(Cartridge::from_ines(&[0x00; 16]).err(), Some(CartridgeError::InvalidHeader));
+        let mut image = build_ines(2, 1, 0x00, 0x00);
+        image.truncate(0x5000);
+        assert_eq!(Cartridge::from_ines(&image).err(), Some(CartridgeError::Truncated { expected: 0xA010, found: 0x5000 }));
+        assert_eq!(Cartridge::from_ines(&build_ines(1, 1, 0xF0, 0xF0)).err(), Some(CartridgeError::UnsupportedMapper(0xFF)));
+        assert_eq!(Cartridge::from_ines(&build_ines(0, 1, 0x00, 0x00)).err(), Some(CartridgeError::MissingPrgRom));
+        // NES 2.0 sizes of 7 * 2^63 and 2^40 bytes
+        let mut image = build_ines(1, 1, 0x00, 0x08);
+        image[4] = 0xFF;
+        image[9] = 0x0F;
+        assert_eq!(Cartridge::from_ines(&image).err(), Some(CartridgeError::RomTooLarge));
+        image[4] = 40 << 2;
+        assert_eq!(Cartridge::from_ines(&image).err(), Some(CartridgeError::RomTooLarge));
+    }
+
+    #[test]
+    fn test_nrom_128_mirrors_prg() {
+        let mut cartridge = Cartridge::from_ines(&build_ines(1, 1, 0x00, 0x00)).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000, 0x00), 0x00);
+        assert_eq!(cartridge.cpu_read(0xC000, 0x00), 0x00);
+        cartridge.cpu_write(0x6010, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6010, 0x00), 0x42);
+        // CHR ROM is not writable
+        cartridge.ppu_write(0x0010, 0x42);
+        assert_eq!(cartridge.ppu_read(0x0010), 0x80);
+    }
+
+    #[test]
+    fn test_nametable_mirroring() {
+        assert_eq!(Mirroring::Horizontal.nametable_offset(0x2400), 0x000);
+        assert_eq!(Mirroring::Horizontal.nametable_offset(0x2800), 0x400);
+        assert_eq!(Mirroring::Vertical.nametable_offset(0x2800), 0x000);
+        assert_eq!(Mirroring::Vertical.nametable_offset(0x2C05), 0x405);
+        assert_eq!(Mirroring::FourScreen.nametable_offset(0x2C05), 0xC05);
+    }
+}
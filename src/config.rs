@@ -0,0 +1,401 @@
+use std::{
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+use toml::{Table, Value};
+
+use crate::{checksum::crc32, controller::Buttons, cpu::Byte, nes::region::Region};
+
+// Settings of the emulator, read from a TOML file where every entry is optional:
+//
+//     cpu = "2a03"
+//     region = "auto"            # or "ntsc", "pal", "dendy"
+//     [video]
+//     scale = 3
+//     palette = "smooth.pal"
+//     ntsc_filter = false
+//     [audio]
+//     sample_rate = 48000
+//     latency = 60               # milliseconds
+//     mute = false
+//     [paths]
+//     saves = "saves"
+//     screenshots = "screenshots"
+//     [player1]                  # also [player2]
+//     a = "X"
+//     b = ["Z", "NumPad1"]
+//     [hotkeys]
+//     pause = "P"
+//     [rom.1A2B3C4D]             # CRC-32 of the ROM without its iNES header
+//     region = "pal"
+//
+// Key names are those of the frontend, the library keeps them as text. Relative paths are
+// relative to the directory of the file.
+
+const BUTTON_NAMES: [(&str, Buttons); 8] = [
+    ("a", Buttons::A),
+    ("b", Buttons::B),
+    ("select", Buttons::SELECT),
+    ("start", Buttons::START),
+    ("up", Buttons::UP),
+    ("down", Buttons::DOWN),
+    ("left", Buttons::LEFT),
+    ("right", Buttons::RIGHT),
+];
+
+const INES_HEADER_SIZE: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    Io(String),
+    Parse(String),
+    // Dotted name of the setting and what is wrong with it
+    Invalid(String, String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(message) => write!(f, "could not read the configuration: {}", message),
+            ConfigError::Parse(message) => write!(f, "invalid TOML: {}", message),
+            ConfigError::Invalid(key, message) => write!(f, "{}: {}", key, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// The console CPU without decimal mode, the only one emulated so far
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum CpuVariant {
+    Rp2a03,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct VideoConfig {
+    // Initial window size in multiples of the picture
+    pub scale: usize,
+    // None for the built-in colors
+    pub palette: Option<PathBuf>,
+    pub ntsc_filter: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AudioConfig {
+    pub sample_rate: u32,
+    pub latency: u32,
+    pub mute: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PathsConfig {
+    // Battery backed RAM, one file per ROM
+    pub saves: PathBuf,
+    pub screenshots: PathBuf,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Hotkeys {
+    pub pause: String,
+    pub reset: String,
+    pub load_rom: String,
+    pub screenshot: String,
+    pub quit: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Config {
+    pub cpu: CpuVariant,
+    // None follows the ROM header
+    pub region: Option<Region>,
+    pub video: VideoConfig,
+    pub audio: AudioConfig,
+    pub paths: PathsConfig,
+    // Key names held for each button of players 1 and 2
+    pub players: [Vec<(String, Buttons)>; 2],
+    pub hotkeys: Hotkeys,
+    // Settings of single ROMs by CRC-32, applied by `for_rom`
+    rom_overrides: Vec<(u32, Table)>,
+    base_directory: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        let keys = |names: [&str; 8]| -> Vec<(String, Buttons)> {
+            return names.iter().zip(BUTTON_NAMES).map(|(key, (_, button))| (key.to_string(), button)).collect();
+        };
+        return Config {
+            cpu: CpuVariant::Rp2a03,
+            region: None,
+            video: VideoConfig { scale: 3, palette: None, ntsc_filter: false },
+            audio: AudioConfig { sample_rate: 48_000, latency: 60, mute: false },
+            paths: PathsConfig { saves: PathBuf::from("saves"), screenshots: PathBuf::from("screenshots") },
+            players: [
+                keys(["X", "Z", "RightShift", "Enter", "Up", "Down", "Left", "Right"]),
+                keys(["K", "J", "U", "I", "W", "S", "A", "D"]),
+            ],
+            hotkeys: Hotkeys {
+                pause: String::from("P"),
+                reset: String::from("F5"),
+                load_rom: String::from("O"),
+                screenshot: String::from("F12"),
+                quit: String::from("Escape"),
+            },
+            rom_overrides: Vec::new(),
+            base_directory: PathBuf::new(),
+        };
+    }
+}
+
+// Name of a ROM in the overrides, from the data after the iNES header
+pub fn rom_hash(rom: &[Byte]) -> u32 {
+    return crc32(rom.get(INES_HEADER_SIZE..).unwrap_or_default());
+}
+
+fn invalid(key: &str, message: impl Into<String>) -> ConfigError {
+    return ConfigError::Invalid(key.to_string(), message.into());
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        return name.to_string();
+    }
+    return format!("{}.{}", prefix, name);
+}
+
+fn as_table<'a>(key: &str, value: &'a Value) -> Result<&'a Table, ConfigError> {
+    return value.as_table().ok_or_else(|| invalid(key, "expected a section"));
+}
+
+fn as_str<'a>(key: &str, value: &'a Value) -> Result<&'a str, ConfigError> {
+    return value.as_str().ok_or_else(|| invalid(key, "expected a string"));
+}
+
+fn as_bool(key: &str, value: &Value) -> Result<bool, ConfigError> {
+    return value.as_bool().ok_or_else(|| invalid(key, "expected true or false"));
+}
+
+fn as_integer_in(key: &str, value: &Value, range: std::ops::RangeInclusive<i64>) -> Result<i64, ConfigError> {
+    let number = value.as_integer().ok_or_else(|| invalid(key, "expected a whole number"))?;
+    if !range.contains(&number) {
+        return Err(invalid(key, format!("must be between {} and {}", range.start(), range.end())));
+    }
+    return Ok(number);
+}
+
+// One key name or a list of them
+fn as_key_names(key: &str, value: &Value) -> Result<Vec<String>, ConfigError> {
+    return match value {
+        Value::String(name) => Ok(vec![name.clone()]),
+        Value::Array(names) => names.iter().map(|name| as_str(key, name).map(String::from)).collect(),
+        _ => Err(invalid(key, "expected a key name or a list of them")),
+    };
+}
+
+pub fn parse_region(key: &str, name: &str) -> Result<Option<Region>, ConfigError> {
+    return match name.to_ascii_lowercase().as_str() {
+        "auto" => Ok(None),
+        "ntsc" => Ok(Some(Region::Ntsc)),
+        "pal" => Ok(Some(Region::Pal)),
+        "dendy" => Ok(Some(Region::Dendy)),
+        _ => Err(invalid(key, format!("unknown region {}, expected auto, ntsc, pal or dendy", name))),
+    };
+}
+
+impl Config {
+    // `base_directory` is where relative paths start
+    pub fn parse(text: &str, base_directory: &Path) -> Result<Config, ConfigError> {
+        let table = text.parse::<Table>().map_err(|error| ConfigError::Parse(error.message().to_string()))?;
+        let mut config = Config { base_directory: base_directory.to_path_buf(), ..Config::default() };
+        config.apply(&table, "", true)?;
+        return Ok(config);
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|error| ConfigError::Io(format!("{}: {}", path.display(), error)))?;
+        return Config::parse(&text, path.parent().unwrap_or(Path::new("")));
+    }
+
+    // The settings with the overrides of a ROM, given as the whole iNES file
+    pub fn for_rom(&self, rom: &[Byte]) -> Config {
+        let mut config = self.clone();
+        let hash = rom_hash(rom);
+        for (_, table) in self.rom_overrides.iter().filter(|(rom_hash, _)| *rom_hash == hash) {
+            // Checked when the file was parsed
+            config.apply(table, &format!("rom.{:08X}", hash), false).unwrap();
+        }
+        return config;
+    }
+
+    fn path(&self, key: &str, value: &Value) -> Result<PathBuf, ConfigError> {
+        return Ok(self.base_directory.join(as_str(key, value)?));
+    }
+
+    // Only the top level of the file holds the settings that apply before a ROM is loaded
+    fn apply(&mut self, table: &Table, prefix: &str, top_level: bool) -> Result<(), ConfigError> {
+        for (name, value) in table.iter() {
+            let key = join(prefix, name);
+            match name.as_str() {
+                "cpu" => {
+                    self.cpu = match as_str(&key, value)?.to_ascii_lowercase().as_str() {
+                        "2a03" | "rp2a03" => CpuVariant::Rp2a03,
+                        variant => return Err(invalid(&key, format!("unsupported CPU {}, only the 2A03 is emulated", variant))),
+                    };
+                }
+                "region" => self.region = parse_region(&key, as_str(&key, value)?)?,
+                "video" => self.apply_video(as_table(&key, value)?, &key)?,
+                "player1" | "player2" => {
+                    let player = if name == "player1" { 0 } else { 1 };
+                    for (button_name, keys) in as_table(&key, value)?.iter() {
+                        let button_key = join(&key, button_name);
+                        let (_, button) = BUTTON_NAMES.iter().find(|(known, _)| known == button_name)
+                            .ok_or_else(|| invalid(&button_key, "unknown button, expected a, b, select, start, up, down, left or right"))?;
+                        let keys = as_key_names(&button_key, keys)?;
+                        self.players[player].retain(|(_, bound)| bound != button);
+                        self.players[player].extend(keys.into_iter().map(|key| (key, *button)));
+                    }
+                }
+                "audio" | "paths" | "hotkeys" | "rom" if !top_level => {
+                    return Err(invalid(&key, "can not be set for a single ROM"));
+                }
+                "audio" => {
+                    for (setting, value) in as_table(&key, value)?.iter() {
+                        let setting_key = join(&key, setting);
+                        match setting.as_str() {
+                            "sample_rate" => self.audio.sample_rate = as_integer_in(&setting_key, value, 8_000..=192_000)? as u32,
+                            "latency" => self.audio.latency = as_integer_in(&setting_key, value, 1..=1_000)? as u32,
+                            "mute" => self.audio.mute = as_bool(&setting_key, value)?,
+                            _ => return Err(invalid(&setting_key, "unknown setting")),
+                        }
+                    }
+                }
+                "paths" => {
+                    for (setting, value) in as_table(&key, value)?.iter() {
+                        let setting_key = join(&key, setting);
+                        match setting.as_str() {
+                            "saves" => self.paths.saves = self.path(&setting_key, value)?,
+                            "screenshots" => self.paths.screenshots = self.path(&setting_key, value)?,
+                            _ => return Err(invalid(&setting_key, "unknown setting")),
+                        }
+                    }
+                }
+                "hotkeys" => {
+                    for (hotkey, value) in as_table(&key, value)?.iter() {
+                        let hotkey_key = join(&key, hotkey);
+                        let binding = match hotkey.as_str() {
+                            "pause" => &mut self.hotkeys.pause,
+                            "reset" => &mut self.hotkeys.reset,
+                            "load_rom" => &mut self.hotkeys.load_rom,
+                            "screenshot" => &mut self.hotkeys.screenshot,
+                            "quit" => &mut self.hotkeys.quit,
+                            _ => return Err(invalid(&hotkey_key, "unknown hotkey, expected pause, reset, load_rom, screenshot or quit")),
+                        };
+                        *binding = as_str(&hotkey_key, value)?.to_string();
+                    }
+                }
+                "rom" => {
+                    for (hash, settings) in as_table(&key, value)?.iter() {
+                        let rom_key = join(&key, hash);
+                        let hash = u32::from_str_radix(hash, 16).ok().filter(|_| hash.len() == 8)
+                            .ok_or_else(|| invalid(&rom_key, "expected the CRC-32 of the ROM as 8 hexadecimal digits"))?;
+                        let settings = as_table(&rom_key, settings)?;
+                        // Checks the settings now, `for_rom` can then not fail
+                        self.clone().apply(settings, &rom_key, false)?;
+                        self.rom_overrides.push((hash, settings.clone()));
+                    }
+                }
+                _ => return Err(invalid(&key, "unknown setting")),
+            }
+        }
+        return Ok(());
+    }
+
+    fn apply_video(&mut self, table: &Table, prefix: &str) -> Result<(), ConfigError> {
+        for (setting, value) in table.iter() {
+            let key = join(prefix, setting);
+            match setting.as_str() {
+                "scale" => self.video.scale = as_integer_in(&key, value, 1..=16)? as usize,
+                "palette" => self.video.palette = Some(self.path(&key, value)?),
+                "ntsc_filter" => self.video.ntsc_filter = as_bool(&key, value)?,
+                _ => return Err(invalid(&key, "unknown setting")),
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::{controller::Buttons, nes::region::Region};
+
+    use super::{Config, ConfigError, rom_hash};
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::parse("", Path::new("")).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.region, None);
+        assert_eq!(config.players[0][0], (String::from("X"), Buttons::A));
+        assert_eq!(config.hotkeys.pause, "P");
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = r#"
+            region = "pal"
+            [video]
+            scale = 2
+            palette = "colors.pal"
+            [audio]
+            sample_rate = 44100
+            [paths]
+            saves = "/var/saves"
+            [player2]
+            a = ["L", "NumPad1"]
+            [hotkeys]
+            reset = "R"
+        "#;
+        let config = Config::parse(text, Path::new("/home/user/.config")).unwrap();
+        assert_eq!(config.region, Some(Region::Pal));
+        assert_eq!(config.video.scale, 2);
+        assert_eq!(config.video.palette, Some(PathBuf::from("/home/user/.config/colors.pal")));
+        assert_eq!(config.audio.sample_rate, 44_100);
+        assert_eq!(config.audio.latency, 60);
+        assert_eq!(config.paths.saves, PathBuf::from("/var/saves"));
+        let a_keys: Vec<&str> = config.players[1].iter().filter(|(_, button)| *button == Buttons::A).map(|(key, _)| key.as_str()).collect();
+        assert_eq!(a_keys, vec!["L", "NumPad1"]);
+        assert_eq!(config.players[1].len(), 9);
+        assert_eq!(config.hotkeys.reset, "R");
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| Config::parse(text, Path::new("")).unwrap_err().to_string();
+        assert_eq!(error("[video]\nscale = 0"), "video.scale: must be between 1 and 16");
+        assert_eq!(error("[video]\nscale = \"big\""), "video.scale: expected a whole number");
+        assert_eq!(error("region = \"secam\""), "region: unknown region secam, expected auto, ntsc, pal or dendy");
+        assert_eq!(error("cpu = \"65c02\""), "cpu: unsupported CPU 65c02, only the 2A03 is emulated");
+        assert_eq!(error("[audio]\nvolume = 3"), "audio.volume: unknown setting");
+        assert_eq!(error("[player1]\njump = \"X\""), "player1.jump: unknown button, expected a, b, select, start, up, down, left or right");
+        assert_eq!(error("[rom.1234]\nregion = \"pal\""), "rom.1234: expected the CRC-32 of the ROM as 8 hexadecimal digits");
+        assert_eq!(error("[rom.00000000.audio]\nmute = true"), "rom.00000000.audio: can not be set for a single ROM");
+        assert!(matches!(Config::parse("scale = [", Path::new("")), Err(ConfigError::Parse(_))));
+    }
+
+    #[test]
+    fn test_rom_overrides() {
+        let mut rom = vec![0x00; 16];
+        rom.extend_from_slice(b"123456789");
+        assert_eq!(rom_hash(&rom), 0xCBF4_3926);
+        let text = "region = \"ntsc\"\n[rom.cbf43926]\nregion = \"pal\"\n[rom.cbf43926.video]\nntsc_filter = true\n";
+        let config = Config::parse(text, Path::new("")).unwrap();
+        assert_eq!(config.region, Some(Region::Ntsc));
+        let rom_config = config.for_rom(&rom);
+        assert_eq!(rom_config.region, Some(Region::Pal));
+        assert!(rom_config.video.ntsc_filter);
+        assert_eq!(config.for_rom(&[0x00; 32]).region, Some(Region::Ntsc));
+    }
+}
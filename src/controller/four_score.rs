@@ -0,0 +1,91 @@
+use crate::cpu::Byte;
+
+use super::{Buttons, Controller, InputDevice};
+
+// NES Four Score: https://www.nesdev.org/wiki/Four_player_adapters
+// Each port serializes 24 bits: the first pad, the second pad, then a signature telling
+// the game an adapter is present ($10 on $4016, $20 on $4017, sent MSB first).
+// One FourScore value handles one port, so the adapter is made of two of them.
+pub struct FourScore {
+    pads: [Controller; 2],
+    signature: Byte,
+    read_count: u8,
+    strobe: bool,
+}
+
+impl FourScore {
+    // `port` is the port this half of the adapter is connected to (0 for $4016, 1 for $4017)
+    pub fn build_four_score(port: usize) -> FourScore {
+        return FourScore {
+            pads: [Controller::build_controller(), Controller::build_controller()],
+            signature: if port == 0 { 0x10 } else { 0x20 },
+            read_count: 0,
+            strobe: false,
+        }
+    }
+}
+
+impl InputDevice for FourScore {
+    fn write_strobe(&mut self, data: Byte) {
+        self.strobe = data & 0x01 != 0;
+        for pad in self.pads.iter_mut() {
+            pad.write_strobe(data);
+        }
+        if self.strobe {
+            self.read_count = 0;
+        }
+    }
+
+    fn read(&mut self) -> Byte {
+        if self.strobe {
+            return self.pads[0].read();
+        }
+        let data = match self.read_count {
+            0..=7 => self.pads[0].read(),
+            8..=15 => self.pads[1].read(),
+            16..=23 => (self.signature >> (23 - self.read_count)) & 0x01,
+            _ => 0x01,
+        };
+        self.read_count = self.read_count.saturating_add(1);
+        return data;
+    }
+
+    fn set_pad_buttons(&mut self, pad: usize, buttons: Buttons) {
+        if let Some(controller) = self.pads.get_mut(pad) {
+            controller.set_buttons(buttons);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::{Buttons, InputDevice};
+
+    use super::FourScore;
+
+    fn read_sequence(four_score: &mut FourScore) -> Vec<u8> {
+        four_score.write_strobe(0x01);
+        four_score.write_strobe(0x00);
+        return (0..24).map(|_| four_score.read()).collect();
+    }
+
+    #[test]
+    fn test_pads_and_signature() {
+        let mut four_score = FourScore::build_four_score(0);
+        four_score.set_pad_buttons(0, Buttons::A);
+        four_score.set_pad_buttons(1, Buttons::RIGHT);
+        assert_eq!(read_sequence(&mut four_score), vec![
+            1, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 1,
+            0, 0, 0, 1, 0, 0, 0, 0,
+        ]);
+        assert_eq!(four_score.read(), 1);
+    }
+
+    #[test]
+    fn test_second_port_signature() {
+        let mut four_score = FourScore::build_four_score(1);
+        let reads = read_sequence(&mut four_score);
+        assert_eq!(reads[16..24], [0, 0, 1, 0, 0, 0, 0, 0]);
+    }
+}
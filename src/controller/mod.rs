@@ -0,0 +1,215 @@
+pub mod four_score;
+pub mod power_pad;
+pub mod zapper;
+
+use std::{cell::RefCell, rc::Rc};
+
+use bitflags::bitflags;
+
+use crate::cpu::{Byte, Word};
+
+// Standard joypad protocol: https://www.nesdev.org/wiki/Standard_controller
+// Writing 1 to bit 0 of $4016 keeps the shift registers reloading with the current buttons,
+// writing 0 freezes them so they can be read serially, one button per read of $4016/$4017.
+
+// Anything that can be plugged in a controller port. Reads return the D0-D4 lines of the port,
+// the upper bits are never driven by the device.
+pub trait InputDevice {
+    fn write_strobe(&mut self, data: Byte);
+    fn read(&mut self) -> Byte;
+    // Devices carrying joypads (standard controller, Four Score) update the given pad, others ignore it
+    fn set_pad_buttons(&mut self, _pad: usize, _buttons: Buttons) {}
+}
+
+pub type SharedInputDevice = Rc<RefCell<dyn InputDevice>>;
+
+bitflags! {
+    pub struct Buttons: Byte {
+        const A = 0b0000_0001;
+        const B = 0b0000_0010;
+        const SELECT = 0b0000_0100;
+        const START = 0b0000_1000;
+        const UP = 0b0001_0000;
+        const DOWN = 0b0010_0000;
+        const LEFT = 0b0100_0000;
+        const RIGHT = 0b1000_0000;
+    }
+}
+
+pub struct Controller {
+    buttons: Buttons,
+    shift_register: Byte,
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn build_controller() -> Controller {
+        return Controller {
+            buttons: Buttons::empty(),
+            shift_register: 0x00,
+            strobe: false,
+        }
+    }
+
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift_register = self.buttons.bits();
+        }
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        return self.buttons;
+    }
+
+    pub fn write_strobe(&mut self, data: Byte) {
+        self.strobe = data & 0x01 != 0;
+        if self.strobe {
+            self.shift_register = self.buttons.bits();
+        }
+    }
+
+    // Returns the serial data line (bit 0). Official pads report 1 once all 8 buttons are shifted out.
+    pub fn read(&mut self) -> Byte {
+        if self.strobe {
+            return self.buttons.bits() & 0x01;
+        }
+        let data = self.shift_register & 0x01;
+        self.shift_register = (self.shift_register >> 1) | 0x80;
+        return data;
+    }
+}
+
+impl InputDevice for Controller {
+    fn write_strobe(&mut self, data: Byte) {
+        Controller::write_strobe(self, data);
+    }
+
+    fn read(&mut self) -> Byte {
+        return Controller::read(self);
+    }
+
+    fn set_pad_buttons(&mut self, pad: usize, buttons: Buttons) {
+        if pad == 0 {
+            self.set_buttons(buttons);
+        }
+    }
+}
+
+// The two controller ports on the front of the console, read at $4016 and $4017
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Port {
+    One,
+    Two,
+}
+
+impl Port {
+    fn index(self) -> usize {
+        return match self {
+            Port::One => 0,
+            Port::Two => 1,
+        };
+    }
+}
+
+// Both controller ports, each one holding whatever device is plugged in (a standard pad by default).
+// The upper 3 bits of a read are left floating and keep the previous value of the data bus.
+pub struct ControllerPorts {
+    ports: [Option<SharedInputDevice>; 2],
+}
+
+impl ControllerPorts {
+    pub fn build_controller_ports() -> ControllerPorts {
+        return ControllerPorts {
+            ports: [
+                Some(Rc::new(RefCell::new(Controller::build_controller()))),
+                Some(Rc::new(RefCell::new(Controller::build_controller()))),
+            ],
+        }
+    }
+
+    pub fn plug(&mut self, port: Port, device: SharedInputDevice) {
+        self.ports[port.index()] = Some(device);
+    }
+
+    pub fn unplug(&mut self, port: Port) {
+        self.ports[port.index()] = None;
+    }
+
+    // Players 1 and 2 are the first pad of each port, players 3 and 4 the second pad of a Four Score
+    pub fn set_buttons(&mut self, player: usize, buttons: Buttons) {
+        if let Some(device) = &self.ports[player % 2] {
+            device.borrow_mut().set_pad_buttons(player / 2, buttons);
+        }
+    }
+
+    // $4016 writes reach both ports
+    pub fn write_strobe(&mut self, data: Byte) {
+        for device in self.ports.iter().flatten() {
+            device.borrow_mut().write_strobe(data);
+        }
+    }
+
+    // $4016 reads port 1, $4017 reads port 2
+    pub fn read(&mut self, address: Word, open_bus: Byte) -> Byte {
+        let port = usize::from(address - 0x4016);
+        let data = match &self.ports[port] {
+            Some(device) => device.borrow_mut().read() & 0x1F,
+            None => 0x00,
+        };
+        return (open_bus & 0xE0) | data;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Buttons, Controller, ControllerPorts, Port};
+
+    #[test]
+    fn test_serial_read_order() {
+        let mut controller = Controller::build_controller();
+        controller.set_buttons(Buttons::A | Buttons::START | Buttons::RIGHT);
+        controller.write_strobe(0x01);
+        controller.write_strobe(0x00);
+        let reads: Vec<u8> = (0..8).map(|_| controller.read()).collect();
+        assert_eq!(reads, vec![1, 0, 0, 1, 0, 0, 0, 1]);
+        // Past the 8th read the shift register is filled with ones
+        assert_eq!(controller.read(), 1);
+        assert_eq!(controller.read(), 1);
+    }
+
+    #[test]
+    fn test_strobe_high_returns_a() {
+        let mut controller = Controller::build_controller();
+        controller.write_strobe(0x01);
+        controller.set_buttons(Buttons::A);
+        assert_eq!(controller.read(), 1);
+        assert_eq!(controller.read(), 1);
+        controller.set_buttons(Buttons::B);
+        assert_eq!(controller.read(), 0);
+    }
+
+    #[test]
+    fn test_buttons_latched_on_strobe_fall() {
+        let mut controller = Controller::build_controller();
+        controller.set_buttons(Buttons::B);
+        controller.write_strobe(0x01);
+        controller.write_strobe(0x00);
+        // Changes after the latch are only seen on the next strobe
+        controller.set_buttons(Buttons::A);
+        assert_eq!(controller.read(), 0);
+        assert_eq!(controller.read(), 1);
+    }
+
+    #[test]
+    fn test_ports_open_bus() {
+        let mut ports = ControllerPorts::build_controller_ports();
+        ports.set_buttons(1, Buttons::A);
+        ports.write_strobe(0x01);
+        ports.write_strobe(0x00);
+        assert_eq!(ports.read(0x4016, 0x40), 0x40);
+        assert_eq!(ports.read(0x4017, 0x40), 0x41);
+        ports.unplug(Port::Two);
+        assert_eq!(ports.read(0x4017, 0xFF), 0xE0);
+    }
+}
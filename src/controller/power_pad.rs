@@ -0,0 +1,129 @@
+use crate::cpu::Byte;
+
+use super::InputDevice;
+
+// Power Pad / Family Trainer mat: https://www.nesdev.org/wiki/Power_Pad
+// The 12 buttons are split in two shift registers read on D3 and D4 of the port:
+//   D3: buttons 2, 1, 5, 9, 6, 10, 11, 7
+//   D4: buttons 4, 3, 12, 8, followed by 1s
+// Button numbers follow side B of the mat, a set bit means the button is stepped on.
+const D3_ORDER: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const D4_ORDER: [u8; 4] = [4, 3, 12, 8];
+const BUTTON_COUNT: u8 = 12;
+
+pub struct PowerPad {
+    // Bit n - 1 holds button n
+    pressed: u16,
+    shift_d3: Byte,
+    shift_d4: Byte,
+    strobe: bool,
+}
+
+impl PowerPad {
+    pub fn build_power_pad() -> PowerPad {
+        return PowerPad {
+            pressed: 0x0000,
+            shift_d3: 0x00,
+            shift_d4: 0x00,
+            strobe: false,
+        }
+    }
+
+    // Buttons are numbered 1 to 12, other numbers are not on the mat and are ignored
+    pub fn set_pressed(&mut self, button: u8, pressed: bool) {
+        if !(1..=BUTTON_COUNT).contains(&button) {
+            return;
+        }
+        let mask = 1 << (button - 1);
+        if pressed {
+            self.pressed |= mask;
+        } else {
+            self.pressed &= !mask;
+        }
+    }
+
+    pub fn set_all_pressed(&mut self, pressed: u16) {
+        self.pressed = pressed & 0x0FFF;
+    }
+
+    fn is_pressed(&self, button: u8) -> bool {
+        return self.pressed & (1 << (button - 1)) != 0;
+    }
+
+    fn latch(&mut self) {
+        self.shift_d3 = 0x00;
+        for (bit, button) in D3_ORDER.iter().enumerate() {
+            if self.is_pressed(*button) {
+                self.shift_d3 |= 1 << bit;
+            }
+        }
+        // Only 4 buttons on the second register, the rest of it reads as 1
+        self.shift_d4 = 0xF0;
+        for (bit, button) in D4_ORDER.iter().enumerate() {
+            if self.is_pressed(*button) {
+                self.shift_d4 |= 1 << bit;
+            }
+        }
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn write_strobe(&mut self, data: Byte) {
+        self.strobe = data & 0x01 != 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    fn read(&mut self) -> Byte {
+        if self.strobe {
+            self.latch();
+        }
+        let data = ((self.shift_d3 & 0x01) << 3) | ((self.shift_d4 & 0x01) << 4);
+        if !self.strobe {
+            self.shift_d3 = (self.shift_d3 >> 1) | 0x80;
+            self.shift_d4 = (self.shift_d4 >> 1) | 0x80;
+        }
+        return data;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::InputDevice;
+
+    use super::PowerPad;
+
+    #[test]
+    fn test_serial_order() {
+        let mut power_pad = PowerPad::build_power_pad();
+        power_pad.set_pressed(1, true);
+        power_pad.set_pressed(12, true);
+        power_pad.set_pressed(7, true);
+        power_pad.write_strobe(0x01);
+        power_pad.write_strobe(0x00);
+        let reads: Vec<u8> = (0..8).map(|_| power_pad.read()).collect();
+        assert_eq!(reads, vec![0x00, 0x08, 0x10, 0x00, 0x10, 0x10, 0x10, 0x18]);
+        // Both registers are exhausted
+        assert_eq!(power_pad.read(), 0x18);
+    }
+
+    #[test]
+    fn test_release_button() {
+        let mut power_pad = PowerPad::build_power_pad();
+        power_pad.set_all_pressed(0x0002);
+        power_pad.write_strobe(0x01);
+        assert_eq!(power_pad.read(), 0x08);
+        power_pad.set_pressed(2, false);
+        assert_eq!(power_pad.read(), 0x00);
+    }
+
+    #[test]
+    fn test_out_of_range_buttons() {
+        let mut power_pad = PowerPad::build_power_pad();
+        power_pad.set_pressed(0, true);
+        power_pad.set_pressed(13, true);
+        power_pad.set_pressed(255, true);
+        assert_eq!(power_pad.pressed, 0x0000);
+    }
+}
@@ -0,0 +1,144 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{cpu::{Byte, Word}, ppu::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH}};
+
+use super::InputDevice;
+
+// Zapper light gun: https://www.nesdev.org/wiki/Zapper
+//   D3: light sensed (0: detected, 1: not detected)
+//   D4: trigger (0: released, 1: pulled)
+// The photodiode reacts to the beam drawing bright pixels close to where the gun is aimed
+// and keeps its output for a number of scanlines afterwards.
+const SENSE_SCANLINES: u16 = 20;
+const SENSE_RADIUS: i32 = 2;
+
+pub struct Zapper {
+    ppu: Rc<RefCell<Ppu>>,
+    // Aimed pixel, None when pointing away from the screen
+    aim: Option<(u16, u16)>,
+    trigger: bool,
+}
+
+impl Zapper {
+    pub fn build_zapper(ppu: Rc<RefCell<Ppu>>) -> Zapper {
+        return Zapper {
+            ppu,
+            aim: None,
+            trigger: false,
+        }
+    }
+
+    pub fn set_aim(&mut self, aim: Option<(u16, u16)>) {
+        self.aim = aim;
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    // Palette entries in the two brightest rows, leaving out the blacks and grays of columns $D-$F
+    fn is_bright(pixel: Word) -> bool {
+        let color = pixel & 0x3F;
+        return color >= 0x20 && color & 0x0F < 0x0D;
+    }
+
+    fn light_detected(&self) -> bool {
+        let (x, y) = match self.aim {
+            Some(aim) => aim,
+            None => return false,
+        };
+        let ppu = self.ppu.borrow();
+        let scanline = ppu.scanline();
+        // The beam has to be on the aimed line past the aimed pixel, or a few lines below it
+        if scanline < y || scanline - y > SENSE_SCANLINES || (scanline == y && ppu.dot() <= x) {
+            return false;
+        }
+        let frame_buffer = ppu.frame_buffer();
+        for dy in -SENSE_RADIUS..=SENSE_RADIUS {
+            for dx in -SENSE_RADIUS..=SENSE_RADIUS {
+                let pixel_x = i32::from(x) + dx;
+                let pixel_y = i32::from(y) + dy;
+                if pixel_x < 0 || pixel_y < 0 || pixel_x >= SCREEN_WIDTH as i32 || pixel_y >= SCREEN_HEIGHT as i32 {
+                    continue;
+                }
+                if Zapper::is_bright(frame_buffer[pixel_y as usize * SCREEN_WIDTH + pixel_x as usize]) {
+                    return true;
+                }
+            }
+        }
+        return false;
+    }
+}
+
+impl InputDevice for Zapper {
+    fn write_strobe(&mut self, _data: Byte) {}
+
+    fn read(&mut self) -> Byte {
+        let light: Byte = if self.light_detected() { 0x00 } else { 0x08 };
+        let trigger: Byte = if self.trigger { 0x10 } else { 0x00 };
+        return light | trigger;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{cartridge::{Cartridge, tests::build_ines}, controller::InputDevice, memory::MemoryMapped, ppu::Ppu};
+
+    use super::Zapper;
+
+    // PPU showing a full screen of the given backdrop color
+    fn build_test_ppu(backdrop: u8) -> Rc<RefCell<Ppu>> {
+        let cartridge = Cartridge::from_ines(&build_ines(1, 0, 0x00, 0x00)).unwrap();
+        let mut ppu = Ppu::build_ppu(Rc::new(RefCell::new(cartridge)));
+        ppu.write_byte(0x2006, 0x3F);
+        ppu.write_byte(0x2006, 0x00);
+        ppu.write_byte(0x2007, backdrop);
+        ppu.write_byte(0x2006, 0x00);
+        ppu.write_byte(0x2006, 0x00);
+        ppu.write_byte(0x2001, 0x0A);
+        return Rc::new(RefCell::new(ppu));
+    }
+
+    fn run_to(ppu: &Rc<RefCell<Ppu>>, scanline: u16) {
+        while ppu.borrow().scanline() != scanline {
+            ppu.borrow_mut().clock();
+        }
+    }
+
+    #[test]
+    fn test_trigger() {
+        let mut zapper = Zapper::build_zapper(build_test_ppu(0x0F));
+        assert_eq!(zapper.read(), 0x08);
+        zapper.set_trigger(true);
+        assert_eq!(zapper.read(), 0x18);
+    }
+
+    #[test]
+    fn test_light_follows_beam() {
+        let ppu = build_test_ppu(0x30);
+        let mut zapper = Zapper::build_zapper(ppu.clone());
+        zapper.set_aim(Some((128, 100)));
+        run_to(&ppu, 90);
+        assert_eq!(zapper.read(), 0x08);
+        run_to(&ppu, 105);
+        assert_eq!(zapper.read(), 0x00);
+        // Output decays once the beam is far below the target
+        run_to(&ppu, 130);
+        assert_eq!(zapper.read(), 0x08);
+        // Off screen aim never sees light
+        run_to(&ppu, 105);
+        zapper.set_aim(None);
+        assert_eq!(zapper.read(), 0x08);
+    }
+
+    #[test]
+    fn test_dark_screen() {
+        let ppu = build_test_ppu(0x0F);
+        let mut zapper = Zapper::build_zapper(ppu.clone());
+        zapper.set_aim(Some((128, 100)));
+        run_to(&ppu, 105);
+        assert_eq!(zapper.read(), 0x08);
+    }
+}
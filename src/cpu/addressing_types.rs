@@ -65,7 +65,7 @@ mod cpu {
 
     use byteorder::{LittleEndian, ByteOrder};
 
-    use crate::{cpu::{addressing_types::Addressing, Cpu, Word, Byte, CpuStatusFlags}, memory::{Memory}};
+    use crate::{cpu::{addressing_types::Addressing, Cpu, Word, Byte, CpuStatusFlags, IRQ_VECTOR}, memory::{Memory}};
 
     use super::AddressingType;
     
@@ -236,27 +236,32 @@ mod cpu {
 
         fn implied_addressing(&mut self, _memory: &mut Memory) {}
 
+        // BRK skips its padding byte, hardware interrupts return to the instruction they interrupted
         fn interrupt_setup_addressing(&mut self, memory: &mut Memory) {
+            let return_address = if self.hardware_interrupt.is_some() { self.pc } else { self.pc + 1 };
+            let vector = self.hardware_interrupt.unwrap_or(IRQ_VECTOR);
             match self.tcu {
                 2 => {
                     let mut pc_buff: [Byte; 2] = [0,0];
-                    LittleEndian::write_u16(&mut pc_buff, self.pc + 1);
+                    LittleEndian::write_u16(&mut pc_buff, return_address);
                     self.stack_push(memory, pc_buff[1]);
                 }
                 3 => {
                     let mut pc_buff: [Byte; 2] = [0,0];
-                    LittleEndian::write_u16(&mut pc_buff, self.pc + 1);
+                    LittleEndian::write_u16(&mut pc_buff, return_address);
                     self.stack_push(memory, pc_buff[0]);
                 }
                 4 => {
-                    self.stack_push(memory, self.ps.bits);
+                    let mut status = self.ps;
+                    status.set(CpuStatusFlags::B, self.hardware_interrupt.is_none());
+                    self.stack_push(memory, status.bits);
                     self.ps.set(CpuStatusFlags::I, true);
                 }
                 5 => {
-                    self.pc = Word::from(memory.read_byte(0xFFFE));
+                    self.pc = Word::from(memory.read_byte(vector));
                 }
                 6 => {
-                    self.pc = Word::from(memory.read_byte(0xFFFF)) << 8;
+                    self.pc += Word::from(memory.read_byte(vector + 1)) << 8;
                 }
                 _ => {}
             }
@@ -60,4 +60,37 @@ mod tests {
         memory.assert_byte(0x01FD, ps.bits());
         
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_hardware_interrupt() {
+        let mut test_memory = Memory::build_memory();
+        test_memory.write_word(INT_VECTOR, START_INTERRUPT);
+        test_memory.write_word(crate::test_utils::RESET_VECTOR_ADDRESS, crate::test_utils::START_PROGRAM);
+        test_memory.write_byte(crate::test_utils::START_PROGRAM, 0xEA);
+        for offset in 0..8 {
+            test_memory.write_byte(START_INTERRUPT + offset, 0xEA);
+        }
+
+        let mut test_cpu = Cpu::build_cpu();
+        test_cpu.reset(&test_memory);
+        test_cpu.set_irq_line(true);
+        for _ in 0..7 {
+            test_cpu.exec_cycle(&mut test_memory);
+        }
+
+        let cpu = TestCpu::clone_from_cpu(&test_cpu);
+        let memory = TestMemory::clone_from_memory(&test_memory);
+        cpu.assert_pc(START_INTERRUPT);
+        cpu.assert_register(Register::SP, 0xFF - 3);
+        // The interrupt is taken before the first instruction, which is where RTI returns to.
+        // B is clear in the pushed status.
+        memory.assert_word(0x01FE, crate::test_utils::START_PROGRAM);
+        memory.assert_byte(0x01FD, 0b0010_0000);
+        // The line is still held but the I flag now masks it
+        for _ in 0..7 {
+            test_cpu.exec_cycle(&mut test_memory);
+        }
+        assert_ne!(test_cpu.get_program_counter(), START_INTERRUPT);
+        assert_eq!(test_cpu.get_register(Register::SP), 0xFF - 3);
+    }
+}
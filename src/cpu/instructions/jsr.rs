@@ -5,7 +5,7 @@ use byteorder::{LittleEndian, ByteOrder};
 use crate::{cpu::{Byte, addressing_types::AddressingType, instruction_set::{Instruction}, Cpu}, memory::Memory};
 
 pub enum Opcode {
-    Abs = 0x4C,
+    Abs = 0x20,
 }
 
 impl Into<Byte> for Opcode {
@@ -3,11 +3,11 @@ use std::collections::HashMap;
 use crate::{cpu::{Byte, addressing_types::AddressingType, instruction_set::{Instruction}, Cpu, CpuStatusFlags, SByte}, memory::Memory};
 
 pub enum Opcode {
-    Zp = 0xA6,
-    Imm = 0xA2,
-    Abs = 0xAE,
-    AbsXIdx = 0xBE,
-    ZpXIdx = 0xB6,
+    Zp = 0xA4,
+    Imm = 0xA0,
+    Abs = 0xAC,
+    AbsXIdx = 0xBC,
+    ZpXIdx = 0xB4,
 }
 
 impl Into<Byte> for Opcode {
@@ -77,7 +77,42 @@ impl crate::cpu::Cpu{
         } else {
             panic!("Missing addressing type for instruction LDY!");
         }
-        self.ps.set(CpuStatusFlags::Z, (self.alu as SByte) < 0);
-        self.ps.set(CpuStatusFlags::N, self.alu == 0);
+        self.ps.set(CpuStatusFlags::Z, self.alu == 0);
+        self.ps.set(CpuStatusFlags::N, (self.alu as SByte) < 0);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu::{Byte, Cpu, CpuStatusFlags, Register, instructions::{ldx, ldy}}, memory::Memory, test_utils::TestCpu};
+
+    #[test]
+    fn test_ldy_does_not_shadow_ldx() {
+        let mut memory = Memory::build_memory();
+        memory.write_word(0xFFFC, 0x8000);
+        // LDX #$05, LDY #$00, LDY #$80
+        memory.write_byte(0x8000, ldx::Opcode::Imm as Byte);
+        memory.write_byte(0x8001, 0x05);
+        memory.write_byte(0x8002, ldy::Opcode::Imm as Byte);
+        memory.write_byte(0x8003, 0x00);
+        memory.write_byte(0x8004, ldy::Opcode::Imm as Byte);
+        memory.write_byte(0x8005, 0x80);
+        let mut cpu = Cpu::build_cpu();
+        cpu.reset(&memory);
+        for _ in 0..5 {
+            cpu.exec_cycle(&mut memory);
+        }
+        let test_cpu = TestCpu::clone_from_cpu(&cpu);
+        test_cpu.assert_register(Register::X, 0x05);
+        test_cpu.assert_register(Register::Y, 0x00);
+        test_cpu.assert_status(CpuStatusFlags::Z, true);
+        test_cpu.assert_status(CpuStatusFlags::N, false);
+        for _ in 0..2 {
+            cpu.exec_cycle(&mut memory);
+        }
+        let test_cpu = TestCpu::clone_from_cpu(&cpu);
+        test_cpu.assert_register(Register::Y, 0x80);
+        test_cpu.assert_status(CpuStatusFlags::Z, false);
+        test_cpu.assert_status(CpuStatusFlags::N, true);
+    }
+}
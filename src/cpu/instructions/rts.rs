@@ -31,7 +31,7 @@ impl crate::cpu::Cpu{
                 self.pc = Word::from(self.stack_pull(memory));
             }
             2 => {
-                self.pc = Word::from(self.stack_pull(memory)) << 8;
+                self.pc |= Word::from(self.stack_pull(memory)) << 8;
             }
             3 => {
                 self.pc += 1;
@@ -39,4 +39,32 @@ impl crate::cpu::Cpu{
             _ => {}
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu::{Byte, Cpu, instructions::{jsr, rts}}, memory::Memory};
+
+    #[test]
+    fn test_subroutine_round_trip() {
+        let mut memory = Memory::build_memory();
+        memory.write_word(0xFFFC, 0x8000);
+        // JSR $9034 at $8000, the subroutine is a single RTS
+        memory.write_byte(0x8000, jsr::Opcode::Abs as Byte);
+        memory.write_word(0x8001, 0x9034);
+        memory.write_byte(0x9034, rts::Opcode::Sta as Byte);
+        let mut cpu = Cpu::build_cpu();
+        cpu.reset(&memory);
+        for _ in 0..7 {
+            cpu.exec_cycle(&mut memory);
+        }
+        // The return address pushed is the last byte of the JSR, the RTS opcode is already fetched
+        assert_eq!(memory.read_word(0x01FE), 0x8002);
+        assert_eq!(cpu.get_program_counter(), 0x9035);
+        for _ in 0..6 {
+            cpu.exec_cycle(&mut memory);
+        }
+        // Back in the caller with the opcode after the JSR fetched
+        assert_eq!(cpu.get_program_counter(), 0x8004);
+    }
+}
@@ -22,6 +22,10 @@ pub type Word = u16;
 // Signed Variations. Will be used sparsely during instructions whenever needed
 pub type SByte = i8;
 
+pub const NMI_VECTOR: Word = 0xFFFA;
+pub const RESET_VECTOR: Word = 0xFFFC;
+pub const IRQ_VECTOR: Word = 0xFFFE;
+
 bitflags! {
     pub struct CpuStatusFlags: Byte {
         const C = 0b0000_0001; // CARRY FLAG
@@ -41,6 +45,23 @@ impl Display for CpuStatusFlags {
     }
 }
 
+// Registers visible to programs, for debuggers and test runners
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub a: Byte,
+    pub x: Byte,
+    pub y: Byte,
+    pub pc: Word,
+    pub sp: Byte,
+    pub ps: CpuStatusFlags,
+}
+
+impl Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PC:{:04X}", self.a, self.x, self.y, self.ps.bits, self.sp, self.pc);
+    }
+}
+
 pub struct Cpu {
     // internal registers
     ir: Byte,
@@ -49,6 +70,13 @@ pub struct Cpu {
     addressing: Word,
     reset: bool,
 
+    // Interrupt lines
+    nmi_line: bool,
+    nmi_pending: bool,
+    irq_line: bool,
+    // Vector of the hardware interrupt being serviced, BRK leaves it empty
+    hardware_interrupt: Option<Word>,
+
     // Registers
     a: Byte,
     x: Byte,
@@ -82,6 +110,11 @@ impl Cpu {
             addressing: 0x0000,
             reset: false,
 
+            nmi_line: false,
+            nmi_pending: false,
+            irq_line: false,
+            hardware_interrupt: None,
+
             a: 0x00,
             x: 0x00,
             y: 0x00,
@@ -101,9 +134,25 @@ impl Cpu {
         self.ps.set(CpuStatusFlags::B, true);
         self.sp = 0xff;
 
-        self.pc = memory.read_word(0xfffc);
+        self.pc = memory.read_word(RESET_VECTOR);
 
         self.reset = true;
+        self.nmi_pending = false;
+        self.hardware_interrupt = None;
+    }
+
+    // NMI is edge triggered: only a transition from high to low on /NMI (here low to high on the line) is latched
+    pub fn set_nmi_line(&mut self, level: bool) {
+        if level && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = level;
+    }
+
+    // IRQ is level triggered: it is serviced between instructions as long as the line is held
+    // and the I flag is clear. Several sources (APU, cartridge) share the line wired-OR.
+    pub fn set_irq_line(&mut self, level: bool) {
+        self.irq_line = level;
     }
 
     pub fn exec_cycle(&mut self, memory: &mut Memory) {
@@ -128,8 +177,34 @@ impl Cpu {
         }
     }
 
+    pub fn registers(&self) -> Registers {
+        return Registers { a: self.a, x: self.x, y: self.y, pc: self.pc, sp: self.sp, ps: self.ps };
+    }
+
+    // Address of the opcode fetched on the last cycle, None on the other cycles of an instruction
+    // and when an interrupt is taken instead
+    pub fn fetched_opcode_address(&self) -> Option<Word> {
+        if self.tcu != 0 || self.reset || self.hardware_interrupt.is_some() {
+            return None;
+        }
+        return Some(self.pc.wrapping_sub(1));
+    }
+
     fn fetch_instruction(&mut self, memory: &Memory) {
         self.tcu = 0;
+        self.hardware_interrupt = None;
+        if self.nmi_pending {
+            // Hardware interrupts are polled between instructions and reuse the BRK sequence
+            self.nmi_pending = false;
+            self.hardware_interrupt = Some(NMI_VECTOR);
+            self.ir = instructions::brk::Opcode::IntSetup as Byte;
+            return
+        }
+        if self.irq_line && !self.ps.contains(CpuStatusFlags::I) {
+            self.hardware_interrupt = Some(IRQ_VECTOR);
+            self.ir = instructions::brk::Opcode::IntSetup as Byte;
+            return
+        }
         self.ir = memory.read_byte(self.pc);
         self.pc += 1;
     }
@@ -0,0 +1,14 @@
+pub mod apu;
+pub mod audio;
+pub mod cartridge;
+pub mod checksum;
+pub mod config;
+pub mod controller;
+pub mod cpu;
+pub mod memory;
+pub mod nes;
+pub mod nsf;
+pub mod ppu;
+pub mod test_utils;
+pub mod vgm;
+pub mod video;
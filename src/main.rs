@@ -1,10 +1,5 @@
-mod cpu;
-mod memory;
-pub mod test_utils;
-
-use cpu::Cpu;
-
-use crate::memory::Memory;
+use cpu6502emu::cpu::Cpu;
+use cpu6502emu::memory::Memory;
 
 fn main() {
     let mut memory: Memory = Memory::build_memory();
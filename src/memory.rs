@@ -1,3 +1,5 @@
+use std::{cell::{Cell, RefCell}, rc::Rc};
+
 use byteorder::{LittleEndian, ByteOrder};
 
 // NOTE: ALL 16-bit data is written in the Little Endian form in memory
@@ -6,39 +8,122 @@ use byteorder::{LittleEndian, ByteOrder};
 
 use crate::cpu::{Byte, Word};
 
+// Any component sitting on the CPU bus (PPU registers, APU, cartridge...) implements this trait
+// and gets mapped on a range of addresses. Reads receive the last value seen on the data bus so
+// devices that only drive some of the lines can leave the rest floating (open bus).
+pub trait MemoryMapped {
+    fn read_byte(&mut self, address: Word, open_bus: Byte) -> Byte;
+    fn write_byte(&mut self, address: Word, data: Byte);
+    // What a read would return without its side effects, for debuggers and memory dumps.
+    // Devices that do not override it show the open bus.
+    fn peek_byte(&self, _address: Word, open_bus: Byte) -> Byte {
+        return open_bus;
+    }
+}
+
+pub type SharedDevice = Rc<RefCell<dyn MemoryMapped>>;
+
+#[derive(Clone)]
+struct DeviceMapping {
+    start: Word,
+    end: Word,
+    device: SharedDevice,
+}
+
 #[derive(Clone, Copy)]
+struct MirrorMapping {
+    start: Word,
+    end: Word,
+    size: Word,
+}
+
+#[derive(Clone)]
 pub struct Memory {
-    data: [Byte; usize::pow(2,16)]
+    data: [Byte; usize::pow(2,16)],
+    devices: Vec<DeviceMapping>,
+    mirrors: Vec<MirrorMapping>,
+    open_bus: Cell<Byte>,
 }
 
 impl Memory {
     pub fn build_memory() -> Memory {
         return Memory {
             data: [0x00; usize::pow(2, 16)],
+            devices: Vec::new(),
+            mirrors: Vec::new(),
+            open_bus: Cell::new(0x00),
         }
     }
 
+    // Routes every access inside [start, end] to the given device. Later mappings take precedence,
+    // and one on the exact range of an earlier mapping replaces it.
+    pub fn map_device(&mut self, start: Word, end: Word, device: SharedDevice) {
+        self.devices.retain(|mapping| mapping.start != start || mapping.end != end);
+        self.devices.push(DeviceMapping { start, end, device });
+    }
+
+    // Makes the range [start, end] repeat the first `size` bytes from `start` (e.g. the 2 KiB of NES RAM)
+    pub fn mirror(&mut self, start: Word, end: Word, size: Word) {
+        self.mirrors.push(MirrorMapping { start, end, size });
+    }
+
+    pub fn open_bus(&self) -> Byte {
+        return self.open_bus.get();
+    }
+
     pub fn read_byte(&self, address: Word) -> Byte {
-        let index = usize::from(address);
-        let result = Byte::from(self.data[index]);
+        let address = self.resolve_mirror(address);
+        let result = match self.find_device(address) {
+            Some(device) => device.borrow_mut().read_byte(address, self.open_bus.get()),
+            None => Byte::from(self.data[usize::from(address)]),
+        };
+        self.open_bus.set(result);
         return result;
     }
 
+    // Like `read_byte`, but leaves the devices and the open bus untouched
+    pub fn peek_byte(&self, address: Word) -> Byte {
+        let address = self.resolve_mirror(address);
+        return match self.find_device(address) {
+            Some(device) => device.borrow().peek_byte(address, self.open_bus.get()),
+            None => self.data[usize::from(address)],
+        };
+    }
+
     pub fn read_word(&self, address: Word) -> Word {
-        let data: [Byte; 2] = [self.data[usize::from(address)], self.data[usize::from(address) + 1]];
+        let data: [Byte; 2] = [self.read_byte(address), self.read_byte(address.wrapping_add(1))];
         return Word::from(LittleEndian::read_u16(&data));
     }
 
     pub fn write_byte(&mut self, address: Word, data: Byte) {
-        let index = usize::from(address);
-        self.data[index] = data;
+        let address = self.resolve_mirror(address);
+        self.open_bus.set(data);
+        match self.find_device(address) {
+            Some(device) => device.borrow_mut().write_byte(address, data),
+            None => self.data[usize::from(address)] = data,
+        }
     }
 
     pub fn write_word(&mut self, address: Word, data: Word) {
         let mut data_arr = [0,0];
         LittleEndian::write_u16(&mut data_arr, data);
         self.write_byte(address, data_arr[0]);
-        self.write_byte(address+1, data_arr[1]);
+        self.write_byte(address.wrapping_add(1), data_arr[1]);
+    }
+
+    fn resolve_mirror(&self, address: Word) -> Word {
+        for mirror in self.mirrors.iter() {
+            if address >= mirror.start && address <= mirror.end {
+                return mirror.start + (address - mirror.start) % mirror.size;
+            }
+        }
+        return address;
+    }
+
+    fn find_device(&self, address: Word) -> Option<&SharedDevice> {
+        return self.devices.iter().rev()
+            .find(|mapping| address >= mapping.start && address <= mapping.end)
+            .map(|mapping| &mapping.device);
     }
 }
 
@@ -49,12 +134,26 @@ mod tests {
     const DATA_BYTE: Byte = 0xEA;
     const DATA_WORD: Word = 0xEAAE;
 
+    struct TestDevice {
+        registers: [Byte; 4],
+    }
+
+    impl MemoryMapped for TestDevice {
+        fn read_byte(&mut self, address: Word, open_bus: Byte) -> Byte {
+            return (self.registers[usize::from(address & 0x03)] & 0x0F) | (open_bus & 0xF0);
+        }
+
+        fn write_byte(&mut self, address: Word, data: Byte) {
+            self.registers[usize::from(address & 0x03)] = data;
+        }
+    }
+
     #[test]
     fn test_read_byte() {
         // setup of memory
         let mut mem_array: [Byte; usize::pow(2,16)] = [0; usize::pow(2,16)];
         mem_array[TEST_ADDRESS] = DATA_BYTE;
-        let memory = Memory { data: mem_array };
+        let memory = Memory { data: mem_array, ..Memory::build_memory() };
         assert_eq!(memory.read_byte(TEST_ADDRESS as Word), DATA_BYTE);
     }
 
@@ -66,14 +165,14 @@ mod tests {
         LittleEndian::write_u16(&mut data_buff, DATA_WORD);
         mem_array[TEST_ADDRESS] = data_buff[0];
         mem_array[TEST_ADDRESS + 1] = data_buff[1];
-        let memory = Memory { data: mem_array };
+        let memory = Memory { data: mem_array, ..Memory::build_memory() };
         assert_eq!(memory.read_word(TEST_ADDRESS as Word), DATA_WORD);
     }
 
     #[test]
     fn test_write_byte() {
         let mem_array: [Byte; usize::pow(2,16)] = [0; usize::pow(2,16)];
-        let mut memory = Memory { data: mem_array };
+        let mut memory = Memory { data: mem_array, ..Memory::build_memory() };
         memory.write_byte(TEST_ADDRESS as Word, DATA_BYTE);
         assert_eq!(memory.data[TEST_ADDRESS], DATA_BYTE);
     }
@@ -81,11 +180,51 @@ mod tests {
     #[test]
     fn test_write_word() {
         let mem_array: [Byte; usize::pow(2,16)] = [0; usize::pow(2,16)];
-        let mut memory = Memory { data: mem_array };
+        let mut memory = Memory { data: mem_array, ..Memory::build_memory() };
         memory.write_word(TEST_ADDRESS as Word, DATA_WORD);
         let mut data_buff: [Byte; 2] = [0,0];
         LittleEndian::write_u16(&mut data_buff, DATA_WORD);
         assert_eq!(memory.data[TEST_ADDRESS], data_buff[0]);
         assert_eq!(memory.data[TEST_ADDRESS + 1], data_buff[1]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_mirrored_range() {
+        let mut memory = Memory::build_memory();
+        memory.mirror(0x0000, 0x1FFF, 0x0800);
+        memory.write_byte(0x0812, DATA_BYTE);
+        assert_eq!(memory.read_byte(0x0012), DATA_BYTE);
+        assert_eq!(memory.read_byte(0x1812), DATA_BYTE);
+        assert_eq!(memory.data[0x0812], 0x00);
+    }
+
+    #[test]
+    fn test_mapped_device() {
+        let mut memory = Memory::build_memory();
+        let device = Rc::new(RefCell::new(TestDevice { registers: [0; 4] }));
+        memory.map_device(0x2000, 0x3FFF, device.clone());
+        memory.write_byte(0x2001, 0x0A);
+        assert_eq!(device.borrow().registers[1], 0x0A);
+        assert_eq!(memory.data[0x2001], 0x00);
+        // Upper bits of the device read come from the last value on the bus
+        memory.write_byte(TEST_ADDRESS as Word, 0x50);
+        memory.read_byte(TEST_ADDRESS as Word);
+        assert_eq!(memory.read_byte(0x3FF9), 0x5A);
+    }
+
+    #[test]
+    fn test_remapped_device() {
+        let mut memory = Memory::build_memory();
+        let first = Rc::new(RefCell::new(TestDevice { registers: [0; 4] }));
+        let second = Rc::new(RefCell::new(TestDevice { registers: [0; 4] }));
+        memory.map_device(0x2000, 0x3FFF, first.clone());
+        memory.map_device(0x2000, 0x2FFF, second.clone());
+        memory.map_device(0x2000, 0x3FFF, second.clone());
+        memory.map_device(0x2000, 0x3FFF, first.clone());
+        assert_eq!(memory.devices.len(), 2);
+        // The narrower mapping is now the older one
+        memory.write_byte(0x2001, 0x0A);
+        assert_eq!(first.borrow().registers[1], 0x0A);
+        assert_eq!(second.borrow().registers[1], 0x00);
+    }
+}
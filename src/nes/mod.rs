@@ -0,0 +1,627 @@
+pub mod region;
+
+use std::{cell::{Ref, RefCell}, rc::Rc};
+
+use crate::{apu::{Apu, AudioChannel, audio_output::{AudioOutput, DEFAULT_SAMPLE_RATE}}, cartridge::Cartridge, controller::{Buttons, ControllerPorts, Port, SharedInputDevice, four_score::FourScore, zapper::Zapper}, cpu::{Byte, Cpu, Word}, memory::{Memory, MemoryMapped}, ppu::Ppu, vgm::{SoundChips, logger::VgmLogger}};
+
+use self::region::Region;
+
+const OAM_DMA_CYCLES: u64 = 513;
+// CPU cycles stolen by a DMC sample fetch, when it does not fall on a write cycle
+const DMC_DMA_CYCLES: u64 = 4;
+
+// Registers at $4000-$401F. The APU owns most of them, but $4014 (OAM DMA) and
+// the controller ports share the range and are dispatched here.
+struct IoRegisters {
+    apu: Rc<RefCell<Apu>>,
+    controllers: Rc<RefCell<ControllerPorts>>,
+    dma_page: Option<Byte>,
+}
+
+impl MemoryMapped for IoRegisters {
+    fn read_byte(&mut self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x4015 => {
+                return self.apu.borrow_mut().read_byte(address, open_bus);
+            }
+            0x4016 | 0x4017 => {
+                return self.controllers.borrow_mut().read(address, open_bus);
+            }
+            _ => {
+                return open_bus;
+            }
+        }
+    }
+
+    fn write_byte(&mut self, address: Word, data: Byte) {
+        match address {
+            0x4014 => {
+                self.dma_page = Some(data);
+            }
+            0x4016 => {
+                self.controllers.borrow_mut().write_strobe(data);
+            }
+            0x4000..=0x4013 | 0x4015 | 0x4017 => {
+                self.apu.borrow_mut().write_byte(address, data);
+            }
+            _ => {}
+        }
+    }
+}
+
+// The whole console: components are clocked from a single master clock so that
+// the CPU/PPU ratio stays exact on every region (3 dots per CPU cycle on NTSC and Dendy, 3.2 on PAL).
+pub struct Nes {
+    cpu: Cpu,
+    memory: Memory,
+    ppu: Rc<RefCell<Ppu>>,
+    apu: Rc<RefCell<Apu>>,
+    cartridge: Rc<RefCell<Cartridge>>,
+    controllers: Rc<RefCell<ControllerPorts>>,
+    io: Rc<RefCell<IoRegisters>>,
+    audio: AudioOutput,
+    // One output per `AudioChannel::ALL` entry, empty unless stems are enabled
+    stems: Vec<AudioOutput>,
+    vgm: Option<VgmLogger>,
+    region: Region,
+
+    master_clock: u64,
+    ppu_clock: u64,
+    cpu_cycles: u64,
+    dma_cycles: u64,
+}
+
+impl Nes {
+    // The region comes from the TV system declared in the ROM header
+    pub fn build_nes(cartridge: Cartridge) -> Nes {
+        let region = Region::from_header(cartridge.header());
+        return Nes::build_nes_with_region(cartridge, region);
+    }
+
+    pub fn build_nes_with_region(cartridge: Cartridge, region: Region) -> Nes {
+        let cartridge = Rc::new(RefCell::new(cartridge));
+        let ppu = Rc::new(RefCell::new(Ppu::build_ppu(cartridge.clone())));
+        ppu.borrow_mut().set_region(region);
+        let apu = Rc::new(RefCell::new(Apu::build_apu()));
+        apu.borrow_mut().set_region(region);
+        let controllers = Rc::new(RefCell::new(ControllerPorts::build_controller_ports()));
+        let io = Rc::new(RefCell::new(IoRegisters { apu: apu.clone(), controllers: controllers.clone(), dma_page: None }));
+
+        // CPU memory map: https://www.nesdev.org/wiki/CPU_memory_map
+        let mut memory = Memory::build_memory();
+        memory.mirror(0x0000, 0x1FFF, 0x0800);
+        memory.map_device(0x2000, 0x3FFF, ppu.clone());
+        memory.map_device(0x4000, 0x401F, io.clone());
+        memory.map_device(0x4020, 0xFFFF, cartridge.clone());
+
+        let mut nes = Nes {
+            cpu: Cpu::build_cpu(),
+            memory,
+            ppu,
+            apu,
+            cartridge,
+            controllers,
+            io,
+            audio: AudioOutput::build_audio_output(region.cpu_clock_rate(), DEFAULT_SAMPLE_RATE),
+            stems: Vec::new(),
+            vgm: None,
+            region,
+            master_clock: 0,
+            ppu_clock: 0,
+            cpu_cycles: 0,
+            dma_cycles: 0,
+        };
+        nes.reset();
+        return nes;
+    }
+
+    pub fn reset(&mut self) {
+        self.ppu.borrow_mut().reset();
+        self.apu.borrow_mut().reset();
+        self.cpu.reset(&self.memory);
+        self.dma_cycles = 0;
+    }
+
+    pub fn region(&self) -> Region {
+        return self.region;
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        return &self.cpu;
+    }
+
+    pub fn memory(&mut self) -> &mut Memory {
+        return &mut self.memory;
+    }
+
+    // What the CPU would read at `address`, without acknowledging interrupts, advancing
+    // controllers or any other side effect of the read. Registers of the PPU, APU and controller
+    // ports show the open bus.
+    pub fn peek_byte(&self, address: Word) -> Byte {
+        return self.memory.peek_byte(address);
+    }
+
+    pub fn ppu(&self) -> Ref<'_, Ppu> {
+        return self.ppu.borrow();
+    }
+
+    pub fn cartridge(&self) -> Ref<'_, Cartridge> {
+        return self.cartridge.borrow();
+    }
+
+    // Buttons held by `player` (0-3), sampled by the game on its next strobe.
+    // Players 3 and 4 are only connected through a Four Score.
+    pub fn set_buttons(&mut self, player: usize, buttons: Buttons) {
+        self.controllers.borrow_mut().set_buttons(player, buttons);
+    }
+
+    pub fn plug_input(&mut self, port: Port, device: SharedInputDevice) {
+        self.controllers.borrow_mut().plug(port, device);
+    }
+
+    pub fn unplug_input(&mut self, port: Port) {
+        self.controllers.borrow_mut().unplug(port);
+    }
+
+    // The Zapper watches the picture, so it is built here where the PPU is reachable.
+    // The returned handle is used to aim and pull the trigger.
+    pub fn plug_zapper(&mut self, port: Port) -> Rc<RefCell<Zapper>> {
+        let zapper = Rc::new(RefCell::new(Zapper::build_zapper(self.ppu.clone())));
+        self.plug_input(port, zapper.clone());
+        return zapper;
+    }
+
+    // The Four Score takes both ports
+    pub fn plug_four_score(&mut self) {
+        self.plug_input(Port::One, Rc::new(RefCell::new(FourScore::build_four_score(0))));
+        self.plug_input(Port::Two, Rc::new(RefCell::new(FourScore::build_four_score(1))));
+    }
+
+    // Host rate the audio is resampled to
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.audio.set_rates(self.region.cpu_clock_rate(), sample_rate);
+        for stem in self.stems.iter_mut() {
+            stem.set_rates(self.region.cpu_clock_rate(), sample_rate);
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        return self.audio.sample_rate();
+    }
+
+    // Factor from `AudioSink::rate_adjustment`, applied to the frames that follow
+    pub fn set_audio_rate_adjustment(&mut self, adjustment: f64) {
+        self.audio.set_rate_adjustment(adjustment);
+        for stem in self.stems.iter_mut() {
+            stem.set_rate_adjustment(adjustment);
+        }
+    }
+
+    // Audio produced by the last `run_frame` or `run_cycles` call
+    pub fn audio_samples(&self) -> &[f32] {
+        return self.audio.samples();
+    }
+
+    pub fn audio_samples_i16(&self) -> Vec<i16> {
+        return self.audio.samples_i16();
+    }
+
+    // Also renders every channel on its own, each through its own resampler and filters
+    pub fn set_stems_enabled(&mut self, enabled: bool) {
+        self.stems.clear();
+        if enabled {
+            for _ in AudioChannel::ALL {
+                let mut stem = AudioOutput::build_audio_output(self.region.cpu_clock_rate(), self.audio.sample_rate());
+                stem.start_frame(self.cpu_cycles);
+                self.stems.push(stem);
+            }
+        }
+    }
+
+    // Samples of one channel for the last frame, empty when stems are disabled
+    pub fn stem_samples(&self, channel: AudioChannel) -> &[f32] {
+        let index = AudioChannel::ALL.iter().position(|entry| *entry == channel).unwrap();
+        return self.stems.get(index).map_or(&[], |stem| stem.samples());
+    }
+
+    // Logs the writes to the sound registers from now on, see `VgmLogger`
+    pub fn start_vgm_log(&mut self) {
+        let chips = SoundChips::from_header(self.cartridge.borrow().header());
+        let logger = VgmLogger::build_vgm_logger(self.region, chips, self.cpu_cycles);
+        self.memory.map_device(0x4000, 0x401F, logger.tap(self.io.clone()));
+        self.memory.map_device(0x4020, 0xFFFF, logger.tap(self.cartridge.clone()));
+        self.vgm = Some(logger);
+    }
+
+    // Returns the VGM file of the writes logged since `start_vgm_log`
+    pub fn stop_vgm_log(&mut self) -> Option<Vec<Byte>> {
+        let logger = self.vgm.take()?;
+        self.memory.map_device(0x4000, 0x401F, self.io.clone());
+        self.memory.map_device(0x4020, 0xFFFF, self.cartridge.clone());
+        return Some(logger.finish(self.cpu_cycles));
+    }
+
+    pub fn cpu_cycles(&self) -> u64 {
+        return self.cpu_cycles;
+    }
+
+    // Runs until the PPU enters vertical blank, leaving a complete picture in its frame buffer
+    pub fn run_frame(&mut self) {
+        self.run_frame_until(|_| false);
+    }
+
+    // Like `run_frame`, but checks `stop` after every CPU cycle and returns early, with true,
+    // the first time it holds. The audio of the partial frame is still available.
+    pub fn run_frame_until(&mut self, mut stop: impl FnMut(&mut Nes) -> bool) -> bool {
+        let mut stopped = false;
+        loop {
+            self.step();
+            if self.ppu.borrow_mut().take_frame_complete() {
+                break;
+            }
+            if stop(self) {
+                stopped = true;
+                break;
+            }
+        }
+        self.end_audio_frame();
+        return stopped;
+    }
+
+    pub fn run_cycles(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.step();
+        }
+        self.end_audio_frame();
+    }
+
+    fn end_audio_frame(&mut self) {
+        self.audio.end_frame(self.cpu_cycles);
+        for stem in self.stems.iter_mut() {
+            stem.end_frame(self.cpu_cycles);
+        }
+    }
+
+    // Advances the master clock by one CPU cycle and catches every other component up to it
+    pub fn step(&mut self) {
+        self.master_clock += self.region.cpu_divider();
+
+        let dma_page = self.io.borrow_mut().dma_page.take();
+        if let Some(page) = dma_page {
+            self.oam_dma(page);
+        }
+        if self.dma_cycles > 0 {
+            self.dma_cycles -= 1;
+        } else {
+            self.cpu.exec_cycle(&mut self.memory);
+        }
+        if let Some(vgm) = self.vgm.as_mut() {
+            let cartridge = &self.cartridge;
+            vgm.log_writes(self.cpu_cycles, |address| cartridge.borrow().cpu_peek(address, 0x00));
+        }
+        self.apu.borrow_mut().clock();
+        let dmc_request = self.apu.borrow().dmc_dma_request();
+        if let Some(address) = dmc_request {
+            self.dmc_dma(address);
+        }
+        self.cartridge.borrow_mut().cpu_clock();
+        // Expansion audio is mixed in on the cartridge side of the 2A03 output
+        let level = self.apu.borrow().output() + self.cartridge.borrow().audio_output();
+        self.audio.update(self.cpu_cycles, level);
+        if !self.stems.is_empty() {
+            self.update_stems();
+        }
+
+        let ppu_divider = self.region.ppu_divider();
+        while self.ppu_clock + ppu_divider <= self.master_clock {
+            self.ppu.borrow_mut().clock();
+            self.ppu_clock += ppu_divider;
+        }
+
+        self.cpu.set_nmi_line(self.ppu.borrow().nmi());
+        self.cpu.set_irq_line(self.apu.borrow().irq() || self.cartridge.borrow().irq());
+        self.cpu_cycles += 1;
+    }
+
+    fn update_stems(&mut self) {
+        let apu = self.apu.borrow();
+        for (channel, stem) in AudioChannel::ALL.iter().zip(self.stems.iter_mut()) {
+            let level = match channel {
+                AudioChannel::Expansion => self.cartridge.borrow().audio_output(),
+                channel => apu.channel_output(*channel),
+            };
+            stem.update(self.cpu_cycles, level);
+        }
+    }
+
+    // The copy is done at once, the CPU is halted for the time the 256 transfers would take
+    fn oam_dma(&mut self, page: Byte) {
+        let start = Word::from(page) << 8;
+        for offset in 0..256 {
+            let data = self.memory.read_byte(start + offset);
+            self.ppu.borrow_mut().write_oam(data);
+        }
+        self.dma_cycles = OAM_DMA_CYCLES + self.cpu_cycles % 2;
+    }
+
+    // The DMC reads its next sample byte over the CPU bus, halting the CPU meanwhile
+    fn dmc_dma(&mut self, address: Word) {
+        let data = self.memory.read_byte(address);
+        self.apu.borrow_mut().dmc_fill_buffer(data);
+        self.dma_cycles += DMC_DMA_CYCLES;
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{cartridge::{Cartridge, tests::build_ines}, controller::Buttons, cpu::{Byte, Word}};
+
+    use super::{Nes, region::Region};
+
+    const NMI_COUNTER: Word = 0x0010;
+
+    // NROM-128 image whose PRG bank is filled with the given program at $8000 and the NMI handler at $9000
+    pub fn build_test_rom(program: &[Byte], nmi_handler: &[Byte]) -> Vec<Byte> {
+        let mut image = build_ines(1, 1, 0x00, 0x00);
+        let prg = &mut image[16..16 + 0x4000];
+        prg.fill(0xEA);
+        prg[0..program.len()].copy_from_slice(program);
+        prg[0x1000..0x1000 + nmi_handler.len()].copy_from_slice(nmi_handler);
+        // NMI, RESET and IRQ vectors
+        prg[0x3FFA..0x4000].copy_from_slice(&[0x00, 0x90, 0x00, 0x80, 0x00, 0x90]);
+        return image;
+    }
+
+    fn build_test_nes(region: Region) -> Nes {
+        let program = [
+            0x78,             // SEI
+            0xA9, 0x80,       // LDA #$80
+            0x8D, 0x00, 0x20, // STA $2000
+            0x4C, 0x06, 0x80, // JMP $8006
+        ];
+        let nmi_handler = [
+            0xE6, NMI_COUNTER as Byte, // INC $10
+            0x40,                      // RTI
+        ];
+        let cartridge = Cartridge::from_ines(&build_test_rom(&program, &nmi_handler)).unwrap();
+        return Nes::build_nes_with_region(cartridge, region);
+    }
+
+    fn ppu_dots(nes: &Nes) -> u64 {
+        let ppu = nes.ppu();
+        let scanlines = u64::from(nes.region().scanlines_per_frame());
+        return ppu.frame_count() * scanlines * 341 + u64::from(ppu.scanline()) * 341 + u64::from(ppu.dot());
+    }
+
+    fn frame_cycles(region: Region) -> u64 {
+        let mut nes = build_test_nes(region);
+        nes.run_frame();
+        let start = nes.cpu_cycles();
+        nes.run_frame();
+        return nes.cpu_cycles() - start;
+    }
+
+    #[test]
+    fn test_ntsc_ppu_ratio() {
+        let mut nes = build_test_nes(Region::Ntsc);
+        nes.run_cycles(10);
+        assert_eq!(ppu_dots(&nes), 30);
+    }
+
+    #[test]
+    fn test_pal_ppu_ratio() {
+        let mut nes = build_test_nes(Region::Pal);
+        nes.run_cycles(5);
+        assert_eq!(ppu_dots(&nes), 16);
+        nes.run_cycles(1);
+        assert_eq!(ppu_dots(&nes), 19);
+    }
+
+    #[test]
+    fn test_run_frame_length() {
+        // 262 * 341 dots with rendering off, 29780.67 CPU cycles
+        let cycles = frame_cycles(Region::Ntsc);
+        assert!(cycles == 29780 || cycles == 29781);
+        // 312 * 341 dots at 3.2 dots per cycle, 33247.5 CPU cycles
+        let cycles = frame_cycles(Region::Pal);
+        assert!(cycles == 33247 || cycles == 33248);
+        // 312 * 341 dots at 3 dots per cycle, 35464 CPU cycles
+        let cycles = frame_cycles(Region::Dendy);
+        assert!(cycles == 35464 || cycles == 35465);
+    }
+
+    #[test]
+    fn test_run_frame_until() {
+        let mut nes = build_test_nes(Region::Ntsc);
+        // The main loop is a JMP to itself at $8006
+        let stopped = nes.run_frame_until(|nes| nes.cpu().fetched_opcode_address() == Some(0x8006));
+        assert!(stopped);
+        assert_eq!(nes.cpu().registers().pc, 0x8007);
+        assert_eq!(nes.cpu().registers().a, 0x80);
+        assert!(nes.cpu_cycles() < 20);
+        assert!(!nes.run_frame_until(|_| false));
+    }
+
+    #[test]
+    fn test_region_from_header() {
+        let mut image = build_test_rom(&[0x4C, 0x00, 0x80], &[0x40]);
+        image[7] = 0x08;
+        image[12] = 0x01;
+        let nes = Nes::build_nes(Cartridge::from_ines(&image).unwrap());
+        assert_eq!(nes.region(), Region::Pal);
+        // The user can still force another region
+        let nes = Nes::build_nes_with_region(Cartridge::from_ines(&image).unwrap(), Region::Dendy);
+        assert_eq!(nes.region(), Region::Dendy);
+    }
+
+    #[test]
+    fn test_vblank_nmi() {
+        let mut nes = build_test_nes(Region::Ntsc);
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        // The NMI of the frame that just completed is serviced at the next instruction boundary
+        assert_eq!(nes.memory().read_byte(NMI_COUNTER), 2);
+        nes.run_cycles(40);
+        assert_eq!(nes.memory().read_byte(NMI_COUNTER), 3);
+        assert_eq!(nes.cpu().get_program_counter() & 0xFFF0, 0x8000);
+    }
+
+    #[test]
+    fn test_mmc3_scanline_irq() {
+        let program = [
+            0xA9, 0x08,       // LDA #$08, sprites at $1000
+            0x8D, 0x00, 0x20, // STA $2000
+            0xA9, 0x18,       // LDA #$18
+            0x8D, 0x01, 0x20, // STA $2001
+            0xA9, 0x40,       // LDA #$40, no frame IRQ
+            0x8D, 0x17, 0x40, // STA $4017
+            0xA9, 0x07,       // LDA #$07, IRQ every 8 scanlines
+            0x8D, 0x00, 0xC0, // STA $C000
+            0x8D, 0x01, 0xC0, // STA $C001
+            0x8D, 0x01, 0xE0, // STA $E001
+            0x4C, 0x1A, 0xE0, // JMP $E01A
+        ];
+        let irq_handler = [
+            0xE6, 0x10,       // INC $10
+            0x8D, 0x00, 0xE0, // STA $E000
+            0x8D, 0x01, 0xE0, // STA $E001
+            0x40,             // RTI
+        ];
+        // 32 KiB of PRG, the last 8 KiB bank is fixed at $E000
+        let mut image = build_ines(2, 1, 0x40, 0x00);
+        let prg = &mut image[16 + 0x6000..16 + 0x8000];
+        prg[0..program.len()].copy_from_slice(&program);
+        prg[0x100..0x100 + irq_handler.len()].copy_from_slice(&irq_handler);
+        // NMI vector points to the RTI of the handler
+        prg[0x1FFA..0x2000].copy_from_slice(&[0x08, 0xE1, 0x00, 0xE0, 0x00, 0xE1]);
+        let mut nes = Nes::build_nes(Cartridge::from_ines(&image).unwrap());
+        nes.run_frame();
+        let first = nes.memory().read_byte(0x0010);
+        nes.run_frame();
+        // 240 visible lines and the pre-render line clock the counter
+        assert_eq!(nes.memory().read_byte(0x0010) - first, 30);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let program = [
+            0xA9, 0x07,       // LDA #$07
+            0x8D, 0x14, 0x40, // STA $4014
+            0x4C, 0x05, 0x80, // JMP $8005
+        ];
+        let cartridge = Cartridge::from_ines(&build_test_rom(&program, &[0x40])).unwrap();
+        let mut nes = Nes::build_nes(cartridge);
+        for offset in 0..256 {
+            nes.memory().write_byte(0x0700 + offset, offset as Byte);
+        }
+        nes.run_cycles(10);
+        let pc = nes.cpu().get_program_counter();
+        nes.run_cycles(500);
+        // CPU is halted during the transfer
+        assert_eq!(nes.cpu().get_program_counter(), pc);
+        nes.memory().write_byte(0x2003, 0x10);
+        assert_eq!(nes.memory().read_byte(0x2004), 0x10);
+    }
+
+    #[test]
+    fn test_dmc_dma_irq() {
+        let program = [
+            0xA9, 0x8F,       // LDA #$8F
+            0x8D, 0x10, 0x40, // STA $4010
+            0xA9, 0x10,       // LDA #$10
+            0x8D, 0x15, 0x40, // STA $4015
+            0x4C, 0x0A, 0x80, // JMP $800A
+        ];
+        let irq_handler = [
+            0xE6, 0x10,       // INC $10
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x15, 0x40, // STA $4015
+            0x40,             // RTI
+        ];
+        let cartridge = Cartridge::from_ines(&build_test_rom(&program, &irq_handler)).unwrap();
+        let mut nes = Nes::build_nes(cartridge);
+        // The one byte sample is fetched from $C000 right after $4015 is written, ending it
+        nes.run_cycles(200);
+        assert_eq!(nes.memory().read_byte(0x0010), 1);
+    }
+
+    #[test]
+    fn test_audio_samples_per_frame() {
+        let program = [
+            0xA9, 0x40,       // LDA #$40
+            0x8D, 0x17, 0x40, // STA $4017
+            0xA9, 0x01,       // LDA #$01
+            0x8D, 0x15, 0x40, // STA $4015
+            0xA9, 0xBF,       // LDA #$BF
+            0x8D, 0x00, 0x40, // STA $4000
+            0xA9, 0xFD,       // LDA #$FD
+            0x8D, 0x02, 0x40, // STA $4002
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x03, 0x40, // STA $4003
+            0x4C, 0x19, 0x80, // JMP $8019
+        ];
+        let cartridge = Cartridge::from_ines(&build_test_rom(&program, &[0x40])).unwrap();
+        let mut nes = Nes::build_nes(cartridge);
+        nes.set_sample_rate(44_100);
+        // The first frame is cut short by the power up state
+        nes.run_frame();
+        for _ in 0..10 {
+            nes.run_frame();
+            // 44100 / 60.0988 samples per frame
+            assert!((nes.audio_samples().len() as i64 - 734).abs() <= 1);
+        }
+        // Pulse 1 plays a 440 Hz square wave at full volume
+        let peak = nes.audio_samples().iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak > 0.03);
+        assert_eq!(nes.audio_samples_i16().len(), nes.audio_samples().len());
+    }
+
+    #[test]
+    fn test_controller_read_sequence() {
+        let mut program = vec![
+            0xA9, 0x01,       // LDA #$01
+            0x8D, 0x16, 0x40, // STA $4016
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x16, 0x40, // STA $4016
+        ];
+        for index in 0..8 {
+            program.extend([0xAD, 0x16, 0x40]); // LDA $4016
+            program.extend([0x8D, index, 0x03]); // STA $03xx
+        }
+        program.extend([0x4C, 0x3A, 0x80]); // JMP $803A
+        let cartridge = Cartridge::from_ines(&build_test_rom(&program, &[0x40])).unwrap();
+        let mut nes = Nes::build_nes(cartridge);
+        nes.set_buttons(0, Buttons::B | Buttons::SELECT | Buttons::LEFT);
+        nes.run_cycles(200);
+        let reads: Vec<Byte> = (0..8).map(|index| nes.memory().read_byte(0x0300 + index) & 0x01).collect();
+        assert_eq!(reads, vec![0, 1, 1, 0, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_four_score_players() {
+        let mut program = vec![
+            0xA9, 0x01,       // LDA #$01
+            0x8D, 0x16, 0x40, // STA $4016
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x16, 0x40, // STA $4016
+        ];
+        for index in 0..24 {
+            program.extend([0xAD, 0x17, 0x40]); // LDA $4017
+            program.extend([0x8D, index, 0x03]); // STA $03xx
+        }
+        program.extend([0x4C, 0x9A, 0x80]); // JMP $809A
+        let cartridge = Cartridge::from_ines(&build_test_rom(&program, &[0x40])).unwrap();
+        let mut nes = Nes::build_nes(cartridge);
+        nes.plug_four_score();
+        nes.set_buttons(1, Buttons::B);
+        nes.set_buttons(3, Buttons::START);
+        nes.run_cycles(400);
+        let reads: Vec<Byte> = (0..24).map(|index| nes.memory().read_byte(0x0300 + index) & 0x01).collect();
+        assert_eq!(reads, vec![
+            0, 1, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 1, 0, 0, 0, 0,
+            0, 0, 1, 0, 0, 0, 0, 0,
+        ]);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::cartridge::{Header, TvSystem};
+
+// Timing of the console variants, expressed in master clock ticks per component clock.
+// NTSC: 21.477272 MHz master clock, CPU = master / 12, PPU = master / 4 (3 dots per CPU cycle)
+// PAL: 26.601712 MHz master clock, CPU = master / 16, PPU = master / 5 (3.2 dots per CPU cycle)
+// Dendy: PAL master clock, CPU = master / 15, PPU = master / 5 (3 dots per CPU cycle)
+// Details on https://www.nesdev.org/wiki/Cycle_reference_chart
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+const NTSC_NOISE_PERIODS: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+const PAL_NOISE_PERIODS: [u16; 16] = [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+
+const NTSC_DMC_RATES: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+const PAL_DMC_RATES: [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+
+// CPU cycles after a $4017 write at which each frame sequencer step happens
+const NTSC_FOUR_STEP_SEQUENCE: [u32; 4] = [7457, 14913, 22371, 29829];
+const NTSC_FIVE_STEP_SEQUENCE: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FOUR_STEP_SEQUENCE: [u32; 4] = [8313, 16627, 24939, 33253];
+const PAL_FIVE_STEP_SEQUENCE: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+impl Region {
+    // Multi-region images run as NTSC unless the user picks otherwise
+    pub fn from_header(header: &Header) -> Region {
+        match header.tv_system {
+            TvSystem::Ntsc | TvSystem::MultiRegion => Region::Ntsc,
+            TvSystem::Pal => Region::Pal,
+            TvSystem::Dendy => Region::Dendy,
+        }
+    }
+
+    pub fn master_clock_rate(&self) -> u64 {
+        match self {
+            Region::Ntsc => 21_477_272,
+            Region::Pal | Region::Dendy => 26_601_712,
+        }
+    }
+
+    pub fn cpu_divider(&self) -> u64 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    pub fn ppu_divider(&self) -> u64 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    pub fn cpu_clock_rate(&self) -> f64 {
+        return self.master_clock_rate() as f64 / self.cpu_divider() as f64;
+    }
+
+    pub fn scanlines_per_frame(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // Scanline on which the vblank flag gets set. Dendy keeps the NTSC vblank length
+    // and pads the frame with 50 extra idle lines after the picture instead.
+    pub fn vblank_scanline(&self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    // Number of scanlines spent in vblank, the pre-render line not included
+    pub fn vblank_scanlines(&self) -> u16 {
+        return self.scanlines_per_frame() - 1 - self.vblank_scanline();
+    }
+
+    // Only the NTSC PPU drops a dot on odd frames while rendering
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        return *self == Region::Ntsc;
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        let (numerator, denominator) = self.frame_rate_ratio();
+        return numerator as f64 / denominator as f64;
+    }
+
+    // Exact frames per second as a reduced fraction, for containers that store the rate that way
+    pub fn frame_rate_ratio(&self) -> (u64, u64) {
+        // Counted in half dots, odd frames are one dot short on NTSC
+        let mut half_dots_per_frame = 2 * 341 * u64::from(self.scanlines_per_frame());
+        if self.skips_odd_frame_dot() {
+            half_dots_per_frame -= 1;
+        }
+        let numerator = 2 * self.master_clock_rate();
+        let denominator = self.ppu_divider() * half_dots_per_frame;
+        let (mut a, mut b) = (numerator, denominator);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        return (numerator / a, denominator / a);
+    }
+
+    // The Dendy APU keeps the NTSC tables, its lower CPU clock makes every channel slightly flat
+    pub fn noise_periods(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_NOISE_PERIODS,
+            Region::Pal => &PAL_NOISE_PERIODS,
+        }
+    }
+
+    pub fn dmc_rates(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_DMC_RATES,
+            Region::Pal => &PAL_DMC_RATES,
+        }
+    }
+
+    pub fn four_step_sequence(&self) -> &'static [u32; 4] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_FOUR_STEP_SEQUENCE,
+            Region::Pal => &PAL_FOUR_STEP_SEQUENCE,
+        }
+    }
+
+    pub fn five_step_sequence(&self) -> &'static [u32; 5] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_FIVE_STEP_SEQUENCE,
+            Region::Pal => &PAL_FIVE_STEP_SEQUENCE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Header, tests::build_ines};
+
+    use super::Region;
+
+    #[test]
+    fn test_region_from_nes2_header() {
+        let mut image = build_ines(1, 1, 0x00, 0x08);
+        for (timing, region) in [(0, Region::Ntsc), (1, Region::Pal), (2, Region::Ntsc), (3, Region::Dendy)] {
+            image[12] = timing;
+            assert_eq!(Region::from_header(&Header::parse(&image).unwrap()), region);
+        }
+    }
+
+    #[test]
+    fn test_vblank_lengths() {
+        assert_eq!(Region::Ntsc.vblank_scanlines(), 20);
+        assert_eq!(Region::Pal.vblank_scanlines(), 70);
+        assert_eq!(Region::Dendy.vblank_scanlines(), 20);
+    }
+
+    #[test]
+    fn test_frame_rates() {
+        assert!((Region::Ntsc.frame_rate() - 60.0988).abs() < 0.001);
+        assert!((Region::Pal.frame_rate() - 50.0070).abs() < 0.001);
+        assert!((Region::Dendy.frame_rate() - 50.0070).abs() < 0.001);
+        assert_eq!(Region::Ntsc.frame_rate_ratio(), (10_738_636, 178_683));
+        assert_eq!(Region::Pal.frame_rate_ratio(), (3_325_214, 66_495));
+    }
+}
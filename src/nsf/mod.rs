@@ -0,0 +1,382 @@
+pub mod player;
+
+use std::fmt::{self, Display};
+
+use bitflags::bitflags;
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{cpu::{Byte, Word}, nes::region::Region};
+
+const NSF_MAGIC: &[Byte] = b"NESM\x1A";
+const NSFE_MAGIC: &[Byte] = b"NSFE";
+const NSF_HEADER_SIZE: usize = 0x80;
+// Play rates in microseconds used when a file does not give its own
+const DEFAULT_NTSC_SPEED: Word = 16_639;
+const DEFAULT_PAL_SPEED: Word = 19_997;
+
+bitflags! {
+    // Sound chips the music expects next to the 2A03
+    pub struct ExpansionChips: Byte {
+        const VRC6 = 0b0000_0001;
+        const VRC7 = 0b0000_0010;
+        const FDS = 0b0000_0100;
+        const MMC5 = 0b0000_1000;
+        const NAMCO_163 = 0b0001_0000;
+        const SUNSOFT_5B = 0b0010_0000;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum NsfError {
+    InvalidHeader,
+    Truncated,
+    MissingChunk(&'static str),
+    // NSFe chunks with an upper case first letter must be understood to play the file
+    UnsupportedChunk(String),
+    UnsupportedChip(&'static str),
+    InvalidTrack(Byte),
+}
+
+impl Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NsfError::InvalidHeader => write!(f, "not an NSF or NSFe file"),
+            NsfError::Truncated => write!(f, "the file is truncated"),
+            NsfError::MissingChunk(chunk) => write!(f, "the NSFe file has no {} chunk", chunk),
+            NsfError::UnsupportedChunk(chunk) => write!(f, "NSFe chunk {} is not supported", chunk),
+            NsfError::UnsupportedChip(chip) => write!(f, "{} expansion audio is not supported", chip),
+            NsfError::InvalidTrack(track) => write!(f, "there is no track {}", track),
+        }
+    }
+}
+
+impl std::error::Error for NsfError {}
+
+// Per track metadata, only NSFe files carry it. Times are in milliseconds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackInfo {
+    pub name: Option<String>,
+    pub duration: Option<u32>,
+    pub fade: Option<u32>,
+}
+
+// NSF music rip: https://www.nesdev.org/wiki/NSF
+// The sound driver of a game with its music data. The player calls INIT once with the track
+// number in A and the region in X, then PLAY at a steady rate, usually 60 Hz.
+#[derive(Debug, Clone)]
+pub struct Nsf {
+    pub version: Byte,
+    pub total_songs: Byte,
+    // 0 based
+    pub starting_song: Byte,
+    pub load_address: Word,
+    pub init_address: Word,
+    pub play_address: Word,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ripper: String,
+    // Microseconds between two PLAY calls
+    pub ntsc_speed: Word,
+    pub pal_speed: Word,
+    // Initial 4 KiB banks of $8000-$FFFF, None when the data is loaded at a fixed address
+    pub bankswitch: Option<[Byte; 8]>,
+    pub pal: bool,
+    pub dual_region: bool,
+    pub chips: ExpansionChips,
+    pub tracks: Vec<TrackInfo>,
+    pub data: Vec<Byte>,
+}
+
+impl Nsf {
+    pub fn parse(data: &[Byte]) -> Result<Nsf, NsfError> {
+        if data.starts_with(NSF_MAGIC) {
+            return Nsf::parse_nsf(data);
+        }
+        if data.starts_with(NSFE_MAGIC) {
+            return Nsf::parse_nsfe(data);
+        }
+        return Err(NsfError::InvalidHeader);
+    }
+
+    fn parse_nsf(data: &[Byte]) -> Result<Nsf, NsfError> {
+        if data.len() < NSF_HEADER_SIZE {
+            return Err(NsfError::Truncated);
+        }
+        let bankswitch = Nsf::bankswitch_from(&data[0x70..0x78]);
+        let mut program = &data[NSF_HEADER_SIZE..];
+        // NSF2 may give the program length, with metadata following it
+        let program_length = usize::from(data[0x7D]) | (usize::from(data[0x7E]) << 8) | (usize::from(data[0x7F]) << 16);
+        if data[0x05] >= 2 && program_length != 0 {
+            program = &program[..program_length.min(program.len())];
+        }
+        return Ok(Nsf {
+            version: data[0x05],
+            total_songs: data[0x06],
+            starting_song: data[0x07].saturating_sub(1),
+            load_address: LittleEndian::read_u16(&data[0x08..]),
+            init_address: LittleEndian::read_u16(&data[0x0A..]),
+            play_address: LittleEndian::read_u16(&data[0x0C..]),
+            title: read_string(&data[0x0E..0x2E]),
+            artist: read_string(&data[0x2E..0x4E]),
+            copyright: read_string(&data[0x4E..0x6E]),
+            ripper: String::new(),
+            ntsc_speed: LittleEndian::read_u16(&data[0x6E..]),
+            pal_speed: LittleEndian::read_u16(&data[0x78..]),
+            bankswitch,
+            pal: data[0x7A] & 0x01 != 0,
+            dual_region: data[0x7A] & 0x02 != 0,
+            chips: ExpansionChips::from_bits_truncate(data[0x7B]),
+            tracks: vec![TrackInfo::default(); usize::from(data[0x06])],
+            data: program.to_vec(),
+        });
+    }
+
+    // NSFe: https://www.nesdev.org/wiki/NSFe
+    // A list of chunks, each a 32 bit length, a 4 letter id and the data
+    fn parse_nsfe(data: &[Byte]) -> Result<Nsf, NsfError> {
+        let mut nsf = Nsf {
+            version: 1,
+            total_songs: 1,
+            starting_song: 0,
+            load_address: 0,
+            init_address: 0,
+            play_address: 0,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: String::new(),
+            ntsc_speed: DEFAULT_NTSC_SPEED,
+            pal_speed: DEFAULT_PAL_SPEED,
+            bankswitch: None,
+            pal: false,
+            dual_region: false,
+            chips: ExpansionChips::empty(),
+            tracks: Vec::new(),
+            data: Vec::new(),
+        };
+        let mut names = Vec::new();
+        let mut durations = Vec::new();
+        let mut fades = Vec::new();
+        let mut has_info = false;
+        let mut has_data = false;
+        let mut offset = NSFE_MAGIC.len();
+        loop {
+            if offset + 8 > data.len() {
+                return Err(NsfError::Truncated);
+            }
+            let length = LittleEndian::read_u32(&data[offset..]) as usize;
+            let id = &data[offset + 4..offset + 8];
+            let start = offset + 8;
+            if start + length > data.len() {
+                return Err(NsfError::Truncated);
+            }
+            let chunk = &data[start..start + length];
+            offset = start + length;
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err(NsfError::Truncated);
+                    }
+                    nsf.load_address = LittleEndian::read_u16(&chunk[0..]);
+                    nsf.init_address = LittleEndian::read_u16(&chunk[2..]);
+                    nsf.play_address = LittleEndian::read_u16(&chunk[4..]);
+                    nsf.pal = chunk[6] & 0x01 != 0;
+                    nsf.dual_region = chunk[6] & 0x02 != 0;
+                    nsf.chips = ExpansionChips::from_bits_truncate(chunk[7]);
+                    nsf.total_songs = chunk.get(8).copied().unwrap_or(1);
+                    nsf.starting_song = chunk.get(9).copied().unwrap_or(0);
+                    has_info = true;
+                }
+                b"DATA" => {
+                    nsf.data = chunk.to_vec();
+                    has_data = true;
+                }
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    for (bank, value) in banks.iter_mut().zip(chunk.iter()) {
+                        *bank = *value;
+                    }
+                    nsf.bankswitch = Nsf::bankswitch_from(&banks);
+                }
+                b"RATE" => {
+                    if chunk.len() >= 2 {
+                        nsf.ntsc_speed = LittleEndian::read_u16(&chunk[0..]);
+                    }
+                    if chunk.len() >= 4 {
+                        nsf.pal_speed = LittleEndian::read_u16(&chunk[2..]);
+                    }
+                }
+                b"auth" => {
+                    let mut strings = split_strings(chunk).into_iter();
+                    nsf.title = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                    nsf.ripper = strings.next().unwrap_or_default();
+                }
+                b"tlbl" => names = split_strings(chunk),
+                b"time" => durations = read_times(chunk),
+                b"fade" => fades = read_times(chunk),
+                b"NEND" => break,
+                id if id[0].is_ascii_uppercase() => {
+                    return Err(NsfError::UnsupportedChunk(String::from_utf8_lossy(id).into_owned()));
+                }
+                _ => {}
+            }
+        }
+        if !has_info {
+            return Err(NsfError::MissingChunk("INFO"));
+        }
+        if !has_data {
+            return Err(NsfError::MissingChunk("DATA"));
+        }
+        nsf.tracks = (0..usize::from(nsf.total_songs))
+            .map(|track| TrackInfo {
+                name: names.get(track).cloned(),
+                duration: durations.get(track).copied().flatten(),
+                fade: fades.get(track).copied().flatten(),
+            })
+            .collect();
+        return Ok(nsf);
+    }
+
+    // All zero banks mean no bankswitching
+    fn bankswitch_from(banks: &[Byte]) -> Option<[Byte; 8]> {
+        if banks.iter().all(|bank| *bank == 0) {
+            return None;
+        }
+        let mut result = [0; 8];
+        result.copy_from_slice(&banks[..8]);
+        return Some(result);
+    }
+
+    // Dual region tunes play as NTSC
+    pub fn region(&self) -> Region {
+        if self.pal && !self.dual_region {
+            return Region::Pal;
+        }
+        return Region::Ntsc;
+    }
+
+    // Microseconds between two PLAY calls, Dendy plays at the PAL rate
+    pub fn play_speed(&self, region: Region) -> Word {
+        let speed = match region {
+            Region::Ntsc => self.ntsc_speed,
+            Region::Pal | Region::Dendy => self.pal_speed,
+        };
+        if speed == 0 {
+            return if region == Region::Ntsc { DEFAULT_NTSC_SPEED } else { DEFAULT_PAL_SPEED };
+        }
+        return speed;
+    }
+}
+
+// Fixed size fields are padded with zeros
+fn read_string(data: &[Byte]) -> String {
+    let end = data.iter().position(|byte| *byte == 0).unwrap_or(data.len());
+    return String::from_utf8_lossy(&data[..end]).into_owned();
+}
+
+fn split_strings(data: &[Byte]) -> Vec<String> {
+    let data = data.strip_suffix(&[0]).unwrap_or(data);
+    return data.split(|byte| *byte == 0).map(|string| String::from_utf8_lossy(string).into_owned()).collect();
+}
+
+// Signed milliseconds, negative values leave the default
+fn read_times(data: &[Byte]) -> Vec<Option<u32>> {
+    return data.chunks_exact(4).map(|time| u32::try_from(LittleEndian::read_i32(time)).ok()).collect();
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{cpu::{Byte, Word}, nes::region::Region};
+
+    use super::{ExpansionChips, Nsf, NsfError, TrackInfo};
+
+    // NSF with the program loaded at `load`, INIT at the start of it and PLAY at `play`
+    pub fn build_nsf(program: &[Byte], load: Word, play: Word, songs: Byte, chips: Byte) -> Vec<Byte> {
+        let mut image = vec![0; 0x80];
+        image[0..5].copy_from_slice(b"NESM\x1A");
+        image[0x05] = 1;
+        image[0x06] = songs;
+        image[0x07] = 1;
+        image[0x08..0x0A].copy_from_slice(&load.to_le_bytes());
+        image[0x0A..0x0C].copy_from_slice(&load.to_le_bytes());
+        image[0x0C..0x0E].copy_from_slice(&play.to_le_bytes());
+        image[0x0E..0x13].copy_from_slice(b"Title");
+        image[0x2E..0x34].copy_from_slice(b"Artist");
+        image[0x6E..0x70].copy_from_slice(&16_639u16.to_le_bytes());
+        image[0x78..0x7A].copy_from_slice(&19_997u16.to_le_bytes());
+        image[0x7B] = chips;
+        image.extend_from_slice(program);
+        return image;
+    }
+
+    fn chunk(id: &[Byte], data: &[Byte]) -> Vec<Byte> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+        return chunk;
+    }
+
+    #[test]
+    fn test_parse_nsf() {
+        let nsf = Nsf::parse(&build_nsf(&[0x60; 16], 0x8000, 0x8008, 3, 0x21)).unwrap();
+        assert_eq!(nsf.total_songs, 3);
+        assert_eq!(nsf.starting_song, 0);
+        assert_eq!(nsf.load_address, 0x8000);
+        assert_eq!(nsf.play_address, 0x8008);
+        assert_eq!(nsf.title, "Title");
+        assert_eq!(nsf.artist, "Artist");
+        assert_eq!(nsf.bankswitch, None);
+        assert_eq!(nsf.chips, ExpansionChips::VRC6 | ExpansionChips::SUNSOFT_5B);
+        assert_eq!(nsf.region(), Region::Ntsc);
+        assert_eq!(nsf.play_speed(Region::Pal), 19_997);
+        assert_eq!(nsf.data.len(), 16);
+        assert_eq!(nsf.tracks.len(), 3);
+
+        let mut image = build_nsf(&[0x60; 16], 0x8000, 0x8008, 1, 0x00);
+        image[0x71] = 0x01;
+        image[0x7A] = 0x01;
+        let nsf = Nsf::parse(&image).unwrap();
+        assert_eq!(nsf.bankswitch, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(nsf.region(), Region::Pal);
+        assert_eq!(Nsf::parse(&image[..0x40]).unwrap_err(), NsfError::Truncated);
+        assert_eq!(Nsf::parse(b"NES\x1A").unwrap_err(), NsfError::InvalidHeader);
+    }
+
+    #[test]
+    fn test_parse_nsfe() {
+        let mut image = b"NSFE".to_vec();
+        image.extend(chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x03, 0x80, 0x00, 0x10, 0x02, 0x01]));
+        image.extend(chunk(b"DATA", &[0x60, 0x60, 0x60, 0x60]));
+        image.extend(chunk(b"auth", b"Song\0Composer\0Company\0Ripper\0"));
+        image.extend(chunk(b"tlbl", b"Intro\0Boss\0"));
+        image.extend(chunk(b"time", &[0x10, 0x27, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]));
+        image.extend(chunk(b"fade", &[0xE8, 0x03, 0x00, 0x00]));
+        image.extend(chunk(b"RATE", &[0x1A, 0x41]));
+        image.extend(chunk(b"text", b"skipped"));
+        image.extend(chunk(b"NEND", &[]));
+        let nsf = Nsf::parse(&image).unwrap();
+        assert_eq!(nsf.play_address, 0x8003);
+        assert_eq!(nsf.chips, ExpansionChips::NAMCO_163);
+        assert_eq!(nsf.total_songs, 2);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.title, "Song");
+        assert_eq!(nsf.ripper, "Ripper");
+        assert_eq!(nsf.ntsc_speed, 16_666);
+        assert_eq!(nsf.data.len(), 4);
+        assert_eq!(nsf.tracks[0], TrackInfo { name: Some(String::from("Intro")), duration: Some(10_000), fade: Some(1_000) });
+        assert_eq!(nsf.tracks[1], TrackInfo { name: Some(String::from("Boss")), duration: None, fade: None });
+
+        // Unknown chunks are an error when they are marked as required
+        let mut image = b"NSFE".to_vec();
+        image.extend(chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x03, 0x80, 0x00, 0x00]));
+        image.extend(chunk(b"XTRA", &[]));
+        assert_eq!(Nsf::parse(&image).unwrap_err(), NsfError::UnsupportedChunk(String::from("XTRA")));
+        let mut image = b"NSFE".to_vec();
+        image.extend(chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x03, 0x80, 0x00, 0x00]));
+        image.extend(chunk(b"NEND", &[]));
+        assert_eq!(Nsf::parse(&image).unwrap_err(), NsfError::MissingChunk("DATA"));
+    }
+}
@@ -0,0 +1,442 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    apu::{Apu, audio_output::AudioOutput, opll::Opll},
+    cartridge::mappers::{fme7::Sunsoft5b, mmc5::Mmc5Audio, namco163::Namco163Audio, vrc6::Vrc6Audio},
+    cpu::{Byte, Cpu, Word},
+    memory::{Memory, MemoryMapped},
+    nes::region::Region,
+    vgm::{SoundChips, logger::VgmLogger},
+};
+
+use super::{ExpansionChips, Nsf, NsfError};
+
+const BANK_SIZE: usize = 0x1000;
+// The driver routine lives in the PPU register range, which nothing uses on an NSF player
+const DRIVER_ADDRESS: Word = 0x3F00;
+const DRIVER_NMI: Word = 0x3F12;
+const DRIVER_IRQ: Word = 0x3F19;
+const INIT_DONE_PORT: Word = 0x3F80;
+const PLAY_DONE_PORT: Word = 0x3F81;
+const DMC_DMA_CYCLES: u64 = 4;
+
+// SEI, CLD, set up the stack, call INIT with the track in A and the region in X then idle.
+// PLAY is called from the NMI handler. Both report back through the ports above so PLAY is never
+// started before INIT or the previous PLAY returned.
+const DRIVER: [Byte; 0x1A] = [
+    0x78,             // $3F00 SEI
+    0xD8,             // $3F01 CLD
+    0xA2, 0xFF,       // $3F02 LDX #$FF
+    0x9A,             // $3F04 TXS
+    0xA9, 0x00,       // $3F05 LDA #track
+    0xA2, 0x00,       // $3F07 LDX #region
+    0x20, 0x00, 0x00, // $3F09 JSR init
+    0x8D, 0x80, 0x3F, // $3F0C STA $3F80
+    0x4C, 0x0F, 0x3F, // $3F0F JMP $3F0F
+    0x20, 0x00, 0x00, // $3F12 JSR play
+    0x8D, 0x81, 0x3F, // $3F15 STA $3F81
+    0x40,             // $3F18 RTI
+    0x40,             // $3F19 RTI
+];
+
+// Everything on the CPU bus of an NSF player but the RAM and the APU: the driver, 8 KiB of RAM
+// at $6000, the ROM in 4 KiB banks switched through $5FF8-$5FFF and the expansion sound chips.
+// The interrupt vectors always point to the driver.
+struct NsfBus {
+    driver: [Byte; 0x1A],
+    init_done: bool,
+    play_done: bool,
+    rom: Vec<Byte>,
+    banks: [Byte; 8],
+    bankswitched: bool,
+    ram: [Byte; 0x2000],
+    vrc6: Option<Vrc6Audio>,
+    vrc7: Option<Opll>,
+    mmc5: Option<Mmc5Audio>,
+    mmc5_exram: [Byte; 0x400],
+    multiplier: [Byte; 2],
+    namco163: Option<Namco163Audio>,
+    sunsoft_5b: Option<Sunsoft5b>,
+}
+
+impl NsfBus {
+    fn build_nsf_bus(nsf: &Nsf) -> NsfBus {
+        // The load address is the offset of the data in its first bank when bankswitching,
+        // otherwise the data sits at its place in a plain 32 KiB ROM
+        let padding = match nsf.bankswitch {
+            Some(_) => usize::from(nsf.load_address) & (BANK_SIZE - 1),
+            None => usize::from(nsf.load_address.saturating_sub(0x8000)),
+        };
+        let mut rom = vec![0; padding];
+        rom.extend_from_slice(&nsf.data);
+        rom.resize(rom.len().div_ceil(BANK_SIZE).max(1) * BANK_SIZE, 0);
+
+        let mut driver = DRIVER;
+        driver[0x0A..0x0C].copy_from_slice(&nsf.init_address.to_le_bytes());
+        driver[0x13..0x15].copy_from_slice(&nsf.play_address.to_le_bytes());
+        return NsfBus {
+            driver,
+            init_done: false,
+            play_done: true,
+            rom,
+            banks: nsf.bankswitch.unwrap_or([0, 1, 2, 3, 4, 5, 6, 7]),
+            bankswitched: nsf.bankswitch.is_some(),
+            ram: [0; 0x2000],
+            vrc6: nsf.chips.contains(ExpansionChips::VRC6).then(Vrc6Audio::build_vrc6_audio),
+            vrc7: nsf.chips.contains(ExpansionChips::VRC7).then(Opll::build_opll),
+            mmc5: nsf.chips.contains(ExpansionChips::MMC5).then(Mmc5Audio::build_mmc5_audio),
+            mmc5_exram: [0; 0x400],
+            multiplier: [0xFF, 0xFF],
+            namco163: nsf.chips.contains(ExpansionChips::NAMCO_163).then(Namco163Audio::build_namco163_audio),
+            sunsoft_5b: nsf.chips.contains(ExpansionChips::SUNSOFT_5B).then(Sunsoft5b::build_sunsoft_5b),
+        };
+    }
+
+    fn start_track(&mut self, track: Byte, region: Region) {
+        self.driver[0x06] = track;
+        self.driver[0x08] = if region == Region::Ntsc { 0 } else { 1 };
+        self.init_done = false;
+        self.play_done = true;
+    }
+
+    // Called when the PLAY period elapses, false when the driver is still busy
+    fn take_play_request(&mut self) -> bool {
+        if self.init_done && self.play_done {
+            self.play_done = false;
+            return true;
+        }
+        return false;
+    }
+
+    fn read_rom(&self, address: Word) -> Byte {
+        let bank = usize::from(self.banks[usize::from(address - 0x8000) / BANK_SIZE]);
+        let offset = (bank * BANK_SIZE + (usize::from(address) & (BANK_SIZE - 1))) % self.rom.len();
+        return self.rom[offset];
+    }
+
+    fn clock(&mut self) {
+        if let Some(vrc6) = self.vrc6.as_mut() {
+            vrc6.clock();
+        }
+        if let Some(vrc7) = self.vrc7.as_mut() {
+            vrc7.clock();
+        }
+        if let Some(mmc5) = self.mmc5.as_mut() {
+            mmc5.clock();
+        }
+        if let Some(namco163) = self.namco163.as_mut() {
+            namco163.clock();
+        }
+        if let Some(sunsoft_5b) = self.sunsoft_5b.as_mut() {
+            sunsoft_5b.clock();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        let mut level = 0.0;
+        level += self.vrc6.as_ref().map_or(0.0, |vrc6| vrc6.output());
+        level += self.vrc7.as_ref().map_or(0.0, |vrc7| vrc7.output());
+        level += self.mmc5.as_ref().map_or(0.0, |mmc5| mmc5.output());
+        level += self.namco163.as_ref().map_or(0.0, |namco163| namco163.output());
+        level += self.sunsoft_5b.as_ref().map_or(0.0, |sunsoft_5b| sunsoft_5b.output());
+        return level;
+    }
+}
+
+impl MemoryMapped for NsfBus {
+    fn read_byte(&mut self, address: Word, open_bus: Byte) -> Byte {
+        match address {
+            0x3F00..=0x3F19 => self.driver[usize::from(address - DRIVER_ADDRESS)],
+            0x4800..=0x4FFF if self.namco163.is_some() => self.namco163.as_mut().unwrap().read_data(),
+            0x5010 | 0x5015 if self.mmc5.is_some() => self.mmc5.as_mut().unwrap().read_register(address).unwrap_or(open_bus),
+            0x5205 if self.mmc5.is_some() => (u16::from(self.multiplier[0]) * u16::from(self.multiplier[1])) as Byte,
+            0x5206 if self.mmc5.is_some() => ((u16::from(self.multiplier[0]) * u16::from(self.multiplier[1])) >> 8) as Byte,
+            0x5C00..=0x5FF5 if self.mmc5.is_some() => self.mmc5_exram[usize::from(address - 0x5C00)],
+            0x6000..=0x7FFF => self.ram[usize::from(address - 0x6000)],
+            0xFFFA..=0xFFFF => {
+                let vector = match address & 0xFFFE {
+                    0xFFFA => DRIVER_NMI,
+                    0xFFFC => DRIVER_ADDRESS,
+                    _ => DRIVER_IRQ,
+                };
+                (vector >> (8 * (address & 0x01))) as Byte
+            }
+            0x8000..=0xFFF9 => self.read_rom(address),
+            _ => open_bus,
+        }
+    }
+
+    fn write_byte(&mut self, address: Word, data: Byte) {
+        match address {
+            INIT_DONE_PORT => self.init_done = true,
+            PLAY_DONE_PORT => self.play_done = true,
+            0x4800..=0x4FFF if self.namco163.is_some() => self.namco163.as_mut().unwrap().write_data(data),
+            0x5000..=0x5015 if self.mmc5.is_some() => self.mmc5.as_mut().unwrap().write_register(address, data),
+            0x5205..=0x5206 if self.mmc5.is_some() => self.multiplier[usize::from(address - 0x5205)] = data,
+            0x5C00..=0x5FF5 if self.mmc5.is_some() => self.mmc5_exram[usize::from(address - 0x5C00)] = data,
+            0x5FF8..=0x5FFF if self.bankswitched => self.banks[usize::from(address - 0x5FF8)] = data,
+            0x6000..=0x7FFF => self.ram[usize::from(address - 0x6000)] = data,
+            0x9000..=0xB002 if self.vrc6.is_some() && address & 0x0FFC == 0 => self.vrc6.as_mut().unwrap().write(address, data),
+            0x9010 if self.vrc7.is_some() => self.vrc7.as_mut().unwrap().write_address(data),
+            0x9030 if self.vrc7.is_some() => self.vrc7.as_mut().unwrap().write_data(data),
+            0xC000..=0xDFFF if self.sunsoft_5b.is_some() => self.sunsoft_5b.as_mut().unwrap().write_address(data),
+            0xE000..=0xFFFF if self.sunsoft_5b.is_some() => self.sunsoft_5b.as_mut().unwrap().write_data(data),
+            0xF800..=0xFFFF if self.namco163.is_some() => self.namco163.as_mut().unwrap().write_address(data),
+            _ => {}
+        }
+    }
+}
+
+// Plays the tracks of an NSF file on the CPU and APU, without the rest of the console.
+// PLAY is called at the rate of the header, and the audio goes through the same mixer,
+// resampler and filters as the console output.
+pub struct NsfPlayer {
+    nsf: Nsf,
+    region: Region,
+    cpu: Cpu,
+    memory: Memory,
+    apu: Rc<RefCell<Apu>>,
+    bus: Rc<RefCell<NsfBus>>,
+    audio: AudioOutput,
+    cpu_cycles: u64,
+    dma_cycles: u64,
+    // CPU cycles between two PLAY calls, and until the next one
+    play_period: f64,
+    play_timer: f64,
+    track: Byte,
+    vgm: Option<VgmLogger>,
+}
+
+impl NsfPlayer {
+    pub fn build_nsf_player(nsf: Nsf, region: Region, sample_rate: u32) -> Result<NsfPlayer, NsfError> {
+        if nsf.chips.contains(ExpansionChips::FDS) {
+            return Err(NsfError::UnsupportedChip("FDS"));
+        }
+        let apu = Rc::new(RefCell::new(Apu::build_apu()));
+        apu.borrow_mut().set_region(region);
+        let bus = Rc::new(RefCell::new(NsfBus::build_nsf_bus(&nsf)));
+
+        let mut memory = Memory::build_memory();
+        memory.mirror(0x0000, 0x1FFF, 0x0800);
+        memory.map_device(0x3F00, 0x3FFF, bus.clone());
+        memory.map_device(0x4000, 0x4017, apu.clone());
+        memory.map_device(0x4018, 0xFFFF, bus.clone());
+
+        let play_period = f64::from(nsf.play_speed(region)) * region.cpu_clock_rate() / 1_000_000.0;
+        let track = nsf.starting_song;
+        let mut player = NsfPlayer {
+            nsf,
+            region,
+            cpu: Cpu::build_cpu(),
+            memory,
+            apu,
+            bus,
+            audio: AudioOutput::build_audio_output(region.cpu_clock_rate(), sample_rate),
+            cpu_cycles: 0,
+            dma_cycles: 0,
+            play_period,
+            play_timer: play_period,
+            track,
+            vgm: None,
+        };
+        player.start_track(track)?;
+        return Ok(player);
+    }
+
+    pub fn nsf(&self) -> &Nsf {
+        return &self.nsf;
+    }
+
+    pub fn region(&self) -> Region {
+        return self.region;
+    }
+
+    pub fn track(&self) -> Byte {
+        return self.track;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        return self.audio.sample_rate();
+    }
+
+    // Resets the sound hardware and runs INIT for a 0 based track number
+    pub fn start_track(&mut self, track: Byte) -> Result<(), NsfError> {
+        if track >= self.nsf.total_songs {
+            return Err(NsfError::InvalidTrack(track));
+        }
+        self.track = track;
+        for address in 0x0000..0x0800 {
+            self.memory.write_byte(address, 0x00);
+        }
+        *self.bus.borrow_mut() = NsfBus::build_nsf_bus(&self.nsf);
+        self.bus.borrow_mut().start_track(track, self.region);
+        // Silence the APU the way the NSF specification asks before INIT
+        self.apu.borrow_mut().reset();
+        for address in 0x4000..=0x4013 {
+            self.memory.write_byte(address, 0x00);
+        }
+        self.memory.write_byte(0x4015, 0x0F);
+        self.memory.write_byte(0x4017, 0x40);
+        self.cpu.reset(&self.memory);
+        self.dma_cycles = 0;
+        self.play_timer = self.play_period;
+        return Ok(());
+    }
+
+    // Logs the writes to the sound registers from now on, start it before `start_track`
+    // so the log begins with the APU initialization
+    pub fn start_vgm_log(&mut self) {
+        let logger = VgmLogger::build_vgm_logger(self.region, SoundChips::from_nsf(&self.nsf), self.cpu_cycles);
+        self.memory.map_device(0x4000, 0x4017, logger.tap(self.apu.clone()));
+        self.memory.map_device(0x4018, 0xFFFF, logger.tap(self.bus.clone()));
+        self.vgm = Some(logger);
+    }
+
+    // Returns the VGM file of the writes logged since `start_vgm_log`
+    pub fn stop_vgm_log(&mut self) -> Option<Vec<Byte>> {
+        let logger = self.vgm.take()?;
+        self.memory.map_device(0x4000, 0x4017, self.apu.clone());
+        self.memory.map_device(0x4018, 0xFFFF, self.bus.clone());
+        return Some(logger.finish(self.cpu_cycles));
+    }
+
+    pub fn run_cycles(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.step();
+        }
+        self.audio.end_frame(self.cpu_cycles);
+    }
+
+    // Runs for a number of milliseconds, returns the samples produced
+    pub fn run_for(&mut self, milliseconds: u32) -> &[f32] {
+        let cycles = self.region.cpu_clock_rate() * f64::from(milliseconds) / 1000.0;
+        self.run_cycles(cycles as u64);
+        return self.audio.samples();
+    }
+
+    // Audio of the last `run_cycles` or `run_for` call
+    pub fn audio_samples(&self) -> &[f32] {
+        return self.audio.samples();
+    }
+
+    fn step(&mut self) {
+        if self.dma_cycles > 0 {
+            self.dma_cycles -= 1;
+        } else {
+            self.cpu.exec_cycle(&mut self.memory);
+        }
+        if let Some(vgm) = self.vgm.as_mut() {
+            let bus = &self.bus;
+            vgm.log_writes(self.cpu_cycles, |address| bus.borrow_mut().read_byte(address, 0x00));
+        }
+        self.apu.borrow_mut().clock();
+        let dmc_request = self.apu.borrow().dmc_dma_request();
+        if let Some(address) = dmc_request {
+            let data = self.memory.read_byte(address);
+            self.apu.borrow_mut().dmc_fill_buffer(data);
+            self.dma_cycles += DMC_DMA_CYCLES;
+        }
+        self.bus.borrow_mut().clock();
+        let level = self.apu.borrow().output() + self.bus.borrow().audio_output();
+        self.audio.update(self.cpu_cycles, level);
+
+        // A PLAY call missed because the driver is still busy is dropped, like on a real player
+        self.play_timer -= 1.0;
+        let mut play = false;
+        if self.play_timer <= 0.0 {
+            self.play_timer += self.play_period;
+            play = self.bus.borrow_mut().take_play_request();
+        }
+        self.cpu.set_nmi_line(play);
+        self.cpu.set_irq_line(self.apu.borrow().irq());
+        self.cpu_cycles += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{nes::region::Region, nsf::{Nsf, NsfError, tests::build_nsf}};
+
+    use super::NsfPlayer;
+
+    #[test]
+    fn test_nsf_player_calls_init_and_play() {
+        let program = [
+            0x85, 0x00,       // $8000 INIT: STA $00
+            0x86, 0x01,       // $8002 STX $01
+            0x60,             // $8004 RTS
+            0xE6, 0x02,       // $8005 PLAY: INC $02
+            0x60,             // $8007 RTS
+        ];
+        let nsf = Nsf::parse(&build_nsf(&program, 0x8000, 0x8005, 3, 0x00)).unwrap();
+        let mut player = NsfPlayer::build_nsf_player(nsf, Region::Ntsc, 48_000).unwrap();
+        player.start_track(2).unwrap();
+        // One second at 60 Hz
+        let samples = player.run_for(1000).len();
+        assert!((samples as i64 - 48_000).abs() <= 1);
+        assert_eq!(player.memory.read_byte(0x0000), 2);
+        assert_eq!(player.memory.read_byte(0x0001), 0);
+        let plays = player.memory.read_byte(0x0002);
+        assert!((59..=60).contains(&plays));
+        assert_eq!(player.start_track(3).unwrap_err(), NsfError::InvalidTrack(3));
+    }
+
+    #[test]
+    fn test_nsf_player_bankswitching() {
+        // 3 banks loaded at $8100: the first 4 KiB bank starts with $100 bytes of padding
+        let mut program = vec![0x00; 0x2F00];
+        program[0x0000] = 0xAD; // $8100 INIT: LDA $9000
+        program[0x0001] = 0x00;
+        program[0x0002] = 0x90;
+        program[0x0003] = 0x85; // STA $00
+        program[0x0004] = 0x00;
+        program[0x0005] = 0x60; // RTS
+        program[0x0006] = 0x60; // PLAY: RTS
+        program[0x0F00] = 0x11;
+        program[0x1F00] = 0x22;
+        let mut image = build_nsf(&program, 0x8100, 0x8106, 1, 0x00);
+        image[0x70..0x78].copy_from_slice(&[0, 2, 0, 0, 0, 0, 0, 0]);
+        let mut player = NsfPlayer::build_nsf_player(Nsf::parse(&image).unwrap(), Region::Ntsc, 48_000).unwrap();
+        player.run_for(20);
+        assert_eq!(player.memory.read_byte(0x0000), 0x22);
+        player.memory.write_byte(0x5FF9, 0x01);
+        assert_eq!(player.memory.read_byte(0x9000), 0x11);
+    }
+
+    #[test]
+    fn test_nsf_player_sound() {
+        let program = [
+            0xA9, 0xBF,       // $8000 INIT: LDA #$BF
+            0x8D, 0x00, 0x40, // STA $4000
+            0xA9, 0xFD,       // LDA #$FD
+            0x8D, 0x02, 0x40, // STA $4002
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x03, 0x40, // STA $4003
+            0x60,             // RTS
+            0x60,             // $8010 PLAY: RTS
+        ];
+        let nsf = Nsf::parse(&build_nsf(&program, 0x8000, 0x8010, 1, 0x00)).unwrap();
+        let mut player = NsfPlayer::build_nsf_player(nsf, Region::Ntsc, 44_100).unwrap();
+        player.run_for(100);
+        let peak = player.run_for(100).iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak > 0.03);
+    }
+
+    #[test]
+    fn test_nsf_player_expansion_audio() {
+        let program = [
+            0xA9, 0x8F,       // $8000 INIT: LDA #$8F
+            0x8D, 0x00, 0x90, // STA $9000
+            0xA9, 0x80,       // LDA #$80
+            0x8D, 0x02, 0x90, // STA $9002
+            0x60,             // RTS
+            0x60,             // $800B PLAY: RTS
+        ];
+        let nsf = Nsf::parse(&build_nsf(&program, 0x8000, 0x800B, 1, 0x01)).unwrap();
+        let mut player = NsfPlayer::build_nsf_player(nsf, Region::Ntsc, 44_100).unwrap();
+        player.run_for(20);
+        assert!((player.bus.borrow().audio_output() - 0.149).abs() < 0.001);
+        let fds = Nsf::parse(&build_nsf(&program, 0x8000, 0x800B, 1, 0x04)).unwrap();
+        assert_eq!(NsfPlayer::build_nsf_player(fds, Region::Ntsc, 44_100).err(), Some(NsfError::UnsupportedChip("FDS")));
+    }
+}
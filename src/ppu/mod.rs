@@ -0,0 +1,759 @@
+pub mod palette;
+
+use std::{cell::RefCell, rc::Rc};
+
+use bitflags::bitflags;
+
+use crate::{cartridge::Cartridge, cpu::{Byte, Word}, memory::MemoryMapped, nes::region::Region};
+
+// Rendering follows the dot timing described in https://www.nesdev.org/wiki/PPU_rendering
+// and the scrolling registers described in https://www.nesdev.org/wiki/PPU_scrolling
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+const DOTS_PER_SCANLINE: u16 = 341;
+const MAX_SPRITES_PER_LINE: usize = 8;
+
+bitflags! {
+    pub struct PpuCtrl: Byte {
+        const NAMETABLE_X = 0b0000_0001;
+        const NAMETABLE_Y = 0b0000_0010;
+        const INCREMENT = 0b0000_0100; // VRAM ADDRESS INCREMENT (0: 1, 1: 32)
+        const SPRITE_TABLE = 0b0000_1000;
+        const BACKGROUND_TABLE = 0b0001_0000;
+        const SPRITE_SIZE = 0b0010_0000; // 0: 8x8, 1: 8x16
+        const MASTER_SLAVE = 0b0100_0000;
+        const NMI_ENABLE = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    pub struct PpuMask: Byte {
+        const GRAYSCALE = 0b0000_0001;
+        const SHOW_BACKGROUND_LEFT = 0b0000_0010;
+        const SHOW_SPRITES_LEFT = 0b0000_0100;
+        const SHOW_BACKGROUND = 0b0000_1000;
+        const SHOW_SPRITES = 0b0001_0000;
+        const EMPHASIZE_RED = 0b0010_0000;
+        const EMPHASIZE_GREEN = 0b0100_0000;
+        const EMPHASIZE_BLUE = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    pub struct PpuStatus: Byte {
+        const SPRITE_OVERFLOW = 0b0010_0000;
+        const SPRITE_ZERO_HIT = 0b0100_0000;
+        const VBLANK = 0b1000_0000;
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct SpriteSlot {
+    x: Byte,
+    attributes: Byte,
+    pattern_address: Word,
+    pattern_low: Byte,
+    pattern_high: Byte,
+    sprite_zero: bool,
+}
+
+pub struct Ppu {
+    cartridge: Rc<RefCell<Cartridge>>,
+
+    // Registers
+    ctrl: PpuCtrl,
+    mask: PpuMask,
+    status: PpuStatus,
+    oam_address: Byte,
+    io_latch: Byte,
+    read_buffer: Byte,
+
+    // Internal scrolling registers (v, t, x and w in the nesdev naming)
+    v: Word,
+    t: Word,
+    fine_x: Byte,
+    write_toggle: bool,
+
+    // Memories
+    oam: [Byte; 256],
+    vram: [Byte; 0x1000],
+    palette: [Byte; 32],
+
+    // Background pipeline
+    next_tile_id: Byte,
+    next_tile_attribute: Byte,
+    next_tile_low: Byte,
+    next_tile_high: Byte,
+    pattern_shift_low: Word,
+    pattern_shift_high: Word,
+    attribute_shift_low: Word,
+    attribute_shift_high: Word,
+
+    // Sprites for the next scanline
+    sprites: [SpriteSlot; MAX_SPRITES_PER_LINE],
+    sprite_count: usize,
+
+    // Timing
+    region: Region,
+    scanline: u16,
+    dot: u16,
+    frame: u64,
+    odd_frame: bool,
+    frame_complete: bool,
+
+    // Palette index of each pixel with the emphasis bits on top (bits 6-8)
+    frame_buffer: Vec<Word>,
+}
+
+impl Ppu {
+    pub fn build_ppu(cartridge: Rc<RefCell<Cartridge>>) -> Ppu {
+        return Ppu {
+            cartridge,
+            ctrl: PpuCtrl::empty(),
+            mask: PpuMask::empty(),
+            status: PpuStatus::empty(),
+            oam_address: 0x00,
+            io_latch: 0x00,
+            read_buffer: 0x00,
+            v: 0x0000,
+            t: 0x0000,
+            fine_x: 0x00,
+            write_toggle: false,
+            oam: [0x00; 256],
+            vram: [0x00; 0x1000],
+            palette: [0x00; 32],
+            next_tile_id: 0x00,
+            next_tile_attribute: 0x00,
+            next_tile_low: 0x00,
+            next_tile_high: 0x00,
+            pattern_shift_low: 0x0000,
+            pattern_shift_high: 0x0000,
+            attribute_shift_low: 0x0000,
+            attribute_shift_high: 0x0000,
+            sprites: [SpriteSlot::default(); MAX_SPRITES_PER_LINE],
+            sprite_count: 0,
+            region: Region::Ntsc,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            odd_frame: false,
+            frame_complete: false,
+            frame_buffer: vec![0x0000; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.ctrl = PpuCtrl::empty();
+        self.mask = PpuMask::empty();
+        self.write_toggle = false;
+        self.read_buffer = 0x00;
+        self.scanline = 0;
+        self.dot = 0;
+        self.odd_frame = false;
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.scanline = 0;
+        self.dot = 0;
+    }
+
+    // Level of the /NMI output, inverted so that true means an interrupt is being requested
+    pub fn nmi(&self) -> bool {
+        return self.status.contains(PpuStatus::VBLANK) && self.ctrl.contains(PpuCtrl::NMI_ENABLE);
+    }
+
+    pub fn frame_buffer(&self) -> &[Word] {
+        return &self.frame_buffer;
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        return self.frame;
+    }
+
+    pub fn scanline(&self) -> u16 {
+        return self.scanline;
+    }
+
+    pub fn dot(&self) -> u16 {
+        return self.dot;
+    }
+
+    // Returns true once per frame, when the PPU enters vertical blank
+    pub fn take_frame_complete(&mut self) -> bool {
+        let complete = self.frame_complete;
+        self.frame_complete = false;
+        return complete;
+    }
+
+    // Used by OAM DMA ($4014), behaves like consecutive writes to $2004
+    pub fn write_oam(&mut self, data: Byte) {
+        self.oam[usize::from(self.oam_address)] = data;
+        self.oam_address = self.oam_address.wrapping_add(1);
+    }
+
+    fn pre_render_scanline(&self) -> u16 {
+        return self.region.scanlines_per_frame() - 1;
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        return self.mask.intersects(PpuMask::SHOW_BACKGROUND | PpuMask::SHOW_SPRITES);
+    }
+
+    pub fn clock(&mut self) {
+        let pre_render = self.scanline == self.pre_render_scanline();
+        let visible = self.scanline < SCREEN_HEIGHT as u16;
+
+        if pre_render && self.dot == 1 {
+            self.status.remove(PpuStatus::VBLANK | PpuStatus::SPRITE_ZERO_HIT | PpuStatus::SPRITE_OVERFLOW);
+        }
+
+        if (visible || pre_render) && self.rendering_enabled() {
+            self.clock_rendering(pre_render);
+        }
+
+        if visible && (1..=256).contains(&self.dot) {
+            self.render_pixel();
+        }
+
+        if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+            self.status.insert(PpuStatus::VBLANK);
+            self.frame_complete = true;
+        }
+
+        self.advance_dot(pre_render);
+    }
+
+    fn advance_dot(&mut self, pre_render: bool) {
+        // Odd frames are one dot shorter while rendering, the idle dot of the pre-render line is skipped
+        if pre_render && self.dot == 339 && self.odd_frame && self.rendering_enabled() && self.region.skips_odd_frame_dot() {
+            self.dot = 340;
+        }
+        self.dot += 1;
+        if self.dot >= DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > self.pre_render_scanline() {
+                self.scanline = 0;
+                self.frame += 1;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    fn clock_rendering(&mut self, pre_render: bool) {
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.shift_background();
+        }
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    self.next_tile_id = self.read(0x2000 | (self.v & 0x0FFF));
+                }
+                2 => {
+                    let address = 0x23C0 | (self.v & 0x0C00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07);
+                    let mut attribute = self.read(address);
+                    if self.v & 0x0040 != 0 {
+                        attribute >>= 4;
+                    }
+                    if self.v & 0x0002 != 0 {
+                        attribute >>= 2;
+                    }
+                    self.next_tile_attribute = attribute & 0x03;
+                }
+                4 => {
+                    self.next_tile_low = self.read(self.background_pattern_address());
+                }
+                6 => {
+                    self.next_tile_high = self.read(self.background_pattern_address() + 8);
+                }
+                7 => {
+                    self.increment_x();
+                }
+                _ => {}
+            }
+        }
+        if dot == 256 {
+            self.increment_y();
+        }
+        if dot == 257 {
+            self.load_background_shifters();
+            self.transfer_x();
+            if pre_render {
+                self.sprite_count = 0;
+            } else {
+                self.evaluate_sprites();
+            }
+        }
+        if (257..=320).contains(&dot) {
+            self.oam_address = 0x00;
+            self.fetch_sprite(dot - 257);
+        }
+        // Unused nametable fetches at the end of the line
+        if dot == 338 || dot == 340 {
+            self.next_tile_id = self.read(0x2000 | (self.v & 0x0FFF));
+        }
+        if pre_render && (280..=304).contains(&dot) {
+            self.transfer_y();
+        }
+    }
+
+    fn background_pattern_address(&self) -> Word {
+        let table: Word = if self.ctrl.contains(PpuCtrl::BACKGROUND_TABLE) { 0x1000 } else { 0x0000 };
+        return table + (Word::from(self.next_tile_id) << 4) + ((self.v >> 12) & 0x07);
+    }
+
+    fn shift_background(&mut self) {
+        self.pattern_shift_low <<= 1;
+        self.pattern_shift_high <<= 1;
+        self.attribute_shift_low <<= 1;
+        self.attribute_shift_high <<= 1;
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.pattern_shift_low = (self.pattern_shift_low & 0xFF00) | Word::from(self.next_tile_low);
+        self.pattern_shift_high = (self.pattern_shift_high & 0xFF00) | Word::from(self.next_tile_high);
+        let attribute_low = if self.next_tile_attribute & 0x01 != 0 { 0x00FF } else { 0x0000 };
+        let attribute_high = if self.next_tile_attribute & 0x02 != 0 { 0x00FF } else { 0x0000 };
+        self.attribute_shift_low = (self.attribute_shift_low & 0xFF00) | attribute_low;
+        self.attribute_shift_high = (self.attribute_shift_high & 0xFF00) | attribute_high;
+    }
+
+    fn increment_x(&mut self) {
+        if self.v & 0x001F == 0x001F {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return
+        }
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    fn transfer_x(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    fn transfer_y(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    fn sprite_height(&self) -> u16 {
+        return if self.ctrl.contains(PpuCtrl::SPRITE_SIZE) { 16 } else { 8 };
+    }
+
+    // Collects the sprites of the next scanline. Hardware evaluation is spread over dots 65-256,
+    // doing it at once is indistinguishable for software but the overflow flag bug is not emulated.
+    fn evaluate_sprites(&mut self) {
+        let height = self.sprite_height();
+        self.sprite_count = 0;
+        for index in 0..64 {
+            let entry = &self.oam[index * 4..index * 4 + 4];
+            let row = self.scanline.wrapping_sub(u16::from(entry[0]));
+            if row >= height {
+                continue;
+            }
+            if self.sprite_count == MAX_SPRITES_PER_LINE {
+                self.status.insert(PpuStatus::SPRITE_OVERFLOW);
+                break;
+            }
+            let attributes = entry[2];
+            let tile = entry[1];
+            let row = if attributes & 0x80 != 0 { height - 1 - row } else { row };
+            let pattern_address = if height == 16 {
+                let table: Word = if tile & 0x01 != 0 { 0x1000 } else { 0x0000 };
+                let tile = Word::from(tile & 0xFE) + if row >= 8 { 1 } else { 0 };
+                table + (tile << 4) + (row & 0x07)
+            } else {
+                let table: Word = if self.ctrl.contains(PpuCtrl::SPRITE_TABLE) { 0x1000 } else { 0x0000 };
+                table + (Word::from(tile) << 4) + row
+            };
+            self.sprites[self.sprite_count] = SpriteSlot {
+                x: entry[3],
+                attributes,
+                pattern_address,
+                pattern_low: 0x00,
+                pattern_high: 0x00,
+                sprite_zero: index == 0,
+            };
+            self.sprite_count += 1;
+        }
+    }
+
+    // Each of the 8 sprite slots takes 8 dots, the pattern bytes are read on the 5th and 7th one.
+    // The first and third dots read the nametable like background tiles do, the result is unused.
+    // Empty slots still fetch tile $FF so that mappers watching A12 see the same accesses as on hardware.
+    fn fetch_sprite(&mut self, cycle: u16) {
+        let slot = usize::from(cycle / 8);
+        let address = if slot < self.sprite_count {
+            self.sprites[slot].pattern_address
+        } else if self.sprite_height() == 16 {
+            0x1FF0
+        } else {
+            let table: Word = if self.ctrl.contains(PpuCtrl::SPRITE_TABLE) { 0x1000 } else { 0x0000 };
+            table + 0x0FF0
+        };
+        match cycle % 8 {
+            0 | 2 => {
+                self.read(0x2000 | (self.v & 0x0FFF));
+            }
+            4 => {
+                let data = self.read(address);
+                if slot < self.sprite_count {
+                    self.sprites[slot].pattern_low = self.flip_sprite_pattern(slot, data);
+                }
+            }
+            6 => {
+                let data = self.read(address + 8);
+                if slot < self.sprite_count {
+                    self.sprites[slot].pattern_high = self.flip_sprite_pattern(slot, data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn flip_sprite_pattern(&self, slot: usize, data: Byte) -> Byte {
+        if self.sprites[slot].attributes & 0x40 != 0 {
+            return data.reverse_bits();
+        }
+        return data;
+    }
+
+    fn render_pixel(&mut self) {
+        let x = usize::from(self.dot - 1);
+        let y = usize::from(self.scanline);
+
+        let mut background_pixel: Byte = 0;
+        let mut background_palette: Byte = 0;
+        if self.mask.contains(PpuMask::SHOW_BACKGROUND) && (x >= 8 || self.mask.contains(PpuMask::SHOW_BACKGROUND_LEFT)) {
+            let mux: Word = 0x8000 >> self.fine_x;
+            background_pixel = (if self.pattern_shift_high & mux != 0 { 2 } else { 0 }) | (if self.pattern_shift_low & mux != 0 { 1 } else { 0 });
+            background_palette = (if self.attribute_shift_high & mux != 0 { 2 } else { 0 }) | (if self.attribute_shift_low & mux != 0 { 1 } else { 0 });
+        }
+
+        let mut sprite_pixel: Byte = 0;
+        let mut sprite_palette: Byte = 0;
+        let mut sprite_behind = false;
+        let mut sprite_zero = false;
+        if self.mask.contains(PpuMask::SHOW_SPRITES) && (x >= 8 || self.mask.contains(PpuMask::SHOW_SPRITES_LEFT)) && y > 0 {
+            for slot in self.sprites.iter().take(self.sprite_count) {
+                let offset = x.wrapping_sub(usize::from(slot.x));
+                if offset >= 8 {
+                    continue;
+                }
+                let bit = 7 - offset;
+                let pixel = (((slot.pattern_high >> bit) & 0x01) << 1) | ((slot.pattern_low >> bit) & 0x01);
+                if pixel != 0 {
+                    sprite_pixel = pixel;
+                    sprite_palette = (slot.attributes & 0x03) + 4;
+                    sprite_behind = slot.attributes & 0x20 != 0;
+                    sprite_zero = slot.sprite_zero;
+                    break;
+                }
+            }
+        }
+
+        if sprite_zero && background_pixel != 0 && sprite_pixel != 0 && x != 255 {
+            self.status.insert(PpuStatus::SPRITE_ZERO_HIT);
+        }
+
+        let palette_address: Word = if sprite_pixel != 0 && (background_pixel == 0 || !sprite_behind) {
+            0x3F00 | Word::from(sprite_palette) << 2 | Word::from(sprite_pixel)
+        } else if background_pixel != 0 {
+            0x3F00 | Word::from(background_palette) << 2 | Word::from(background_pixel)
+        } else if !self.rendering_enabled() && self.v & 0x3F00 == 0x3F00 {
+            // With rendering off the backdrop shows the palette entry the VRAM address points to
+            self.v
+        } else {
+            0x3F00
+        };
+        let mut color = self.read_palette(palette_address);
+        if self.mask.contains(PpuMask::GRAYSCALE) {
+            color &= 0x30;
+        }
+        let emphasis = Word::from(self.mask.bits() >> 5);
+        self.frame_buffer[y * SCREEN_WIDTH + x] = emphasis << 6 | Word::from(color);
+    }
+
+    fn palette_index(address: Word) -> usize {
+        let index = usize::from(address & 0x1F);
+        // $3F10/$3F14/$3F18/$3F1C mirror the backdrop entries
+        if index & 0x13 == 0x10 {
+            return index & 0x0F;
+        }
+        return index;
+    }
+
+    fn read_palette(&self, address: Word) -> Byte {
+        return self.palette[Ppu::palette_index(address)] & 0x3F;
+    }
+
+    fn read(&mut self, address: Word) -> Byte {
+        let address = address & 0x3FFF;
+        match address {
+            0x0000..=0x1FFF => {
+                return self.cartridge.borrow_mut().ppu_read(address);
+            }
+            0x2000..=0x3EFF => {
+                return self.cartridge.borrow_mut().read_nametable(address, &self.vram);
+            }
+            _ => {
+                return self.read_palette(address);
+            }
+        }
+    }
+
+    fn write(&mut self, address: Word, data: Byte) {
+        let address = address & 0x3FFF;
+        match address {
+            0x0000..=0x1FFF => {
+                self.cartridge.borrow_mut().ppu_write(address, data);
+            }
+            0x2000..=0x3EFF => {
+                self.cartridge.borrow_mut().write_nametable(address, data, &mut self.vram);
+            }
+            _ => {
+                self.palette[Ppu::palette_index(address)] = data & 0x3F;
+            }
+        }
+    }
+
+    fn increment_vram_address(&mut self) {
+        let increment = if self.ctrl.contains(PpuCtrl::INCREMENT) { 32 } else { 1 };
+        self.v = self.v.wrapping_add(increment) & 0x7FFF;
+    }
+}
+
+// CPU side registers at $2000-$2007, mirrored every 8 bytes up to $3FFF
+impl MemoryMapped for Ppu {
+    fn read_byte(&mut self, address: Word, _open_bus: Byte) -> Byte {
+        match address & 0x0007 {
+            0x0002 => {
+                self.io_latch = (self.status.bits() & 0xE0) | (self.io_latch & 0x1F);
+                self.status.remove(PpuStatus::VBLANK);
+                self.write_toggle = false;
+            }
+            0x0004 => {
+                self.io_latch = self.oam[usize::from(self.oam_address)];
+            }
+            0x0007 => {
+                let address = self.v & 0x3FFF;
+                if address >= 0x3F00 {
+                    // Palette reads are not buffered, the buffer gets the nametable byte underneath
+                    self.io_latch = (self.read_palette(address)) | (self.io_latch & 0xC0);
+                    self.read_buffer = self.read(address - 0x1000);
+                } else {
+                    self.io_latch = self.read_buffer;
+                    self.read_buffer = self.read(address);
+                }
+                self.increment_vram_address();
+            }
+            _ => {}
+        }
+        return self.io_latch;
+    }
+
+    fn write_byte(&mut self, address: Word, data: Byte) {
+        self.io_latch = data;
+        self.cartridge.borrow_mut().ppu_register_write(0x2000 | (address & 0x0007), data);
+        match address & 0x0007 {
+            0x0000 => {
+                self.ctrl = PpuCtrl::from_bits_truncate(data);
+                self.t = (self.t & 0xF3FF) | (Word::from(data & 0x03) << 10);
+            }
+            0x0001 => {
+                self.mask = PpuMask::from_bits_truncate(data);
+            }
+            0x0003 => {
+                self.oam_address = data;
+            }
+            0x0004 => {
+                self.write_oam(data);
+            }
+            0x0005 => {
+                if !self.write_toggle {
+                    self.t = (self.t & 0xFFE0) | Word::from(data >> 3);
+                    self.fine_x = data & 0x07;
+                } else {
+                    self.t = (self.t & 0x8C1F) | (Word::from(data & 0x07) << 12) | (Word::from(data & 0xF8) << 2);
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            0x0006 => {
+                if !self.write_toggle {
+                    self.t = (self.t & 0x00FF) | (Word::from(data & 0x3F) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | Word::from(data);
+                    self.v = self.t;
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            0x0007 => {
+                self.write(self.v, data);
+                self.increment_vram_address();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{cartridge::{Cartridge, tests::build_ines}, memory::MemoryMapped, nes::region::Region};
+
+    use super::{Ppu, PpuStatus, DOTS_PER_SCANLINE};
+
+    fn build_test_ppu() -> Ppu {
+        // CHR RAM cartridge with vertical mirroring
+        let cartridge = Cartridge::from_ines(&build_ines(1, 0, 0x01, 0x00)).unwrap();
+        return Ppu::build_ppu(Rc::new(RefCell::new(cartridge)));
+    }
+
+    fn set_vram_address(ppu: &mut Ppu, address: u16) {
+        ppu.write_byte(0x2006, (address >> 8) as u8);
+        ppu.write_byte(0x2006, address as u8);
+    }
+
+    #[test]
+    fn test_vram_read_is_buffered() {
+        let mut ppu = build_test_ppu();
+        set_vram_address(&mut ppu, 0x2400);
+        ppu.write_byte(0x2007, 0x11);
+        ppu.write_byte(0x2007, 0x22);
+        // Vertical mirroring: $2C00 is the same table as $2400
+        set_vram_address(&mut ppu, 0x2C00);
+        ppu.read_byte(0x2007, 0x00);
+        assert_eq!(ppu.read_byte(0x2007, 0x00), 0x11);
+        assert_eq!(ppu.read_byte(0x2007, 0x00), 0x22);
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let mut ppu = build_test_ppu();
+        set_vram_address(&mut ppu, 0x3F10);
+        ppu.write_byte(0x2007, 0x2C);
+        set_vram_address(&mut ppu, 0x3F00);
+        assert_eq!(ppu.read_byte(0x2007, 0x00), 0x2C);
+    }
+
+    #[test]
+    fn test_vblank_flag_and_nmi() {
+        let mut ppu = build_test_ppu();
+        ppu.write_byte(0x2000, 0x80);
+        for _ in 0..(u32::from(DOTS_PER_SCANLINE) * 241 + 2) {
+            ppu.clock();
+        }
+        assert!(ppu.nmi());
+        assert!(ppu.take_frame_complete());
+        assert!(!ppu.take_frame_complete());
+        assert_eq!(ppu.read_byte(0x2002, 0x00) & 0x80, 0x80);
+        // Reading status acknowledges vblank
+        assert!(!ppu.nmi());
+        assert!(!ppu.status.contains(PpuStatus::VBLANK));
+    }
+
+    #[test]
+    fn test_frame_length() {
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+            let mut ppu = build_test_ppu();
+            ppu.set_region(region);
+            let frame = ppu.frame_count();
+            for _ in 0..(u32::from(DOTS_PER_SCANLINE) * u32::from(region.scanlines_per_frame())) {
+                ppu.clock();
+            }
+            assert_eq!(ppu.frame_count(), frame + 1);
+            assert_eq!(ppu.scanline(), 0);
+            assert_eq!(ppu.dot(), 0);
+        }
+    }
+
+    #[test]
+    fn test_odd_frame_dot_skip() {
+        for (region, skipped) in [(Region::Ntsc, 1), (Region::Pal, 0)] {
+            let mut ppu = build_test_ppu();
+            ppu.set_region(region);
+            ppu.write_byte(0x2001, 0x08);
+            let dots_per_frame = u32::from(DOTS_PER_SCANLINE) * u32::from(region.scanlines_per_frame());
+            // The first frame is even, the second one is shortened on NTSC
+            for _ in 0..(dots_per_frame * 2 - skipped) {
+                ppu.clock();
+            }
+            assert_eq!(ppu.frame_count(), 2);
+            assert_eq!(ppu.scanline(), 0);
+            assert_eq!(ppu.dot(), 0);
+        }
+    }
+
+    #[test]
+    fn test_dendy_vblank_start() {
+        let mut ppu = build_test_ppu();
+        ppu.set_region(Region::Dendy);
+        for _ in 0..(u32::from(DOTS_PER_SCANLINE) * 241 + 2) {
+            ppu.clock();
+        }
+        assert!(!ppu.take_frame_complete());
+        for _ in 0..(u32::from(DOTS_PER_SCANLINE) * 50) {
+            ppu.clock();
+        }
+        assert!(ppu.take_frame_complete());
+        assert_eq!(ppu.scanline(), 291);
+    }
+
+    #[test]
+    fn test_renders_background_tile() {
+        let mut ppu = build_test_ppu();
+        // Tile 1 is a solid block of color 3
+        set_vram_address(&mut ppu, 0x0010);
+        for _ in 0..16 {
+            ppu.write_byte(0x2007, 0xFF);
+        }
+        // Place it at the top left corner with palette 0
+        set_vram_address(&mut ppu, 0x2000);
+        ppu.write_byte(0x2007, 0x01);
+        set_vram_address(&mut ppu, 0x3F00);
+        ppu.write_byte(0x2007, 0x0F);
+        ppu.write_byte(0x2007, 0x01);
+        ppu.write_byte(0x2007, 0x02);
+        ppu.write_byte(0x2007, 0x30);
+        // Reset scroll and enable background
+        set_vram_address(&mut ppu, 0x0000);
+        ppu.write_byte(0x2005, 0x00);
+        ppu.write_byte(0x2005, 0x00);
+        ppu.write_byte(0x2001, 0x0A);
+        // Run a whole frame so the pre-render line sets up the pipeline, then the visible area
+        while !ppu.take_frame_complete() {
+            ppu.clock();
+        }
+        while !ppu.take_frame_complete() {
+            ppu.clock();
+        }
+        assert_eq!(ppu.frame_buffer()[0], 0x30);
+        assert_eq!(ppu.frame_buffer()[7], 0x30);
+        assert_eq!(ppu.frame_buffer()[8], 0x0F);
+        assert_eq!(ppu.frame_buffer()[7 * 256], 0x30);
+        assert_eq!(ppu.frame_buffer()[8 * 256], 0x0F);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{fmt::{self, Display}, path::Path};
+
+use crate::cpu::{Byte, Word};
+
+// Colors of the 64 palette entries, as commonly measured from a 2C02
+const DEFAULT_COLORS: [[Byte; 3]; 64] = [
+    [84, 84, 84], [0, 30, 116], [8, 16, 144], [48, 0, 136], [68, 0, 100], [92, 0, 48], [84, 4, 0], [60, 24, 0],
+    [32, 42, 0], [8, 58, 0], [0, 64, 0], [0, 60, 0], [0, 50, 60], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [152, 150, 152], [8, 76, 196], [48, 50, 236], [92, 30, 228], [136, 20, 176], [160, 20, 100], [152, 34, 32], [120, 60, 0],
+    [84, 90, 0], [40, 114, 0], [8, 124, 0], [0, 118, 40], [0, 102, 120], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [76, 154, 236], [120, 124, 236], [176, 98, 236], [228, 84, 236], [236, 88, 180], [236, 106, 100], [212, 136, 32],
+    [160, 170, 0], [116, 196, 0], [76, 208, 32], [56, 204, 108], [56, 180, 204], [60, 60, 60], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [168, 204, 236], [188, 188, 236], [212, 178, 236], [236, 174, 236], [236, 174, 212], [236, 180, 176], [228, 196, 144],
+    [204, 210, 120], [180, 222, 120], [168, 226, 144], [152, 226, 180], [160, 214, 228], [160, 162, 160], [0, 0, 0], [0, 0, 0],
+];
+
+// Emphasis darkens the color components that are not emphasized by about 18%
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PaletteError {
+    // .pal files hold 64 colors, or 512 with the emphasis variants, of 3 bytes each
+    InvalidSize(usize),
+    Io(String),
+}
+
+impl Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::InvalidSize(size) => write!(f, "a palette file has 192 or 1536 bytes, not {}", size),
+            PaletteError::Io(message) => write!(f, "could not read the palette: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+// RGB colors of the 512 values a pixel of the frame buffer can take: the 6 bit palette entry
+// with the 3 emphasis bits of PPUMASK above it (red, green, blue from bit 6 on NTSC).
+pub struct Palette {
+    colors: Vec<[Byte; 3]>,
+}
+
+impl Palette {
+    pub fn build_default_palette() -> Palette {
+        return Palette::from_base_colors(&DEFAULT_COLORS);
+    }
+
+    // The emphasis variants are derived from the 64 base colors
+    fn from_base_colors(base: &[[Byte; 3]]) -> Palette {
+        let mut colors = Vec::with_capacity(512);
+        for emphasis in 0..8 {
+            for color in base {
+                colors.push(Palette::emphasize(*color, emphasis));
+            }
+        }
+        return Palette { colors };
+    }
+
+    // Contents of a .pal file: RGB triplets for the 64 entries, optionally followed by the
+    // 7 sets of emphasized colors in the order of the emphasis bits
+    pub fn from_pal(data: &[Byte]) -> Result<Palette, PaletteError> {
+        if data.len() != 64 * 3 && data.len() != 512 * 3 {
+            return Err(PaletteError::InvalidSize(data.len()));
+        }
+        let colors: Vec<[Byte; 3]> = data.chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect();
+        if colors.len() == 64 {
+            return Ok(Palette::from_base_colors(&colors));
+        }
+        return Ok(Palette { colors });
+    }
+
+    pub fn load(path: &Path) -> Result<Palette, PaletteError> {
+        let data = std::fs::read(path).map_err(|error| PaletteError::Io(error.to_string()))?;
+        return Palette::from_pal(&data);
+    }
+
+    fn emphasize(color: [Byte; 3], emphasis: usize) -> [Byte; 3] {
+        if emphasis == 0 {
+            return color;
+        }
+        let mut result = color;
+        for (component, value) in result.iter_mut().enumerate() {
+            if emphasis & (1 << component) == 0 {
+                *value = (f32::from(*value) * EMPHASIS_ATTENUATION).round() as Byte;
+            }
+        }
+        return result;
+    }
+
+    pub fn rgb(&self, pixel: Word) -> [Byte; 3] {
+        return self.colors[usize::from(pixel) & 0x1FF];
+    }
+
+    // 3 bytes per pixel
+    pub fn frame_to_rgb(&self, frame: &[Word]) -> Vec<Byte> {
+        return frame.iter().flat_map(|pixel| self.rgb(*pixel)).collect();
+    }
+
+    // 4 bytes per pixel, alpha always opaque
+    pub fn frame_to_rgba(&self, frame: &[Word]) -> Vec<Byte> {
+        let mut rgba = vec![0x00; frame.len() * 4];
+        self.fill_rgba(frame, &mut rgba);
+        return rgba;
+    }
+
+    // For frontends converting every frame into the same texture buffer
+    pub fn fill_rgba(&self, frame: &[Word], rgba: &mut [Byte]) {
+        for (pixel, output) in frame.iter().zip(rgba.chunks_exact_mut(4)) {
+            let [red, green, blue] = self.rgb(*pixel);
+            output.copy_from_slice(&[red, green, blue, 0xFF]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Palette, PaletteError};
+
+    #[test]
+    fn test_default_palette() {
+        let palette = Palette::build_default_palette();
+        assert_eq!(palette.rgb(0x0F), [0, 0, 0]);
+        assert_eq!(palette.rgb(0x30), [236, 238, 236]);
+        // Red emphasis keeps red and darkens green and blue
+        assert_eq!(palette.rgb(0x40 | 0x30), [236, 194, 193]);
+        assert_eq!(palette.frame_to_rgb(&[0x30, 0x0F]), vec![236, 238, 236, 0, 0, 0]);
+        assert_eq!(palette.frame_to_rgba(&[0x30, 0x0F]), vec![236, 238, 236, 255, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_pal_files() {
+        let mut data: Vec<u8> = (0..64).flat_map(|entry| [entry, 100, 200]).collect();
+        let palette = Palette::from_pal(&data).unwrap();
+        assert_eq!(palette.rgb(0x21), [0x21, 100, 200]);
+        // Blue emphasis on a 64 color file darkens red and green
+        assert_eq!(palette.rgb(0x100 | 0x21), [27, 82, 200]);
+
+        // A 512 color file gives every emphasized color itself
+        data.extend((64..512).flat_map(|entry: u32| [(entry / 64) as u8, 0, 0]));
+        let palette = Palette::from_pal(&data).unwrap();
+        assert_eq!(palette.rgb(0x21), [0x21, 100, 200]);
+        assert_eq!(palette.rgb(0x100 | 0x21), [4, 0, 0]);
+        assert_eq!(Palette::from_pal(&data[..100]).err(), Some(PaletteError::InvalidSize(100)));
+    }
+}
@@ -9,7 +9,7 @@ pub const ABS_PTR: crate::cpu::Word = 0x6000;
 #[cfg(test)]
 pub const ZP_PTR: crate::cpu::Byte = 0x20;
 
-#[cfg(test)] #[derive(Copy, Clone)]
+#[cfg(test)] #[derive(Clone)]
 pub struct TestMemory {
     memory: crate::memory::Memory,
     next_address: crate::cpu::Word,
@@ -97,7 +97,7 @@ impl TestMemory {
     }
 
     pub fn get_memory(&self) -> crate::memory::Memory {
-        return self.memory
+        return self.memory.clone()
     }
 
     pub fn write_data_byte(&mut self, address: crate::cpu::Word, data: crate::cpu::Byte) {
@@ -255,7 +255,7 @@ pub fn setup_test_with_ps(a_reg: Option<crate::cpu::Byte>, x_reg: Option<crate::
     }
     let mut test_memory = setup_memory(START_PROGRAM, program, program_data);
     // This ensures cpu and memory is built for test according to the addressing mode
-    assert_setup(TestCpu::clone_from_cpu(&test_cpu), test_memory, a_reg, x_reg, y_reg, sp_reg, opcode, addressing_type, data.into());
+    assert_setup(TestCpu::clone_from_cpu(&test_cpu), test_memory.clone(), a_reg, x_reg, y_reg, sp_reg, opcode, addressing_type, data.into());
     run_test_for_x_clock(&mut test_cpu, &mut test_memory, clock_cycles);
     return (TestCpu::clone_from_cpu(&test_cpu), test_memory);
 }
@@ -0,0 +1,290 @@
+use std::{cell::RefCell, rc::{Rc, Weak}};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{cpu::{Byte, Word}, memory::{MemoryMapped, SharedDevice}, nes::region::Region};
+
+use super::*;
+
+type WriteQueue = RefCell<Vec<(Word, Byte)>>;
+
+// Sits in front of a device on the CPU bus and queues the writes it receives for the logger.
+// Once the logger is dropped it only forwards the accesses.
+struct RegisterTap {
+    device: SharedDevice,
+    writes: Weak<WriteQueue>,
+}
+
+impl MemoryMapped for RegisterTap {
+    fn read_byte(&mut self, address: Word, open_bus: Byte) -> Byte {
+        return self.device.borrow_mut().read_byte(address, open_bus);
+    }
+
+    fn write_byte(&mut self, address: Word, data: Byte) {
+        if let Some(writes) = self.writes.upgrade() {
+            writes.borrow_mut().push((address, data));
+        }
+        self.device.borrow_mut().write_byte(address, data);
+    }
+
+    fn peek_byte(&self, address: Word, open_bus: Byte) -> Byte {
+        return self.device.borrow().peek_byte(address, open_bus);
+    }
+}
+
+// Records the sound register writes of a running program as a VGM file, timed to the CPU cycle
+// they happened on (rounded to the 44.1 kHz VGM sample). The DMC reads its samples from the
+// cartridge, so the bytes a sample covers are put in the file when it is started.
+// The APU is expected to be in its power up state when logging starts.
+pub struct VgmLogger {
+    region: Region,
+    chips: SoundChips,
+    writes: Rc<WriteQueue>,
+    commands: Vec<Byte>,
+    start_cycle: u64,
+    // VGM samples waited so far
+    samples: u64,
+    opll_address: Byte,
+    psg_address: Byte,
+    dmc_address: Byte,
+    dmc_length: Byte,
+    // Contents of $C000-$FFFF already given to the DMC by the file
+    dmc_memory: Vec<Option<Byte>>,
+}
+
+impl VgmLogger {
+    pub fn build_vgm_logger(region: Region, chips: SoundChips, cycle: u64) -> VgmLogger {
+        return VgmLogger {
+            region,
+            chips,
+            writes: Rc::new(RefCell::new(Vec::new())),
+            commands: Vec::new(),
+            start_cycle: cycle,
+            samples: 0,
+            opll_address: 0,
+            psg_address: 0,
+            dmc_address: 0,
+            dmc_length: 0,
+            dmc_memory: vec![None; 0x4000],
+        };
+    }
+
+    // Wraps a device so the writes it receives get logged, to be mapped in its place
+    pub fn tap(&self, device: SharedDevice) -> SharedDevice {
+        return Rc::new(RefCell::new(RegisterTap { device, writes: Rc::downgrade(&self.writes) }));
+    }
+
+    // Logs the writes the taps received since the last call as happening on `cycle`.
+    // `read` reads the CPU bus, for the DMC sample bytes.
+    pub fn log_writes(&mut self, cycle: u64, mut read: impl FnMut(Word) -> Byte) {
+        let writes = std::mem::take(&mut *self.writes.borrow_mut());
+        if writes.is_empty() {
+            return;
+        }
+        self.wait_until(cycle);
+        for (address, data) in writes {
+            self.log_write(address, data, &mut read);
+        }
+    }
+
+    fn log_write(&mut self, address: Word, data: Byte, read: &mut impl FnMut(Word) -> Byte) {
+        match address {
+            0x4000..=0x4011 | 0x4017 => self.apu_write(address, data),
+            0x4012 => {
+                self.dmc_address = data;
+                self.apu_write(address, data);
+            }
+            0x4013 => {
+                self.dmc_length = data;
+                self.apu_write(address, data);
+            }
+            0x4015 => {
+                // The sample must be in the file before the write that starts it
+                if data & 0x10 != 0 {
+                    self.log_dmc_sample(read);
+                }
+                self.apu_write(address, data);
+            }
+            _ => self.log_expansion_write(address, data),
+        }
+    }
+
+    fn log_expansion_write(&mut self, address: Word, data: Byte) {
+        if let Some(select_mask) = self.chips.vrc7 {
+            match vrc7::register(select_mask, address) {
+                0x9010 => {
+                    self.opll_address = data;
+                    return;
+                }
+                0x9030 => {
+                    self.commands.extend_from_slice(&[YM2413_WRITE, self.opll_address, data]);
+                    return;
+                }
+                _ => {}
+            }
+        }
+        if self.chips.sunsoft_5b {
+            match address {
+                0xC000..=0xDFFF => self.psg_address = data,
+                0xE000..=0xFFFF => self.commands.extend_from_slice(&[AY8910_WRITE, self.psg_address, data]),
+                _ => {}
+            }
+        }
+    }
+
+    fn apu_write(&mut self, address: Word, data: Byte) {
+        self.commands.extend_from_slice(&[NES_APU_WRITE, (address - 0x4000) as Byte, data]);
+    }
+
+    // Sample addresses start at $C000 + A * 64 and samples are L * 16 + 1 bytes long
+    fn log_dmc_sample(&mut self, read: &mut impl FnMut(Word) -> Byte) {
+        let start = 0xC000 + usize::from(self.dmc_address) * 64;
+        let length = (usize::from(self.dmc_length) * 16 + 1).min(0x10000 - start);
+        let sample: Vec<Byte> = (start..start + length).map(|address| read(address as Word)).collect();
+        let known = &mut self.dmc_memory[start - 0xC000..start - 0xC000 + length];
+        if known.iter().zip(sample.iter()).all(|(known, data)| *known == Some(*data)) {
+            return;
+        }
+        for (known, data) in known.iter_mut().zip(sample.iter()) {
+            *known = Some(*data);
+        }
+        // 0x67 0x66 type size, 0x66 makes older players stop instead of misreading the block
+        self.commands.extend_from_slice(&[DATA_BLOCK, END_OF_DATA, NES_APU_RAM_BLOCK]);
+        self.commands.extend_from_slice(&(length as u32 + 2).to_le_bytes());
+        self.commands.extend_from_slice(&(start as Word).to_le_bytes());
+        self.commands.extend_from_slice(&sample);
+    }
+
+    fn wait_until(&mut self, cycle: u64) {
+        let elapsed = cycle.saturating_sub(self.start_cycle) as f64;
+        let target = (elapsed * f64::from(VGM_SAMPLE_RATE) / self.region.cpu_clock_rate()) as u64;
+        let mut wait = target.saturating_sub(self.samples);
+        self.samples += wait;
+        while wait > 0 {
+            let step = wait.min(0xFFFF);
+            match step {
+                735 => self.commands.push(WAIT_NTSC_FRAME),
+                882 => self.commands.push(WAIT_PAL_FRAME),
+                1..=16 => self.commands.push(WAIT_SHORT + (step - 1) as Byte),
+                _ => {
+                    self.commands.push(WAIT);
+                    self.commands.extend_from_slice(&(step as Word).to_le_bytes());
+                }
+            }
+            wait -= step;
+        }
+    }
+
+    // Closes the log at `cycle` and returns the file
+    pub fn finish(mut self, cycle: u64) -> Vec<Byte> {
+        self.wait_until(cycle);
+        self.commands.push(END_OF_DATA);
+        let cpu_clock_rate = self.region.cpu_clock_rate();
+
+        let mut file = vec![0x00; HEADER_SIZE];
+        file[0..4].copy_from_slice(VGM_MAGIC);
+        LittleEndian::write_u32(&mut file[EOF_OFFSET..], (HEADER_SIZE + self.commands.len() - EOF_OFFSET) as u32);
+        LittleEndian::write_u32(&mut file[VERSION..], VGM_VERSION);
+        LittleEndian::write_u32(&mut file[TOTAL_SAMPLES..], self.samples as u32);
+        LittleEndian::write_u32(&mut file[RATE..], if self.region == Region::Ntsc { 60 } else { 50 });
+        LittleEndian::write_u32(&mut file[DATA_OFFSET..], (HEADER_SIZE - DATA_OFFSET) as u32);
+        LittleEndian::write_u32(&mut file[NES_APU_CLOCK..], cpu_clock_rate.round() as u32);
+        if self.chips.vrc7.is_some() {
+            // The VRC7 runs its OPLL from a 3.58 MHz crystal, twice the NTSC CPU clock
+            LittleEndian::write_u32(&mut file[YM2413_CLOCK..], 3_579_545 | VRC7_FLAG);
+        }
+        if self.chips.sunsoft_5b {
+            // The 5B halves its clock, its tones run at CPU / (32 * period)
+            LittleEndian::write_u32(&mut file[AY8910_CLOCK..], (cpu_clock_rate / 2.0).round() as u32);
+            file[AY8910_TYPE] = YM2149_TYPE;
+            file[AY8910_FLAGS] = 0x01;
+        }
+        file.extend_from_slice(&self.commands);
+        return file;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use byteorder::{ByteOrder, LittleEndian};
+
+    use crate::{apu::Apu, cpu::{Byte, Word}, memory::{Memory, MemoryMapped}, nes::region::Region, vgm::SoundChips};
+
+    use super::VgmLogger;
+
+    // Stands for the cartridge: its ROM holds the byte of the low address lines
+    struct TestRom;
+
+    impl MemoryMapped for TestRom {
+        fn read_byte(&mut self, address: Word, _open_bus: Byte) -> Byte {
+            return address as Byte;
+        }
+
+        fn write_byte(&mut self, _address: Word, _data: Byte) {}
+    }
+
+    #[test]
+    fn test_vgm_logger_commands() {
+        let chips = SoundChips { vrc7: None, sunsoft_5b: true };
+        let mut logger = VgmLogger::build_vgm_logger(Region::Ntsc, chips, 1000);
+        let mut memory = Memory::build_memory();
+        memory.map_device(0x4000, 0x4017, logger.tap(Rc::new(RefCell::new(Apu::build_apu()))));
+        memory.map_device(0x4020, 0xFFFF, logger.tap(Rc::new(RefCell::new(TestRom))));
+
+        memory.write_byte(0x4008, 0x81);
+        logger.log_writes(1000, |address| memory.read_byte(address));
+        // One NTSC frame later
+        memory.write_byte(0xC000, 0x07);
+        memory.write_byte(0xE000, 0x38);
+        logger.log_writes(1000 + 29_830, |address| memory.read_byte(address));
+        // Two samples later, a DMC sample of 17 bytes at $C040
+        memory.write_byte(0x4012, 0x01);
+        memory.write_byte(0x4013, 0x01);
+        memory.write_byte(0x4015, 0x10);
+        logger.log_writes(1000 + 29_830 + 81, |address| memory.read_byte(address));
+        // Starting the same sample again does not repeat its data
+        memory.write_byte(0x4015, 0x10);
+        logger.log_writes(1000 + 29_830 + 81, |address| memory.read_byte(address));
+
+        let file = logger.finish(1000 + 29_830 + 81);
+        assert_eq!(&file[0..4], b"Vgm ");
+        assert_eq!(LittleEndian::read_u32(&file[0x04..]) as usize, file.len() - 4);
+        assert_eq!(LittleEndian::read_u32(&file[0x08..]), 0x171);
+        assert_eq!(LittleEndian::read_u32(&file[0x18..]), 737);
+        assert_eq!(LittleEndian::read_u32(&file[0x34..]), 0xCC);
+        assert_eq!(LittleEndian::read_u32(&file[0x84..]), 1_789_773);
+        assert_eq!(LittleEndian::read_u32(&file[0x74..]), 894_886);
+        assert_eq!(file[0x78], 0x10);
+
+        let mut expected = vec![
+            0xB4, 0x08, 0x81,
+            0x62,
+            0xA0, 0x07, 0x38,
+            0x71,
+            0xB4, 0x12, 0x01,
+            0xB4, 0x13, 0x01,
+            0x67, 0x66, 0xC2, 19, 0, 0, 0, 0x40, 0xC0,
+        ];
+        expected.extend(0x40..=0x50);
+        expected.extend_from_slice(&[0xB4, 0x15, 0x10, 0xB4, 0x15, 0x10, 0x66]);
+        assert_eq!(&file[0x100..], &expected[..]);
+    }
+
+    #[test]
+    fn test_vgm_logger_vrc7() {
+        let chips = SoundChips { vrc7: Some(0x0010), sunsoft_5b: false };
+        let mut logger = VgmLogger::build_vgm_logger(Region::Ntsc, chips, 0);
+        let mut memory = Memory::build_memory();
+        memory.map_device(0x4020, 0xFFFF, logger.tap(Rc::new(RefCell::new(TestRom))));
+        // A PRG bank write, then register $30 of the OPLL
+        memory.write_byte(0x9000, 0x05);
+        memory.write_byte(0x9010, 0x30);
+        memory.write_byte(0x9030, 0x1F);
+        logger.log_writes(0, |address| memory.read_byte(address));
+        let file = logger.finish(0);
+        assert_eq!(LittleEndian::read_u32(&file[0x10..]), 0x8036_9E99);
+        assert_eq!(&file[0x100..], &[0x51, 0x30, 0x1F, 0x66]);
+    }
+}
@@ -0,0 +1,93 @@
+pub mod logger;
+pub mod player;
+
+use std::fmt::{self, Display};
+
+use crate::{cartridge::{Header, mappers::vrc7}, cpu::{Byte, Word}, nsf::{ExpansionChips, Nsf}};
+
+// VGM 1.71: https://vgmrips.net/wiki/VGM_Specification
+// A header giving the clock of every chip used, then a stream of register writes separated by
+// waits counted in samples of 44.1 kHz.
+pub const VGM_SAMPLE_RATE: u32 = 44_100;
+const VGM_MAGIC: &[Byte] = b"Vgm ";
+const VGM_VERSION: u32 = 0x0000_0171;
+const HEADER_SIZE: usize = 0x100;
+
+// Header fields
+const EOF_OFFSET: usize = 0x04;
+const VERSION: usize = 0x08;
+const YM2413_CLOCK: usize = 0x10;
+const TOTAL_SAMPLES: usize = 0x18;
+const RATE: usize = 0x24;
+const DATA_OFFSET: usize = 0x34;
+const AY8910_CLOCK: usize = 0x74;
+const AY8910_TYPE: usize = 0x78;
+const AY8910_FLAGS: usize = 0x79;
+const NES_APU_CLOCK: usize = 0x84;
+
+// Commands
+const YM2413_WRITE: Byte = 0x51;
+const WAIT: Byte = 0x61;
+const WAIT_NTSC_FRAME: Byte = 0x62;
+const WAIT_PAL_FRAME: Byte = 0x63;
+const END_OF_DATA: Byte = 0x66;
+const DATA_BLOCK: Byte = 0x67;
+// 0x70-0x7F wait 1 to 16 samples
+const WAIT_SHORT: Byte = 0x70;
+const AY8910_WRITE: Byte = 0xA0;
+const NES_APU_WRITE: Byte = 0xB4;
+
+// Data block holding bytes for the $8000-$FFFF memory the DMC reads its samples from,
+// starting with the address they go to
+const NES_APU_RAM_BLOCK: Byte = 0xC2;
+// YM2413 clock flag asking for the VRC7 variant and its instrument set
+const VRC7_FLAG: u32 = 0x8000_0000;
+const YM2149_TYPE: Byte = 0x10;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum VgmError {
+    InvalidHeader,
+    Truncated,
+    // The log does not use the NES APU
+    NoNesApu,
+    UnsupportedCommand(Byte),
+}
+
+impl Display for VgmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VgmError::InvalidHeader => write!(f, "not a VGM file"),
+            VgmError::Truncated => write!(f, "the file is truncated"),
+            VgmError::NoNesApu => write!(f, "the file does not use the NES APU"),
+            VgmError::UnsupportedCommand(command) => write!(f, "unknown VGM command {:02X}", command),
+        }
+    }
+}
+
+impl std::error::Error for VgmError {}
+
+// Expansion sound chips that have a VGM counterpart: the VRC7 is a YM2413 variant and the
+// Sunsoft 5B a YM2149. VGM has no command for the VRC6, MMC5 and Namco 163 sound.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SoundChips {
+    // CPU address bits selecting the second VRC7 register of each pair
+    pub vrc7: Option<Word>,
+    pub sunsoft_5b: bool,
+}
+
+impl SoundChips {
+    pub fn from_header(header: &Header) -> SoundChips {
+        return SoundChips {
+            vrc7: (header.mapper == 85).then(|| vrc7::select_mask(header.submapper)),
+            sunsoft_5b: header.mapper == 69,
+        };
+    }
+
+    // NSF players decode $9010 and $9030 for the VRC7
+    pub fn from_nsf(nsf: &Nsf) -> SoundChips {
+        return SoundChips {
+            vrc7: nsf.chips.contains(ExpansionChips::VRC7).then_some(0x0010),
+            sunsoft_5b: nsf.chips.contains(ExpansionChips::SUNSOFT_5B),
+        };
+    }
+}
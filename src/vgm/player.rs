@@ -0,0 +1,278 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{
+    apu::{Apu, audio_output::AudioOutput, opll::Opll},
+    cartridge::mappers::fme7::Sunsoft5b,
+    cpu::{Byte, Word},
+    memory::MemoryMapped,
+    nes::region::Region,
+};
+
+use super::*;
+
+// Offset of the commands for files older than 1.50, which have no data offset field
+const LEGACY_DATA_START: usize = 0x40;
+
+// Length of a command with its operands, data blocks carry their size
+fn command_length(data: &[Byte], position: usize) -> Result<usize, VgmError> {
+    let command = data[position];
+    let length = match command {
+        0x30..=0x3F | 0x4F | 0x50 | 0x94 => 2,
+        0x40..=0x4E | 0x51..=0x5F | 0x61 | 0xA0..=0xBF => 3,
+        0x62 | 0x63 | 0x66 | 0x70..=0x8F => 1,
+        DATA_BLOCK => {
+            if position + 7 > data.len() {
+                return Err(VgmError::Truncated);
+            }
+            // Bit 31 of the size selects the second chip of a pair
+            7 + (LittleEndian::read_u32(&data[position + 3..]) & 0x7FFF_FFFF) as usize
+        }
+        0x68 => 12,
+        0xC0..=0xDF => 4,
+        0x90 | 0x91 | 0x95 | 0xE0..=0xFF => 5,
+        0x92 => 6,
+        0x93 => 11,
+        _ => return Err(VgmError::UnsupportedCommand(command)),
+    };
+    if position + length > data.len() {
+        return Err(VgmError::Truncated);
+    }
+    return Ok(length);
+}
+
+// Plays a VGM file of the NES APU, with the VRC7 and Sunsoft 5B when the file uses them,
+// through the emulated chips and the console mixer and filters. Comparing its output with a
+// recording of the emulator tells a playback problem from a sound generation one.
+// Commands of other chips are skipped and loops are not followed.
+pub struct VgmPlayer {
+    data: Vec<Byte>,
+    position: usize,
+    end: usize,
+    total_samples: u32,
+    region: Region,
+    apu: Apu,
+    opll: Option<Opll>,
+    psg: Option<Sunsoft5b>,
+    // $0000-$FFFF as seen by the DMC, filled by the data blocks
+    dmc_memory: Vec<Byte>,
+    audio: AudioOutput,
+    cpu_cycles: u64,
+    // VGM samples waited so far, and the CPU cycle of the next command
+    samples: u64,
+    next_cycle: u64,
+    finished: bool,
+}
+
+impl VgmPlayer {
+    pub fn build_vgm_player(data: &[Byte], sample_rate: u32) -> Result<VgmPlayer, VgmError> {
+        if data.len() < LEGACY_DATA_START || !data.starts_with(VGM_MAGIC) {
+            return Err(VgmError::InvalidHeader);
+        }
+        let version = LittleEndian::read_u32(&data[VERSION..]);
+        let data_offset = LittleEndian::read_u32(&data[DATA_OFFSET..]) as usize;
+        let start = if version >= 0x150 && data_offset != 0 { DATA_OFFSET + data_offset } else { LEGACY_DATA_START };
+        if start > data.len() {
+            return Err(VgmError::Truncated);
+        }
+        // Header fields overlapped by the commands are not part of the header
+        let field = |offset: usize| -> u32 {
+            if offset + 4 > start {
+                return 0;
+            }
+            return LittleEndian::read_u32(&data[offset..]);
+        };
+        // Bit 31 tells the FDS is used as well
+        let nes_clock = field(NES_APU_CLOCK) & 0x7FFF_FFFF;
+        if nes_clock == 0 {
+            return Err(VgmError::NoNesApu);
+        }
+        let region = [Region::Ntsc, Region::Pal, Region::Dendy].into_iter()
+            .min_by(|a, b| (a.cpu_clock_rate() - f64::from(nes_clock)).abs().total_cmp(&(b.cpu_clock_rate() - f64::from(nes_clock)).abs()))
+            .unwrap();
+        let end = match field(EOF_OFFSET) {
+            0 => data.len(),
+            offset => (offset as usize + EOF_OFFSET).clamp(start, data.len()),
+        };
+
+        // Checks every command once so playing cannot fail
+        let mut position = start;
+        while position < end {
+            position += command_length(&data[..end], position)?;
+        }
+
+        let mut apu = Apu::build_apu();
+        apu.set_region(region);
+        return Ok(VgmPlayer {
+            data: data.to_vec(),
+            position: start,
+            end,
+            total_samples: field(TOTAL_SAMPLES),
+            region,
+            apu,
+            opll: (field(YM2413_CLOCK) != 0).then(Opll::build_opll),
+            psg: (field(AY8910_CLOCK) != 0).then(Sunsoft5b::build_sunsoft_5b),
+            dmc_memory: vec![0x00; 0x10000],
+            audio: AudioOutput::build_audio_output(region.cpu_clock_rate(), sample_rate),
+            cpu_cycles: 0,
+            samples: 0,
+            next_cycle: 0,
+            finished: false,
+        });
+    }
+
+    pub fn region(&self) -> Region {
+        return self.region;
+    }
+
+    // Length of the file in milliseconds
+    pub fn duration(&self) -> u32 {
+        return (u64::from(self.total_samples) * 1000 / u64::from(VGM_SAMPLE_RATE)) as u32;
+    }
+
+    // True once the end of the commands is reached
+    pub fn is_finished(&self) -> bool {
+        return self.finished;
+    }
+
+    // Runs for a number of milliseconds, returns the samples produced
+    pub fn run_for(&mut self, milliseconds: u32) -> &[f32] {
+        let cycles = self.region.cpu_clock_rate() * f64::from(milliseconds) / 1000.0;
+        let end = self.cpu_cycles + cycles as u64;
+        while self.cpu_cycles < end {
+            while !self.finished && self.next_cycle <= self.cpu_cycles {
+                self.execute_command();
+            }
+            self.step();
+        }
+        self.audio.end_frame(self.cpu_cycles);
+        return self.audio.samples();
+    }
+
+    fn execute_command(&mut self) {
+        if self.position >= self.end {
+            self.finished = true;
+            return;
+        }
+        let position = self.position;
+        let command = self.data[position];
+        // Validated when the file was loaded
+        self.position += command_length(&self.data, position).unwrap();
+        let operands = &self.data[position + 1..self.position];
+        match command {
+            NES_APU_WRITE if operands[0] < 0x20 => self.apu.write_byte(0x4000 + Word::from(operands[0]), operands[1]),
+            YM2413_WRITE => {
+                if let Some(opll) = self.opll.as_mut() {
+                    opll.write_address(operands[0]);
+                    opll.write_data(operands[1]);
+                }
+            }
+            AY8910_WRITE => {
+                if let Some(psg) = self.psg.as_mut() {
+                    psg.write_address(operands[0]);
+                    psg.write_data(operands[1]);
+                }
+            }
+            WAIT => self.wait(u64::from(LittleEndian::read_u16(operands))),
+            WAIT_NTSC_FRAME => self.wait(735),
+            WAIT_PAL_FRAME => self.wait(882),
+            0x70..=0x7F => self.wait(u64::from(command - WAIT_SHORT) + 1),
+            // YM2612 DAC writes followed by a wait
+            0x80..=0x8F => self.wait(u64::from(command & 0x0F)),
+            DATA_BLOCK if operands[1] == NES_APU_RAM_BLOCK && operands.len() >= 8 => {
+                let address = usize::from(LittleEndian::read_u16(&operands[6..]));
+                let bytes = &operands[8..];
+                let length = bytes.len().min(self.dmc_memory.len() - address);
+                self.dmc_memory[address..address + length].copy_from_slice(&bytes[..length]);
+            }
+            END_OF_DATA => self.finished = true,
+            _ => {}
+        }
+    }
+
+    fn wait(&mut self, samples: u64) {
+        self.samples += samples;
+        self.next_cycle = (self.samples as f64 * self.region.cpu_clock_rate() / f64::from(VGM_SAMPLE_RATE)) as u64;
+    }
+
+    fn step(&mut self) {
+        self.apu.clock();
+        if let Some(address) = self.apu.dmc_dma_request() {
+            self.apu.dmc_fill_buffer(self.dmc_memory[usize::from(address)]);
+        }
+        let mut level = self.apu.output();
+        if let Some(opll) = self.opll.as_mut() {
+            opll.clock();
+            level += opll.output();
+        }
+        if let Some(psg) = self.psg.as_mut() {
+            psg.clock();
+            level += psg.output();
+        }
+        self.audio.update(self.cpu_cycles, level);
+        self.cpu_cycles += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cartridge::Cartridge, nes::{Nes, tests::build_test_rom}};
+
+    use super::{VgmError, VgmPlayer};
+
+    #[test]
+    fn test_vgm_player_invalid_files() {
+        assert_eq!(VgmPlayer::build_vgm_player(b"RIFF", 44_100).err(), Some(VgmError::InvalidHeader));
+        let mut file = vec![0x00; 0x100];
+        file[0..4].copy_from_slice(b"Vgm ");
+        file[0x08] = 0x71;
+        file[0x09] = 0x01;
+        file[0x34] = 0xCC;
+        assert_eq!(VgmPlayer::build_vgm_player(&file, 44_100).err(), Some(VgmError::NoNesApu));
+        file[0x84..0x88].copy_from_slice(&1_789_773u32.to_le_bytes());
+        file.push(0x61);
+        assert_eq!(VgmPlayer::build_vgm_player(&file, 44_100).err(), Some(VgmError::Truncated));
+        file.pop();
+        file.push(0x01);
+        assert_eq!(VgmPlayer::build_vgm_player(&file, 44_100).err(), Some(VgmError::UnsupportedCommand(0x01)));
+    }
+
+    #[test]
+    fn test_vgm_log_round_trip() {
+        let program = [
+            0xA9, 0x40,       // LDA #$40
+            0x8D, 0x17, 0x40, // STA $4017
+            0xA9, 0x01,       // LDA #$01
+            0x8D, 0x15, 0x40, // STA $4015
+            0xA9, 0xBF,       // LDA #$BF
+            0x8D, 0x00, 0x40, // STA $4000
+            0xA9, 0xFD,       // LDA #$FD
+            0x8D, 0x02, 0x40, // STA $4002
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x03, 0x40, // STA $4003
+            0x4C, 0x19, 0x80, // JMP $8019
+        ];
+        let cartridge = Cartridge::from_ines(&build_test_rom(&program, &[0x40])).unwrap();
+        let mut nes = Nes::build_nes(cartridge);
+        nes.start_vgm_log();
+        let mut live = Vec::new();
+        for _ in 0..30 {
+            nes.run_frame();
+            live.extend_from_slice(nes.audio_samples());
+        }
+        let log = nes.stop_vgm_log().unwrap();
+        assert!(log.windows(3).any(|command| command == [0xB4, 0x02, 0xFD]));
+
+        let mut player = VgmPlayer::build_vgm_player(&log, nes.sample_rate()).unwrap();
+        assert!((player.duration() as i32 - 500).abs() < 10);
+        let mut replayed = Vec::new();
+        while !player.is_finished() {
+            replayed.extend_from_slice(player.run_for(100));
+        }
+        // Both play the same 440 Hz square at the same loudness
+        let rms = |samples: &[f32]| (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt();
+        let live_rms = rms(&live[live.len() / 2..]);
+        let replayed_rms = rms(&replayed[replayed.len() / 4..replayed.len() * 3 / 4]);
+        assert!(live_rms > 0.02);
+        assert!((live_rms - replayed_rms).abs() < live_rms * 0.05);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod ntsc;
+pub mod png;
+pub mod recorder;
+pub mod y4m;
@@ -0,0 +1,261 @@
+use std::f32::consts::PI;
+
+use crate::{
+    cpu::{Byte, Word},
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH},
+};
+
+// Simulates the composite signal of the NTSC PPU and how a TV decodes it, which gives the
+// blending of dithered patterns and the color fringes games were drawn for.
+// Signal model: https://www.nesdev.org/wiki/NTSC_video
+// Every pixel lasts 8 master clock ticks and the color subcarrier 12, so the generator outputs
+// a square wave that is high for 6 of 12 phases. The phase of a pixel depends on its position:
+// each scanline starts 4 phases later than the previous one, each frame 4 phases later unless
+// the odd frame dot skip happened.
+
+const SAMPLES_PER_PIXEL: usize = 8;
+const PHASES: usize = 12;
+// Blank signal around the line so the decoding windows need no bounds checks
+const PADDING: usize = 2 * PHASES;
+const LINE_SAMPLES: usize = SCREEN_WIDTH * SAMPLES_PER_PIXEL + 2 * PADDING;
+const LINE_PHASE_STEP: usize = 4;
+
+// Output voltages for the 4 luminance levels, when the square wave is low and high
+const LOW_LEVELS: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const HIGH_LEVELS: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+// Decoder calibration, chosen so the plain colors come out close to the default palette:
+// phase of the demodulation reference, gain of the chroma, and the gamma of a TV (2.2)
+// against the one the palette colors are measured for (1.8)
+const REFERENCE_PHASE: f32 = 4.0;
+const CHROMA_GAIN: f32 = 1.5;
+const GAMMA: f32 = 2.2 / 1.8;
+
+// Window lengths in samples: a full subcarrier cycle removes the chroma from the luma, a shorter
+// one lets some through as artifacts, a longer one is the reference for sharpening
+const ARTIFACT_WINDOW: usize = 4;
+const SHARPNESS_WINDOW: usize = 2 * PHASES;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct NtscSettings {
+    // Degrees added to the phase of every color
+    pub hue: f32,
+    // 1.0 is the nominal color amount, 0.0 is black and white
+    pub saturation: f32,
+    // -1.0 blurs, 0.0 keeps the decoded picture, 1.0 sharpens
+    pub sharpness: f32,
+    // How much of the subcarrier leaks into the brightness, from 0.0 (clean) to 1.0
+    pub artifacts: f32,
+}
+
+impl Default for NtscSettings {
+    fn default() -> NtscSettings {
+        return NtscSettings { hue: 0.0, saturation: 1.0, sharpness: 0.0, artifacts: 0.25 };
+    }
+}
+
+// The generator is high for 6 of the 12 phases, starting at a phase given by the color number
+fn in_color_phase(color: usize, phase: usize) -> bool {
+    return (color + phase) % PHASES < 6;
+}
+
+// Normalized signal of a pixel, 0.0 is black and 1.0 white
+fn signal_level(pixel: usize, phase: usize) -> f32 {
+    let color = pixel & 0x0F;
+    let emphasis = pixel >> 6;
+    // Colors $xE and $xF output the black of the second level
+    let level = if color > 0x0D { 1 } else { (pixel >> 4) & 0x03 };
+    let mut low = LOW_LEVELS[level];
+    let mut high = HIGH_LEVELS[level];
+    // $x0 stays high and $xD low, they carry no color
+    if color == 0x00 {
+        low = high;
+    }
+    if color > 0x0C {
+        high = low;
+    }
+    let mut signal = if in_color_phase(color, phase) { high } else { low };
+    // Red, green and blue emphasis darken the signal during a third of the cycle each
+    if (emphasis & 0x01 != 0 && in_color_phase(0, phase))
+        || (emphasis & 0x02 != 0 && in_color_phase(4, phase))
+        || (emphasis & 0x04 != 0 && in_color_phase(8, phase)) {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+    return (signal - BLACK) / (WHITE - BLACK);
+}
+
+pub struct NtscFilter {
+    settings: NtscSettings,
+    output_width: usize,
+    // Signal of the 512 pixel values at each of the 12 phases
+    levels: Vec<[f32; PHASES]>,
+    // Carrier references at each phase, rotated by the hue
+    cosines: [f32; PHASES],
+    sines: [f32; PHASES],
+    odd_frame: bool,
+    // Running sums of the luma, and of the chroma against both references, along a line
+    luma_sums: Vec<f32>,
+    i_sums: Vec<f32>,
+    q_sums: Vec<f32>,
+}
+
+impl NtscFilter {
+    // The output has `output_width` pixels per line, 256 keeps the size of the PPU picture
+    // and wider ones show the color fringes in more detail
+    pub fn build_ntsc_filter(settings: NtscSettings, output_width: usize) -> NtscFilter {
+        let levels = (0..512).map(|pixel| std::array::from_fn(|phase| signal_level(pixel, phase))).collect();
+        let mut filter = NtscFilter {
+            settings,
+            output_width,
+            levels,
+            cosines: [0.0; PHASES],
+            sines: [0.0; PHASES],
+            odd_frame: false,
+            luma_sums: vec![0.0; LINE_SAMPLES + 1],
+            i_sums: vec![0.0; LINE_SAMPLES + 1],
+            q_sums: vec![0.0; LINE_SAMPLES + 1],
+        };
+        filter.set_settings(settings);
+        return filter;
+    }
+
+    pub fn settings(&self) -> NtscSettings {
+        return self.settings;
+    }
+
+    pub fn set_settings(&mut self, settings: NtscSettings) {
+        self.settings = settings;
+        let hue = settings.hue.to_radians();
+        for phase in 0..PHASES {
+            let angle = PI * (phase as f32 + REFERENCE_PHASE) / 6.0 + hue;
+            self.cosines[phase] = angle.cos();
+            self.sines[phase] = angle.sin();
+        }
+    }
+
+    pub fn output_width(&self) -> usize {
+        return self.output_width;
+    }
+
+    pub fn frame_to_rgba(&mut self, frame: &[Word]) -> Vec<Byte> {
+        let mut rgba = vec![0x00; self.output_width * SCREEN_HEIGHT * 4];
+        self.fill_rgba(frame, &mut rgba);
+        return rgba;
+    }
+
+    // Decodes a frame of the PPU into `output_width` x 240 RGBA pixels. Consecutive calls
+    // alternate the phase of the frames like a console rendering with the dot skip.
+    pub fn fill_rgba(&mut self, frame: &[Word], rgba: &mut [Byte]) {
+        let frame_phase = if self.odd_frame { LINE_PHASE_STEP } else { 0 };
+        self.odd_frame = !self.odd_frame;
+        for (line, (pixels, output)) in frame.chunks(SCREEN_WIDTH).zip(rgba.chunks_mut(self.output_width * 4)).enumerate() {
+            let phase = (frame_phase + line * LINE_PHASE_STEP) % PHASES;
+            self.encode_line(pixels, phase);
+            self.decode_line(output);
+        }
+    }
+
+    fn encode_line(&mut self, pixels: &[Word], line_phase: usize) {
+        let black = self.levels[0x0F][0];
+        let (mut luma, mut i, mut q) = (0.0, 0.0, 0.0);
+        for sample in 0..LINE_SAMPLES {
+            // The padding keeps the phases aligned with the pixels
+            let phase = (line_phase + sample + PHASES - PADDING % PHASES) % PHASES;
+            let level = match sample.checked_sub(PADDING).map(|offset| offset / SAMPLES_PER_PIXEL) {
+                Some(pixel) if pixel < pixels.len() => self.levels[usize::from(pixels[pixel]) & 0x1FF][phase],
+                _ => black,
+            };
+            luma += level;
+            i += level * self.cosines[phase];
+            q += level * self.sines[phase];
+            self.luma_sums[sample + 1] = luma;
+            self.i_sums[sample + 1] = i;
+            self.q_sums[sample + 1] = q;
+        }
+    }
+
+    fn decode_line(&self, output: &mut [Byte]) {
+        let settings = &self.settings;
+        let average = |sums: &[f32], center: usize, length: usize| -> f32 {
+            let start = center - length / 2;
+            return (sums[start + length] - sums[start]) / length as f32;
+        };
+        let line_width = SCREEN_WIDTH * SAMPLES_PER_PIXEL;
+        for (x, pixel) in output.chunks_exact_mut(4).enumerate() {
+            let center = PADDING + (2 * x + 1) * line_width / (2 * self.output_width);
+            let clean = average(&self.luma_sums, center, PHASES);
+            let mut y = clean + settings.artifacts * (average(&self.luma_sums, center, ARTIFACT_WINDOW) - clean);
+            y += settings.sharpness * (clean - average(&self.luma_sums, center, SHARPNESS_WINDOW));
+            let i = CHROMA_GAIN * settings.saturation * average(&self.i_sums, center, PHASES);
+            let q = CHROMA_GAIN * settings.saturation * average(&self.q_sums, center, PHASES);
+            let red = y + 0.946_882 * i + 0.623_557 * q;
+            let green = y - 0.274_788 * i - 0.635_691 * q;
+            let blue = y - 1.108_545 * i + 1.709_007 * q;
+            let to_byte = |value: f32| (value.clamp(0.0, 1.0).powf(GAMMA) * 255.0).round() as Byte;
+            pixel.copy_from_slice(&[to_byte(red), to_byte(green), to_byte(blue), 0xFF]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    use super::{NtscFilter, NtscSettings};
+
+    fn decode(filter: &mut NtscFilter, pixel: u16) -> [u16; 3] {
+        let rgba = filter.frame_to_rgba(&vec![pixel; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        // The middle of the picture, away from the blank borders
+        let offset = (120 * filter.output_width() + 128) * 4;
+        return [rgba[offset], rgba[offset + 1], rgba[offset + 2]].map(u16::from);
+    }
+
+    #[test]
+    fn test_grays() {
+        let mut filter = NtscFilter::build_ntsc_filter(NtscSettings::default(), SCREEN_WIDTH);
+        assert_eq!(decode(&mut filter, 0x0F), [0, 0, 0]);
+        assert_eq!(decode(&mut filter, 0x30), [255, 255, 255]);
+        let gray = decode(&mut filter, 0x10);
+        assert!(gray[0] == gray[1] && gray[1] == gray[2] && gray[0] > 100 && gray[0] < 200);
+    }
+
+    #[test]
+    fn test_colors() {
+        let mut filter = NtscFilter::build_ntsc_filter(NtscSettings::default(), SCREEN_WIDTH);
+        let [red, green, blue] = decode(&mut filter, 0x16);
+        assert!(red > 2 * green && red > 2 * blue);
+        let [red, green, blue] = decode(&mut filter, 0x1A);
+        assert!(green > 2 * red && green > blue);
+        let [red, green, blue] = decode(&mut filter, 0x12);
+        assert!(blue > 2 * red && blue > green);
+        // Blue emphasis on white darkens red and green
+        let [red, green, blue] = decode(&mut filter, 0x100 | 0x30);
+        assert!(blue > red && blue > green);
+
+        // Half a turn of hue gives the opposite color
+        filter.set_settings(NtscSettings { hue: 180.0, ..NtscSettings::default() });
+        let [red, green, blue] = decode(&mut filter, 0x16);
+        assert!(green > red && blue > red);
+        filter.set_settings(NtscSettings { saturation: 0.0, ..NtscSettings::default() });
+        let [red, green, blue] = decode(&mut filter, 0x16);
+        assert!(red == green && green == blue);
+    }
+
+    #[test]
+    fn test_dithering_blends() {
+        // Columns alternating between two colors decode to a mix of them
+        let frame: Vec<u16> = (0..SCREEN_WIDTH * SCREEN_HEIGHT).map(|pixel| if pixel % 2 == 0 { 0x0F } else { 0x30 }).collect();
+        let mut filter = NtscFilter::build_ntsc_filter(NtscSettings { artifacts: 0.0, ..NtscSettings::default() }, SCREEN_WIDTH);
+        let rgba = filter.frame_to_rgba(&frame);
+        let offset = (120 * SCREEN_WIDTH + 128) * 4;
+        // 1 pixel wide stripes also come out colored, only their brightness is checked
+        for pixel in rgba[offset..offset + 16].chunks(4) {
+            let brightness = (299 * u32::from(pixel[0]) + 587 * u32::from(pixel[1]) + 114 * u32::from(pixel[2])) / 1000;
+            assert!(brightness > 60 && brightness < 200);
+        }
+        assert_eq!(filter.frame_to_rgba(&frame).len(), SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+    }
+}
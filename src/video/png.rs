@@ -0,0 +1,302 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    checksum::crc32,
+    cpu::{Byte, Word},
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH, palette::Palette},
+};
+
+const SIGNATURE: [Byte; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const BIT_DEPTH: Byte = 8;
+const COLOR_TYPE_RGBA: Byte = 6;
+const FILTER_NONE: Byte = 0;
+
+// Deflate limits: https://www.rfc-editor.org/rfc/rfc1951
+const WINDOW_SIZE: usize = 32_768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// Candidates tried per position, enough for the repeated rows of a frame
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const END_OF_BLOCK: u16 = 256;
+
+// Deflate streams are packed from the least significant bit of each byte
+struct BitWriter {
+    bytes: Vec<Byte>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn build_bit_writer() -> BitWriter {
+        return BitWriter { bytes: Vec::new(), buffer: 0, count: 0 };
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as Byte);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes are stored from their most significant bit
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn finish(mut self) -> Vec<Byte> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as Byte);
+        }
+        return self.bytes;
+    }
+}
+
+// Code of the fixed Huffman table for a literal, a length or the end of block
+fn write_symbol(writer: &mut BitWriter, symbol: u16) {
+    let symbol = u32::from(symbol);
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASES.iter().rposition(|base| usize::from(*base) <= length).unwrap();
+    write_symbol(writer, 257 + code as u16);
+    writer.write_bits((length - usize::from(LENGTH_BASES[code])) as u32, u32::from(LENGTH_EXTRA_BITS[code]));
+    let code = DISTANCE_BASES.iter().rposition(|base| usize::from(*base) <= distance).unwrap();
+    writer.write_code(code as u32, 5);
+    writer.write_bits((distance - usize::from(DISTANCE_BASES[code])) as u32, u32::from(DISTANCE_EXTRA_BITS[code]));
+}
+
+fn hash(data: &[Byte]) -> usize {
+    let value = u32::from(data[0]) << 16 | u32::from(data[1]) << 8 | u32::from(data[2]);
+    return (value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize;
+}
+
+// A single block with the fixed Huffman codes and greedy LZ77 matching. Frames are mostly runs
+// and repeated rows, which compress well without building dynamic tables.
+fn deflate(data: &[Byte]) -> Vec<Byte> {
+    let mut writer = BitWriter::build_bit_writer();
+    // BFINAL then BTYPE 01
+    writer.write_bits(0b011, 3);
+    // Most recent position of each hash, and the previous position with the same hash
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; data.len()];
+    let insert = |position: usize, head: &mut [usize], previous: &mut [usize]| {
+        if position + MIN_MATCH <= data.len() {
+            let slot = &mut head[hash(&data[position..])];
+            previous[position] = *slot;
+            *slot = position;
+        }
+    };
+
+    let mut position = 0;
+    while position < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        if position + MIN_MATCH <= data.len() {
+            let limit = MAX_MATCH.min(data.len() - position);
+            let mut candidate = head[hash(&data[position..])];
+            let mut chain = 0;
+            while candidate != usize::MAX && position - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..].iter().zip(&data[position..position + limit]).take_while(|(a, b)| a == b).count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = position - candidate;
+                    if length == limit {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+        if best_length >= MIN_MATCH {
+            write_match(&mut writer, best_length, best_distance);
+            for skipped in position..position + best_length {
+                insert(skipped, &mut head, &mut previous);
+            }
+            position += best_length;
+        } else {
+            write_symbol(&mut writer, u16::from(data[position]));
+            insert(position, &mut head, &mut previous);
+            position += 1;
+        }
+    }
+    write_symbol(&mut writer, END_OF_BLOCK);
+    return writer.finish();
+}
+
+fn adler32(data: &[Byte]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // 5552 bytes is the longest run before the sums can overflow
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += u32::from(*byte);
+            b += a;
+        }
+        a %= 65_521;
+        b %= 65_521;
+    }
+    return b << 16 | a;
+}
+
+// zlib container: https://www.rfc-editor.org/rfc/rfc1950
+fn zlib(data: &[Byte]) -> Vec<Byte> {
+    // Deflate with a 32K window, no dictionary, header check bits for a multiple of 31
+    let mut stream = vec![0x78, 0x01];
+    stream.extend(deflate(data));
+    stream.extend(adler32(data).to_be_bytes());
+    return stream;
+}
+
+fn write_chunk(png: &mut Vec<Byte>, kind: &[Byte; 4], data: &[Byte]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+// 8 bit RGBA image, 4 bytes per pixel row after row: https://www.w3.org/TR/png/
+pub fn encode_png(width: usize, height: usize, rgba: &[Byte]) -> Vec<Byte> {
+    assert_eq!(rgba.len(), width * height * 4, "the image is {}x{} pixels", width, height);
+    let mut header = Vec::with_capacity(13);
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // No compression method, filter method or interlacing choices besides 0
+    header.extend([BIT_DEPTH, COLOR_TYPE_RGBA, 0, 0, 0]);
+
+    // Every row starts with its filter type
+    let mut pixels = Vec::with_capacity(rgba.len() + height);
+    for row in rgba.chunks(width * 4) {
+        pixels.push(FILTER_NONE);
+        pixels.extend(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib(&pixels));
+    write_chunk(&mut png, b"IEND", &[]);
+    return png;
+}
+
+// Writes a frame of the PPU, so golden images can be compared byte for byte with the encoder
+pub fn save_frame_png(path: &Path, frame: &[Word], palette: &Palette) -> io::Result<()> {
+    return fs::write(path, encode_png(SCREEN_WIDTH, SCREEN_HEIGHT, &palette.frame_to_rgba(frame)));
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, ByteOrder};
+
+    use super::*;
+
+    // Inflates the fixed Huffman blocks written by the encoder
+    fn inflate(data: &[Byte]) -> Vec<Byte> {
+        let mut position = 0;
+        let mut bit = |count: u32| -> u32 {
+            let mut value = 0;
+            for index in 0..count {
+                value |= u32::from(data[position / 8] >> (position % 8) & 1) << index;
+                position += 1;
+            }
+            return value;
+        };
+        assert_eq!(bit(3), 0b011);
+        let mut output: Vec<Byte> = Vec::new();
+        loop {
+            // Longest codes first, reading the code from its most significant bit
+            let mut code = 0;
+            for _ in 0..7 {
+                code = code << 1 | bit(1);
+            }
+            let symbol = if code <= 0x17 {
+                code + 256
+            } else {
+                code = code << 1 | bit(1);
+                match code {
+                    0x30..=0xBF => code - 0x30,
+                    0xC0..=0xC7 => code - 0xC0 + 280,
+                    _ => (code << 1 | bit(1)) - 0x190 + 144,
+                }
+            };
+            match symbol {
+                0..=255 => output.push(symbol as Byte),
+                256 => return output,
+                _ => {
+                    let index = (symbol - 257) as usize;
+                    let length = usize::from(LENGTH_BASES[index]) + bit(u32::from(LENGTH_EXTRA_BITS[index])) as usize;
+                    let mut index = 0;
+                    for _ in 0..5 {
+                        index = index << 1 | bit(1) as usize;
+                    }
+                    let distance = usize::from(DISTANCE_BASES[index]) + bit(u32::from(DISTANCE_EXTRA_BITS[index])) as usize;
+                    for _ in 0..length {
+                        output.push(output[output.len() - distance]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(&[0xFF; 100_000]), 0x149A_302C);
+    }
+
+    #[test]
+    fn test_deflate_round_trip() {
+        let mut data: Vec<Byte> = (0..1000u32).map(|value| (value * value % 251) as Byte).collect();
+        data.extend(vec![0x0F; 5000]);
+        data.extend_from_within(0..3000);
+        data.extend(0..=255);
+        let compressed = deflate(&data);
+        assert!(compressed.len() < data.len() / 3);
+        assert_eq!(inflate(&compressed), data);
+    }
+
+    #[test]
+    fn test_encode_png() {
+        let frame: Vec<Word> = (0..SCREEN_WIDTH * SCREEN_HEIGHT).map(|pixel| ((pixel / 8) % 64) as Word).collect();
+        let rgba = Palette::build_default_palette().frame_to_rgba(&frame);
+        let png = encode_png(SCREEN_WIDTH, SCREEN_HEIGHT, &rgba);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(BigEndian::read_u32(&png[16..]), 256);
+        assert_eq!(BigEndian::read_u32(&png[20..]), 240);
+        assert_eq!(png[24..26], [BIT_DEPTH, COLOR_TYPE_RGBA]);
+        assert_eq!(png[png.len() - 12..], [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+
+        let idat_length = BigEndian::read_u32(&png[33..]) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let stream = &png[41..41 + idat_length];
+        let pixels = inflate(&stream[2..stream.len() - 4]);
+        assert_eq!(BigEndian::read_u32(&stream[stream.len() - 4..]), adler32(&pixels));
+        assert_eq!(pixels.len(), rgba.len() + SCREEN_HEIGHT);
+        assert_eq!(pixels[0], FILTER_NONE);
+        assert_eq!(pixels[1..1 + SCREEN_WIDTH * 4], rgba[..SCREEN_WIDTH * 4]);
+        assert!(png.len() < 10_000);
+    }
+}
@@ -0,0 +1,114 @@
+use std::{collections::VecDeque, fs::File, io::{self, BufWriter}, path::Path};
+
+use crate::{
+    audio::wav::{SampleFormat, WavWriter},
+    nes::Nes,
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH, palette::Palette},
+};
+
+use super::y4m::Y4mWriter;
+
+// Records a running console to a Y4M video and a WAV file that share one timeline: after n frames
+// the WAV holds exactly n / frame rate seconds of samples, so muxing them cannot drift. The few
+// samples a frame produces beyond that wait for the next frame, a missing sample repeats the
+// last one. This also absorbs the rate adjustment a frontend applies to follow its audio device.
+pub struct Recorder {
+    video: Y4mWriter<BufWriter<File>>,
+    audio: WavWriter<BufWriter<File>>,
+    palette: Palette,
+    frame_rate: (u64, u64),
+    sample_rate: u32,
+    frames: u64,
+    samples_written: u64,
+    pending: VecDeque<f32>,
+    last_sample: f32,
+}
+
+impl Recorder {
+    pub fn build_recorder(nes: &Nes, video_path: &Path, audio_path: &Path, palette: Palette) -> io::Result<Recorder> {
+        let frame_rate = nes.region().frame_rate_ratio();
+        let sample_rate = nes.sample_rate();
+        return Ok(Recorder {
+            video: Y4mWriter::create(video_path, SCREEN_WIDTH, SCREEN_HEIGHT, frame_rate)?,
+            audio: WavWriter::create(audio_path, sample_rate, 1, SampleFormat::Pcm16)?,
+            palette,
+            frame_rate,
+            sample_rate,
+            frames: 0,
+            samples_written: 0,
+            pending: VecDeque::new(),
+            last_sample: 0.0,
+        });
+    }
+
+    // Call after every `run_frame`
+    pub fn write_frame(&mut self, nes: &Nes) -> io::Result<()> {
+        self.video.write_frame(&self.palette.frame_to_rgb(nes.ppu().frame_buffer()))?;
+        self.frames += 1;
+
+        self.pending.extend(nes.audio_samples());
+        let (numerator, denominator) = self.frame_rate;
+        let total = u128::from(self.frames) * u128::from(self.sample_rate) * u128::from(denominator) / u128::from(numerator);
+        let count = (total as u64 - self.samples_written) as usize;
+        let mut samples = Vec::with_capacity(count);
+        for _ in 0..count {
+            if let Some(sample) = self.pending.pop_front() {
+                self.last_sample = sample;
+            }
+            samples.push(self.last_sample);
+        }
+        // At most a frame of slack is kept, more means the audio runs faster than the video
+        self.pending.truncate(count);
+        self.audio.write_samples(&samples)?;
+        self.samples_written += count as u64;
+        return Ok(());
+    }
+
+    pub fn frames(&self) -> u64 {
+        return self.frames;
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.video.finish()?;
+        self.audio.finish()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cartridge::Cartridge, nes::{Nes, tests::build_test_rom}, ppu::palette::Palette};
+
+    use super::Recorder;
+
+    #[test]
+    fn test_recorder_timeline() {
+        let program = [
+            0x4C, 0x00, 0x80, // JMP $8000
+        ];
+        let cartridge = Cartridge::from_ines(&build_test_rom(&program, &[0x40])).unwrap();
+        let mut nes = Nes::build_nes(cartridge);
+        // Faster audio than nominal, the recording must still follow the video
+        nes.set_audio_rate_adjustment(1.005);
+        let directory = std::env::temp_dir().join(format!("recorder_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let video_path = directory.join("run.y4m");
+        let audio_path = directory.join("run.wav");
+        let mut recorder = Recorder::build_recorder(&nes, &video_path, &audio_path, Palette::build_default_palette()).unwrap();
+        for _ in 0..120 {
+            nes.run_frame();
+            recorder.write_frame(&nes).unwrap();
+        }
+        assert_eq!(recorder.frames(), 120);
+        recorder.finish().unwrap();
+
+        let video = std::fs::read(&video_path).unwrap();
+        let header = b"YUV4MPEG2 W256 H240 F10738636:178683 Ip A1:1 C444\n";
+        assert_eq!(video[..header.len()], header[..]);
+        assert_eq!(video.len(), header.len() + 120 * (6 + 256 * 240 * 3));
+        // 120 frames at 60.0988 fps
+        let samples = (std::fs::read(&audio_path).unwrap().len() - 44) / 2;
+        assert_eq!(samples as u64, 120 * u64::from(nes.sample_rate()) * 178_683 / 10_738_636);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
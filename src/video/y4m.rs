@@ -0,0 +1,78 @@
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path};
+
+use crate::cpu::Byte;
+
+// Uncompressed video: https://wiki.multimedia.cx/index.php/YUV4MPEG2
+// A text header, then every frame as "FRAME\n" and its Y, Cb and Cr planes at full resolution
+// (4:4:4, the 1 pixel wide details of the picture would bleed with subsampled chroma).
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+    planes: Vec<Byte>,
+}
+
+impl Y4mWriter<BufWriter<File>> {
+    pub fn create(path: &Path, width: usize, height: usize, frame_rate: (u64, u64)) -> io::Result<Y4mWriter<BufWriter<File>>> {
+        return Y4mWriter::build_y4m_writer(BufWriter::new(File::create(path)?), width, height, frame_rate);
+    }
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn build_y4m_writer(mut writer: W, width: usize, height: usize, frame_rate: (u64, u64)) -> io::Result<Y4mWriter<W>> {
+        let (numerator, denominator) = frame_rate;
+        writeln!(writer, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444", width, height, numerator, denominator)?;
+        return Ok(Y4mWriter { writer, width, height, planes: vec![0x00; width * height * 3] });
+    }
+
+    // 3 bytes per pixel
+    pub fn write_frame(&mut self, rgb: &[Byte]) -> io::Result<()> {
+        let size = self.width * self.height;
+        assert_eq!(rgb.len(), size * 3, "the video is {}x{} pixels", self.width, self.height);
+        for (index, pixel) in rgb.chunks_exact(3).enumerate() {
+            let [y, cb, cr] = to_ycbcr([pixel[0], pixel[1], pixel[2]]);
+            self.planes[index] = y;
+            self.planes[size + index] = cb;
+            self.planes[2 * size + index] = cr;
+        }
+        self.writer.write_all(b"FRAME\n")?;
+        return self.writer.write_all(&self.planes);
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        return self.writer.flush();
+    }
+}
+
+// BT.601 with the studio range Y4M readers assume, in 8 bit fixed point
+fn to_ycbcr(rgb: [Byte; 3]) -> [Byte; 3] {
+    let [red, green, blue] = rgb.map(i32::from);
+    let y = ((66 * red + 129 * green + 25 * blue + 128) >> 8) + 16;
+    let cb = ((-38 * red - 74 * green + 112 * blue + 128) >> 8) + 128;
+    let cr = ((112 * red - 94 * green - 18 * blue + 128) >> 8) + 128;
+    return [y as Byte, cb as Byte, cr as Byte];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Y4mWriter, to_ycbcr};
+
+    #[test]
+    fn test_to_ycbcr() {
+        assert_eq!(to_ycbcr([0, 0, 0]), [16, 128, 128]);
+        assert_eq!(to_ycbcr([255, 255, 255]), [235, 128, 128]);
+        assert_eq!(to_ycbcr([255, 0, 0]), [82, 90, 240]);
+    }
+
+    #[test]
+    fn test_y4m_frames() {
+        let mut output = Vec::new();
+        let mut writer = Y4mWriter::build_y4m_writer(&mut output, 2, 1, (10_738_636, 178_683)).unwrap();
+        writer.write_frame(&[0, 0, 0, 255, 255, 255]).unwrap();
+        writer.write_frame(&[255, 255, 255, 0, 0, 0]).unwrap();
+        writer.finish().unwrap();
+        let header = b"YUV4MPEG2 W2 H1 F10738636:178683 Ip A1:1 C444\n";
+        assert_eq!(output[..header.len()], header[..]);
+        assert_eq!(output[header.len()..], *b"FRAME\n\x10\xEB\x80\x80\x80\x80FRAME\n\xEB\x10\x80\x80\x80\x80");
+    }
+}